@@ -0,0 +1,164 @@
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBToBytes};
+
+const UNKNOWN: usize = usize::MAX;
+
+// Remembers the byte size of a body that's serialized over and over without changing, like a negotiate
+// response or a directory listing built once. Any mutable access forgets the size, so it can't go stale.
+// Atomic rather than a Cell so a shared template can still be sized from several connections
+pub struct CachedSize<T> {
+    inner: T,
+    size: AtomicUsize,
+}
+
+impl<T> CachedSize<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            size: AtomicUsize::new(UNKNOWN),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    pub fn invalidate(&mut self) {
+        *self.size.get_mut() = UNKNOWN;
+    }
+}
+
+impl<T> Deref for CachedSize<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T> DerefMut for CachedSize<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.invalidate();
+        &mut self.inner
+    }
+}
+
+impl<T: SMBByteSize> SMBByteSize for CachedSize<T> {
+    fn smb_byte_size(&self) -> usize {
+        match self.size.load(Ordering::Relaxed) {
+            UNKNOWN => {
+                let size = self.inner.smb_byte_size();
+                self.size.store(size, Ordering::Relaxed);
+                size
+            },
+            size => size,
+        }
+    }
+}
+
+impl<T: SMBFromBytes> SMBFromBytes for CachedSize<T> {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let (remaining, inner) = T::smb_from_bytes(input)?;
+        Ok((remaining, Self::new(inner)))
+    }
+}
+
+impl<T: SMBToBytes> SMBToBytes for CachedSize<T> {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        self.inner.smb_to_bytes()
+    }
+}
+
+impl<T: Clone> Clone for CachedSize<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            size: AtomicUsize::new(self.size.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl<T: Debug> Debug for CachedSize<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl<T: PartialEq> PartialEq for CachedSize<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
+
+impl<T: Eq> Eq for CachedSize<T> {}
+
+impl<T: Default> Default for CachedSize<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T> From<T> for CachedSize<T> {
+    fn from(inner: T) -> Self {
+        Self::new(inner)
+    }
+}
+
+impl<T: Serialize> Serialize for CachedSize<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.inner.serialize(serializer)
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for CachedSize<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cell::Cell;
+
+    use crate::cached_size::CachedSize;
+    use crate::{SMBByteSize, SMBToBytes};
+
+    struct Listing {
+        names: Vec<u8>,
+        sized: Cell<usize>,
+    }
+
+    impl SMBByteSize for Listing {
+        fn smb_byte_size(&self) -> usize {
+            self.sized.set(self.sized.get() + 1);
+            self.names.len()
+        }
+    }
+
+    impl SMBToBytes for Listing {
+        fn smb_to_bytes(&self) -> Vec<u8> {
+            self.names.clone()
+        }
+    }
+
+    #[test]
+    fn size_is_computed_once_until_mutated() {
+        let mut listing = CachedSize::new(Listing { names: vec![1, 2, 3], sized: Cell::new(0) });
+        assert_eq!(listing.smb_byte_size(), 3);
+        assert_eq!(listing.smb_byte_size(), 3);
+        assert_eq!(listing.sized.get(), 1);
+
+        listing.names.push(4);
+        assert_eq!(listing.smb_byte_size(), 4);
+        assert_eq!(listing.sized.get(), 2);
+        assert_eq!(listing.smb_to_bytes(), vec![1, 2, 3, 4]);
+    }
+}
@@ -1,5 +1,9 @@
-use std::error::Error;
-use std::fmt::{Display, Formatter};
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter};
+#[cfg(feature = "std")]
 use std::io;
 
 use crate::nt_status::NTStatus;
@@ -9,6 +13,7 @@ pub enum SMBError {
     ParseError(SMBParseError),
     CryptoError(SMBCryptoError),
     PreconditionFailed(SMBPreconditionFailedError),
+    #[cfg(feature = "std")]
     IOError(SMBIOError),
     ResponseError(SMBResponseError),
     PayloadTooSmall(SMBPayloadTooSmallError),
@@ -28,6 +33,7 @@ impl SMBError {
         Self::PreconditionFailed(error.into())
     }
 
+    #[cfg(feature = "std")]
     pub fn io_error<T: Into<SMBIOError>>(error: T) -> Self {
         Self::IOError(error.into())
     }
@@ -36,6 +42,14 @@ impl SMBError {
         Self::ResponseError(error.into())
     }
 
+    // For statuses whose error response carries ErrorData, like a CREATE that stopped on a symlink
+    pub fn response_error_with_data<T: Into<NTStatus>>(status: T, error_data: Vec<u8>) -> Self {
+        Self::ResponseError(SMBResponseError {
+            status: status.into(),
+            error_data,
+        })
+    }
+
     pub fn payload_too_small<T: Into<usize>, U: Into<usize>>(expected: T, actual: U) -> Self {
         Self::PayloadTooSmall((expected, actual).into())
     }
@@ -45,6 +59,26 @@ impl SMBError {
     }
 }
 
+#[cfg(feature = "std")]
+impl From<io::Error> for SMBError {
+    fn from(value: io::Error) -> Self {
+        Self::io_error(value)
+    }
+}
+
+impl From<nom::Err<nom::error::Error<&[u8]>>> for SMBError {
+    #[cfg(feature = "std")]
+    fn from(value: nom::Err<nom::error::Error<&[u8]>>) -> Self {
+        Self::parse_error(value.to_owned())
+    }
+
+    // nom only implements Error for its error types with std, so keep the rendered message instead
+    #[cfg(not(feature = "std"))]
+    fn from(value: nom::Err<nom::error::Error<&[u8]>>) -> Self {
+        Self::parse_error(alloc::format!("{:?}", value))
+    }
+}
+
 #[derive(Debug)]
 pub struct SMBParseError {
     error: Box<dyn Error + Send + Sync>,
@@ -59,7 +93,7 @@ impl<T: Into<Box<dyn Error + Send + Sync>>> From<T> for SMBParseError {
 }
 
 impl Display for SMBParseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Parse failed with error: {}", self.error)
     }
 }
@@ -79,7 +113,7 @@ impl<T: Into<Box<dyn Error + Send + Sync>>> From<T> for SMBCryptoError {
 
 
 impl Display for SMBCryptoError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Crypto operation failed with error: {}", self.message)
     }
 }
@@ -98,16 +132,18 @@ impl<T: Into<String>> From<T> for SMBPreconditionFailedError {
 }
 
 impl Display for SMBPreconditionFailedError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Operation failed with unmet precondition: {}", self.message)
     }
 }
 
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub struct SMBIOError {
     error: io::Error,
 }
 
+#[cfg(feature = "std")]
 impl<T: Into<io::Error>> From<T> for SMBIOError {
     fn from(value: T) -> Self {
         Self {
@@ -116,8 +152,9 @@ impl<T: Into<io::Error>> From<T> for SMBIOError {
     }
 }
 
+#[cfg(feature = "std")]
 impl Display for SMBIOError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "SMB I/O operation failed with error: {}", self.error)
     }
 }
@@ -125,18 +162,30 @@ impl Display for SMBIOError {
 #[derive(Debug)]
 pub struct SMBResponseError {
     status: NTStatus,
+    error_data: Vec<u8>,
 }
 
 impl<T: Into<NTStatus>> From<T> for SMBResponseError {
     fn from(value: T) -> Self {
         Self {
-            status: value.into()
+            status: value.into(),
+            error_data: Vec::new(),
         }
     }
 }
 
+impl SMBResponseError {
+    pub fn status(&self) -> NTStatus {
+        self.status
+    }
+
+    pub fn error_data(&self) -> &[u8] {
+        &self.error_data
+    }
+}
+
 impl Display for SMBResponseError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "SMB response generation failed with: {:?}", self.status)
     }
 }
@@ -157,7 +206,7 @@ impl<T: Into<usize>, U: Into<usize>> From<(T, U)> for SMBPayloadTooSmallError {
 }
 
 impl Display for SMBPayloadTooSmallError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(f, "Expected {} bytes, was actually {} bytes", self.expected, self.actual)
     }
 }
@@ -176,17 +225,18 @@ impl<T: Into<Box<dyn Error + Send + Sync>>> From<T> for SMBServerError {
 }
 
 impl Display for SMBServerError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Parse failed with error: {}", self.error)
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Server operation failed with error: {}", self.error)
     }
 }
 
 impl Display for SMBError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::ParseError(x) => write!(f, "{}", x),
             Self::CryptoError(x) => write!(f, "{}", x),
             Self::PreconditionFailed(x) => write!(f, "{}", x),
+            #[cfg(feature = "std")]
             Self::IOError(x) => write!(f, "{}", x),
             Self::ResponseError(x) => write!(f, "{}", x),
             Self::PayloadTooSmall(x) => write!(f, "{}", x),
@@ -195,4 +245,67 @@ impl Display for SMBError {
     }
 }
 
-impl std::error::Error for SMBError {}
\ No newline at end of file
+impl Error for SMBError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ParseError(x) => Some(x.error.as_ref()),
+            Self::CryptoError(x) => Some(x.message.as_ref()),
+            #[cfg(feature = "std")]
+            Self::IOError(x) => Some(&x.error),
+            Self::ServerError(x) => Some(x.error.as_ref()),
+            Self::PreconditionFailed(_) | Self::ResponseError(_) | Self::PayloadTooSmall(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::io;
+
+    use crate::error::SMBError;
+    use crate::nt_status::NTStatus;
+
+    #[test]
+    fn formats_each_variant() {
+        let cases = [
+            (SMBError::parse_error("bad tag"), "Parse failed with error: bad tag"),
+            (SMBError::crypto_error("bad key"), "Crypto operation failed with error: bad key"),
+            (SMBError::precondition_failed("no session"), "Operation failed with unmet precondition: no session"),
+            (SMBError::io_error(io::Error::other("closed")), "SMB I/O operation failed with error: closed"),
+            (SMBError::response_error(NTStatus::AccessDenied), "SMB response generation failed with: AccessDenied"),
+            (SMBError::payload_too_small(8_usize, 4_usize), "Expected 8 bytes, was actually 4 bytes"),
+            (SMBError::server_error("no listener"), "Server operation failed with error: no listener"),
+        ];
+        for (error, expected) in cases {
+            assert_eq!(error.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn chains_underlying_sources() {
+        let io_error = SMBError::io_error(io::Error::other("closed"));
+        let source = io_error.source().expect("I/O errors carry their source");
+        assert_eq!(source.to_string(), "closed");
+        assert!(source.downcast_ref::<io::Error>().is_some());
+
+        assert_eq!(SMBError::parse_error("bad tag").source().unwrap().to_string(), "bad tag");
+        assert_eq!(SMBError::server_error("no listener").source().unwrap().to_string(), "no listener");
+        assert!(SMBError::response_error(NTStatus::AccessDenied).source().is_none());
+        assert!(SMBError::payload_too_small(8_usize, 4_usize).source().is_none());
+    }
+
+    #[test]
+    fn converts_io_and_nom_errors() {
+        fn read_closed() -> Result<(), SMBError> {
+            Err(io::Error::other("closed"))?
+        }
+        fn parse_short(input: &[u8]) -> Result<u32, SMBError> {
+            let (_, value) = nom::number::complete::le_u32::<_, nom::error::Error<&[u8]>>(input)?;
+            Ok(value)
+        }
+        assert!(matches!(read_closed(), Err(SMBError::IOError(_))));
+        assert!(matches!(parse_short(&[1, 2]), Err(SMBError::ParseError(_))));
+        assert_eq!(parse_short(&[1, 0, 0, 0]).unwrap(), 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,123 @@
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::error::SMBError;
+use crate::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBToBytes};
+
+// An opaque blob whose length the protocol fixes, like a resume key or a QFid. The size is part of the type so
+// it never has to be read off the wire or checked against a buffer
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FixedBytes<const N: usize>([u8; N]);
+
+impl<const N: usize> FixedBytes<N> {
+    pub const SIZE: usize = N;
+
+    pub const fn new(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; N] {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> [u8; N] {
+        self.0
+    }
+}
+
+impl<const N: usize> Default for FixedBytes<N> {
+    fn default() -> Self {
+        Self([0; N])
+    }
+}
+
+impl<const N: usize> From<[u8; N]> for FixedBytes<N> {
+    fn from(bytes: [u8; N]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl<const N: usize> AsRef<[u8]> for FixedBytes<N> {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl<const N: usize> Debug for FixedBytes<N> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "FixedBytes<{}>(", N)?;
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl<const N: usize> SMBByteSize for FixedBytes<N> {
+    fn smb_byte_size(&self) -> usize {
+        N
+    }
+}
+
+impl<const N: usize> SMBFromBytes for FixedBytes<N> {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        if input.len() < N {
+            return Err(SMBError::payload_too_small(N, input.len()));
+        }
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(&input[..N]);
+        Ok((&input[N..], Self(bytes)))
+    }
+}
+
+impl<const N: usize> SMBToBytes for FixedBytes<N> {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+// serde only covers arrays up to 32 elements, so the blob goes through as plain bytes
+impl<const N: usize> Serialize for FixedBytes<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for FixedBytes<N> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        <[u8; N]>::try_from(bytes.as_slice())
+            .map(Self)
+            .map_err(|_| serde::de::Error::invalid_length(bytes.len(), &"a fixed number of bytes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixed_bytes::FixedBytes;
+    use crate::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    fn round_trip<const N: usize>() {
+        let bytes: [u8; N] = core::array::from_fn(|idx| idx as u8);
+        let blob = FixedBytes::new(bytes);
+        assert_eq!(blob.smb_byte_size(), N);
+        let encoded = [&blob.smb_to_bytes()[..], &[0xFF, 0xFE]].concat();
+        assert_eq!(&encoded[..N], &bytes);
+        let (remaining, parsed) = FixedBytes::<N>::smb_from_bytes(&encoded).unwrap();
+        assert_eq!(parsed, blob);
+        assert_eq!(remaining, &[0xFF, 0xFE]);
+    }
+
+    #[test]
+    fn fixed_blobs_round_trip() {
+        round_trip::<24>();
+        round_trip::<32>();
+    }
+
+    #[test]
+    fn short_input_is_too_small() {
+        assert!(FixedBytes::<32>::smb_from_bytes(&[0; 31]).is_err());
+    }
+}
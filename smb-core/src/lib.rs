@@ -1,11 +1,22 @@
-use std::marker::PhantomData;
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
 
 use uuid::Uuid;
 
 use error::SMBError;
 
+pub mod cached_size;
+
 pub mod error;
 
+pub mod fixed_bytes;
+
 pub mod nt_status;
 
 pub type SMBParseResult<I, O, E = SMBError> = Result<(I, O), E>;
@@ -17,32 +28,51 @@ pub trait SMBByteSize {
 
 pub trait SMBFromBytes: SMBByteSize {
     fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized;
+
+    // For a standalone message, which has to be used up by its parse. Bytes left over mean a length or offset
+    // was off, compound chains keep going from the remainder of smb_from_bytes instead
+    fn smb_from_bytes_exact(input: &[u8]) -> SMBResult<Self> where Self: Sized {
+        let (remaining, value) = Self::smb_from_bytes(input)?;
+        match remaining.is_empty() {
+            true => Ok(value),
+            false => Err(SMBError::parse_error(alloc::format!("{} trailing bytes after a {} byte message", remaining.len(), input.len() - remaining.len()))),
+        }
+    }
 }
 
 pub trait SMBToBytes: SMBByteSize {
     fn smb_to_bytes(&self) -> Vec<u8>;
 }
 
+// Used by derived SMBToBytes impls to put bytes at a position in the output. The output starts out at the
+// struct's byte size, but a layout that runs past it grows the output rather than panicking
+pub fn smb_write_at(item: &mut Vec<u8>, position: usize, bytes: &[u8]) {
+    let end = position + bytes.len();
+    if item.len() < end {
+        item.resize(end, 0);
+    }
+    item[position..end].copy_from_slice(bytes);
+}
+
+// Vector elements go out in order, each starting on the next multiple of `align` counted from `start`.
+// Parsing skips the same padding, so a serialized vector reads back in the order it was written
 pub trait SMBVecByteSize {
     fn smb_byte_size_vec(&self, align: usize, start: usize) -> usize;
 }
 
 impl<T: SMBByteSize> SMBVecByteSize for Vec<T> {
     fn smb_byte_size_vec(&self, align: usize, start: usize) -> usize {
-        let align = std::cmp::max(align, 1);
+        let align = core::cmp::max(align, 1);
+        if align == 1 {
+            return self.iter().map(T::smb_byte_size).sum();
+        }
         self.iter().fold(start, |prev, x| {
-            if align > 1 {
-                // println!("Start position for item at {prev} with align {align}");
-            }
             let size = x.smb_byte_size();
             let aligned_start = if prev % align == 0 {
                 prev
             } else {
                 prev + (align - prev % align)
             };
-            if align > 1 {
-                // println!("adj Start position for item at {aligned_start} with align {align} and size {size}");
-            }
             aligned_start + size
         }) - start
     }
@@ -67,6 +97,25 @@ impl<T> SMBByteSize for PhantomData<T> {
     }
 }
 
+// An absent body or field, takes up no bytes either way
+impl SMBFromBytes for () {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        Ok((input, ()))
+    }
+}
+
+impl SMBToBytes for () {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        vec![]
+    }
+}
+
+impl SMBByteSize for () {
+    fn smb_byte_size(&self) -> usize {
+        0
+    }
+}
+
 impl SMBVecFromBytesCnt for String {
     fn smb_from_bytes_vec_cnt(input: &[u8], align: usize, count: usize) -> SMBParseResult<&[u8], Self> where Self: Sized {
         let (remaining, vec) = <Vec<u8>>::smb_from_bytes_vec_cnt(input, align, count)?;
@@ -77,8 +126,12 @@ impl SMBVecFromBytesCnt for String {
 }
 
 impl SMBVecByteSize for String {
+    // The alignment doubles as the code unit width, so UTF-16 lengths are counted rather than taken from the UTF-8 length
     fn smb_byte_size_vec(&self, align: usize, _: usize) -> usize {
-        self.as_bytes().len() * align
+        match align {
+            2 => self.encode_utf16().count() * 2,
+            _ => self.len() * align,
+        }
     }
 }
 
@@ -103,7 +156,8 @@ impl<T: SMBFromBytes> SMBVecFromBytesCnt for Vec<T> {
         let mut pos = 0;
         let mut extra = 0;
         while done_cnt < count {
-            remaining = &remaining[extra..];
+            remaining = remaining.get(extra..)
+                .ok_or(SMBError::payload_too_small(extra, remaining.len()))?;
             let (r, val) = T::smb_from_bytes(remaining)?;
             pos += T::smb_byte_size(&val);
             extra = if align > 0 && pos % align != 0 {
@@ -127,17 +181,23 @@ impl<T: SMBFromBytes> SMBVecFromBytesLen for Vec<T> {
         let mut pos = 0;
         let mut extra = 0;
         while pos < len {
-            remaining = &remaining[extra..];
+            remaining = remaining.get(extra..)
+                .ok_or(SMBError::payload_too_small(extra, remaining.len()))?;
             let (_, val) = T::smb_from_bytes(remaining)?;
             let size = T::smb_byte_size(&val);
-            pos += size; 
+            // An element that takes up no bytes would never move pos towards len
+            if size == 0 {
+                return Err(SMBError::parse_error("Zero sized element in length-bounded vector"));
+            }
+            pos += size;
             extra = if align > 0 && pos % align != 0 {
                 align - (pos % align)
             } else {
                 0
             };
             msg_vec.push(val);
-            remaining = &input[pos..];
+            remaining = input.get(pos..)
+                .ok_or(SMBError::payload_too_small(pos, input.len()))?;
             pos += extra;
         }
         Ok((remaining, msg_vec))
@@ -175,7 +235,7 @@ macro_rules! impl_parse_fixed_slice {
         }
         let res = <[u8; $size]>::try_from(&$input[0..$size])
             .map_err(|_e| SMBError::parse_error("Invalid byte slice"))?;
-        Ok((&$input[$size..], res))
+        Ok::<_, SMBError>((&$input[$size..], res))
     }}
 }
 
@@ -221,7 +281,7 @@ macro_rules! impl_smb_byte_size_unsigned_type {(
     $(
         impl SMBByteSize for $t {
             fn smb_byte_size(&self) -> usize {
-                std::mem::size_of_val(self)
+                core::mem::size_of_val(self)
             }
         }
     )*
@@ -233,7 +293,7 @@ macro_rules! impl_smb_from_bytes_unsigned_type {(
     $(
         impl SMBFromBytes for $t {
             fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> {
-                const T_SIZE: usize = std::mem::size_of::<$t>();
+                const T_SIZE: usize = core::mem::size_of::<$t>();
                 let value = impl_parse_fixed_slice!(T_SIZE, input)?;
                 Ok((value.0, <$t>::from_le_bytes(value.1)))
             }
@@ -284,4 +344,47 @@ impl_smb_from_bytes_unsigned_type! {
 
 impl_smb_to_bytes_unsigned_type! {
     u8 u16 u32 u64 u128
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::error::SMBError;
+    use crate::{SMBByteSize, SMBFromBytes, SMBToBytes, SMBVecByteSize, SMBVecFromBytesCnt, SMBVecFromBytesLen};
+
+    #[test]
+    fn unit_is_zero_sized() {
+        let input = [1u8, 2];
+        assert_eq!(<()>::smb_from_bytes(&input).unwrap(), (&input[..], ()));
+        assert!(().smb_to_bytes().is_empty());
+        assert_eq!(().smb_byte_size(), 0);
+    }
+
+    #[test]
+    fn sizes_are_computed_from_code_units() {
+        assert_eq!(String::from("share").smb_byte_size_vec(2, 0), 10);
+        assert_eq!(String::from("caf\u{e9}").smb_byte_size_vec(2, 0), 8);
+        assert_eq!(String::from("\u{1F4C1}").smb_byte_size_vec(2, 0), 4);
+        assert_eq!(String::from("caf\u{e9}").smb_byte_size_vec(1, 0), 5);
+        assert_eq!(vec![1u32, 2, 3].smb_byte_size_vec(0, 0), 12);
+        assert_eq!(vec![1u16, 2, 3].smb_byte_size_vec(8, 0), 18);
+        assert_eq!(vec![1u16, 2, 3].smb_byte_size_vec(8, 4), 22);
+    }
+
+    #[test]
+    fn truncated_vectors_are_errors() {
+        let input = [1u8, 0, 0, 0, 2, 0];
+        assert!(<Vec<u32>>::smb_from_bytes_vec_cnt(&input, 8, 2).is_err());
+        assert!(<Vec<u32>>::smb_from_bytes_vec_len(&input, 8, 12).is_err());
+        assert!(<Vec<u16>>::smb_from_bytes_vec_len(&input, 0, 12).is_err());
+        assert_eq!(<Vec<u16>>::smb_from_bytes_vec_cnt(&input, 2, 3).unwrap().1, vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn exact_parse_rejects_trailing_bytes() {
+        let input = [1u8, 0, 0, 0, 0xFF];
+        assert_eq!(u32::smb_from_bytes_exact(&input[..4]).unwrap(), 1);
+        assert!(matches!(u32::smb_from_bytes_exact(&input), Err(SMBError::ParseError(_))));
+        assert_eq!(u32::smb_from_bytes(&input).unwrap(), (&input[4..], 1));
+        assert!(u32::smb_from_bytes_exact(&input[..3]).is_err());
+    }
+}
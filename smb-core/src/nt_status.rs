@@ -1,3 +1,6 @@
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
@@ -8,14 +11,36 @@ use crate::error::SMBError;
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, TryFromPrimitive, Copy)]
 pub enum NTStatus {
     StatusSuccess = 0x0,
+    Pending = 0x00000103,
+    BufferOverflow = 0x80000005,
+    NoMoreFiles = 0x80000006,
+    StoppedOnSymlink = 0x8000002D,
     MoreProcessingRequired = 0xC0000016,
     SecIContinueNeeded = 0x00090312,
+    NotImplemented = 0xC0000002,
+    InvalidInfoClass = 0xC0000003,
+    InfoLengthMismatch = 0xC0000004,
     InvalidParameter = 0xC000000D,
+    NoSuchFile = 0xC000000F,
+    EndOfFile = 0xC0000011,
     AccessDenied = 0xC0000022,
+    BufferTooSmall = 0xC0000023,
+    ObjectNameInvalid = 0xC0000033,
+    ObjectNameNotFound = 0xC0000034,
+    ObjectNameCollision = 0xC0000035,
+    ObjectPathNotFound = 0xC000003A,
+    SharingViolation = 0xC0000043,
     LogonFailure = 0xC000006D,
+    MediaWriteProtected = 0xC00000A2,
+    BadImpersonationLevel = 0xC00000A5,
+    FileIsADirectory = 0xC00000BA,
     NotSupported = 0xC00000BB,
     BadNetworkName = 0xC00000CC,
     RequestNotAccepted = 0xC00000D0,
+    InvalidOplockProtocol = 0xC00000E3,
+    DirectoryNotEmpty = 0xC0000101,
+    NotADirectory = 0xC0000103,
+    FileClosed = 0xC0000128,
     UserSessionDeleted = 0xC0000203,
     NetworkSessionExpired = 0xC000035C,
     FileNotAvailable = 0xC0000467,
@@ -24,7 +49,7 @@ pub enum NTStatus {
 
 impl SMBByteSize for NTStatus {
     fn smb_byte_size(&self) -> usize {
-        std::mem::size_of_val(&(*self as u32))
+        core::mem::size_of_val(&(*self as u32))
     }
 }
 
@@ -33,7 +58,7 @@ impl SMBFromBytes for NTStatus {
         u32::smb_from_bytes(input)
             .map(|(remaining, underlying)| {
                 let res = Self::try_from_primitive(underlying)
-                    .map_err(SMBError::parse_error)?;
+                    .map_err(|e| SMBError::parse_error(e.to_string()))?;
                 Ok((remaining, res))
             })?
     }
@@ -58,25 +58,24 @@ impl DirectInner {
     }
 
     fn smb_to_bytes<T: Spanned>(&self, name: &str, spanned: &T, name_val: Option<TokenStream>) -> TokenStream {
-        let start = self.start;
+        let value = self.smb_to_bytes_value(name, spanned, name_val);
+        let patch = self.smb_patch(name, spanned);
+        quote! {
+            #value
+            #patch
+        }
+    }
+
+    // Settles the field's value without writing it. An offset with no value given is the current position,
+    // which a min_val can push out, so the data it points at moves with it
+    fn smb_to_bytes_value<T: Spanned>(&self, name: &str, spanned: &T, name_val: Option<TokenStream>) -> TokenStream {
         let subtract = self.subtract;
         let name = format_ident!("{}", name);
-        let ty = &self.get_type(spanned);
-        let name_start = format_ident!("{}_start", name);
-        let name_len = format_ident!("{}_len", name);
         let name_add = format_ident!("{}_add", name);
-        let name_bytes = format_ident!("{}_bytes", name);
         let min_val = self.min_val;
-        let end = if self.num_type == "direct" {
-            quote! {0}
-        } else {
-            quote! { ::smb_core::SMBByteSize::smb_byte_size(&(0 as #ty)) }
-        };
 
         let new_current_pos = if name_val.is_some() {
-            quote! {
-                current_pos = current_pos;
-            }
+            quote! {}
         } else {
             quote! {
                 current_pos = (#name - #name_add);
@@ -88,14 +87,26 @@ impl DirectInner {
         });
 
         quote_spanned! {spanned.span()=>
-            let #name_start = #start;
             let #name_add = #subtract;
-            let #name_len = #end;
             let #name = ::std::cmp::max(#name_val, #min_val);
-            let #name_bytes = ::smb_core::SMBToBytes::smb_to_bytes(&(#name as #ty));
-            item[#name_start..(#name_start + #name_len)].copy_from_slice(&#name_bytes);
             #new_current_pos
-            current_pos = ::std::cmp::max(current_pos, #name_start + #name_len);
+        }
+    }
+
+    // Writes a settled value into the field's slot in the fixed part of the struct
+    fn smb_patch<T: Spanned>(&self, name: &str, spanned: &T) -> TokenStream {
+        if self.num_type == "direct" {
+            return quote! {};
+        }
+        let start = self.start;
+        let name = format_ident!("{}", name);
+        let ty = &self.get_type(spanned);
+        let name_bytes = format_ident!("{}_bytes", name);
+
+        quote_spanned! {spanned.span()=>
+            let #name_bytes = ::smb_core::SMBToBytes::smb_to_bytes(&(#name as #ty));
+            ::smb_core::smb_write_at(&mut item, #start, &#name_bytes);
+            current_pos = ::std::cmp::max(current_pos, #start + #name_bytes.len());
         }
     }
 }
@@ -169,15 +180,50 @@ impl AttributeInfo {
     }
 
     pub(crate) fn smb_to_bytes<T: Spanned>(&self, spanned: &T, name: &str, name_val: Option<TokenStream>) -> TokenStream {
+        match self {
+            Self::Inner(inner) => inner.smb_to_bytes(name, spanned, name_val),
+            _ => self.smb_to_bytes_value(spanned, name, name_val),
+        }
+    }
+
+    // The two halves of smb_to_bytes, so an offset or length can be settled before the data it describes is
+    // laid out and written into its slot after
+    pub(crate) fn smb_to_bytes_value<T: Spanned>(&self, spanned: &T, name: &str, name_val: Option<TokenStream>) -> TokenStream {
         let name_ident = format_ident!("{}", name);
         match self {
             Self::CurrentPos => quote! { let #name_ident = current_pos; },
             Self::Fixed(start) => quote! { let #name_ident = #start; },
-            Self::Inner(inner) => inner.smb_to_bytes(name, spanned, name_val),
+            Self::Inner(inner) => inner.smb_to_bytes_value(name, spanned, name_val),
             Self::NullTerminated(_) => quote! {},
         }
     }
 
+    pub(crate) fn smb_patch<T: Spanned>(&self, spanned: &T, name: &str) -> TokenStream {
+        match self {
+            Self::Inner(inner) => inner.smb_patch(name, spanned),
+            _ => quote! {},
+        }
+    }
+
+    // Moves current_pos past a back-referenced field so the data it describes can't be laid out on top of it
+    pub(crate) fn reserve_field<T: Spanned>(&self, spanned: &T) -> TokenStream {
+        match self {
+            Self::Inner(inner) => {
+                let start = inner.start;
+                let ty = inner.get_type(spanned);
+                quote! { current_pos = ::std::cmp::max(current_pos, #start + ::std::mem::size_of::<#ty>()); }
+            },
+            _ => quote! {},
+        }
+    }
+
+    pub(crate) fn reserve_offset<T: Spanned>(&self, spanned: &T) -> TokenStream {
+        match self {
+            Self::Fixed(start) => quote! { current_pos = ::std::cmp::max(current_pos, #start); },
+            _ => self.reserve_field(spanned),
+        }
+    }
+
     pub(crate) fn get_pos(&self) -> usize {
         match self {
             Self::CurrentPos | Self::NullTerminated(_) => 0,
@@ -237,7 +283,7 @@ impl Direct {
             #start
             let size = ::smb_core::SMBByteSize::smb_byte_size(#token);
             let bytes = ::smb_core::SMBToBytes::smb_to_bytes(#token);
-            item[(item_start as usize)..(item_start as usize + size)].copy_from_slice(&bytes);
+            ::smb_core::smb_write_at(&mut item, item_start as usize, &bytes);
             current_pos = item_start as usize + size;
         }
     }
@@ -272,21 +318,40 @@ impl Buffer {
         }
     }
 
+    // The offset and length slots, claimed before any buffer is laid out so none lands on top of them
+    pub(crate) fn reserve_fields<T: Spanned>(&self, spanned: &T) -> TokenStream {
+        let reserve_offset = self.offset.reserve_field(spanned);
+        let reserve_length = self.length.reserve_field(spanned);
+        quote! {
+            #reserve_offset
+            #reserve_length
+        }
+    }
+
     pub(crate) fn smb_to_bytes<T: Spanned>(&self, spanned: &T, token: &TokenStream) -> TokenStream {
-        let offset_info = self.offset.smb_to_bytes(spanned, "offset", None);
-        let length_info = self.length.smb_to_bytes(spanned, "length", Some(quote! {
+        let reserve_offset = self.offset.reserve_offset(spanned);
+        let reserve_length = self.length.reserve_field(spanned);
+        let offset_value = self.offset.smb_to_bytes_value(spanned, "offset", None);
+        let length_value = self.length.smb_to_bytes_value(spanned, "length", Some(quote! {
             bytes.len()
         }));
+        let offset_patch = self.offset.smb_patch(spanned, "offset");
+        let length_patch = self.length.smb_patch(spanned, "length");
 
         quote_spanned! {spanned.span()=>
             let bytes = #token;
 
-            #offset_info
-            #length_info
+            #reserve_offset
+            #reserve_length
+
+            #offset_value
+            ::smb_core::smb_write_at(&mut item, current_pos, &bytes);
+            current_pos += bytes.len();
 
-            let length = bytes.len();
-            item[current_pos..(current_pos + length)].copy_from_slice(&bytes);
-            current_pos += length;
+            // The data is down, so the fields describing it can be filled in
+            #length_value
+            #offset_patch
+            #length_patch
         }
     }
 
@@ -327,25 +392,26 @@ impl Vector {
         // println!("Count: {}", vec_count_or_len);
         let align = self.align;
         let offset = self.offset.smb_from_bytes(spanned, "item_offset");
-        let parser = if self.count == AttributeInfo::default() {
-            quote! {
-                let (remaining, #name): (&[u8], #ty) = ::smb_core::SMBVecFromBytesLen::smb_from_bytes_vec_len(&input[item_offset..], #align as usize, item_length as usize)?;
-            }
+        let (parser, is_empty) = if self.count == AttributeInfo::default() {
+            (quote! {
+                let (remaining, #name): (&[u8], #ty) = ::smb_core::SMBVecFromBytesLen::smb_from_bytes_vec_len(input.get(item_offset..).unwrap_or_default(), #align as usize, item_length as usize)?;
+            }, quote! { item_length == 0 })
         } else {
-            quote! {
-                let (remaining, #name): (&[u8], #ty) = ::smb_core::SMBVecFromBytesCnt::smb_from_bytes_vec_cnt(&input[item_offset..], #align as usize, item_count as usize)?;
-            }
+            (quote! {
+                let (remaining, #name): (&[u8], #ty) = ::smb_core::SMBVecFromBytesCnt::smb_from_bytes_vec_cnt(input.get(item_offset..).unwrap_or_default(), #align as usize, item_count as usize)?;
+            }, quote! { item_count == 0 })
         };
         let name_str = name.to_string();
         quote_spanned! { spanned.span() =>
             // println!("cnt/len parse for {:?}", #name_str);
             #vec_count_or_len
-            if #align > 0 && current_pos % #align != 0 {
+            if #align > 0 && !current_pos.is_multiple_of(#align) {
                 current_pos += #align - (current_pos % #align);
             }
             #offset
             let item_offset = item_offset as usize;
-            if item_offset >= input.len() {
+            // An empty vector may point just past the body, so only a non-empty one has to start inside it
+            if item_offset >= input.len() && !(#is_empty) {
                 return Err(::smb_core::error::SMBError::payload_too_small(item_offset as usize, input.len()));
             }
             #parser
@@ -354,50 +420,70 @@ impl Vector {
         }
     }
 
+    pub(crate) fn reserve_fields<T: Spanned>(&self, spanned: &T) -> TokenStream {
+        let reserve_fields = [
+            self.offset.reserve_field(spanned),
+            self.count.reserve_field(spanned),
+            self.length.reserve_field(spanned),
+        ];
+        quote! {
+            #(#reserve_fields)*
+        }
+    }
+
     pub(crate) fn smb_to_bytes<T: Spanned>(&self, spanned: &T, raw_token: &TokenStream) -> TokenStream {
-        let count_info = if self.count == AttributeInfo::default() {
+        let count_value = if self.count == AttributeInfo::default() {
             quote! {}
         } else {
-            self.count.smb_to_bytes(spanned, "item_count", Some(quote! {
+            self.count.smb_to_bytes_value(spanned, "item_count", Some(quote! {
               #raw_token.len()
             }))
         };
-        let len_info = if self.length == AttributeInfo::default() {
+        let len_value = if self.length == AttributeInfo::default() {
             quote! {}
         } else {
-            self.length.smb_to_bytes(spanned, "item_length", Some(quote! {
+            self.length.smb_to_bytes_value(spanned, "item_length", Some(quote! {
                 byte_size
             }))
         };
-        let offset_info = self.offset.smb_to_bytes(spanned, "item_offset", None);
+        let offset_value = self.offset.smb_to_bytes_value(spanned, "item_offset", None);
+        let reserve_fields = [
+            self.offset.reserve_offset(spanned),
+            self.count.reserve_field(spanned),
+            self.length.reserve_field(spanned),
+        ];
+        let patches = [
+            self.offset.smb_patch(spanned, "item_offset"),
+            self.count.smb_patch(spanned, "item_count"),
+            self.length.smb_patch(spanned, "item_length"),
+        ];
         let align = self.align;
 
         quote_spanned! { spanned.span()=>
-            #count_info
+            #(#reserve_fields)*
             let get_aligned_pos = |align: usize, current_pos: usize| {
-                if align > 0 && current_pos % align != 0 {
-                    current_pos + (8 - current_pos % align)
+                if align > 0 && !current_pos.is_multiple_of(align) {
+                    current_pos + (align - current_pos % align)
                 } else {
                     current_pos
                 }
             };
             current_pos = get_aligned_pos(#align, current_pos);
+            #offset_value
+            // A min_val on the offset can push the start out, so realign before counting the length
+            current_pos = get_aligned_pos(#align, current_pos);
             let start_pos = current_pos;
-            #offset_info
             for entry in #raw_token.iter() {
                 let item_bytes = ::smb_core::SMBToBytes::smb_to_bytes(entry);
-                // if (#align > 0) {
-                //     println!("item with align {} initial starting pos {}, item bytes: {:?}", #align, current_pos, item_bytes);
-                // }
                 current_pos = get_aligned_pos(#align, current_pos);
-                item[current_pos..(current_pos + item_bytes.len())].copy_from_slice(&item_bytes);
-                // if (#align > 0) {
-                //     println!("adding item with align {} at starting pos {}, item bytes: {:?}", #align, current_pos, item_bytes);
-                // }
+                ::smb_core::smb_write_at(&mut item, current_pos, &item_bytes);
                 current_pos += item_bytes.len();
             }
             let byte_size = current_pos - start_pos;
-            #len_info
+            // The items are down, so the fields describing them can be filled in
+            #count_value
+            #len_value
+            #(#patches)*
         }
     }
 
@@ -448,30 +534,50 @@ impl SMBString {
         };
 
         let num_type = get_type(&self.underlying, spanned);
+        // Lengths on the wire are in bytes, so a UTF-16 string holds half as many code units
+        let unit_count = match self.underlying.as_str() {
+            "u16" => quote! { item_count / 2 },
+            _ => quote! { item_count },
+        };
 
         quote_spanned! { spanned.span() =>
             #start
             let item_offset = item_offset as usize;
             #length
-            if item_offset >= input.len() {
+            // Like an empty vector, an empty string may point just past the body
+            if item_offset >= input.len() && item_count != 0 {
                 return Err(::smb_core::error::SMBError::payload_too_small(item_offset as usize, input.len()));
             }
-            let (remaining, #vec_name): (&[u8], Vec<#num_type>) = ::smb_core::SMBVecFromBytesCnt::smb_from_bytes_vec_cnt(&input[item_offset..], 0, (item_count/2) as usize)?;
+            let (remaining, #vec_name): (&[u8], Vec<#num_type>) = ::smb_core::SMBVecFromBytesCnt::smb_from_bytes_vec_cnt(input.get(item_offset..).unwrap_or_default(), 0, (#unit_count) as usize)?;
             #string_parser
             current_pos = item_offset + ::smb_core::SMBVecByteSize::smb_byte_size_vec(&#name, 0, item_offset);
         }
     }
 
+    pub(crate) fn reserve_fields<T: Spanned>(&self, spanned: &T) -> TokenStream {
+        let reserve_start = self.start.reserve_field(spanned);
+        let reserve_length = self.length.reserve_field(spanned);
+        quote! {
+            #reserve_start
+            #reserve_length
+        }
+    }
+
     pub(crate) fn smb_to_bytes<T: Spanned>(&self, spanned: &T, raw_token: &TokenStream) -> TokenStream {
-        let count_info = self.length.smb_to_bytes(spanned, "item_count", Some(quote! {
-          #raw_token.len()
-        }));
-        let offset_info = self.start.smb_to_bytes(spanned, "item_offset", None);
+        let reserve_fields = self.reserve_fields(spanned);
+        let byte_len = match self.underlying.as_str() {
+            "u16" => quote! { #raw_token.encode_utf16().count() * 2 },
+            _ => quote! { #raw_token.len() },
+        };
+        let count_value = self.length.smb_to_bytes_value(spanned, "item_count", Some(byte_len));
+        let offset_value = self.start.smb_to_bytes_value(spanned, "item_offset", None);
+        let offset_patch = self.start.smb_patch(spanned, "item_offset");
+        let count_patch = self.length.smb_patch(spanned, "item_count");
 
         // TODO make this work to convert back to u8 & u16 vecs
         let string_to_bytes = match self.underlying.as_str() {
             "u8" => quote! {
-                let token_vec = #raw_token.as_bytes().to_vec();
+                let token_vec = #raw_token.as_bytes().iter().copied();
             },
             "u16" => quote! {
                 let token_vec = #raw_token.encode_utf16();
@@ -479,21 +585,18 @@ impl SMBString {
             _ => quote! {}
         };
         quote_spanned! { spanned.span()=>
-            #count_info
-            #offset_info
+            #reserve_fields
+            #offset_value
             #string_to_bytes
             for entry in token_vec {
                 let item_bytes = ::smb_core::SMBToBytes::smb_to_bytes(&entry);
-                // if (#align > 0) {
-                //     println!("item with align {} initial starting pos {}, item bytes: {:?}", #align, current_pos, item_bytes);
-                // }
-                // current_pos = get_aligned_pos(#align, current_pos);
-                item[current_pos..(current_pos + item_bytes.len())].copy_from_slice(&item_bytes);
-                // if (#align > 0) {
-                //     println!("adding item with align {} at starting pos {}, item bytes: {:?}", #align, current_pos, item_bytes);
-                // }
+                ::smb_core::smb_write_at(&mut item, current_pos, &item_bytes);
                 current_pos += item_bytes.len();
             }
+            // The string is down, so the fields describing it can be filled in
+            #count_value
+            #offset_patch
+            #count_patch
         }
     }
 
@@ -593,7 +696,7 @@ impl SMBEnum {
             #start_info
             let size = ::smb_core::SMBByteSize::smb_byte_size(#token);
             let bytes = ::smb_core::SMBToBytes::smb_to_bytes(#token);
-            item[(item_start as usize)..(item_start as usize + size)].copy_from_slice(&bytes);
+            ::smb_core::smb_write_at(&mut item, item_start as usize, &bytes);
             current_pos = item_start as usize + size;
         }
     }
@@ -613,16 +716,19 @@ impl ByteTag {
     pub(crate) fn smb_from_bytes<T: Spanned>(&self, spanned: &T) -> TokenStream {
         let start_byte = self.value;
         quote_spanned! {spanned.span()=>
-            while input[current_pos] != #start_byte {
+            while current_pos < input.len() && input[current_pos] != #start_byte {
                 current_pos += 1;
             }
+            if current_pos >= input.len() {
+                return Err(::smb_core::error::SMBError::parse_error("struct did not have the valid starting tag"));
+            }
             let remaining = &input[current_pos..];
         }
     }
     pub(crate) fn smb_to_bytes<T: Spanned>(&self, spanned: &T) -> TokenStream {
         let start_byte = self.value;
         quote_spanned! {spanned.span()=>
-            item[current_pos] = #start_byte;
+            ::smb_core::smb_write_at(&mut item, current_pos, &[#start_byte]);
             current_pos += 1;
         }
     }
@@ -644,14 +750,15 @@ impl StringTag {
         quote_spanned! {spanned.span()=>
              let mut tagged = false;
              let mut next_pos = current_pos;
-             while let Some(pos) = input[current_pos..].iter().position(|x| *x == #start_val.as_bytes()[0]) {
-                if input[(pos)..].starts_with(#start_val.as_bytes()) {
+             while let Some(pos) = input.get(current_pos..).and_then(|x| x.iter().position(|x| *x == #start_val.as_bytes()[0])) {
+                let pos = current_pos + pos;
+                if input[pos..].starts_with(#start_val.as_bytes()) {
                     current_pos = pos;
                     tagged = true;
                     next_pos = pos;
                     break;
                 }
-                current_pos += 1;
+                current_pos = pos + 1;
             }
             if (!tagged) {
                 return Err(::smb_core::error::SMBError::parse_error("struct did not have the valid starting tag"));
@@ -663,7 +770,7 @@ impl StringTag {
         let start_val = &self.value;
         quote_spanned! {spanned.span()=>
             let bytes = #start_val.as_bytes();
-            item[current_pos..(current_pos + bytes.len())].copy_from_slice(&bytes);
+            ::smb_core::smb_write_at(&mut item, current_pos, &bytes);
             current_pos += bytes.len();
         }
     }
@@ -695,6 +802,9 @@ impl Skip {
 
         quote_spanned! {spanned.span() =>
             current_pos = #start + #length;
+            if current_pos > input.len() {
+                return Err(::smb_core::error::SMBError::payload_too_small(current_pos, input.len()));
+            }
             let remaining = &input[current_pos..];
             let #name: #ty = ::std::marker::PhantomData;
         }
@@ -706,7 +816,7 @@ impl Skip {
             let value = self.value.clone();
             quote_spanned! {spanned.span()=>
                 let value = [#(#value,)*];
-                item[#start..(#start + #length)].copy_from_slice(&value);
+                ::smb_core::smb_write_at(&mut item, #start, &value);
                 current_pos = #start + #length;
             }
         } else {
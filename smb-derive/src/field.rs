@@ -91,6 +91,19 @@ impl<'a, T: Spanned> SMBField<'a, T> {
         }
     }
 
+    // Buffers, vectors and strings, laid out after the fixed part of the struct
+    pub(crate) fn is_variable(&self) -> bool {
+        self.val_type.iter().any(|field_ty| field_ty.weight_of_enum() == 2)
+    }
+
+    pub(crate) fn smb_reserve(&self) -> TokenStream {
+        let field = self.spanned;
+        let reserves = self.val_type.iter().map(|field_ty| field_ty.smb_reserve(field));
+        quote! {
+            #(#reserves)*
+        }
+    }
+
     pub(crate) fn attr_byte_size(&self) -> usize {
         let mut current_ptr = 0;
         let mut skip_ptr = 0;
@@ -204,7 +217,9 @@ impl<'a, T: Spanned + Debug> SMBField<'a, T> {
 
         if ty.weight_of_enum() == 2 {
             quote_spanned! {self.spanned.span()=>
-                let size = ::std::cmp::max(size, #attr_start_ty) + ::smb_core::SMBVecByteSize::smb_byte_size_vec(#size_tokens, #align, size);
+                // Alignment is measured from where the serializer actually starts writing the items
+                let vec_start = ::std::cmp::max(size, #attr_start_ty);
+                let size = vec_start + ::smb_core::SMBVecByteSize::smb_byte_size_vec(#size_tokens, #align, vec_start);
             }
         } else {
             quote_spanned! {self.spanned.span()=>
@@ -260,6 +275,14 @@ impl SMBFieldType {
             SMBFieldType::StringTag(string_tag) => string_tag.smb_to_bytes(field),
         }
     }
+    fn smb_reserve<T: Spanned>(&self, field: &T) -> TokenStream {
+        match self {
+            SMBFieldType::Buffer(buffer) => buffer.reserve_fields(field),
+            SMBFieldType::Vector(vector) => vector.reserve_fields(field),
+            SMBFieldType::String(string) => string.reserve_fields(field),
+            _ => quote! {},
+        }
+    }
     fn attr_size(&self) -> usize {
         match self {
             SMBFieldType::Direct(direct) => direct.attr_byte_size(),
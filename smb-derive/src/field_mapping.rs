@@ -72,11 +72,26 @@ impl<T: Spanned + PartialEq + Eq, U: Spanned + PartialEq + Eq + Debug> SMBFieldM
         let key = self.variant_ident.clone().map(|variant| quote! {
             Self::#variant(#(#names,)*)
         }).unwrap_or(quote! {_});
+        let (fixed, variable) = size.split_at(first_variable(&self.fields, size.len()));
+        // Sized the way the serializer lays it out, with every slot claimed before the first variable field
+        let reserve = match variable.is_empty() {
+            true => quote! {},
+            false => {
+                let reserves = reserve_variable_fields(&self.fields);
+                quote! {
+                    let mut current_pos = size;
+                    #reserves
+                    let size = current_pos;
+                }
+            },
+        };
 
         quote! {
             #key => {
                 let size = #parent_size;
-                #(#size)*
+                #(#fixed)*
+                #reserve
+                #(#variable)*
                 size
             },
         }
@@ -344,6 +359,8 @@ pub(crate) fn smb_to_bytes<T: Spanned + PartialEq + Eq, U: Spanned + PartialEq +
     };
 
     let names = mapping.fields.iter().map(|field| field.get_name());
+    let (fixed, variable) = recurse.split_at(first_variable(vector, recurse.len()));
+    let reserve = reserve_variable_fields(vector);
 
     let key = mapping.variant_ident.clone().map(|variant| quote! {
         Self::#variant(#(#names,)*)
@@ -353,8 +370,27 @@ pub(crate) fn smb_to_bytes<T: Spanned + PartialEq + Eq, U: Spanned + PartialEq +
             let mut current_pos = 0;
             let mut item = vec![0; ::smb_core::SMBByteSize::smb_byte_size(self)];
             #parent
-            #(#recurse)*
+            #(#fixed)*
+            #reserve
+            #(#variable)*
             item
         },
     }
+}
+
+// Fields are sorted with the variable ones last, so this is where the fixed part of the struct ends
+fn first_variable<U: Spanned + PartialEq + Eq>(fields: &[SMBField<U>], default: usize) -> usize {
+    fields.iter().position(SMBField::is_variable).unwrap_or(default)
+}
+
+// Serializing takes two passes over the variable fields. Every offset, length and count slot they use is
+// claimed up front, then each one's data is laid out and its slots patched with where it ended up. Claiming
+// them one field at a time would let an earlier buffer land on a later one's slots
+fn reserve_variable_fields<U: Spanned + PartialEq + Eq>(fields: &[SMBField<U>]) -> proc_macro2::TokenStream {
+    let reserves = fields.iter()
+        .filter(|field| field.is_variable())
+        .map(SMBField::smb_reserve);
+    quote! {
+        #(#reserves)*
+    }
 }
\ No newline at end of file
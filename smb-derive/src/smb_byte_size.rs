@@ -20,7 +20,7 @@ fn create_byte_size_impl<T: Spanned + PartialEq + Eq, U: Spanned + PartialEq + E
     let size = mappings.iter().map(|mapping| smb_byte_size_impl(mapping));
     Ok(quote! {
         impl ::smb_core::SMBByteSize for #name {
-            #[allow(unused_variables, unused_assignments, modulo_one)]
+            #[allow(unused_variables, unused_assignments, unused_mut, modulo_one)]
             fn smb_byte_size(&self) -> usize {
                 match self {
                     #(#size)*
@@ -0,0 +1,25 @@
+use std::marker::PhantomData;
+
+use smb_core::error::SMBError;
+use smb_core::SMBFromBytes;
+use smb_derive::{SMBByteSize, SMBFromBytes};
+
+#[derive(SMBFromBytes, SMBByteSize, Debug, PartialEq)]
+struct Named {
+    #[smb_skip(start = 0, length = 4)]
+    header: PhantomData<Vec<u8>>,
+    #[smb_string(order = 0, start(inner(start = 0, num_type = "u16")), length(inner(start = 2, num_type = "u16")), underlying = "u16")]
+    name: String,
+}
+
+#[test]
+fn empty_string_may_point_just_past_the_body() {
+    let (_, named) = Named::smb_from_bytes(&[4, 0, 0, 0]).unwrap();
+    assert_eq!(named.name, "");
+}
+
+#[test]
+fn non_empty_string_past_the_body_is_too_small() {
+    let result = Named::smb_from_bytes(&[4, 0, 2, 0]);
+    assert!(matches!(result, Err(SMBError::PayloadTooSmall(_))));
+}
@@ -0,0 +1,90 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use smb_core::cached_size::CachedSize;
+use smb_core::SMBByteSize;
+use smb_derive::SMBByteSize;
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// Mirrors FILE_ID_BOTH_DIR_INFORMATION with the name kept as a String so sizing has to account for UTF-16
+#[derive(SMBByteSize)]
+struct DirectoryEntry {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 96))]
+    file_id: u64,
+    #[smb_string(order = 0, start(fixed = 104), length(inner(start = 60, num_type = "u32")), underlying = "u16")]
+    file_name: String,
+}
+
+#[derive(SMBByteSize)]
+struct DirectoryResponse {
+    #[smb_direct(start(fixed = 0))]
+    structure_size: u16,
+    #[smb_vector(order = 0, align = 8, count(inner(start = 4, num_type = "u32")), offset(fixed = 8))]
+    entries: Vec<DirectoryEntry>,
+}
+
+const ENTRY_COUNT: usize = 1000;
+const ITERATIONS: usize = 1000;
+
+fn main() {
+    let response = DirectoryResponse {
+        structure_size: 9,
+        entries: (0..ENTRY_COUNT).map(|idx| DirectoryEntry {
+            next_entry_offset: 0,
+            file_index: idx as u32,
+            end_of_file: idx as u64 * 512,
+            file_id: idx as u64,
+            file_name: format!("r\u{e9}sum\u{e9}-{idx:04}.docx"),
+        }).collect(),
+    };
+
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    let mut size = 0;
+    for _ in 0..ITERATIONS {
+        size = black_box(&response).smb_byte_size();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+
+    println!("{ENTRY_COUNT}-entry response is {size} bytes");
+    println!("{:?} per smb_byte_size call, {allocations} allocations over {ITERATIONS} calls", elapsed / ITERATIONS as u32);
+    assert_eq!(allocations, 0, "sizing a directory response should not allocate");
+
+    // The same listing held as a template only walks its entries the first time
+    let cached = CachedSize::new(response);
+    let start = Instant::now();
+    let mut cached_size = 0;
+    for _ in 0..ITERATIONS {
+        cached_size = black_box(&cached).smb_byte_size();
+    }
+    let cached_elapsed = start.elapsed();
+
+    println!("{:?} per cached smb_byte_size call", cached_elapsed / ITERATIONS as u32);
+    assert_eq!(cached_size, size, "a cached size should match the computed one");
+}
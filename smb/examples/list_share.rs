@@ -0,0 +1,31 @@
+// Prints the names in a share's root directory:
+// cargo run --example list_share --features async -- <host:port> <share> <user> <password> [domain]
+use std::env;
+use std::process;
+
+use smb_core::SMBResult;
+use smb_reader::client::SMBClient;
+use smb_reader::protocol::body::dialect::SMBDialect;
+
+pub async fn list_share_root(addr: &str, share: &str, domain: &str, user: &str, password: &str) -> SMBResult<Vec<String>> {
+    let mut client = SMBClient::connect(addr).await?;
+    client.negotiate(vec![SMBDialect::V2_0_2, SMBDialect::V2_1_0]).await?;
+    client.authenticate(domain, user, password).await?;
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    let tree_id = client.tree_connect(&format!("\\\\{}\\{}", host, share)).await?;
+    client.list_directory(tree_id, "").await
+}
+
+#[tokio::main]
+async fn main() -> SMBResult<()> {
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    let [addr, share, user, password, rest @ ..] = args.as_slice() else {
+        eprintln!("usage: list_share <host:port> <share> <user> <password> [domain]");
+        process::exit(2);
+    };
+    let domain = rest.first().map_or("", String::as_str);
+    for name in list_share_root(addr, share, domain, user, password).await? {
+        println!("{}", name);
+    }
+    Ok(())
+}
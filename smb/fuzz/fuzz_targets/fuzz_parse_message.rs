@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use smb_reader::protocol::body::SMBBody;
+use smb_reader::protocol::header::SMBSyncHeader;
+use smb_reader::protocol::message::{Message, SMBMessage};
+
+// Any input has to come back as Ok or Err, a panic is a bug in the parser
+fuzz_target!(|data: &[u8]| {
+    let _ = SMBMessage::<SMBSyncHeader, SMBBody>::parse(data);
+});
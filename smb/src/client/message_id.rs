@@ -0,0 +1,113 @@
+use std::sync::Mutex;
+
+use tokio::sync::Notify;
+
+#[derive(Debug)]
+struct MessageIdWindow {
+    next_id: u64,
+    credits: u32,
+}
+
+// Hands out MessageIds in order, never more than the server has granted credits for. A request charging
+// several credits takes that many consecutive ids, MS-SMB2 3.2.4.1.3
+#[derive(Debug)]
+pub struct MessageIdAllocator {
+    window: Mutex<MessageIdWindow>,
+    credits_returned: Notify,
+}
+
+impl Default for MessageIdAllocator {
+    // A fresh connection holds the one credit its NEGOTIATE needs
+    fn default() -> Self {
+        Self {
+            window: Mutex::new(MessageIdWindow { next_id: 0, credits: 1 }),
+            credits_returned: Notify::new(),
+        }
+    }
+}
+
+impl MessageIdAllocator {
+    pub fn credits(&self) -> u32 {
+        self.window.lock().unwrap().credits
+    }
+
+    // Waits until the window holds enough credits for the charge, then returns the first id it takes
+    pub async fn allocate(&self, credit_charge: u16) -> u64 {
+        loop {
+            // Registered before checking so a grant landing in between still wakes us
+            let returned = self.credits_returned.notified();
+            if let Some(message_id) = self.try_allocate(credit_charge) {
+                return message_id;
+            }
+            returned.await;
+        }
+    }
+
+    pub fn try_allocate(&self, credit_charge: u16) -> Option<u64> {
+        let charge = credit_charge.max(1) as u32;
+        let mut window = self.window.lock().unwrap();
+        if window.credits < charge {
+            return None;
+        }
+        window.credits -= charge;
+        let message_id = window.next_id;
+        window.next_id += charge as u64;
+        Some(message_id)
+    }
+
+    // Adds the CreditResponse from a server reply back to the window
+    pub fn grant(&self, credits: u16) {
+        if credits == 0 {
+            return;
+        }
+        self.window.lock().unwrap().credits += credits as u32;
+        self.credits_returned.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::client::message_id::MessageIdAllocator;
+
+    #[test]
+    fn ids_increase_by_the_charge() {
+        let allocator = MessageIdAllocator::default();
+        assert_eq!(allocator.try_allocate(0), Some(0));
+        allocator.grant(10);
+        assert_eq!(allocator.try_allocate(1), Some(1));
+        assert_eq!(allocator.try_allocate(4), Some(2));
+        assert_eq!(allocator.try_allocate(1), Some(6));
+        assert_eq!(allocator.credits(), 4);
+        assert_eq!(allocator.try_allocate(5), None);
+        assert_eq!(allocator.try_allocate(4), Some(7));
+    }
+
+    #[tokio::test]
+    async fn exhausted_window_waits_for_credits() {
+        let allocator = Arc::new(MessageIdAllocator::default());
+        assert_eq!(allocator.allocate(1).await, 0);
+        assert_eq!(allocator.credits(), 0);
+
+        let waiting = tokio::spawn({
+            let allocator = allocator.clone();
+            async move { allocator.allocate(2).await }
+        });
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!waiting.is_finished());
+
+        // One credit isn't enough for a charge of two
+        allocator.grant(1);
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!waiting.is_finished());
+
+        allocator.grant(1);
+        assert_eq!(waiting.await.unwrap(), 1);
+        assert_eq!(allocator.credits(), 0);
+    }
+}
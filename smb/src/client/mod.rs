@@ -0,0 +1,321 @@
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use uuid::Uuid;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::client::message_id::MessageIdAllocator;
+use crate::protocol::body::capabilities::Capabilities;
+use crate::protocol::body::close::SMBCloseRequest;
+use crate::protocol::body::create::disposition::SMBCreateDisposition;
+use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::create::options::SMBCreateOptions;
+use crate::protocol::body::create::share_access::SMBShareAccess;
+use crate::protocol::body::create::SMBCreateRequest;
+use crate::protocol::body::dialect::SMBDialect;
+use crate::protocol::body::negotiate::security_mode::NegotiateSecurityMode;
+use crate::protocol::body::negotiate::SMBNegotiateRequest;
+use crate::protocol::body::query_directory::directory_information::SMBFileNamesInformation;
+use crate::protocol::body::query_directory::flags::SMBQueryDirectoryFlags;
+use crate::protocol::body::query_directory::information_class::SMBInformationClass;
+use crate::protocol::body::query_directory::SMBQueryDirectoryRequest;
+use crate::protocol::body::session_setup::security_mode::SessionSetupSecurityMode;
+use crate::protocol::body::session_setup::SMBSessionSetupRequest;
+use crate::protocol::body::SMBBody;
+use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask};
+use crate::protocol::body::tree_connect::{SMBTreeConnectRequest, SMBTreeConnectResponse};
+use crate::protocol::header::command_code::SMBCommandCode;
+use crate::protocol::header::flags::SMBFlags;
+use crate::protocol::header::SMBSyncHeader;
+use crate::protocol::message::{SMBFrame, SMBMessage, SMBSyncMessage};
+use crate::socket::message_stream::{SMBReadStream, SMBSocketConnection, SMBWriteStream};
+use crate::util::auth::AuthMessage;
+use crate::util::auth::ntlm::{NTLMAuthenticateMessageBody, NTLMAuthProvider, NTLMChallengeMessageBody, NTLMMessage, NTLMNegotiateFlags, NTLMNegotiateMessageBody};
+use crate::util::auth::spnego::{SPNEGOToken, SPNEGOTokenInitBody, SPNEGOTokenResponseBody};
+
+pub mod message_id;
+
+// Large enough for a directory listing to come back in one response
+const QUERY_DIRECTORY_OUTPUT_LEN: u32 = 65536;
+// Credits asked for on every request, enough headroom that requests never wait on one another
+const CREDIT_REQUEST: u16 = 32;
+
+// A minimal SMB2 client: negotiates, logs on with NTLMv2 and issues requests one at a time
+#[derive(Debug)]
+pub struct SMBClient<R: SMBReadStream, W: SMBWriteStream> {
+    socket: SMBSocketConnection<R, W>,
+    client_guid: Uuid,
+    dialect: SMBDialect,
+    message_ids: MessageIdAllocator,
+    session_id: u64,
+    session_key: Vec<u8>,
+}
+
+impl SMBClient<OwnedReadHalf, OwnedWriteHalf> {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> SMBResult<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        let peer_addr = stream.peer_addr()?;
+        let (read, write) = stream.into_split();
+        let socket = SMBSocketConnection::new(peer_addr.to_string(), read, write)
+            .with_peer_addr(peer_addr);
+        Ok(Self::new(socket))
+    }
+}
+
+impl<R: SMBReadStream, W: SMBWriteStream> SMBClient<R, W> {
+    pub fn new(socket: SMBSocketConnection<R, W>) -> Self {
+        Self {
+            socket,
+            client_guid: Uuid::new_v4(),
+            dialect: SMBDialect::default(),
+            message_ids: MessageIdAllocator::default(),
+            session_id: 0,
+            session_key: Vec::new(),
+        }
+    }
+
+    pub fn dialect(&self) -> SMBDialect {
+        self.dialect
+    }
+
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub fn session_key(&self) -> &[u8] {
+        &self.session_key
+    }
+
+    pub async fn negotiate(&mut self, dialects: Vec<SMBDialect>) -> SMBResult<SMBDialect> {
+        let request = SMBNegotiateRequest::new(NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED, Capabilities::empty(), self.client_guid, dialects);
+        let response = self.request(SMBCommandCode::Negotiate, 0, SMBBody::NegotiateRequest(request)).await?;
+        let SMBBody::NegotiateResponse(body) = response.body else {
+            return Err(SMBError::parse_error("Expected a negotiate response"));
+        };
+        self.dialect = body.dialect();
+        Ok(self.dialect)
+    }
+
+    pub async fn authenticate(&mut self, domain_name: &str, user_name: &str, password: &str) -> SMBResult<()> {
+        self.logon(0, domain_name, user_name, password).await
+    }
+
+    // Logs on again after a dropped connection, asking the server to tear down the session this client had before
+    pub async fn reconnect(&mut self, previous_session_id: u64, domain_name: &str, user_name: &str, password: &str) -> SMBResult<()> {
+        self.logon(previous_session_id, domain_name, user_name, password).await
+    }
+
+    // Two legs of SPNEGO-wrapped NTLM: NEGOTIATE out and CHALLENGE back, then AUTHENTICATE
+    async fn logon(&mut self, previous_session_id: u64, domain_name: &str, user_name: &str, password: &str) -> SMBResult<()> {
+        let challenge = match self.session_setup(ntlm_negotiate_token(), previous_session_id).await? {
+            (NTStatus::MoreProcessingRequired, Some(NTLMMessage::Challenge(challenge))) => challenge,
+            (status, _) => return Err(SMBError::response_error(status)),
+        };
+
+        let (authenticate, session_key) = ntlm_authenticate_token(&challenge, domain_name, user_name, password)?;
+        match self.session_setup(authenticate, previous_session_id).await? {
+            (NTStatus::StatusSuccess, _) => {
+                self.session_key = session_key;
+                Ok(())
+            },
+            (status, _) => Err(SMBError::response_error(status)),
+        }
+    }
+
+    // Returns the tree id for a UNC path such as \\server\share
+    pub async fn tree_connect(&mut self, path: &str) -> SMBResult<u32> {
+        self.tree_connect_response(path).await
+            .map(|(tree_id, _)| tree_id)
+    }
+
+    // The tree id along with what the server said about the share, its flags and capabilities
+    pub async fn tree_connect_response(&mut self, path: &str) -> SMBResult<(u32, SMBTreeConnectResponse)> {
+        let request = SMBTreeConnectRequest::new(path);
+        let response = self.request(SMBCommandCode::TreeConnect, 0, SMBBody::TreeConnectRequest(request)).await?;
+        Self::check_status(&response.header)?;
+        let SMBBody::TreeConnectResponse(body) = response.body else {
+            return Err(SMBError::parse_error("Expected a tree connect response"));
+        };
+        Ok((response.header.tree_id, body))
+    }
+
+    pub async fn open_directory(&mut self, tree_id: u32, path: &str) -> SMBResult<SMBFileId> {
+        let access = SMBAccessMask::Directory(SMBDirectoryAccessMask::FILE_LIST_DIRECTORY
+            | SMBDirectoryAccessMask::FILE_READ_ATTRIBUTES | SMBDirectoryAccessMask::SYNCHRONIZE);
+        let request = SMBCreateRequest::new(path, access, SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::DIRECTORY_FILE);
+        let response = self.request(SMBCommandCode::Create, tree_id, SMBBody::CreateRequest(request)).await?;
+        Self::check_status(&response.header)?;
+        let SMBBody::CreateResponse(body) = response.body else {
+            return Err(SMBError::parse_error("Expected a create response"));
+        };
+        Ok(body.file_id().clone())
+    }
+
+    // A single restarted scan, so every entry has to fit in one output buffer
+    pub async fn query_directory(&mut self, tree_id: u32, file_id: &SMBFileId, pattern: &str) -> SMBResult<Vec<String>> {
+        let request = SMBQueryDirectoryRequest::new(SMBInformationClass::FileNamesInformation, SMBQueryDirectoryFlags::RESTART_SCANS, file_id.clone(), QUERY_DIRECTORY_OUTPUT_LEN, pattern);
+        let response = self.request(SMBCommandCode::QueryDirectory, tree_id, SMBBody::QueryDirectoryRequest(request)).await?;
+        Self::check_status(&response.header)?;
+        let SMBBody::QueryDirectoryResponse(body) = response.body else {
+            return Err(SMBError::parse_error("Expected a query directory response"));
+        };
+        let names = SMBFileNamesInformation::parse_list(body.buffer())?
+            .iter()
+            .map(SMBFileNamesInformation::file_name)
+            .collect();
+        Ok(names)
+    }
+
+    pub async fn close(&mut self, tree_id: u32, file_id: &SMBFileId) -> SMBResult<()> {
+        let request = SMBCloseRequest::new(file_id.clone());
+        let response = self.request(SMBCommandCode::Close, tree_id, SMBBody::CloseRequest(request)).await?;
+        Self::check_status(&response.header)
+    }
+
+    pub async fn list_directory(&mut self, tree_id: u32, path: &str) -> SMBResult<Vec<String>> {
+        let file_id = self.open_directory(tree_id, path).await?;
+        let names = self.query_directory(tree_id, &file_id, "*").await;
+        self.close(tree_id, &file_id).await?;
+        names
+    }
+
+    async fn session_setup(&mut self, buffer: Vec<u8>, previous_session_id: u64) -> SMBResult<(NTStatus, Option<NTLMMessage>)> {
+        let request = SMBSessionSetupRequest::new(SessionSetupSecurityMode::NEGOTIATE_SIGNING_ENABLED, Capabilities::empty(), buffer)
+            .with_previous_session_id(previous_session_id);
+        let response = self.request(SMBCommandCode::SessionSetup, 0, SMBBody::SessionSetupRequest(request)).await?;
+        let status = Self::response_status(&response.header);
+        self.session_id = response.header.session_id;
+        let SMBBody::SessionSetupResponse(body) = response.body else {
+            return Ok((status, None));
+        };
+        if body.buffer().is_empty() {
+            return Ok((status, None));
+        }
+        let (_, token) = SPNEGOToken::<NTLMAuthProvider>::parse(body.buffer())?;
+        let message = match token {
+            SPNEGOToken::Response(response) => match response.response_token {
+                Some(token) => Some(NTLMMessage::parse(&token)?.1),
+                None => None,
+            },
+            _ => None,
+        };
+        Ok((status, message))
+    }
+
+    async fn request(&mut self, command: SMBCommandCode, tree_id: u32, body: SMBBody) -> SMBResult<SMBSyncMessage> {
+        let message_id = self.message_ids.allocate(1).await;
+        let mut header = SMBSyncHeader::new(command, SMBFlags::empty(), 0, message_id, tree_id, self.session_id, [0; 16]);
+        header.credits = CREDIT_REQUEST;
+        self.socket.write().write_message(&SMBMessage::new(header, body)).await?;
+        let response = self.socket.read().messages().next_response().await
+            .ok_or(SMBError::parse_error("Connection closed before a response arrived"))?;
+        self.message_ids.grant(response.interim_credits);
+        // The client never asks for encryption, so a sealed response isn't one it can open
+        let SMBFrame::Plain(response, _) = response.frame else {
+            return Err(SMBError::parse_error("Sealed responses aren't supported"));
+        };
+        self.message_ids.grant(response.header.credits);
+        if response.header.message_id != message_id {
+            return Err(SMBError::parse_error("Response didn't answer the request's MessageId"));
+        }
+        Ok(response)
+    }
+
+    fn response_status(header: &SMBSyncHeader) -> NTStatus {
+        NTStatus::try_from(header.channel_sequence).unwrap_or(NTStatus::UnknownError)
+    }
+
+    fn check_status(header: &SMBSyncHeader) -> SMBResult<()> {
+        match Self::response_status(header) {
+            NTStatus::StatusSuccess => Ok(()),
+            status => Err(SMBError::response_error(status)),
+        }
+    }
+}
+
+fn ntlm_negotiate_token() -> Vec<u8> {
+    let flags = NTLMNegotiateFlags::UNICODE_ENCODING | NTLMNegotiateFlags::TARGET_NAME_SUPPLIED
+        | NTLMNegotiateFlags::SIGN | NTLMNegotiateFlags::NEGOTIATE_NTLM_KEY | NTLMNegotiateFlags::ALWAYS_SIGN
+        | NTLMNegotiateFlags::EXTENDED_SESSION_SECURITY | NTLMNegotiateFlags::TARGET_INFO
+        | NTLMNegotiateFlags::USE_128_BIT_ENCRYPTION | NTLMNegotiateFlags::KEY_EXCHANGE;
+    let mut init = SPNEGOTokenInitBody::<NTLMAuthProvider>::new();
+    init.mech_token = Some(NTLMNegotiateMessageBody::new(flags).as_bytes());
+    SPNEGOToken::Init(init).as_bytes(true)
+}
+
+// Returns the wrapped AUTHENTICATE along with the session key it establishes
+fn ntlm_authenticate_token(challenge: &NTLMChallengeMessageBody, domain_name: &str, user_name: &str, password: &str) -> SMBResult<(Vec<u8>, Vec<u8>)> {
+    let (authenticate, session_key) = NTLMAuthenticateMessageBody::for_challenge(challenge, domain_name, user_name, password)?;
+    let token = SPNEGOTokenResponseBody::<NTLMAuthProvider>::new(NTStatus::MoreProcessingRequired, NTLMMessage::Authenticate(authenticate));
+    Ok((SPNEGOToken::Response(token).as_bytes(false), session_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::client::{ntlm_authenticate_token, ntlm_negotiate_token, SMBClient};
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::server::StartSMBServer;
+    use crate::test_util::{read_frame, serve, share_server_builder, TempDir, test_server};
+    use crate::util::auth::ntlm::NTLMMessage;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn each_session_setup_leg_carries_the_provider_status() {
+        let root = TempDir::new("setup_legs");
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        let (first_status, challenge) = client.session_setup(ntlm_negotiate_token(), 0).await.unwrap();
+        let Some(NTLMMessage::Challenge(challenge)) = challenge else {
+            panic!("Expected a challenge on the first leg");
+        };
+        let (authenticate, _) = ntlm_authenticate_token(&challenge, "", "alice", "password").unwrap();
+        let (final_status, _) = client.session_setup(authenticate, 0).await.unwrap();
+
+        let mut wrong = SMBClient::connect(addr).await.unwrap();
+        wrong.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        let (_, challenge) = wrong.session_setup(ntlm_negotiate_token(), 0).await.unwrap();
+        let Some(NTLMMessage::Challenge(challenge)) = challenge else {
+            panic!("Expected a challenge on the first leg");
+        };
+        let (authenticate, _) = ntlm_authenticate_token(&challenge, "", "alice", "wrong").unwrap();
+        let (refused_status, _) = wrong.session_setup(authenticate, 0).await.unwrap();
+
+        server.read().await.shutdown();
+        assert_eq!(first_status, NTStatus::MoreProcessingRequired);
+        assert_eq!(final_status, NTStatus::StatusSuccess);
+        assert_eq!(refused_status, NTStatus::LogonFailure);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn responses_must_answer_the_request_message_id() {
+        let (server, addr) = test_server().await;
+        server.clone().spawn();
+        // Relays the NEGOTIATE to a real server and bumps the MessageId on the way back
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let relayed = tokio::spawn(async move {
+            let (mut client, _) = relay.accept().await.unwrap();
+            let mut upstream = TcpStream::connect(addr).await.unwrap();
+            upstream.write_all(&read_frame(&mut client).await).await.unwrap();
+            let mut response = read_frame(&mut upstream).await;
+            response[28] += 1;
+            client.write_all(&response).await.unwrap();
+        });
+
+        let mut client = SMBClient::connect(relay_addr).await.unwrap();
+        let result = client.negotiate(vec![SMBDialect::V2_1_0]).await;
+        relayed.await.unwrap();
+        server.read().await.shutdown();
+        assert!(matches!(result, Err(SMBError::ParseError(e)) if e.to_string().contains("MessageId")));
+    }
+}
@@ -9,8 +9,11 @@ use crate::protocol::message::Message;
 pub mod protocol;
 pub mod util;
 pub mod server;
+pub mod client;
 pub mod socket;
 mod byte_helper;
+#[cfg(test)]
+mod test_util;
 
 #[cfg(test)]
 mod tests {
@@ -24,7 +24,7 @@ async fn main() -> SMBResult<()> {
         .unencrypted_access(true)
         .require_message_signing(false)
         .encrypt_data(false)
-        .add_fs_share("test".into(), "".into(), file_allowed, get_file_perms)
+        .add_fs_share("test".into(), "".into(), file_allowed, get_file_perms, false)
         .auth_provider(NTLMAuthProvider::new(vec![
             User::new("tejasmehta", "password"),
             User::new("tejas2", "password"),
@@ -1,3 +1,60 @@
 use crate::protocol::body::empty::SMBEmpty;
+use crate::protocol::header::SMBSyncHeader;
 
-pub type SMBCancelRequest = SMBEmpty;
\ No newline at end of file
+pub type SMBCancelRequest = SMBEmpty;
+
+// MS-SMB2 3.3.5.16: an async CANCEL names its target by AsyncId, a sync one by MessageId
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CancelKey {
+    MessageId(u64),
+    AsyncId(u64),
+}
+
+impl CancelKey {
+    pub fn for_header(header: &SMBSyncHeader) -> Self {
+        match header.cancel_ids() {
+            (_, Some(async_id)) => Self::AsyncId(async_id),
+            (message_id, None) => Self::MessageId(message_id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::{SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::cancel::CancelKey;
+    use crate::protocol::body::empty::SMBEmpty;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::{Message, SMBSyncMessage};
+
+    #[test]
+    fn async_cancel_matches_its_pending_request() {
+        let async_id = 0x0000_0012_3456_789A;
+        let mut interim = SMBSyncHeader::new(SMBCommandCode::ChangeNotify, SMBFlags::SERVER_TO_REDIR, 0, 21, 3, 9, [0; 16]);
+        interim.set_async_id(async_id);
+
+        let mut cancel_header = SMBSyncHeader::new(SMBCommandCode::Cancel, SMBFlags::empty(), 0, 0, 0, 9, [0; 16]);
+        cancel_header.set_async_id(async_id);
+        let bytes = SMBSyncMessage::new(cancel_header, SMBBody::CancelRequest(SMBEmpty)).as_bytes();
+        let (_, cancel) = SMBSyncMessage::parse(&bytes).unwrap();
+
+        assert_eq!(cancel.header.cancel_ids(), (0, Some(async_id)));
+        assert_eq!(CancelKey::for_header(&cancel.header), CancelKey::for_header(&interim));
+        assert_eq!(CancelKey::for_header(&cancel.header), CancelKey::AsyncId(async_id));
+    }
+
+    #[test]
+    fn sync_cancel_matches_by_message_id() {
+        let request = SMBSyncHeader::new(SMBCommandCode::Read, SMBFlags::empty(), 0, 44, 3, 9, [0; 16]);
+        let cancel = SMBSyncHeader::new(SMBCommandCode::Cancel, SMBFlags::empty(), 0, 44, 3, 9, [0; 16]);
+        let (_, parsed) = SMBSyncHeader::smb_from_bytes(&cancel.smb_to_bytes()).unwrap();
+
+        assert_eq!(parsed.cancel_ids(), (44, None));
+        assert_eq!(CancelKey::for_header(&parsed), CancelKey::for_header(&request));
+        assert_ne!(CancelKey::for_header(&parsed), CancelKey::AsyncId(44));
+    }
+}
@@ -2,16 +2,18 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::close::flags::SMBCloseFlags;
 use crate::protocol::body::create::file_attributes::SMBFileAttributes;
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::filetime::FileTime;
+use crate::server::open::Open;
 
 mod flags;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 24)]
 pub struct SMBCloseRequest {
     #[smb_direct(start(fixed = 2))]
@@ -22,7 +24,25 @@ pub struct SMBCloseRequest {
     file_id: SMBFileId,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+impl SMBCloseRequest {
+    pub fn new(file_id: SMBFileId) -> Self {
+        Self {
+            flags: SMBCloseFlags::empty(),
+            reserved: PhantomData,
+            file_id,
+        }
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn post_query_attributes(&self) -> bool {
+        self.flags.contains(SMBCloseFlags::POSTQUERY_ATTRIB)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 60)]
 pub struct SMBCloseResponse {
     #[smb_direct(start(fixed = 2))]
@@ -43,4 +63,76 @@ pub struct SMBCloseResponse {
     end_of_file: u64,
     #[smb_direct(start(fixed = 56))]
     file_attributes: SMBFileAttributes,
-}
\ No newline at end of file
+}
+
+impl SMBCloseResponse {
+    // The attributes are only returned when asked for, otherwise every field after the flags is zero
+    pub fn for_open<O: Open>(open: &O, post_query_attributes: bool) -> SMBResult<Self> {
+        if !post_query_attributes {
+            return Ok(Self {
+                flags: SMBCloseFlags::empty(),
+                reserved: PhantomData,
+                creation_time: FileTime::zero(),
+                last_access_time: FileTime::zero(),
+                last_write_time: FileTime::zero(),
+                change_time: FileTime::zero(),
+                allocation_size: 0,
+                end_of_file: 0,
+                file_attributes: SMBFileAttributes::empty(),
+            });
+        }
+        let metadata = open.file_metadata()?;
+        Ok(Self {
+            flags: SMBCloseFlags::POSTQUERY_ATTRIB,
+            reserved: PhantomData,
+            creation_time: metadata.creation_time,
+            last_access_time: metadata.last_access_time,
+            last_write_time: metadata.last_write_time,
+            change_time: metadata.last_modification_time,
+            allocation_size: metadata.allocated_size,
+            end_of_file: metadata.actual_size,
+            file_attributes: open.file_attributes(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::protocol::body::close::flags::SMBCloseFlags;
+    use crate::protocol::body::close::SMBCloseResponse;
+    use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::SMBSyncMessage;
+
+    #[test]
+    fn json_dumps_show_readable_timestamps() {
+        // 2023-11-14T22:13:20Z plus a quarter second
+        let written = FileTime::from_ticks(133_444_736_002_500_000);
+        let response = SMBCloseResponse {
+            flags: SMBCloseFlags::POSTQUERY_ATTRIB,
+            reserved: PhantomData,
+            creation_time: FileTime::zero(),
+            last_access_time: written.clone(),
+            last_write_time: written.clone(),
+            change_time: written,
+            allocation_size: 4096,
+            end_of_file: 12,
+            file_attributes: SMBFileAttributes::ARCHIVE,
+        };
+        let header = SMBSyncHeader::new(SMBCommandCode::Close, SMBFlags::SERVER_TO_REDIR, 0, 3, 1, 9, [0; 16]);
+        let message = SMBSyncMessage::new(header, SMBBody::CloseResponse(response));
+
+        let json = serde_json::to_value(&message).unwrap();
+        let body = &json["body"]["CloseResponse"];
+        assert_eq!(body["creation_time"], "1601-01-01T00:00:00.0000000Z");
+        assert_eq!(body["last_write_time"], "2023-11-14T22:13:20.2500000Z");
+        let parsed: SMBSyncMessage = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, message);
+    }
+}
@@ -1,8 +1,13 @@
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
+use crate::protocol::body::create::action::SMBCreateAction;
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, SMBFromBytes, SMBToBytes, SMBByteSize, TryFromPrimitive, Serialize, Deserialize)]
 pub enum SMBCreateDisposition {
@@ -21,4 +26,17 @@ impl SMBCreateDisposition {
             Self::Open | Self::Create | Self::OpenIf => true,
         }
     }
+
+    // MS-FSA 2.1.5.1.2, what the create reports given whether the file was already there. Dispositions that
+    // need it one way or the other fail before anything is opened
+    pub fn action(&self, exists: bool) -> SMBResult<SMBCreateAction> {
+        match (self, exists) {
+            (Self::Supersede, true) => Ok(SMBCreateAction::Superseded),
+            (Self::Open | Self::OpenIf, true) => Ok(SMBCreateAction::Opened),
+            (Self::Overwrite | Self::OverwriteIf, true) => Ok(SMBCreateAction::Overwritten),
+            (Self::Create, true) => Err(SMBError::response_error(NTStatus::ObjectNameCollision)),
+            (Self::Supersede | Self::Create | Self::OpenIf | Self::OverwriteIf, false) => Ok(SMBCreateAction::Created),
+            (Self::Open | Self::Overwrite, false) => Err(SMBError::response_error(NTStatus::ObjectNameNotFound)),
+        }
+    }
 }
\ No newline at end of file
@@ -33,19 +33,23 @@ pub mod disposition;
 pub mod request_context;
 pub mod file_id;
 mod flags;
-mod action;
+pub mod action;
 mod response_context;
 
 #[macro_use]
 pub(crate) mod context_helper;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+// MS-FSCC 2.1.5.2: characters no name component may contain, on top of the control characters
+pub const INVALID_NAME_CHARACTERS: [char; 9] = ['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 57)]
 pub struct SMBCreateRequest {
     #[smb_direct(start(fixed = 3))]
     oplock_level: SMBOplockLevel,
+    // Kept raw so an unknown level can be rejected with the proper status rather than failing the parse
     #[smb_direct(start(fixed = 4))]
-    impersonation_level: SMBImpersonationLevel,
+    impersonation_level: u32,
     #[smb_enum(start(fixed = 24), discriminator(inner(start = 28, num_type = "u32")), modifier(and = 0x10), modifier(right_shift = 4))]
     desired_access: SMBAccessMask,
     #[smb_direct(start(fixed = 28))]
@@ -63,6 +67,34 @@ pub struct SMBCreateRequest {
 }
 
 impl SMBCreateRequest {
+    pub fn new(file_name: &str, desired_access: SMBAccessMask, share_access: SMBShareAccess, disposition: SMBCreateDisposition, options: SMBCreateOptions) -> Self {
+        // The access mask is read back as a directory mask only when the DIRECTORY attribute is set
+        let attributes = match desired_access {
+            SMBAccessMask::Directory(_) => SMBFileAttributes::DIRECTORY,
+            SMBAccessMask::FilePipePrinter(_) => SMBFileAttributes::NORMAL,
+        };
+        Self {
+            oplock_level: SMBOplockLevel::None,
+            impersonation_level: SMBImpersonationLevel::Impersonation as u32,
+            desired_access,
+            attributes,
+            share_access,
+            create_disposition: disposition,
+            create_options: options,
+            file_name: file_name.into(),
+            contexts: vec![],
+        }
+    }
+
+    pub fn requested_oplock_level(&self) -> SMBOplockLevel {
+        self.oplock_level
+    }
+
+    pub fn with_oplock_level(mut self, oplock_level: SMBOplockLevel) -> Self {
+        self.oplock_level = oplock_level;
+        self
+    }
+
     pub fn file_name(&self) -> &str {
         &self.file_name
     }
@@ -81,10 +113,20 @@ impl SMBCreateRequest {
             self.create_disposition == SMBCreateDisposition::Create
     }
 
+    pub fn impersonation_level(&self) -> SMBResult<SMBImpersonationLevel> {
+        u8::try_from(self.impersonation_level).ok()
+            .and_then(|level| SMBImpersonationLevel::try_from(level).ok())
+            .ok_or(SMBError::response_error(NTStatus::BadImpersonationLevel))
+    }
+
     pub fn desired_access(&self) -> &SMBAccessMask {
         &self.desired_access
     }
 
+    pub fn share_access(&self) -> SMBShareAccess {
+        self.share_access
+    }
+
     pub fn options(&self) -> SMBCreateOptions {
         self.create_options
     }
@@ -94,9 +136,21 @@ impl SMBCreateRequest {
     }
 
     pub fn validate<R: SharedResource>(&self, resource: &R) -> SMBResult<(&str, SMBCreateDisposition, bool)> {
+        self.impersonation_level()?;
+        // Anything other than opening an existing file would modify the share
+        if self.desired_access.includes_write() || self.create_disposition != SMBCreateDisposition::Open {
+            resource.check_writable()?;
+        }
+        // MS-SMB2 section 3.3.5.9, delete-on-close is only honoured for opens that asked for DELETE
+        if self.create_options.contains(SMBCreateOptions::DELETE_ON_CLOSE) && !self.desired_access.includes_delete() {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
         if resource.resource_type() == ResourceType::PRINT_QUEUE && !self.validate_print() {
             return Err(SMBError::response_error(NTStatus::NotSupported))
         }
+        if self.create_options.contains(SMBCreateOptions::DIRECTORY_FILE | SMBCreateOptions::NON_DIRECTORY_FILE) {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
         if self.create_options.contains(SMBCreateOptions::DIRECTORY_FILE) &&
             !self.validate_directory() {
             // TODO make this the right error code
@@ -104,9 +158,29 @@ impl SMBCreateRequest {
         }
         Ok((&self.file_name(), self.disposition(), self.create_options.contains(SMBCreateOptions::DIRECTORY_FILE)))
     }
+
+    // Only the last component is checked, the ones before it already exist or fail to resolve on their own
+    pub fn validate_name(&self, invalid_characters: &[char]) -> SMBResult<()> {
+        let name = self.file_name.rsplit(['\\', '/']).next().unwrap_or_default();
+        if name.chars().any(|c| (c as u32) < 0x20 || invalid_characters.contains(&c)) {
+            return Err(SMBError::response_error(NTStatus::ObjectNameInvalid));
+        }
+        Ok(())
+    }
+
+    // What's already at the path has to be the kind of file DIRECTORY_FILE or NON_DIRECTORY_FILE asked for
+    pub fn validate_file_type(&self, is_directory: bool) -> SMBResult<()> {
+        if is_directory && self.create_options.contains(SMBCreateOptions::NON_DIRECTORY_FILE) {
+            return Err(SMBError::response_error(NTStatus::FileIsADirectory));
+        }
+        if !is_directory && self.create_options.contains(SMBCreateOptions::DIRECTORY_FILE) {
+            return Err(SMBError::response_error(NTStatus::NotADirectory));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 89)]
 pub struct SMBCreateResponse {
     #[smb_direct(start(fixed = 2))]
@@ -143,12 +217,24 @@ pub struct SMBCreateResponse {
 }
 
 impl SMBCreateResponse {
-    pub fn for_open<S: Server>(open: &S::Open) -> SMBResult<Self> {
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn attributes(&self) -> SMBFileAttributes {
+        self.attributes
+    }
+
+    pub fn action(&self) -> SMBCreateAction {
+        self.action
+    }
+
+    pub fn for_open<S: Server>(open: &S::Open, action: SMBCreateAction) -> SMBResult<Self> {
         let metadata = open.file_metadata()?;
         Ok(Self {
             oplock_level: open.oplock_level(),
             flags: SMBCreateFlags::empty(),
-            action: SMBCreateAction::Created,
+            action,
             creation_time: metadata.creation_time,
             last_access_time: metadata.last_access_time,
             last_write_time: metadata.last_write_time,
@@ -161,4 +247,142 @@ impl SMBCreateResponse {
             contexts: vec![],
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::{SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+    use crate::protocol::body::create::impersonation_level::SMBImpersonationLevel;
+    use crate::protocol::body::create::oplock::SMBOplockLevel;
+    use crate::protocol::body::create::options::SMBCreateOptions;
+    use crate::protocol::body::create::share_access::SMBShareAccess;
+    use crate::protocol::body::create::{INVALID_NAME_CHARACTERS, SMBCreateRequest};
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::server::share::file_system::{SMBFileSystemHandle, SMBFileSystemShare};
+
+    pub(crate) fn create_request(file_name: &str, disposition: SMBCreateDisposition, options: SMBCreateOptions) -> SMBCreateRequest {
+        SMBCreateRequest {
+            oplock_level: SMBOplockLevel::None,
+            impersonation_level: SMBImpersonationLevel::Impersonation as u32,
+            desired_access: SMBAccessMask::access_no_connect_security(false),
+            attributes: SMBFileAttributes::NORMAL,
+            share_access: SMBShareAccess::READ | SMBShareAccess::WRITE,
+            create_disposition: disposition,
+            create_options: options,
+            file_name: file_name.into(),
+            contexts: vec![],
+        }
+    }
+
+    fn share() -> SMBFileSystemShare<(), SMBFileSystemHandle> {
+        SMBFileSystemShare::path("test".into(), std::env::temp_dir().to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+    }
+
+    #[test]
+    fn known_impersonation_levels_pass_through() {
+        for level in [SMBImpersonationLevel::Anonymous, SMBImpersonationLevel::Identification, SMBImpersonationLevel::Impersonation, SMBImpersonationLevel::Delegate] {
+            let mut request = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+            request.impersonation_level = level as u32;
+            assert_eq!(request.impersonation_level().unwrap(), level);
+            assert!(request.validate(&share()).is_ok());
+        }
+    }
+
+    #[test]
+    fn unknown_impersonation_level_is_rejected() {
+        for level in [4, 0x100, u32::MAX] {
+            let mut request = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+            request.impersonation_level = level;
+            let result = request.validate(&share());
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::BadImpersonationLevel));
+        }
+    }
+
+    #[test]
+    fn read_only_share_rejects_writes_but_allows_reads() {
+        let share = share().with_read_only(true);
+        let mut read = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        read.desired_access = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA | SMBFilePipePrinterAccessMask::FILE_READ_ATTRIBUTES);
+        assert!(read.validate(&share).is_ok());
+
+        let write = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        let result = write.validate(&share);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::MediaWriteProtected));
+
+        let mut create = create_request("new.txt", SMBCreateDisposition::Create, SMBCreateOptions::empty());
+        create.desired_access = read.desired_access.clone();
+        let result = create.validate(&share);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::MediaWriteProtected));
+    }
+
+    #[test]
+    fn delete_on_close_requires_delete_access() {
+        let mut request = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::DELETE_ON_CLOSE);
+        request.desired_access = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let result = request.validate(&share());
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+
+        request.desired_access = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA | SMBFilePipePrinterAccessMask::DELETE);
+        assert!(request.validate(&share()).is_ok());
+    }
+
+    #[test]
+    fn directory_options_must_match_what_is_on_disk() {
+        let directory = create_request("docs", SMBCreateDisposition::Open, SMBCreateOptions::DIRECTORY_FILE);
+        assert!(directory.validate_file_type(true).is_ok());
+        let result = directory.validate_file_type(false);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotADirectory));
+
+        let file = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::NON_DIRECTORY_FILE);
+        assert!(file.validate_file_type(false).is_ok());
+        let result = file.validate_file_type(true);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::FileIsADirectory));
+
+        let either = create_request("any", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        assert!(either.validate_file_type(true).is_ok());
+        assert!(either.validate_file_type(false).is_ok());
+    }
+
+    #[test]
+    fn directory_and_non_directory_together_are_invalid() {
+        let request = create_request("docs", SMBCreateDisposition::Open, SMBCreateOptions::DIRECTORY_FILE | SMBCreateOptions::NON_DIRECTORY_FILE);
+        let result = request.validate(&share());
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+    }
+
+    #[test]
+    fn invalid_characters_in_the_name_are_rejected() {
+        let invalid = INVALID_NAME_CHARACTERS.iter().copied().chain(['\0', '\u{1}', '\u{1f}']);
+        for c in invalid {
+            // Separators split the path rather than ending up in the name
+            let name = match c {
+                '/' | '\\' => continue,
+                c => format!("docs\\bad{}name.txt", c),
+            };
+            let request = create_request(&name, SMBCreateDisposition::Create, SMBCreateOptions::empty());
+            let result = request.validate_name(&INVALID_NAME_CHARACTERS);
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::ObjectNameInvalid), "{:?} was accepted", c);
+        }
+
+        let unicode = create_request("docs\\résumé 履歴書 📄.txt", SMBCreateDisposition::Create, SMBCreateOptions::empty());
+        assert!(unicode.validate_name(&INVALID_NAME_CHARACTERS).is_ok());
+        let root = create_request("", SMBCreateDisposition::Open, SMBCreateOptions::DIRECTORY_FILE);
+        assert!(root.validate_name(&INVALID_NAME_CHARACTERS).is_ok());
+    }
+
+    #[test]
+    fn parsed_request_clones_equal() {
+        let bytes = create_request("docs\\report.txt", SMBCreateDisposition::OpenIf, SMBCreateOptions::NON_DIRECTORY_FILE).smb_to_bytes();
+        let (_, parsed) = SMBCreateRequest::smb_from_bytes(&bytes).unwrap();
+        let body = SMBBody::CreateRequest(parsed);
+        let kept = body.clone();
+        assert_eq!(kept, body);
+        assert_eq!(kept.smb_to_bytes(), body.smb_to_bytes());
+    }
+}
@@ -5,6 +5,7 @@ use serde::{Deserialize, Serialize};
 
 use smb_core::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBToBytes};
 use smb_core::error::SMBError;
+use smb_core::fixed_bytes::FixedBytes;
 use smb_core::nt_status::NTStatus;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
@@ -22,7 +23,7 @@ const RESPONSE_LEASE_TAG: &[u8] = REQUEST_LEASE_TAG;
 const DURABLE_HANDLE_RESPONSE_V2_TAG: &[u8] = DURABLE_HANDLE_REQUEST_V2_TAG;
 const SVHDX_OPEN_DEVICE_CONTEXT_RESPONSE_TAG: &[u8] = SVHDX_OPEN_DEVICE_CONTEXT_TAG;
 
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CreateResponseContext {
     DurableHandleResponse(DurableHandleResponse),
     QueryMaximalAccessResponse(QueryMaximalAccessResponse),
@@ -126,14 +127,14 @@ pub struct QueryMaximalAccessResponse {
     maximal_access: SMBFilePipePrinterAccessMask,
 }
 
+pub const QUERY_ON_DISK_ID_SIZE: usize = 32;
+
+// MS-SMB2 2.2.14.2.9, the DiskFileId and VolumeId followed by 16 reserved bytes. Clients treat the whole QFid as
+// one opaque identifier, so it's kept as the blob
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
 pub struct QueryOnDiskIDResponse {
     #[smb_direct(start(fixed = 0))]
-    disk_file_id: u64,
-    #[smb_direct(start(fixed = 8))]
-    volume_id: u64,
-    #[smb_skip(start = 16, length = 16)]
-    reserved: PhantomData<Vec<u8>>,
+    on_disk_id: FixedBytes<QUERY_ON_DISK_ID_SIZE>,
 }
 
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
@@ -1,10 +1,15 @@
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
 use crate::util::flags_helper::{impl_smb_byte_size_for_bitflag, impl_smb_from_bytes_for_bitflag, impl_smb_to_bytes_for_bitflag};
 
 bitflags! {
-    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
     pub struct SMBShareAccess: u32 {
         const READ = 0x1;
         const WRITE = 0x2;
@@ -12,6 +17,72 @@ bitflags! {
     }
 }
 
+impl SMBShareAccess {
+    // The share access an open's rights require the other opens of the file to allow
+    fn required_by(access: &SMBAccessMask) -> Self {
+        let mut required = Self::empty();
+        required.set(Self::READ, access.includes_read_data());
+        required.set(Self::WRITE, access.includes_write_data());
+        required.set(Self::DELETE, access.includes_delete());
+        required
+    }
+
+    // Share access check from MS-FSA section 2.1.5.1.2.1, which has to pass in both directions.
+    // Opens that only touch attributes take no part in sharing
+    pub fn check_sharing(&self, access: &SMBAccessMask, existing_share: SMBShareAccess, existing_access: &SMBAccessMask) -> SMBResult<()> {
+        let required = Self::required_by(access);
+        let existing_required = Self::required_by(existing_access);
+        if required.is_empty() || existing_required.is_empty() {
+            return Ok(());
+        }
+        if !existing_share.contains(required) || !self.contains(existing_required) {
+            return Err(SMBError::response_error(NTStatus::SharingViolation));
+        }
+        Ok(())
+    }
+}
+
 impl_smb_byte_size_for_bitflag! { SMBShareAccess }
 impl_smb_to_bytes_for_bitflag! { SMBShareAccess }
-impl_smb_from_bytes_for_bitflag! { SMBShareAccess }
\ No newline at end of file
+impl_smb_from_bytes_for_bitflag! { SMBShareAccess }
+
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::share_access::SMBShareAccess;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+
+    fn access(mask: SMBFilePipePrinterAccessMask) -> SMBAccessMask {
+        SMBAccessMask::FilePipePrinter(mask)
+    }
+
+    #[test]
+    fn second_writer_violates_exclusive_write() {
+        let write = access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        let result = SMBShareAccess::READ.check_sharing(&write, SMBShareAccess::READ, &write);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::SharingViolation));
+    }
+
+    #[test]
+    fn sharing_is_checked_in_both_directions() {
+        let read = access(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let write = access(SMBFilePipePrinterAccessMask::GENERIC_WRITE);
+        let all = SMBShareAccess::READ | SMBShareAccess::WRITE | SMBShareAccess::DELETE;
+
+        assert!(all.check_sharing(&read, all, &write).is_ok());
+        // The new reader allows everything, but the existing writer didn't share reads
+        assert!(all.check_sharing(&read, SMBShareAccess::WRITE, &write).is_err());
+        // The existing writer shares reads, but the new reader won't share writes
+        assert!(SMBShareAccess::READ.check_sharing(&read, all, &write).is_err());
+    }
+
+    #[test]
+    fn attribute_only_opens_never_conflict() {
+        let attributes = access(SMBFilePipePrinterAccessMask::FILE_READ_ATTRIBUTES | SMBFilePipePrinterAccessMask::SYNCHRONIZE);
+        let write = access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        assert!(SMBShareAccess::empty().check_sharing(&attributes, SMBShareAccess::empty(), &attributes).is_ok());
+        assert!(SMBShareAccess::empty().check_sharing(&attributes, SMBShareAccess::WRITE, &write).is_ok());
+    }
+}
@@ -1,11 +1,14 @@
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
-use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+use smb_core::{SMBFromBytes, SMBParseResult};
+use smb_derive::{SMBByteSize, SMBToBytes};
 
 #[repr(u16)]
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Serialize, Deserialize, Copy, Clone, Ord, PartialOrd, SMBFromBytes, SMBByteSize, SMBToBytes, Default)]
+#[derive(Debug, Eq, PartialEq, TryFromPrimitive, Serialize, Deserialize, Copy, Clone, Ord, PartialOrd, SMBByteSize, SMBToBytes, Default)]
 pub enum SMBDialect {
+    // Stands in for the 0x0000 sentinel and any dialect newer than this server knows, never negotiated
+    Unknown = 0x000,
     V2_0_2 = 0x202,
     V2_1_0 = 0x210,
     V3_0_0 = 0x300,
@@ -19,4 +22,73 @@ impl SMBDialect {
     pub fn is_smb3(&self) -> bool {
         *self as u16 >= 0x300
     }
-}
\ No newline at end of file
+
+    // Every dialect past 2.0.2 can charge multiple credits per request over TCP
+    pub fn supports_multi_credit(&self) -> bool {
+        !matches!(self, Self::Unknown | Self::V2_0_2 | Self::V2_X_X)
+    }
+
+    // A dialect actually spoken on the wire, as opposed to the wildcard or a value we couldn't place
+    pub fn is_concrete(&self) -> bool {
+        !matches!(self, Self::Unknown | Self::V2_X_X)
+    }
+
+    // Read/write/transact size offered when the server isn't configured with one; a single credit only covers 64KiB
+    pub fn default_max_io_size(&self, multi_credit: bool) -> u32 {
+        match (multi_credit, self.is_smb3()) {
+            (false, _) => 65536,
+            (true, false) => 1048576,
+            (true, true) => 8388608,
+        }
+    }
+}
+
+// Unrecognised values parse as Unknown, a client offering a future dialect alongside ours can still negotiate
+impl SMBFromBytes for SMBDialect {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> {
+        let (remaining, value) = u16::smb_from_bytes(input)?;
+        let dialect = match value {
+            0x202 => Self::V2_0_2,
+            0x210 => Self::V2_1_0,
+            0x300 => Self::V3_0_0,
+            0x302 => Self::V3_0_2,
+            0x311 => Self::V3_1_1,
+            0x2FF => Self::V2_X_X,
+            _ => Self::Unknown,
+        };
+        Ok((remaining, dialect))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::SMBFromBytes;
+
+    use crate::protocol::body::dialect::SMBDialect;
+
+    #[test]
+    fn default_max_io_size_follows_dialect_and_multi_credit() {
+        assert!(!SMBDialect::V2_0_2.supports_multi_credit());
+        assert_eq!(SMBDialect::V2_0_2.default_max_io_size(SMBDialect::V2_0_2.supports_multi_credit()), 65536);
+        assert!(SMBDialect::V2_1_0.supports_multi_credit());
+        assert_eq!(SMBDialect::V2_1_0.default_max_io_size(true), 1048576);
+        assert!(SMBDialect::V3_1_1.supports_multi_credit());
+        assert_eq!(SMBDialect::V3_1_1.default_max_io_size(true), 8388608);
+        assert_eq!(SMBDialect::V3_1_1.default_max_io_size(false), 65536);
+    }
+
+    #[test]
+    fn wildcard_sentinel_and_unknown_values_parse() {
+        let bytes = [0xFF, 0x02, 0x00, 0x00, 0x12, 0x03, 0x11, 0x03];
+        let (remaining, wildcard) = SMBDialect::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(wildcard, SMBDialect::V2_X_X);
+        let (remaining, sentinel) = SMBDialect::smb_from_bytes(remaining).unwrap();
+        assert_eq!(sentinel, SMBDialect::Unknown);
+        let (remaining, future) = SMBDialect::smb_from_bytes(remaining).unwrap();
+        assert_eq!(future, SMBDialect::Unknown);
+        let (remaining, known) = SMBDialect::smb_from_bytes(remaining).unwrap();
+        assert_eq!(known, SMBDialect::V3_1_1);
+        assert!(remaining.is_empty());
+        assert!(!SMBDialect::Unknown.is_concrete() && !SMBDialect::V2_X_X.is_concrete());
+    }
+}
@@ -2,7 +2,40 @@ use serde::{Deserialize, Serialize};
 
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBToBytes, SMBByteSize)]
+// Bodies with nothing but a StructureSize of 4 and two reserved bytes: logoff, tree disconnect, echo and
+// the flush, lock and cancel messages that share the layout
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBToBytes, SMBByteSize)]
 #[smb_byte_tag(value = 4)]
 #[smb_skip(start = 0, length = 4)]
-pub struct SMBEmpty;
\ No newline at end of file
+pub struct SMBEmpty;
+
+#[cfg(test)]
+mod tests {
+    use smb_core::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::echo::SMBEchoResponse;
+    use crate::protocol::body::empty::SMBEmpty;
+    use crate::protocol::body::logoff::SMBLogoffResponse;
+    use crate::protocol::body::set_info::SMBSetInfoResponse;
+    use crate::protocol::body::tree_disconnect::SMBTreeDisconnectResponse;
+
+    #[test]
+    fn empty_responses_serialize_to_their_structure_size() {
+        let logoff: SMBLogoffResponse = SMBEmpty;
+        let tree_disconnect: SMBTreeDisconnectResponse = SMBEmpty;
+        let echo: SMBEchoResponse = SMBEmpty;
+        for body in [logoff, tree_disconnect, echo] {
+            assert_eq!(body.smb_to_bytes(), vec![4, 0, 0, 0]);
+            assert_eq!(body.smb_byte_size(), 4);
+        }
+        let (remaining, parsed) = SMBEmpty::smb_from_bytes(&[4, 0, 0, 0]).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, SMBEmpty);
+
+        assert_eq!(SMBSetInfoResponse.smb_to_bytes(), vec![2, 0]);
+        assert_eq!(SMBSetInfoResponse.smb_byte_size(), 2);
+        let (remaining, parsed) = SMBSetInfoResponse::smb_from_bytes(&[2, 0]).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, SMBSetInfoResponse);
+    }
+}
@@ -1,17 +1,378 @@
-// #[derive(Debug, SMBFromBytes, SMBToBytes, SMBByteSize)]
-// struct SMBErrorResponse {
-//     #[smb_skip(start = 0, length = 4)]
-//     reserved: PhantomData<Vec<u8>>,
-//     #[smb_enum(start(fixed = 8), discriminator(inner(start = 2, num_type = "u8")))]
-//     data: SMBErrorData,
-// }
-
-// #[derive(Debug, SMBEnumFromBytes, SMBToBytes, SMBByteSize)]
-// pub enum SMBErrorData {
-//     #[smb_discriminator(value = 0x0)]
-//     #[smb_direct(start = 0)]
-//     Single(u8),
-//     #[smb_discriminator(value = 0x1)]
-//     #[smb_direct(start = 0)]
-//     Contexts(u8)
-// }
\ No newline at end of file
+use serde::{Deserialize, Serialize};
+
+use smb_core::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBResult, SMBToBytes};
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+
+use crate::protocol::body::create::file_attributes::IO_REPARSE_TAG_SYMLINK;
+use crate::protocol::body::dialect::SMBDialect;
+
+const ERROR_STRUCTURE_SIZE: u16 = 9;
+const SYMLINK_ERROR_TAG: u32 = 0x4C4D5953;
+const SYMLINK_FLAG_RELATIVE: u32 = 0x1;
+// Everything in the symlink error up to the path buffer
+const SYMLINK_HEADER_LEN: usize = 28;
+// ErrorDataLength and ErrorId ahead of each context's data
+const ERROR_CONTEXT_HEADER_LEN: usize = 8;
+
+pub const SMB2_ERROR_ID_DEFAULT: u32 = 0x0;
+pub const SMB2_ERROR_ID_SHARE_REDIRECT: u32 = 0x72645253;
+
+// MS-SMB2 2.2.2.1, one entry of a 3.1.1 error response's ErrorData
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct SMBErrorContext {
+    error_id: u32,
+    error_data: Vec<u8>,
+}
+
+impl SMBErrorContext {
+    pub fn new(error_id: u32, error_data: Vec<u8>) -> Self {
+        Self { error_id, error_data }
+    }
+
+    pub fn error_id(&self) -> u32 {
+        self.error_id
+    }
+
+    pub fn error_data(&self) -> &[u8] {
+        &self.error_data
+    }
+}
+
+impl SMBByteSize for SMBErrorContext {
+    fn smb_byte_size(&self) -> usize {
+        ERROR_CONTEXT_HEADER_LEN + self.error_data.len()
+    }
+}
+
+impl SMBToBytes for SMBErrorContext {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        [
+            &(self.error_data.len() as u32).to_le_bytes()[..],
+            &self.error_id.to_le_bytes(),
+            &self.error_data,
+        ].concat()
+    }
+}
+
+impl SMBFromBytes for SMBErrorContext {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let (remaining, error_data_length) = u32::smb_from_bytes(input)?;
+        let (remaining, error_id) = u32::smb_from_bytes(remaining)?;
+        let length = error_data_length as usize;
+        if remaining.len() < length {
+            return Err(SMBError::payload_too_small(length, remaining.len()));
+        }
+        Ok((&remaining[length..], Self { error_id, error_data: remaining[..length].to_vec() }))
+    }
+}
+
+fn padded_to_8(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+// MS-SMB2 2.2.2, sent in place of the command's own response body when it fails
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct SMBErrorResponse {
+    error_contexts: Vec<SMBErrorContext>,
+    error_data: Vec<u8>,
+}
+
+impl SMBErrorResponse {
+    pub fn new(error_data: Vec<u8>) -> Self {
+        Self { error_contexts: Vec::new(), error_data }
+    }
+
+    pub fn with_contexts(error_contexts: Vec<SMBErrorContext>) -> Self {
+        Self { error_contexts, error_data: Vec::new() }
+    }
+
+    // 3.1.1 wraps any error data in a context, earlier dialects send it as is
+    pub fn for_dialect(dialect: SMBDialect, error_data: Vec<u8>) -> Self {
+        match dialect == SMBDialect::V3_1_1 && !error_data.is_empty() {
+            true => Self::with_contexts(vec![SMBErrorContext::new(SMB2_ERROR_ID_DEFAULT, error_data)]),
+            false => Self::new(error_data),
+        }
+    }
+
+    pub fn error_contexts(&self) -> &[SMBErrorContext] {
+        &self.error_contexts
+    }
+
+    // The raw ErrorData of a response without contexts
+    pub fn error_data(&self) -> &[u8] {
+        &self.error_data
+    }
+
+    fn byte_count(&self) -> usize {
+        match self.error_contexts.split_last() {
+            // Every context but the last is padded out so the next one starts 8-byte aligned
+            Some((last, rest)) => rest.iter()
+                .map(|context| padded_to_8(context.smb_byte_size()))
+                .sum::<usize>() + last.smb_byte_size(),
+            None => self.error_data.len(),
+        }
+    }
+}
+
+impl SMBByteSize for SMBErrorResponse {
+    fn smb_byte_size(&self) -> usize {
+        // An empty ErrorData is still sent as a single zero byte
+        8 + self.byte_count().max(1)
+    }
+}
+
+impl SMBToBytes for SMBErrorResponse {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        let error_data = match (self.error_contexts.is_empty(), self.error_data.is_empty()) {
+            (true, true) => vec![0],
+            (true, false) => self.error_data.clone(),
+            (false, _) => {
+                let mut bytes = Vec::with_capacity(self.byte_count());
+                for context in self.error_contexts.iter() {
+                    bytes.resize(padded_to_8(bytes.len()), 0);
+                    bytes.extend_from_slice(&context.smb_to_bytes());
+                }
+                bytes
+            }
+        };
+        [
+            &ERROR_STRUCTURE_SIZE.to_le_bytes()[..],
+            &[self.error_contexts.len() as u8, 0],
+            &(self.byte_count() as u32).to_le_bytes(),
+            &error_data,
+        ].concat()
+    }
+}
+
+impl SMBFromBytes for SMBErrorResponse {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let (remaining, structure_size) = u16::smb_from_bytes(input)?;
+        if structure_size != ERROR_STRUCTURE_SIZE {
+            return Err(SMBError::parse_error("Invalid structure size for error response"));
+        }
+        let (remaining, context_count) = u8::smb_from_bytes(remaining)?;
+        let (remaining, _reserved) = u8::smb_from_bytes(remaining)?;
+        let (remaining, byte_count) = u32::smb_from_bytes(remaining)?;
+        let length = (byte_count as usize).max(1);
+        if remaining.len() < length {
+            return Err(SMBError::payload_too_small(length, remaining.len()));
+        }
+        let error_data = &remaining[..(byte_count as usize)];
+        let mut error_contexts = Vec::with_capacity(context_count as usize);
+        let mut offset = 0;
+        for _ in 0..context_count {
+            let context_bytes = error_data.get(offset..)
+                .ok_or(SMBError::parse_error("Error context runs past the error data"))?;
+            let (_, context) = SMBErrorContext::smb_from_bytes(context_bytes)?;
+            offset += padded_to_8(context.smb_byte_size());
+            error_contexts.push(context);
+        }
+        let error_data = match context_count {
+            0 => error_data.to_vec(),
+            _ => Vec::new(),
+        };
+        Ok((&remaining[length..], Self { error_contexts, error_data }))
+    }
+}
+
+// MS-SMB2 2.2.2.2, the ErrorData of STATUS_BUFFER_TOO_SMALL is the size the client should retry with
+pub fn buffer_too_small(required_size: usize) -> SMBError {
+    SMBError::response_error_with_data(NTStatus::BufferTooSmall, (required_size as u32).to_le_bytes().to_vec())
+}
+
+// MS-SMB2 2.2.2.2.1, the ErrorData of a STATUS_STOPPED_ON_SYMLINK response
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize, Clone)]
+pub struct SMBSymbolicLinkErrorResponse {
+    unparsed_path_length: u16,
+    substitute_name: String,
+    print_name: String,
+    relative: bool,
+}
+
+impl SMBSymbolicLinkErrorResponse {
+    // unparsed_path_length is the byte length of the UTF-16 name left over after the link, separator included
+    pub fn new(unparsed_path_length: u16, target: &str, relative: bool) -> Self {
+        let target = target.replace('/', "\\");
+        Self {
+            unparsed_path_length,
+            substitute_name: target.clone(),
+            print_name: target,
+            relative,
+        }
+    }
+
+    pub fn unparsed_path_length(&self) -> u16 {
+        self.unparsed_path_length
+    }
+
+    pub fn substitute_name(&self) -> &str {
+        &self.substitute_name
+    }
+
+    pub fn print_name(&self) -> &str {
+        &self.print_name
+    }
+
+    pub fn relative(&self) -> bool {
+        self.relative
+    }
+}
+
+fn utf16_bytes(value: &str) -> Vec<u8> {
+    value.encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+impl SMBByteSize for SMBSymbolicLinkErrorResponse {
+    fn smb_byte_size(&self) -> usize {
+        SYMLINK_HEADER_LEN + utf16_bytes(&self.substitute_name).len() + utf16_bytes(&self.print_name).len()
+    }
+}
+
+impl SMBToBytes for SMBSymbolicLinkErrorResponse {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        let substitute_name = utf16_bytes(&self.substitute_name);
+        let print_name = utf16_bytes(&self.print_name);
+        let path_buffer_len = substitute_name.len() + print_name.len();
+        let flags = match self.relative {
+            true => SYMLINK_FLAG_RELATIVE,
+            false => 0,
+        };
+        [
+            &((SYMLINK_HEADER_LEN - 4 + path_buffer_len) as u32).to_le_bytes()[..],
+            &SYMLINK_ERROR_TAG.to_le_bytes(),
+            &IO_REPARSE_TAG_SYMLINK.to_le_bytes(),
+            // The reparse data runs from the name offsets to the end of the path buffer
+            &((12 + path_buffer_len) as u16).to_le_bytes(),
+            &self.unparsed_path_length.to_le_bytes(),
+            &0_u16.to_le_bytes(),
+            &(substitute_name.len() as u16).to_le_bytes(),
+            &(substitute_name.len() as u16).to_le_bytes(),
+            &(print_name.len() as u16).to_le_bytes(),
+            &flags.to_le_bytes(),
+            &substitute_name,
+            &print_name,
+        ].concat()
+    }
+}
+
+impl SMBFromBytes for SMBSymbolicLinkErrorResponse {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let (_, symlink_length) = u32::smb_from_bytes(input)?;
+        let total_length = symlink_length as usize + 4;
+        if input.len() < total_length || total_length < SYMLINK_HEADER_LEN {
+            return Err(SMBError::payload_too_small(total_length.max(SYMLINK_HEADER_LEN), input.len()));
+        }
+        let (_, error_tag) = u32::smb_from_bytes(&input[4..])?;
+        let (_, reparse_tag) = u32::smb_from_bytes(&input[8..])?;
+        if error_tag != SYMLINK_ERROR_TAG || reparse_tag != IO_REPARSE_TAG_SYMLINK {
+            return Err(SMBError::parse_error("Error data is not a symbolic link error response"));
+        }
+        let (_, unparsed_path_length) = u16::smb_from_bytes(&input[14..])?;
+        let path_buffer = &input[SYMLINK_HEADER_LEN..total_length];
+        let name_at = |offset_at: usize| -> SMBResult<String> {
+            let (_, offset) = u16::smb_from_bytes(&input[offset_at..])?;
+            let (_, length) = u16::smb_from_bytes(&input[(offset_at + 2)..])?;
+            let bytes = path_buffer.get((offset as usize)..(offset as usize + length as usize))
+                .ok_or(SMBError::parse_error("Symbolic link name runs past the path buffer"))?;
+            let units = bytes.chunks_exact(2)
+                .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+                .collect::<Vec<u16>>();
+            String::from_utf16(&units).map_err(SMBError::parse_error)
+        };
+        let (_, flags) = u32::smb_from_bytes(&input[24..])?;
+        Ok((&input[total_length..], Self {
+            unparsed_path_length,
+            substitute_name: name_at(16)?,
+            print_name: name_at(20)?,
+            relative: flags & SYMLINK_FLAG_RELATIVE != 0,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::error::{SMB2_ERROR_ID_DEFAULT, SMB2_ERROR_ID_SHARE_REDIRECT, SMBErrorContext, SMBErrorResponse, SMBSymbolicLinkErrorResponse};
+
+    #[test]
+    fn empty_error_response_is_nine_bytes() {
+        let response = SMBErrorResponse::new(vec![]);
+        let bytes = response.smb_to_bytes();
+        assert_eq!(bytes, vec![9, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(response.smb_byte_size(), bytes.len());
+        let (remaining, parsed) = SMBErrorResponse::smb_from_bytes(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, response);
+    }
+
+    #[test]
+    fn symlink_error_round_trips_through_the_error_response() {
+        let link = SMBSymbolicLinkErrorResponse::new(12, "../target/dir", true);
+        let response = SMBErrorResponse::new(link.smb_to_bytes());
+        let bytes = response.smb_to_bytes();
+        assert_eq!(&bytes[4..8], &(link.smb_byte_size() as u32).to_le_bytes());
+
+        let (_, parsed) = SMBErrorResponse::smb_from_bytes(&bytes).unwrap();
+        let (remaining, parsed_link) = SMBSymbolicLinkErrorResponse::smb_from_bytes(parsed.error_data()).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed_link, link);
+        assert_eq!(parsed_link.substitute_name(), "..\\target\\dir");
+        assert_eq!(parsed_link.unparsed_path_length(), 12);
+        assert!(parsed_link.relative());
+
+        let data = parsed.error_data();
+        assert_eq!(&data[0..4], &((data.len() - 4) as u32).to_le_bytes());
+        assert_eq!(&data[4..8], b"SYML");
+        assert_eq!(&data[8..12], &0xA000000C_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn single_error_context_is_counted_without_padding() {
+        let response = SMBErrorResponse::with_contexts(vec![SMBErrorContext::new(SMB2_ERROR_ID_DEFAULT, vec![1, 2, 3])]);
+        let bytes = response.smb_to_bytes();
+        assert_eq!(bytes[2], 1);
+        assert_eq!(&bytes[4..8], &11_u32.to_le_bytes());
+        assert_eq!(&bytes[8..], &[3, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(response.smb_byte_size(), bytes.len());
+
+        let (remaining, parsed) = SMBErrorResponse::smb_from_bytes(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, response);
+        assert!(parsed.error_data().is_empty());
+    }
+
+    #[test]
+    fn chained_error_contexts_start_8_byte_aligned() {
+        let first = SMBErrorContext::new(SMB2_ERROR_ID_DEFAULT, vec![0xAA; 5]);
+        let second = SMBErrorContext::new(SMB2_ERROR_ID_SHARE_REDIRECT, vec![0xBB; 4]);
+        let response = SMBErrorResponse::with_contexts(vec![first.clone(), second.clone()]);
+        let bytes = response.smb_to_bytes();
+        // 13 bytes for the first context padded to 16, then 12 for the second
+        assert_eq!(bytes[2], 2);
+        assert_eq!(&bytes[4..8], &28_u32.to_le_bytes());
+        assert_eq!(&bytes[(8 + 13)..(8 + 16)], &[0, 0, 0]);
+        assert_eq!(&bytes[(8 + 16)..(8 + 20)], &4_u32.to_le_bytes());
+        assert_eq!(&bytes[(8 + 20)..(8 + 24)], &SMB2_ERROR_ID_SHARE_REDIRECT.to_le_bytes());
+        assert_eq!(response.smb_byte_size(), bytes.len());
+
+        let (remaining, parsed) = SMBErrorResponse::smb_from_bytes(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed.error_contexts(), &[first, second]);
+    }
+
+    #[test]
+    fn only_3_1_1_wraps_error_data_in_a_context() {
+        let data = vec![1, 2, 3, 4];
+        let wrapped = SMBErrorResponse::for_dialect(SMBDialect::V3_1_1, data.clone());
+        assert_eq!(wrapped.error_contexts(), &[SMBErrorContext::new(SMB2_ERROR_ID_DEFAULT, data.clone())]);
+        let raw = SMBErrorResponse::for_dialect(SMBDialect::V3_0_2, data.clone());
+        assert!(raw.error_contexts().is_empty());
+        assert_eq!(raw.error_data(), &data[..]);
+        assert_eq!(SMBErrorResponse::for_dialect(SMBDialect::V3_1_1, vec![]).smb_to_bytes().len(), 9);
+    }
+}
@@ -1,12 +1,13 @@
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::byte_helper::{bytes_to_u32, bytes_to_u64, u32_to_bytes, u64_to_bytes};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBToBytes, SMBByteSize, Default)]
+#[derive(PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBToBytes, SMBByteSize, Default)]
 pub struct FileTime {
     #[smb_direct(start(fixed = 0))]
     low_date_time: u32,
@@ -15,6 +16,9 @@ pub struct FileTime {
 }
 
 const TIME_SINCE_1601_AND_EPOCH: u64 = 11644473600000;
+// FILETIMEs count 100ns intervals from the start of 1601
+const TICKS_PER_SECOND: u64 = 10_000_000;
+const SECONDS_FROM_1601_TO_EPOCH: i64 = 11_644_473_600;
 
 impl FileTime {
     pub fn from_unix(unix_timestamp: u64) -> Self {
@@ -43,9 +47,117 @@ impl FileTime {
         bytes_to_u64(&bytes)
     }
 
+    pub fn from_ticks(ticks: u64) -> Self {
+        Self {
+            low_date_time: ticks as u32,
+            high_date_time: (ticks >> 32) as u32,
+        }
+    }
+
+    pub fn ticks(&self) -> u64 {
+        ((self.high_date_time as u64) << 32) | self.low_date_time as u64
+    }
+
+    // RFC 3339 in UTC, with all seven digits of the 100ns precision so nothing is lost
+    pub fn to_rfc3339(&self) -> String {
+        let ticks = self.ticks();
+        let seconds = (ticks / TICKS_PER_SECOND) as i64 - SECONDS_FROM_1601_TO_EPOCH;
+        let (days, time) = (seconds.div_euclid(86400), seconds.rem_euclid(86400));
+        let (year, month, day) = civil_from_days(days);
+        format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:07}Z", year, month, day,
+                time / 3600, time % 3600 / 60, time % 60, ticks % TICKS_PER_SECOND)
+    }
+
+    pub fn from_rfc3339(timestamp: &str) -> Option<Self> {
+        let timestamp = timestamp.strip_suffix('Z')?;
+        let (date, time) = timestamp.split_once('T')?;
+        let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+        let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+        let (time, fraction) = time.split_once('.').unwrap_or((time, "0"));
+        let mut time = time.splitn(3, ':').map(|part| part.parse::<u8>().map(i64::from));
+        let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59
+            || fraction.is_empty() || fraction.len() > 7 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let sub_second = format!("{:0<7}", fraction).parse::<u64>().ok()?;
+        let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second + SECONDS_FROM_1601_TO_EPOCH;
+        let ticks = u64::try_from(seconds).ok()?.checked_mul(TICKS_PER_SECOND)?.checked_add(sub_second)?;
+        Some(Self::from_ticks(ticks))
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let low_bytes = u32_to_bytes(self.low_date_time);
         let high_bytes = u32_to_bytes(self.high_date_time);
         [low_bytes, high_bytes].concat()
     }
-}
\ No newline at end of file
+}
+
+// Dumped as a readable timestamp rather than the two raw halves, the SMB byte layout is unaffected
+impl Serialize for FileTime {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_rfc3339())
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTime {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let timestamp = String::deserialize(deserializer)?;
+        Self::from_rfc3339(&timestamp)
+            .ok_or_else(|| D::Error::custom(format!("invalid RFC 3339 timestamp {}", timestamp)))
+    }
+}
+
+// Days since 1970-01-01 to a proleptic Gregorian date, from Howard Hinnant's chrono-compatible algorithms
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::filetime::FileTime;
+
+    #[test]
+    fn filetimes_format_as_rfc3339() {
+        assert_eq!(FileTime::zero().to_rfc3339(), "1601-01-01T00:00:00.0000000Z");
+        // 2023-11-14T22:13:20Z is 1_700_000_000 seconds after the Unix epoch
+        let ticks = (1_700_000_000 + 11_644_473_600) * 10_000_000 + 1234567;
+        assert_eq!(FileTime::from_ticks(ticks).to_rfc3339(), "2023-11-14T22:13:20.1234567Z");
+        assert_eq!(FileTime::from_ticks(ticks).ticks(), ticks);
+        // Leap day handling
+        let leap = (951_782_400 + 11_644_473_600) * 10_000_000;
+        assert_eq!(FileTime::from_ticks(leap).to_rfc3339(), "2000-02-29T00:00:00.0000000Z");
+    }
+
+    #[test]
+    fn rfc3339_parses_back_to_the_same_ticks() {
+        for ticks in [0, 1, 133_444_737_001_234_567, 0x7FFF_FFFF_FFFF_FFFF] {
+            let time = FileTime::from_ticks(ticks);
+            assert_eq!(FileTime::from_rfc3339(&time.to_rfc3339()), Some(time));
+        }
+        assert_eq!(FileTime::from_rfc3339("2023-11-14T22:13:20Z"), Some(FileTime::from_ticks(133_444_736_000_000_000)));
+        assert_eq!(FileTime::from_rfc3339("2023-11-14T22:13:20.5Z"), Some(FileTime::from_ticks(133_444_736_005_000_000)));
+        for invalid in ["2023-11-14 22:13:20Z", "2023-13-14T22:13:20Z", "2023-11-14T22:13:20", "2023-11-14T22:13:20.12345678Z", "1600-12-31T23:59:59Z"] {
+            assert_eq!(FileTime::from_rfc3339(invalid), None, "{}", invalid);
+        }
+    }
+}
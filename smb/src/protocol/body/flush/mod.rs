@@ -2,12 +2,17 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::empty::SMBEmpty;
+use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::server::share::ResourceHandle;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 24)]
 pub struct SMBFlushRequest {
     #[smb_skip(start = 2, length = 2)]
@@ -18,4 +23,64 @@ pub struct SMBFlushRequest {
     file_id: SMBFileId,
 }
 
-pub type SMBFlushResponse = SMBEmpty;
\ No newline at end of file
+impl SMBFlushRequest {
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    // MS-SMB2 3.3.5.11: only an open that can write has anything to flush
+    pub fn flush<H: ResourceHandle + ?Sized>(&self, handle: &H, granted_access: &SMBAccessMask) -> SMBResult<()> {
+        if !granted_access.includes_write_data() {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
+        handle.sync()
+    }
+}
+
+pub type SMBFlushResponse = SMBEmpty;
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::flush::SMBFlushRequest;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::protocol::body::write::flags::SMBWriteFlags;
+    use crate::protocol::body::write::tests::write_request;
+    use crate::server::share::recording::RecordingHandle;
+
+    fn flush_request() -> SMBFlushRequest {
+        SMBFlushRequest {
+            reserved_1: PhantomData,
+            reserved_2: PhantomData,
+            file_id: SMBFileId { persistent: 0, volatile: 0 },
+        }
+    }
+
+    #[test]
+    fn flush_syncs_written_handle() {
+        let handle = RecordingHandle::default();
+        let access = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        write_request(SMBWriteFlags::empty()).write_to(&handle, &access).unwrap();
+        assert_eq!(handle.syncs(), 0);
+        flush_request().flush(&handle, &access).unwrap();
+        assert_eq!(handle.writes().len(), 1);
+        assert_eq!(handle.syncs(), 1);
+    }
+
+    #[test]
+    fn flush_needs_write_or_append_access() {
+        let handle = RecordingHandle::default();
+        let read_only = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let denied = flush_request().flush(&handle, &read_only);
+        assert!(matches!(denied, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+        assert_eq!(handle.syncs(), 0);
+        let append = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_APPEND_DATA);
+        flush_request().flush(&handle, &append).unwrap();
+        assert_eq!(handle.syncs(), 1);
+    }
+}
@@ -0,0 +1,178 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use smb_core::error::SMBError;
+use smb_core::fixed_bytes::FixedBytes;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+use crate::server::share::ResourceHandle;
+use crate::server::Server;
+
+pub const RESUME_KEY_SIZE: usize = 24;
+
+// Opaque to the client, it only ever hands the key back as a copychunk source
+pub type SMBResumeKey = FixedBytes<RESUME_KEY_SIZE>;
+
+// SRV_REQUEST_RESUME_KEY response, MS-SMB2 2.2.32.3. The context is never used but clients expect its 4 bytes
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBSrvRequestResumeKeyResponse {
+    #[smb_direct(start(fixed = 0))]
+    resume_key: SMBResumeKey,
+    #[smb_direct(start(fixed = 24))]
+    context_length: u32,
+    #[smb_skip(start = 28, length = 4)]
+    context: PhantomData<Vec<u8>>,
+}
+
+impl SMBSrvRequestResumeKeyResponse {
+    pub fn new(resume_key: SMBResumeKey) -> Self {
+        Self {
+            resume_key,
+            context_length: 0,
+            context: PhantomData,
+        }
+    }
+
+    pub fn resume_key(&self) -> &SMBResumeKey {
+        &self.resume_key
+    }
+}
+
+// SRV_COPYCHUNK, MS-SMB2 2.2.31.1.1
+#[derive(Debug, PartialEq, Eq, Clone, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBSrvCopyChunk {
+    #[smb_direct(start(fixed = 0))]
+    source_offset: u64,
+    #[smb_direct(start(fixed = 8))]
+    target_offset: u64,
+    #[smb_direct(start(fixed = 16))]
+    length: u32,
+    #[smb_skip(start = 20, length = 4)]
+    reserved: PhantomData<Vec<u8>>,
+}
+
+impl SMBSrvCopyChunk {
+    pub fn new(source_offset: u64, target_offset: u64, length: u32) -> Self {
+        Self {
+            source_offset,
+            target_offset,
+            length,
+            reserved: PhantomData,
+        }
+    }
+
+    // A source that ends inside the range can't fill the chunk, so nothing of it is written
+    fn copy<H: ResourceHandle + ?Sized>(&self, source: &H, target: &H) -> SMBResult<u32> {
+        let data = source.read_at(self.source_offset, self.length)?;
+        if data.len() < self.length as usize {
+            return Err(SMBError::response_error(NTStatus::EndOfFile));
+        }
+        target.write_at(self.target_offset, &data)
+    }
+}
+
+// SRV_COPYCHUNK_COPY, MS-SMB2 2.2.31.1
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBSrvCopyChunkCopy {
+    #[smb_direct(start(fixed = 0))]
+    source_key: SMBResumeKey,
+    #[smb_skip(start = 28, length = 4)]
+    reserved: PhantomData<Vec<u8>>,
+    #[smb_vector(order = 1, count(inner(start = 24, num_type = "u32")))]
+    chunks: Vec<SMBSrvCopyChunk>,
+}
+
+impl SMBSrvCopyChunkCopy {
+    pub fn new(source_key: SMBResumeKey, chunks: Vec<SMBSrvCopyChunk>) -> Self {
+        Self {
+            source_key,
+            reserved: PhantomData,
+            chunks,
+        }
+    }
+
+    pub fn source_key(&self) -> &SMBResumeKey {
+        &self.source_key
+    }
+
+    // MS-SMB2 3.3.5.15.6.2, a request over the limits copies nothing and answers with the limits themselves.
+    // Otherwise chunks go in order until one fails, the response counting what made it
+    pub fn copy<H: ResourceHandle + ?Sized>(&self, source: &H, target: &H, limits: &SMBCopyChunkLimits) -> SMBResult<(NTStatus, SMBSrvCopyChunkResponse)> {
+        if !limits.allow(&self.chunks) {
+            return Ok((NTStatus::InvalidParameter, limits.as_response()));
+        }
+        let mut response = SMBSrvCopyChunkResponse::default();
+        for chunk in self.chunks.iter() {
+            match chunk.copy(source, target) {
+                Ok(written) => {
+                    response.chunks_written += 1;
+                    response.total_bytes_written += written;
+                },
+                Err(SMBError::ResponseError(e)) => return Ok((e.status(), response)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok((NTStatus::StatusSuccess, response))
+    }
+}
+
+// SRV_COPYCHUNK_RESPONSE, MS-SMB2 2.2.32.1
+#[derive(Debug, Clone, PartialEq, Eq, Default, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBSrvCopyChunkResponse {
+    #[smb_direct(start(fixed = 0))]
+    chunks_written: u32,
+    #[smb_direct(start(fixed = 4))]
+    chunk_bytes_written: u32,
+    #[smb_direct(start(fixed = 8))]
+    total_bytes_written: u32,
+}
+
+impl SMBSrvCopyChunkResponse {
+    pub fn chunks_written(&self) -> u32 {
+        self.chunks_written
+    }
+
+    pub fn chunk_bytes_written(&self) -> u32 {
+        self.chunk_bytes_written
+    }
+
+    pub fn total_bytes_written(&self) -> u32 {
+        self.total_bytes_written
+    }
+}
+
+// The ServerSideCopyMax* values the server advertises, MS-SMB2 3.3.1.5
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SMBCopyChunkLimits {
+    pub max_chunks: u64,
+    pub max_chunk_size: u64,
+    pub max_data_size: u64,
+}
+
+impl SMBCopyChunkLimits {
+    pub fn for_server<S: Server>(server: &S) -> Self {
+        Self {
+            max_chunks: server.copy_max_chunks(),
+            max_chunk_size: server.copy_max_chunk_size(),
+            max_data_size: server.copy_max_data_size(),
+        }
+    }
+
+    fn allow(&self, chunks: &[SMBSrvCopyChunk]) -> bool {
+        let total: u64 = chunks.iter().map(|chunk| chunk.length as u64).sum();
+        chunks.len() as u64 <= self.max_chunks
+            && chunks.iter().all(|chunk| chunk.length as u64 <= self.max_chunk_size)
+            && total <= self.max_data_size
+    }
+
+    fn as_response(&self) -> SMBSrvCopyChunkResponse {
+        SMBSrvCopyChunkResponse {
+            chunks_written: self.max_chunks as u32,
+            chunk_bytes_written: self.max_chunk_size as u32,
+            total_bytes_written: self.max_data_size as u32,
+        }
+    }
+}
@@ -1,98 +1,135 @@
 use serde::{Deserialize, Serialize};
 
-use smb_derive::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBToBytes};
+use smb_core::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBParseResult, SMBToBytes};
 
-#[derive(SMBEnumFromBytes, Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes)]
+use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
+use crate::protocol::body::ioctl::reparse_point::SMBReparseDataBuffer;
+
+// An IOCTL's input typed by its CtlCode. Codes without a structure here keep their raw input so they can still
+// be answered, or refused, by code
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
 pub enum SMBIoCtlMethod {
-    #[smb_discriminator(value = 0x00060194)]
-    #[smb_direct(start(fixed = 0))]
-    DfsGetReferrals(DfsGetReferrals),
-    #[smb_discriminator(value = 0x011400C)]
-    #[smb_direct(start(fixed = 0))]
-    PipePeek(PipePeek),
-    #[smb_discriminator(value = 0x0110018)]
-    #[smb_direct(start(fixed = 0))]
-    PipeWait(PipeWait),
-    #[smb_discriminator(value = 0x011C017)]
-    #[smb_direct(start(fixed = 0))]
-    PipeTransceive(PipeTransceive),
-    #[smb_discriminator(value = 0x001440F2)]
-    #[smb_direct(start(fixed = 0))]
-    SrvCopyChunk(SrvCopyChunk),
-    #[smb_discriminator(value = 0x00140198)]
-    #[smb_direct(start(fixed = 0))]
-    SrvEnumerateSnapshots(SrvEnumerateSnapshots),
-    #[smb_discriminator(value = 0x00140194)]
-    #[smb_direct(start(fixed = 0))]
-    SrvRequestResumeKey(SrvRequestResumeKey),
-    #[smb_discriminator(value = 0x00140190)]
-    #[smb_direct(start(fixed = 0))]
-    SrvReadHash(SrvReadHash),
-    #[smb_discriminator(value = 0x001480F2)]
-    #[smb_direct(start(fixed = 0))]
-    SrvCopyChunkWrite(SrvCopyChunkWrite),
-    #[smb_discriminator(value = 0x001401D4)]
-    #[smb_direct(start(fixed = 0))]
-    LmrRequestResiliency(LmrRequestResiliency),
-    #[smb_discriminator(value = 0x001401FC)]
-    #[smb_direct(start(fixed = 0))]
-    NetworkInterfaceInfo(NetworkInterfaceInfo),
-    #[smb_discriminator(value = 0x000900A4)]
-    #[smb_direct(start(fixed = 0))]
-    SetReparsePoint(SetReparsePoint),
-    #[smb_discriminator(value = 0x000601B0)]
-    #[smb_direct(start(fixed = 0))]
-    DfsGetReferralsEx(DfsGetReferralsEx),
-    #[smb_discriminator(value = 0x00098208)]
-    #[smb_direct(start(fixed = 0))]
-    FileLevelTrim(FileLevelTrip),
-    #[smb_discriminator(value = 0x00140204)]
-    #[smb_direct(start(fixed = 0))]
-    ValidateNegotiateInfo(ValidateNegotiateInfo),
+    // Takes no input, the interfaces come back in the output
+    QueryNetworkInterfaceInfo,
+    // The bytes written to the pipe, usually a DCE/RPC request
+    PipeTransceive(Vec<u8>),
+    GetReparsePoint,
+    SetReparsePoint(SMBReparseDataBuffer),
+    Unknown(u32, Vec<u8>),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct DfsGetReferrals {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct PipePeek {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct PipeWait {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct PipeTransceive {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SrvCopyChunk {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SrvEnumerateSnapshots {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SrvRequestResumeKey {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SrvReadHash {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SrvCopyChunkWrite {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct LmrRequestResiliency {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct NetworkInterfaceInfo {}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct SetReparsePoint {}
+impl SMBIoCtlMethod {
+    pub fn ctl_code(&self) -> u32 {
+        match self {
+            SMBIoCtlMethod::QueryNetworkInterfaceInfo => SMBIoCtlCode::QueryNetworkInterfaceInfo as u32,
+            SMBIoCtlMethod::PipeTransceive(_) => SMBIoCtlCode::PipeTransceive as u32,
+            SMBIoCtlMethod::GetReparsePoint => SMBIoCtlCode::GetReparsePoint as u32,
+            SMBIoCtlMethod::SetReparsePoint(_) => SMBIoCtlCode::SetReparsePoint as u32,
+            SMBIoCtlMethod::Unknown(ctl_code, _) => *ctl_code,
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct DfsGetReferralsEx {}
+impl SMBEnumFromBytes for SMBIoCtlMethod {
+    fn smb_enum_from_bytes(input: &[u8], discriminator: u64) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let ctl_code = discriminator as u32;
+        let rest = &input[input.len()..];
+        match SMBIoCtlCode::try_from(ctl_code) {
+            Ok(SMBIoCtlCode::QueryNetworkInterfaceInfo) => Ok((input, SMBIoCtlMethod::QueryNetworkInterfaceInfo)),
+            Ok(SMBIoCtlCode::PipeTransceive) => Ok((rest, SMBIoCtlMethod::PipeTransceive(input.to_vec()))),
+            Ok(SMBIoCtlCode::GetReparsePoint) => Ok((input, SMBIoCtlMethod::GetReparsePoint)),
+            Ok(SMBIoCtlCode::SetReparsePoint) => SMBReparseDataBuffer::smb_from_bytes(input)
+                .map(|(remaining, buffer)| (remaining, SMBIoCtlMethod::SetReparsePoint(buffer))),
+            _ => Ok((rest, SMBIoCtlMethod::Unknown(ctl_code, input.to_vec()))),
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct FileLevelTrip {}
+impl SMBByteSize for SMBIoCtlMethod {
+    fn smb_byte_size(&self) -> usize {
+        match self {
+            SMBIoCtlMethod::QueryNetworkInterfaceInfo | SMBIoCtlMethod::GetReparsePoint => 0,
+            SMBIoCtlMethod::PipeTransceive(data) | SMBIoCtlMethod::Unknown(_, data) => data.len(),
+            SMBIoCtlMethod::SetReparsePoint(buffer) => buffer.smb_byte_size(),
+        }
+    }
+}
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, SMBByteSize, SMBToBytes, SMBFromBytes)]
-pub struct ValidateNegotiateInfo {}
+impl SMBToBytes for SMBIoCtlMethod {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        match self {
+            SMBIoCtlMethod::QueryNetworkInterfaceInfo | SMBIoCtlMethod::GetReparsePoint => Vec::new(),
+            SMBIoCtlMethod::PipeTransceive(data) | SMBIoCtlMethod::Unknown(_, data) => data.clone(),
+            SMBIoCtlMethod::SetReparsePoint(buffer) => buffer.smb_to_bytes(),
+        }
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use smb_core::{SMBEnumFromBytes, SMBToBytes};
+
+    use crate::protocol::body::create::file_attributes::IO_REPARSE_TAG_SYMLINK;
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
+    use crate::protocol::body::ioctl::method::SMBIoCtlMethod;
+    use crate::protocol::body::ioctl::network_interface::{SMBInterfaceCapabilities, SMBNetworkInterfaceInfo};
+    use crate::protocol::body::ioctl::reparse_point::SMBReparseDataBuffer;
+    use crate::protocol::body::ioctl::SMBIoCtlRequest;
+
+    fn method_of(ctl_code: SMBIoCtlCode, input: Vec<u8>) -> SMBIoCtlMethod {
+        let request = SMBIoCtlRequest::new(ctl_code, SMBFileId { persistent: u64::MAX, volatile: u64::MAX }, input, 1024);
+        request.method().unwrap()
+    }
+
+    #[test]
+    fn inputless_codes_parse_to_their_variants() {
+        assert_eq!(method_of(SMBIoCtlCode::QueryNetworkInterfaceInfo, vec![]), SMBIoCtlMethod::QueryNetworkInterfaceInfo);
+        assert_eq!(method_of(SMBIoCtlCode::GetReparsePoint, vec![]), SMBIoCtlMethod::GetReparsePoint);
+    }
+
+    #[test]
+    fn pipe_transceive_keeps_the_rpc_request() {
+        let rpc = vec![5, 0, 0, 3, 0x10, 0, 0, 0];
+        let method = method_of(SMBIoCtlCode::PipeTransceive, rpc.clone());
+        assert_eq!(method, SMBIoCtlMethod::PipeTransceive(rpc.clone()));
+        assert_eq!(method.smb_to_bytes(), rpc);
+    }
+
+    #[test]
+    fn set_reparse_point_reads_the_data_buffer() {
+        let input = [
+            &IO_REPARSE_TAG_SYMLINK.to_le_bytes()[..],
+            &4u16.to_le_bytes(),
+            &[0, 0],
+            &[1, 2, 3, 4],
+        ].concat();
+        let method = method_of(SMBIoCtlCode::SetReparsePoint, input.clone());
+        assert_eq!(method, SMBIoCtlMethod::SetReparsePoint(SMBReparseDataBuffer::new(IO_REPARSE_TAG_SYMLINK, vec![1, 2, 3, 4])));
+        assert_eq!(method.smb_to_bytes(), input);
+    }
+
+    #[test]
+    fn other_codes_stay_raw() {
+        let method = SMBIoCtlMethod::smb_enum_from_bytes(&[1, 2, 3], 0x00090000).unwrap().1;
+        assert_eq!(method, SMBIoCtlMethod::Unknown(0x00090000, vec![1, 2, 3]));
+        assert_eq!(method.ctl_code(), 0x00090000);
+    }
+
+    #[test]
+    fn network_interfaces_chain_by_next_offset() {
+        let interfaces = vec![
+            SMBNetworkInterfaceInfo::new(1, SMBInterfaceCapabilities::RSS_CAPABLE, 10_000_000_000, "192.168.1.10:445".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(2, SMBInterfaceCapabilities::empty(), 1_000_000_000, "[fe80::1]:445".parse::<SocketAddr>().unwrap()),
+        ];
+        let bytes = SMBNetworkInterfaceInfo::encode_list(&interfaces);
+        assert_eq!(bytes.len(), 304);
+        assert_eq!(&bytes[0..4], &152u32.to_le_bytes());
+        // AF_INET then the port in network order
+        assert_eq!(&bytes[24..30], &[2, 0, 0x01, 0xBD, 192, 168]);
+        assert_eq!(&bytes[152..156], &[0; 4]);
+        assert_eq!(SMBNetworkInterfaceInfo::decode_list(&bytes).unwrap(), interfaces);
+    }
+}
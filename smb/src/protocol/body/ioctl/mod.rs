@@ -2,16 +2,26 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBResult, SMBToBytes};
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::ioctl::copy_chunk::{SMBResumeKey, SMBSrvCopyChunkCopy, SMBSrvCopyChunkResponse, SMBSrvRequestResumeKeyResponse};
+use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
 use crate::protocol::body::ioctl::flags::SMBIoCtlRequestFlags;
 use crate::protocol::body::ioctl::method::SMBIoCtlMethod;
+use crate::protocol::body::ioctl::network_interface::SMBNetworkInterfaceInfo;
 
+pub mod copy_chunk;
+pub mod ctl_code;
 mod flags;
-mod method;
+pub mod method;
+pub mod network_interface;
+pub mod reparse_point;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 57)]
 pub struct SMBIoCtlRequest {
     #[smb_skip(start = 2, length = 2)]
@@ -28,11 +38,82 @@ pub struct SMBIoCtlRequest {
     flags: SMBIoCtlRequestFlags,
     #[smb_skip(start = 52, length = 4)]
     reserved2: PhantomData<Vec<u8>>,
-    #[smb_enum(start(inner(start = 24, num_type = "u32")), discriminator(inner(start = 4, num_type = "u32")))]
-    input_method: SMBIoCtlMethod,
+    #[smb_buffer(order = 0, offset(inner(start = 24, num_type = "u32", subtract = 64)), length(inner(start = 28, num_type = "u32")))]
+    input_buffer: Vec<u8>,
+    #[smb_buffer(order = 1, offset(inner(start = 36, num_type = "u32", subtract = 64)), length(inner(start = 40, num_type = "u32")))]
+    output_buffer: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+impl SMBIoCtlRequest {
+    pub fn new(ctl_code: SMBIoCtlCode, file_id: SMBFileId, input: Vec<u8>, max_output_response: u32) -> Self {
+        Self {
+            reserved: PhantomData,
+            ctl_code: ctl_code as u32,
+            file_id,
+            max_input_response: 0,
+            max_output_response,
+            flags: SMBIoCtlRequestFlags::FSCTL,
+            reserved2: PhantomData,
+            input_buffer: input,
+            output_buffer: Vec::new(),
+        }
+    }
+
+    // Only the codes we know about, anything else is answered with STATUS_NOT_SUPPORTED
+    pub fn ctl_code(&self) -> SMBResult<SMBIoCtlCode> {
+        SMBIoCtlCode::try_from(self.ctl_code)
+            .map_err(|_| SMBError::response_error(NTStatus::NotSupported))
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn input(&self) -> &[u8] {
+        &self.input_buffer
+    }
+
+    pub fn method(&self) -> SMBResult<SMBIoCtlMethod> {
+        SMBIoCtlMethod::smb_enum_from_bytes(&self.input_buffer, self.ctl_code as u64)
+            .map(|(_, method)| method)
+    }
+
+    // A client that left no room for the reply gets nothing, each FSCTL says which status tells it so
+    fn check_output_room(&self, required: usize, too_small: NTStatus) -> SMBResult<()> {
+        if self.flags != SMBIoCtlRequestFlags::FSCTL {
+            return Err(SMBError::response_error(NTStatus::NotSupported));
+        }
+        match (self.max_output_response as usize) < required {
+            true => Err(SMBError::response_error(too_small)),
+            false => Ok(()),
+        }
+    }
+
+    // FSCTL_SRV_REQUEST_RESUME_KEY, MS-SMB2 3.3.5.15.5
+    pub fn resume_key_response(&self, resume_key: SMBResumeKey) -> SMBResult<SMBIoCtlResponse> {
+        let output = SMBSrvRequestResumeKeyResponse::new(resume_key);
+        self.check_output_room(output.smb_byte_size(), NTStatus::InvalidParameter)?;
+        Ok(SMBIoCtlResponse::new(self, output.smb_to_bytes()))
+    }
+
+    // FSCTL_SRV_COPYCHUNK and FSCTL_SRV_COPYCHUNK_WRITE, MS-SMB2 3.3.5.15.6
+    pub fn copy_chunk_request(&self) -> SMBResult<SMBSrvCopyChunkCopy> {
+        self.check_output_room(SMBSrvCopyChunkResponse::default().smb_byte_size(), NTStatus::InvalidParameter)?;
+        SMBSrvCopyChunkCopy::smb_from_bytes(&self.input_buffer)
+            .map(|(_, copy)| copy)
+            .map_err(|_| SMBError::response_error(NTStatus::InvalidParameter))
+    }
+
+    // FSCTL_QUERY_NETWORK_INTERFACE_INFO, MS-SMB2 3.3.5.15.11. The list can't be cut short, so a client
+    // that left too little room is told so rather than given part of it
+    pub fn network_interfaces_response(&self, interfaces: &[SMBNetworkInterfaceInfo]) -> SMBResult<SMBIoCtlResponse> {
+        let output = SMBNetworkInterfaceInfo::encode_list(interfaces);
+        self.check_output_room(output.len(), NTStatus::BufferTooSmall)?;
+        Ok(SMBIoCtlResponse::new(self, output))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 49)]
 pub struct SMBIoCtlResponse {
     #[smb_skip(start = 2, length = 2)]
@@ -45,6 +126,152 @@ pub struct SMBIoCtlResponse {
     flags: PhantomData<Vec<u8>>,
     #[smb_skip(start = 44, length = 4)]
     reserved2: PhantomData<Vec<u8>>,
-    #[smb_enum(start(inner(start = 30, num_type = "u32")), discriminator(inner(start = 4, num_type = "u32")))]
-    input_method: SMBIoCtlMethod,
-}
\ No newline at end of file
+    #[smb_buffer(order = 0, offset(inner(start = 24, num_type = "u32", subtract = 64)), length(inner(start = 28, num_type = "u32")))]
+    input_buffer: Vec<u8>,
+    #[smb_buffer(order = 1, offset(inner(start = 32, num_type = "u32", subtract = 64)), length(inner(start = 36, num_type = "u32")))]
+    output_buffer: Vec<u8>,
+}
+
+impl SMBIoCtlResponse {
+    // None of the FSCTLs we answer echo their input back
+    pub fn new(request: &SMBIoCtlRequest, output: Vec<u8>) -> Self {
+        Self {
+            reserved: PhantomData,
+            ctl_code: request.ctl_code,
+            file_id: request.file_id.clone(),
+            flags: PhantomData,
+            reserved2: PhantomData,
+            input_buffer: Vec::new(),
+            output_buffer: output,
+        }
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output_buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::{SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::ioctl::copy_chunk::{SMBCopyChunkLimits, SMBResumeKey, SMBSrvCopyChunk, SMBSrvCopyChunkCopy, SMBSrvCopyChunkResponse, SMBSrvRequestResumeKeyResponse};
+    use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
+    use crate::protocol::body::ioctl::network_interface::{SMBInterfaceCapabilities, SMBNetworkInterfaceInfo};
+    use crate::protocol::body::ioctl::{SMBIoCtlRequest, SMBIoCtlResponse};
+    use crate::server::share::recording::RecordingHandle;
+    use crate::server::share::ResourceHandle;
+
+    const LIMITS: SMBCopyChunkLimits = SMBCopyChunkLimits { max_chunks: 16, max_chunk_size: 1024, max_data_size: 4096 };
+
+    fn file_id(volatile: u64) -> SMBFileId {
+        SMBFileId { persistent: 0, volatile }
+    }
+
+    // Every request and reply goes through its wire form, the way it would between client and server
+    fn over_the_wire(request: SMBIoCtlRequest) -> SMBIoCtlRequest {
+        SMBIoCtlRequest::smb_from_bytes(&request.smb_to_bytes()).unwrap().1
+    }
+
+    fn output_of(response: SMBIoCtlResponse) -> Vec<u8> {
+        SMBIoCtlResponse::smb_from_bytes(&response.smb_to_bytes()).unwrap().1.output_buffer
+    }
+
+    fn copy_request(source_key: SMBResumeKey, chunks: Vec<SMBSrvCopyChunk>) -> SMBIoCtlRequest {
+        let copy = SMBSrvCopyChunkCopy::new(source_key, chunks);
+        over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::SrvCopyChunk, file_id(2), copy.smb_to_bytes(), 12))
+    }
+
+    #[test]
+    fn resume_key_then_copychunk_copies_within_the_share() {
+        let source = RecordingHandle::default();
+        source.write_at(0, b"hello, server-side copy").unwrap();
+        let target = RecordingHandle::default();
+
+        let key_request = over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::SrvRequestResumeKey, file_id(1), Vec::new(), 32));
+        assert_eq!(key_request.ctl_code().unwrap(), SMBIoCtlCode::SrvRequestResumeKey);
+        let key_output = output_of(key_request.resume_key_response([7; 24].into()).unwrap());
+        assert_eq!(key_output.len(), 32);
+        let (_, key) = SMBSrvRequestResumeKeyResponse::smb_from_bytes(&key_output).unwrap();
+
+        let request = copy_request(*key.resume_key(), vec![SMBSrvCopyChunk::new(7, 0, 6), SMBSrvCopyChunk::new(0, 6, 5)]);
+        let copy = request.copy_chunk_request().unwrap();
+        assert_eq!(copy.source_key().as_bytes(), &[7; 24]);
+        let (status, copied) = copy.copy(&source, &target, &LIMITS).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+        assert_eq!(target.contents(), b"serverhello".to_vec());
+
+        let output = output_of(SMBIoCtlResponse::new(&request, copied.smb_to_bytes()));
+        let (_, copied) = SMBSrvCopyChunkResponse::smb_from_bytes(&output).unwrap();
+        assert_eq!((copied.chunks_written(), copied.chunk_bytes_written(), copied.total_bytes_written()), (2, 0, 11));
+    }
+
+    #[test]
+    fn copies_over_the_limits_answer_with_the_limits() {
+        let source = RecordingHandle::default();
+        source.write_at(0, &[1; 2048]).unwrap();
+        let target = RecordingHandle::default();
+
+        for chunks in [vec![SMBSrvCopyChunk::new(0, 0, 2048)], vec![SMBSrvCopyChunk::new(0, 0, 1); 17], vec![SMBSrvCopyChunk::new(0, 0, 1024); 5]] {
+            let copy = copy_request(SMBResumeKey::default(), chunks).copy_chunk_request().unwrap();
+            let (status, copied) = copy.copy(&source, &target, &LIMITS).unwrap();
+            assert_eq!(status, NTStatus::InvalidParameter);
+            assert_eq!((copied.chunks_written(), copied.chunk_bytes_written(), copied.total_bytes_written()), (16, 1024, 4096));
+        }
+        assert!(target.writes().is_empty());
+    }
+
+    #[test]
+    fn copy_stops_at_the_first_chunk_past_the_source() {
+        let source = RecordingHandle::default();
+        source.write_at(0, b"short").unwrap();
+        let target = RecordingHandle::default();
+
+        let copy = copy_request(SMBResumeKey::default(), vec![SMBSrvCopyChunk::new(0, 0, 5), SMBSrvCopyChunk::new(3, 5, 4), SMBSrvCopyChunk::new(0, 9, 1)])
+            .copy_chunk_request().unwrap();
+        let (status, copied) = copy.copy(&source, &target, &LIMITS).unwrap();
+        assert_eq!(status, NTStatus::EndOfFile);
+        assert_eq!((copied.chunks_written(), copied.total_bytes_written()), (1, 5));
+        assert_eq!(target.contents(), b"short".to_vec());
+    }
+
+    #[test]
+    fn requests_without_room_for_the_reply_are_refused() {
+        let request = over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::SrvRequestResumeKey, file_id(1), Vec::new(), 24));
+        let result = request.resume_key_response(SMBResumeKey::default());
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+        let truncated = over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::SrvCopyChunk, file_id(1), vec![0; 16], 12));
+        let result = truncated.copy_chunk_request();
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+    }
+
+    #[test]
+    fn network_interfaces_are_chained_in_the_output() {
+        let interfaces = vec![
+            SMBNetworkInterfaceInfo::new(3, SMBInterfaceCapabilities::RSS_CAPABLE, 10_000_000_000, "10.0.0.5:0".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(3, SMBInterfaceCapabilities::RSS_CAPABLE, 10_000_000_000, "[fd00::5]:0".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(7, SMBInterfaceCapabilities::empty(), 1_000_000_000, "192.168.0.9:0".parse::<SocketAddr>().unwrap()),
+        ];
+        let all_files = SMBFileId { persistent: u64::MAX, volatile: u64::MAX };
+        let request = over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::QueryNetworkInterfaceInfo, all_files.clone(), Vec::new(), 65536));
+        let output = output_of(request.network_interfaces_response(&interfaces).unwrap());
+        assert_eq!(output.len(), 3 * 152);
+        let next_offsets: Vec<u32> = output.chunks(152).map(|entry| u32::from_le_bytes(entry[0..4].try_into().unwrap())).collect();
+        assert_eq!(next_offsets, vec![152, 152, 0]);
+        // IfIndex, Capabilities, then LinkSpeed after the reserved field
+        assert_eq!(&output[4..12], &[3, 0, 0, 0, 1, 0, 0, 0]);
+        assert_eq!(&output[16..24], &10_000_000_000u64.to_le_bytes());
+        assert_eq!(&output[152 + 24..152 + 26], &[0x17, 0]);
+        assert_eq!(&output[304 + 24..304 + 32], &[2, 0, 0, 0, 192, 168, 0, 9]);
+        assert_eq!(SMBNetworkInterfaceInfo::decode_list(&output).unwrap(), interfaces);
+
+        let cramped = over_the_wire(SMBIoCtlRequest::new(SMBIoCtlCode::QueryNetworkInterfaceInfo, all_files, Vec::new(), 304));
+        let result = cramped.network_interfaces_response(&interfaces);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::BufferTooSmall));
+    }
+}
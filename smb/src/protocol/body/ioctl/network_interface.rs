@@ -0,0 +1,173 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use smb_core::error::SMBError;
+use smb_core::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBResult, SMBToBytes};
+
+use crate::util::flags_helper::{impl_smb_byte_size_for_bitflag, impl_smb_from_bytes_for_bitflag, impl_smb_to_bytes_for_bitflag};
+
+const AF_INET: u16 = 0x2;
+const AF_INET6: u16 = 0x17;
+const SOCKADDR_STORAGE_SIZE: usize = 128;
+const FIXED_SIZE: usize = 24;
+
+bitflags! {
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+    pub struct SMBInterfaceCapabilities: u32 {
+        const RSS_CAPABLE = 0x1;
+        const RDMA_CAPABLE = 0x2;
+    }
+}
+
+impl_smb_from_bytes_for_bitflag!(SMBInterfaceCapabilities);
+impl_smb_to_bytes_for_bitflag!(SMBInterfaceCapabilities);
+impl_smb_byte_size_for_bitflag!(SMBInterfaceCapabilities);
+
+// NETWORK_INTERFACE_INFO, MS-SMB2 2.2.32.5, what FSCTL_QUERY_NETWORK_INTERFACE_INFO answers with so a
+// multichannel client knows where else it can connect
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct SMBNetworkInterfaceInfo {
+    if_index: u32,
+    capabilities: SMBInterfaceCapabilities,
+    // In bits per second
+    link_speed: u64,
+    address: SocketAddr,
+}
+
+impl SMBNetworkInterfaceInfo {
+    pub fn new(if_index: u32, capabilities: SMBInterfaceCapabilities, link_speed: u64, address: SocketAddr) -> Self {
+        Self {
+            if_index,
+            capabilities,
+            link_speed,
+            address,
+        }
+    }
+
+    pub fn if_index(&self) -> u32 {
+        self.if_index
+    }
+
+    pub fn capabilities(&self) -> SMBInterfaceCapabilities {
+        self.capabilities
+    }
+
+    pub fn link_speed(&self) -> u64 {
+        self.link_speed
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn with_capabilities(mut self, capabilities: SMBInterfaceCapabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_link_speed(mut self, link_speed: u64) -> Self {
+        self.link_speed = link_speed;
+        self
+    }
+
+    // Entries are chained by their Next field, zero on the last one
+    pub fn encode_list(interfaces: &[Self]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (idx, interface) in interfaces.iter().enumerate() {
+            let mut entry = interface.smb_to_bytes();
+            if idx + 1 < interfaces.len() {
+                let next = entry.len() as u32;
+                entry[0..4].copy_from_slice(&next.to_le_bytes());
+            }
+            bytes.extend_from_slice(&entry);
+        }
+        bytes
+    }
+
+    pub fn decode_list(input: &[u8]) -> SMBResult<Vec<Self>> {
+        let mut interfaces = Vec::new();
+        let mut position = 0;
+        loop {
+            let entry = input.get(position..)
+                .ok_or(SMBError::payload_too_small(position, input.len()))?;
+            let (_, next) = u32::smb_from_bytes(entry)?;
+            let (_, interface) = Self::smb_from_bytes(entry)?;
+            interfaces.push(interface);
+            if next == 0 {
+                return Ok(interfaces);
+            }
+            position += next as usize;
+        }
+    }
+}
+
+impl SMBByteSize for SMBNetworkInterfaceInfo {
+    fn smb_byte_size(&self) -> usize {
+        FIXED_SIZE + SOCKADDR_STORAGE_SIZE
+    }
+}
+
+impl SMBFromBytes for SMBNetworkInterfaceInfo {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let size = FIXED_SIZE + SOCKADDR_STORAGE_SIZE;
+        if input.len() < size {
+            return Err(SMBError::payload_too_small(size, input.len()));
+        }
+        let (_, if_index) = u32::smb_from_bytes(&input[4..])?;
+        let (_, capabilities) = SMBInterfaceCapabilities::smb_from_bytes(&input[8..])?;
+        let (_, link_speed) = u64::smb_from_bytes(&input[16..])?;
+        let address = parse_sockaddr(&input[FIXED_SIZE..size])?;
+        Ok((&input[size..], Self::new(if_index, capabilities, link_speed, address)))
+    }
+}
+
+impl SMBToBytes for SMBNetworkInterfaceInfo {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; self.smb_byte_size()];
+        bytes[4..8].copy_from_slice(&self.if_index.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.capabilities.bits().to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.link_speed.to_le_bytes());
+        let sockaddr = encode_sockaddr(&self.address);
+        bytes[FIXED_SIZE..(FIXED_SIZE + sockaddr.len())].copy_from_slice(&sockaddr);
+        bytes
+    }
+}
+
+// SOCKADDR_IN and SOCKADDR_IN6 as Windows lays them out, ports in network order
+fn encode_sockaddr(address: &SocketAddr) -> Vec<u8> {
+    match address {
+        SocketAddr::V4(address) => [
+            &AF_INET.to_le_bytes()[..],
+            &address.port().to_be_bytes(),
+            &address.ip().octets(),
+            &[0; 8],
+        ].concat(),
+        SocketAddr::V6(address) => [
+            &AF_INET6.to_le_bytes()[..],
+            &address.port().to_be_bytes(),
+            &address.flowinfo().to_le_bytes(),
+            &address.ip().octets(),
+            &address.scope_id().to_le_bytes(),
+        ].concat(),
+    }
+}
+
+fn parse_sockaddr(input: &[u8]) -> SMBResult<SocketAddr> {
+    let (_, family) = u16::smb_from_bytes(input)?;
+    let port = u16::from_be_bytes([input[2], input[3]]);
+    match family {
+        AF_INET => {
+            let (_, octets) = <[u8; 4]>::smb_from_bytes(&input[4..])?;
+            Ok(SocketAddr::new(IpAddr::V4(Ipv4Addr::from(octets)), port))
+        },
+        AF_INET6 => {
+            let (_, flowinfo) = u32::smb_from_bytes(&input[4..])?;
+            let (_, octets) = <[u8; 16]>::smb_from_bytes(&input[8..])?;
+            let (_, scope_id) = u32::smb_from_bytes(&input[24..])?;
+            Ok(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::from(octets), port, flowinfo, scope_id)))
+        },
+        _ => Err(SMBError::parse_error("Unknown socket address family")),
+    }
+}
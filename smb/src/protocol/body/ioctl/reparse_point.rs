@@ -0,0 +1,35 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+// REPARSE_DATA_BUFFER from MS-FSCC 2.1.2.2, taken by FSCTL_SET_REPARSE_POINT and returned by
+// FSCTL_GET_REPARSE_POINT. The tag decides how the data reads, so it's kept as is
+#[derive(Debug, PartialEq, Eq, Clone, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBReparseDataBuffer {
+    #[smb_direct(start(fixed = 0))]
+    reparse_tag: u32,
+    #[smb_skip(start = 6, length = 2)]
+    reserved: PhantomData<Vec<u8>>,
+    #[smb_buffer(order = 0, length(inner(start = 4, num_type = "u16")))]
+    data: Vec<u8>,
+}
+
+impl SMBReparseDataBuffer {
+    pub fn new(reparse_tag: u32, data: Vec<u8>) -> Self {
+        Self {
+            reparse_tag,
+            reserved: PhantomData,
+            data,
+        }
+    }
+
+    pub fn reparse_tag(&self) -> u32 {
+        self.reparse_tag
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
@@ -6,7 +6,7 @@ use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::lock::flags::SMBLockFlags;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 pub struct SMBLockInfo {
     #[smb_direct(start(fixed = 0))]
     offset: u64,
@@ -16,4 +16,131 @@ pub struct SMBLockInfo {
     flags: SMBLockFlags,
     #[smb_skip(start = 20, length = 4)]
     reserved: PhantomData<Vec<u8>>,
-}
\ No newline at end of file
+}
+impl SMBLockInfo {
+    pub fn new(offset: u64, length: u64, flags: SMBLockFlags) -> Self {
+        Self {
+            offset,
+            length,
+            flags,
+            reserved: PhantomData,
+        }
+    }
+
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    pub fn flags(&self) -> SMBLockFlags {
+        self.flags
+    }
+
+    // One past the last locked byte, None when the range runs off the end of the file's address space
+    pub fn end(&self) -> Option<u64> {
+        self.offset.checked_add(self.length)
+    }
+
+    pub fn is_shared(&self) -> bool {
+        self.flags.contains(SMBLockFlags::SHARED)
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.flags.contains(SMBLockFlags::EXCLUSIVE)
+    }
+
+    pub fn is_unlock(&self) -> bool {
+        self.flags.contains(SMBLockFlags::UNLOCK)
+    }
+
+    pub fn fail_immediately(&self) -> bool {
+        self.flags.contains(SMBLockFlags::FAIL_IMMEDIATELY)
+    }
+
+    // Exactly one of shared, exclusive or unlock, and only a lock can ask to fail immediately
+    pub fn has_valid_flags(&self) -> bool {
+        let lock = SMBLockFlags::SHARED | SMBLockFlags::EXCLUSIVE;
+        let kinds = (self.flags & (lock | SMBLockFlags::UNLOCK)).bits().count_ones();
+        let known = (lock | SMBLockFlags::UNLOCK | SMBLockFlags::FAIL_IMMEDIATELY).contains(self.flags);
+        known && kinds == 1 && !(self.is_unlock() && self.fail_immediately())
+    }
+
+    // Zero-length ranges cover no bytes, so they never overlap anything
+    pub fn overlaps(&self, other: &SMBLockInfo) -> bool {
+        if self.length == 0 || other.length == 0 {
+            return false;
+        }
+        let (start, end) = (self.offset as u128, self.offset as u128 + self.length as u128);
+        let (other_start, other_end) = (other.offset as u128, other.offset as u128 + other.length as u128);
+        start < other_end && other_start < end
+    }
+
+    // Shared locks only keep out exclusive ones
+    pub fn conflicts_with(&self, other: &SMBLockInfo) -> bool {
+        self.overlaps(other) && (self.is_exclusive() || other.is_exclusive())
+    }
+
+    // Unlocks have to name a held range exactly, they can't split or merge locks
+    pub fn same_range(&self, other: &SMBLockInfo) -> bool {
+        self.offset == other.offset && self.length == other.length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::lock::flags::SMBLockFlags;
+    use crate::protocol::body::lock::info::SMBLockInfo;
+
+    fn exclusive(offset: u64, length: u64) -> SMBLockInfo {
+        SMBLockInfo::new(offset, length, SMBLockFlags::EXCLUSIVE)
+    }
+
+    fn shared(offset: u64, length: u64) -> SMBLockInfo {
+        SMBLockInfo::new(offset, length, SMBLockFlags::SHARED)
+    }
+
+    #[test]
+    fn adjacent_ranges_do_not_overlap() {
+        assert!(!exclusive(0, 10).overlaps(&exclusive(10, 10)));
+        assert!(!exclusive(10, 10).overlaps(&exclusive(0, 10)));
+        assert!(!exclusive(0, 10).conflicts_with(&exclusive(10, 10)));
+    }
+
+    #[test]
+    fn overlapping_ranges_conflict_unless_both_shared() {
+        assert!(exclusive(0, 10).overlaps(&shared(9, 1)));
+        assert!(exclusive(5, 10).overlaps(&exclusive(0, 100)));
+        assert!(exclusive(0, 10).conflicts_with(&shared(5, 10)));
+        assert!(shared(0, 10).conflicts_with(&exclusive(5, 10)));
+        assert!(!shared(0, 10).conflicts_with(&shared(5, 10)));
+    }
+
+    #[test]
+    fn disjoint_and_empty_ranges_never_overlap() {
+        assert!(!exclusive(0, 10).overlaps(&exclusive(100, 10)));
+        assert!(!exclusive(0, 0).overlaps(&exclusive(0, 10)));
+        assert!(!exclusive(5, 10).overlaps(&exclusive(7, 0)));
+    }
+
+    #[test]
+    fn ranges_reaching_the_end_of_the_address_space() {
+        assert!(exclusive(u64::MAX - 1, 1).overlaps(&exclusive(0, u64::MAX)));
+        assert_eq!(exclusive(u64::MAX, 1).end(), None);
+        assert_eq!(exclusive(10, 5).end(), Some(15));
+        assert!(exclusive(10, 5).same_range(&SMBLockInfo::new(10, 5, SMBLockFlags::UNLOCK)));
+    }
+
+    #[test]
+    fn flag_combinations() {
+        assert!(shared(0, 1).is_shared() && !shared(0, 1).is_exclusive());
+        assert!(exclusive(0, 1).has_valid_flags());
+        assert!(SMBLockInfo::new(0, 1, SMBLockFlags::SHARED | SMBLockFlags::FAIL_IMMEDIATELY).has_valid_flags());
+        assert!(SMBLockInfo::new(0, 1, SMBLockFlags::UNLOCK).has_valid_flags());
+        assert!(!SMBLockInfo::new(0, 1, SMBLockFlags::UNLOCK | SMBLockFlags::FAIL_IMMEDIATELY).has_valid_flags());
+        assert!(!SMBLockInfo::new(0, 1, SMBLockFlags::SHARED | SMBLockFlags::EXCLUSIVE).has_valid_flags());
+        assert!(!SMBLockInfo::new(0, 1, SMBLockFlags::empty()).has_valid_flags());
+    }
+}
@@ -6,10 +6,10 @@ use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::empty::SMBEmpty;
 use crate::protocol::body::lock::info::SMBLockInfo;
 
-mod info;
-mod flags;
+pub mod info;
+pub mod flags;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 48)]
 pub struct SMBLockRequest {
     #[smb_direct(start(fixed = 4))]
@@ -1,8 +1,7 @@
 use std::str;
 
-use nom::bytes::complete::{take, take_till};
-use nom::multi::many1;
-use nom::number::complete::le_u8;
+use nom::bytes::complete::take;
+use nom::number::complete::{le_u16, le_u8};
 use serde::{Deserialize, Serialize};
 
 use smb_core::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBParseResult, SMBToBytes};
@@ -14,6 +13,7 @@ use crate::protocol::body::change_notify::{SMBChangeNotifyRequest, SMBChangeNoti
 use crate::protocol::body::close::{SMBCloseRequest, SMBCloseResponse};
 use crate::protocol::body::create::{SMBCreateRequest, SMBCreateResponse};
 use crate::protocol::body::echo::{SMBEchoRequest, SMBEchoResponse};
+use crate::protocol::body::error::SMBErrorResponse;
 use crate::protocol::body::flush::{SMBFlushRequest, SMBFlushResponse};
 use crate::protocol::body::ioctl::{SMBIoCtlRequest, SMBIoCtlResponse};
 use crate::protocol::body::lock::{SMBLockRequest, SMBLockResponse};
@@ -27,8 +27,10 @@ use crate::protocol::body::session_setup::{SMBSessionSetupRequest, SMBSessionSet
 use crate::protocol::body::set_info::{SMBSetInfoRequest, SMBSetInfoResponse};
 use crate::protocol::body::tree_connect::{SMBTreeConnectRequest, SMBTreeConnectResponse};
 use crate::protocol::body::tree_disconnect::{SMBTreeDisconnectRequest, SMBTreeDisconnectResponse};
+use crate::protocol::body::unknown::SMBUnknownBody;
 use crate::protocol::body::write::{SMBWriteRequest, SMBWriteResponse};
 use crate::protocol::header::command_code::{LegacySMBCommandCode, SMBCommandCode};
+use crate::protocol::header::flags2::LegacySMBFlags2;
 use crate::protocol::header::Header;
 use crate::protocol::header::LegacySMBHeader;
 use crate::protocol::header::SMBSyncHeader;
@@ -44,7 +46,7 @@ pub mod tree_connect;
 pub mod tree_disconnect;
 pub mod empty;
 pub mod create;
-mod error;
+pub mod error;
 pub mod close;
 pub mod flush;
 pub mod read;
@@ -58,13 +60,14 @@ pub mod query_info;
 pub mod ioctl;
 pub mod set_info;
 pub mod oplock_break;
+pub mod unknown;
 
 pub trait Body<S: Header>: SMBEnumFromBytes + SMBToBytes {
     fn parse_with_cc(bytes: &[u8], command_code: S::CommandCode) -> SMBParseResult<&[u8], Self> where Self: Sized;
     fn as_bytes(&self) -> Vec<u8>;
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBEnumFromBytes, SMBToBytes, SMBByteSize)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBEnumFromBytes, SMBToBytes, SMBByteSize)]
 pub enum SMBBody {
     #[smb_discriminator(value = 0x0)]
     #[smb_direct(start(fixed = 0))]
@@ -195,14 +198,23 @@ pub enum SMBBody {
     #[smb_discriminator(flag = 0x10000)]
     #[smb_direct(start(fixed = 0))]
     OplockBreakAcknowledgement(SMBOplockBreakAcknowledgement),
+    // Any command's response can come back as an error body instead, see SMBSyncHeader::carries_error_body
+    #[smb_discriminator(value = 0x0, value = 0x1, value = 0x2, value = 0x3, value = 0x4, value = 0x5, value = 0x6, value = 0x7, value = 0x8, value = 0x9, value = 0xA, value = 0xB, value = 0xC, value = 0xD, value = 0xE, value = 0xF, value = 0x10, value = 0x11, value = 0x12)]
+    #[smb_discriminator(flag = 0x30000)]
+    #[smb_direct(start(fixed = 0))]
+    ErrorResponse(SMBErrorResponse),
     #[smb_discriminator(value = 0x999)]
     #[smb_enum(start(fixed = 0), discriminator(inner(start = 0, num_type = "u8")))]
     LegacyCommand(LegacySMBBody),
+    // Commands we don't know, in either direction, keep their raw body
+    #[smb_discriminator(value = 0x998, value = 0x10998, value = 0x30998)]
+    #[smb_direct(start(fixed = 0))]
+    UnknownCommand(SMBUnknownBody),
 }
 
 impl Body<SMBSyncHeader> for SMBBody {
     fn parse_with_cc(bytes: &[u8], command_code: SMBCommandCode) -> SMBParseResult<&[u8], Self> {
-        Self::smb_enum_from_bytes(bytes, command_code as u64)
+        Self::smb_enum_from_bytes(bytes, command_code.into())
     }
 
     fn as_bytes(&self) -> Vec<u8> {
@@ -210,31 +222,59 @@ impl Body<SMBSyncHeader> for SMBBody {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum LegacySMBBody {
     None,
-    Negotiate(Vec<String>),
+    // The header's flags2 are carried along so the upgraded negotiate still knows what the client asked for
+    Negotiate(Vec<String>, LegacySMBFlags2),
+}
+
+pub const SMB2_WILDCARD_DIALECT: &str = "SMB 2.???";
+
+impl LegacySMBBody {
+    pub fn offers_smb2(&self) -> bool {
+        match self {
+            LegacySMBBody::Negotiate(dialects, _) => dialects.iter()
+                .any(|dialect| dialect == SMB2_WILDCARD_DIALECT),
+            LegacySMBBody::None => false,
+        }
+    }
+
+    // SPNEGO is only offered to clients that negotiated extended security
+    pub fn extended_security(&self) -> bool {
+        match self {
+            LegacySMBBody::Negotiate(_, flags2) => flags2.contains(LegacySMBFlags2::EXTENDED_SECURITY),
+            LegacySMBBody::None => false,
+        }
+    }
+
+    pub fn with_flags2(self, flags2: LegacySMBFlags2) -> Self {
+        match self {
+            LegacySMBBody::Negotiate(dialects, _) => LegacySMBBody::Negotiate(dialects, flags2),
+            LegacySMBBody::None => LegacySMBBody::None,
+        }
+    }
 }
 
 impl smb_core::SMBEnumFromBytes for LegacySMBBody {
     fn smb_enum_from_bytes(input: &[u8], discriminator: u64) -> SMBParseResult<&[u8], Self> where Self: Sized {
         match LegacySMBCommandCode::try_from(discriminator as u8).map(|x| x == LegacySMBCommandCode::Negotiate) {
             Ok(true) => {
-                let (remaining, cnt) = le_u8(input)
-                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("Invalid count"))?;
-                let (_, protocol_vecs) = many1(take_till(|n: u8| n == 0x02))(remaining)
-                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("No valid payload"))?;
+                let (remaining, _) = le_u8(input)
+                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("Invalid word count"))?;
+                let (remaining, byte_count) = le_u16(remaining)
+                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("Invalid byte count"))?;
+                let (remaining, dialect_bytes) = take(byte_count as usize)(remaining)
+                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("Size too small for parse length"))?;
                 let mut protocol_strs = Vec::new();
-                for slice in protocol_vecs {
+                for slice in dialect_bytes.split(|x| *x == 0x02).filter(|x| !x.is_empty()) {
                     let mut vec = slice.to_vec();
                     vec.retain(|x| *x != 0);
                     protocol_strs.push(String::from_utf8(vec).map_err(
                         |_| SMBError::parse_error("Could not map protocol to string"))?
                     );
                 }
-                let (remaining, _) = take(cnt as usize)(input)
-                    .map_err(|_: nom::Err<nom::error::Error<&[u8]>>| SMBError::parse_error("Size too small for parse length"))?;
-                Ok((remaining, LegacySMBBody::Negotiate(protocol_strs)))
+                Ok((remaining, LegacySMBBody::Negotiate(protocol_strs, LegacySMBFlags2::empty())))
             },
             _ => Err(SMBError::parse_error("Unknown parse error for LegacySMBBody")),
         }
@@ -251,7 +291,7 @@ impl SMBByteSize for LegacySMBBody {
     fn smb_byte_size(&self) -> usize {
         match self {
             LegacySMBBody::None => 0,
-            LegacySMBBody::Negotiate(x) => x.len() * 2
+            LegacySMBBody::Negotiate(x, _) => x.len() * 2
         }
     }
 }
@@ -264,4 +304,87 @@ impl Body<LegacySMBHeader> for LegacySMBBody {
     fn as_bytes(&self) -> Vec<u8> {
         self.smb_to_bytes()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use smb_core::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBToBytes};
+    use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::{LegacySMBBody, SMBBody};
+    use crate::protocol::header::command_code::LegacySMBCommandCode;
+    use crate::protocol::header::flags2::LegacySMBFlags2;
+    use crate::protocol::message::{Message, SMBLegacyMessage, SMBMessage};
+
+    fn legacy_negotiate_bytes(dialects: &[&str]) -> Vec<u8> {
+        let mut dialect_bytes = Vec::new();
+        for dialect in dialects {
+            dialect_bytes.push(0x02);
+            dialect_bytes.extend_from_slice(dialect.as_bytes());
+            dialect_bytes.push(0);
+        }
+        [
+            &[0][0..],
+            &(dialect_bytes.len() as u16).to_le_bytes(),
+            &dialect_bytes,
+        ].concat()
+    }
+
+    #[test]
+    fn legacy_negotiate_with_smb2_wildcard() {
+        let bytes = legacy_negotiate_bytes(&["NT LM 0.12", "SMB 2.002", "SMB 2.???"]);
+        let (remaining, body) = LegacySMBBody::smb_enum_from_bytes(&bytes, LegacySMBCommandCode::Negotiate as u64).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(body, LegacySMBBody::Negotiate(vec!["NT LM 0.12".into(), "SMB 2.002".into(), "SMB 2.???".into()], LegacySMBFlags2::empty()));
+        assert!(body.offers_smb2());
+    }
+
+    #[test]
+    fn legacy_negotiate_without_smb2_wildcard() {
+        let bytes = legacy_negotiate_bytes(&["NT LM 0.12", "SMB 2.002"]);
+        let (_, body) = LegacySMBBody::smb_enum_from_bytes(&bytes, LegacySMBCommandCode::Negotiate as u64).unwrap();
+        assert!(!body.offers_smb2());
+    }
+
+    pub(crate) fn legacy_negotiate_message(flags2: LegacySMBFlags2) -> Vec<u8> {
+        let mut header = vec![0xFF, b'S', b'M', b'B', LegacySMBCommandCode::Negotiate as u8, 0, 0, 0, 0, 0x18];
+        header.extend_from_slice(&flags2.bits().to_le_bytes());
+        header.resize(32, 0);
+        [header, legacy_negotiate_bytes(&["NT LM 0.12", "SMB 2.002", "SMB 2.???"])].concat()
+    }
+
+    #[test]
+    fn upgraded_negotiate_keeps_extended_security() {
+        for (flags2, extended_security) in [
+            (LegacySMBFlags2::UNICODE_STRINGS | LegacySMBFlags2::EXTENDED_SECURITY, true),
+            (LegacySMBFlags2::UNICODE_STRINGS, false),
+        ] {
+            let bytes = legacy_negotiate_message(flags2);
+            let (_, legacy) = SMBLegacyMessage::parse(&bytes).unwrap();
+            let message = SMBMessage::from_legacy(legacy).unwrap();
+            let SMBBody::LegacyCommand(body) = message.body else {
+                panic!("expected a legacy command body");
+            };
+            assert!(body.offers_smb2());
+            assert_eq!(body.extended_security(), extended_security);
+        }
+    }
+
+    // Both offsets point past the fixed part, so neither is known until every buffer has its place
+    #[derive(PartialEq, Eq, Debug, SMBFromBytes, SMBByteSize, SMBToBytes)]
+    struct TwoBuffers {
+        #[smb_buffer(order = 0, offset(inner(start = 0, num_type = "u16")), length(inner(start = 2, num_type = "u16")))]
+        first: Vec<u8>,
+        #[smb_buffer(order = 1, offset(inner(start = 4, num_type = "u16")), length(inner(start = 6, num_type = "u16")))]
+        second: Vec<u8>,
+    }
+
+    #[test]
+    fn buffer_offsets_are_patched_after_layout() {
+        let buffers = TwoBuffers { first: vec![1, 2, 3], second: vec![9, 9] };
+        assert_eq!(buffers.smb_byte_size(), 13);
+        let bytes = buffers.smb_to_bytes();
+        assert_eq!(bytes, vec![8, 0, 3, 0, 11, 0, 2, 0, 1, 2, 3, 9, 9]);
+        assert_eq!(TwoBuffers::smb_from_bytes(&bytes).unwrap(), (&[][..], buffers));
+    }
+}
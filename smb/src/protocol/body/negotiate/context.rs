@@ -43,22 +43,23 @@ macro_rules! ctx_smb_to_bytes {
 macro_rules! ctx_smb_from_bytes_enumify {
     ($enumType: expr, $bodyType: expr, $data: expr, $len: expr) => {{
         let (_, body) = $bodyType($data)?;
-        let padding = if $len % 8 == 0 || (6 + $len) as usize >= $data.len() {
+        let padding = if $len % 8 == 0 || 6 + $len as usize >= $data.len() {
             0
         } else {
             8 - $len % 8
         };
-        let remove_size = (6 + $len) as usize;
+        let remove_size = 6 + $len as usize;
         // let remove_size = (6 + $len + padding) as usize;
         // if remove_size > $data.len() {
         //     return Err(SMBError::parse_error("Invalid padding block"));
         // }
-        let remaining = &$data[remove_size..];
+        let remaining = $data.get(remove_size..)
+            .ok_or(SMBError::payload_too_small(remove_size, $data.len()))?;
         Ok((remaining, $enumType(body)))
     }};
 }
 
-#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum NegotiateContext {
     PreAuthIntegrityCapabilities(PreAuthIntegrityCapabilities),
     EncryptionCapabilities(EncryptionCapabilities),
@@ -67,7 +68,9 @@ pub enum NegotiateContext {
     TransportCapabilities(TransportCapabilities),
     RDMATransformCapabilities(RDMATransformCapabilities),
     SigningCapabilities(SigningCapabilities),
-    PosixExtensions(PosixExtensions)
+    PosixExtensions(PosixExtensions),
+    // A context type we don't know, kept so the request still parses but otherwise ignored
+    Unknown(UnknownNegotiateContext),
 }
 
 impl SMBByteSize for NegotiateContext {
@@ -80,7 +83,8 @@ impl SMBByteSize for NegotiateContext {
             NegotiateContext::TransportCapabilities(x) => x.smb_byte_size(),
             NegotiateContext::RDMATransformCapabilities(x) => x.smb_byte_size(),
             NegotiateContext::SigningCapabilities(x) => x.smb_byte_size(),
-            NegotiateContext::PosixExtensions(x) => x.smb_byte_size()
+            NegotiateContext::PosixExtensions(x) => x.smb_byte_size(),
+            NegotiateContext::Unknown(x) => x.smb_byte_size(),
         }) + 2
     }
 }
@@ -142,7 +146,11 @@ impl SMBFromBytes for NegotiateContext {
                 remaining,
                 ctx_len
             ),
-            _ => Err(SMBError::parse_error("Invalid negotiate context type"))
+            // Unknown contexts MUST be ignored, MS-SMB2 3.3.5.4, so only the length is trusted to skip past it
+            _ => {
+                let (remaining, body) = UnknownNegotiateContext::parse(ctx_type, remaining)?;
+                Ok((remaining, Self::Unknown(body)))
+            },
         }
     }
 }
@@ -158,6 +166,7 @@ impl SMBToBytes for NegotiateContext {
             NegotiateContext::RDMATransformCapabilities(body) => ctx_smb_to_bytes!(body),
             NegotiateContext::SigningCapabilities(body) => ctx_smb_to_bytes!(body),
             NegotiateContext::PosixExtensions(body) => ctx_smb_to_bytes!(body),
+            NegotiateContext::Unknown(body) => ctx_smb_to_bytes!(body),
         }
     }
 }
@@ -173,6 +182,7 @@ impl NegotiateContext {
             NegotiateContext::RDMATransformCapabilities(x) => x.byte_code(),
             NegotiateContext::SigningCapabilities(x) => x.byte_code(),
             NegotiateContext::PosixExtensions(x) => x.byte_code(),
+            NegotiateContext::Unknown(x) => x.byte_code(),
         }
     }
     pub fn from_connection_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(connection: &SMBConnection<R, W, S>, request_contexts: HashSet<u16>) -> Vec<Self> {
@@ -204,13 +214,14 @@ impl NegotiateContext {
     pub fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(&self, connection: SMBConnectionUpdate<R, W, S>, server: &S) -> SMBResult<(SMBConnectionUpdate<R, W, S>, bool)> {
         match self {
             NegotiateContext::PreAuthIntegrityCapabilities(x) => x.validate_and_set_state(connection),
-            NegotiateContext::EncryptionCapabilities(x) => x.validate_and_set_state(connection),
+            NegotiateContext::EncryptionCapabilities(x) => x.validate_and_set_state(connection, server),
             NegotiateContext::CompressionCapabilities(x) => x.validate_and_set_state(connection, server),
-            NegotiateContext::NetnameNegotiateContextID(x) => Ok((connection, false)),
+            NegotiateContext::NetnameNegotiateContextID(x) => x.validate_and_set_state(connection),
             NegotiateContext::TransportCapabilities(x) => x.validate_and_set_state(connection),
             NegotiateContext::RDMATransformCapabilities(x) => x.validate_and_set_state(connection, server),
             NegotiateContext::SigningCapabilities(x) => x.validate_and_set_state(connection),
             NegotiateContext::PosixExtensions(x) => x.validate_and_set_state(connection),
+            NegotiateContext::Unknown(_) => Ok((connection, false)),
         }
     }
 }
@@ -279,6 +290,13 @@ pub enum EncryptionCipher {
     AES256CCM,
 }
 
+const SERVER_CIPHER_PREFERENCE: [EncryptionCipher; 4] = [
+    EncryptionCipher::AES128GCM,
+    EncryptionCipher::AES128CCM,
+    EncryptionCipher::AES256GCM,
+    EncryptionCipher::AES256CCM,
+];
+
 impl EncryptionCapabilities {
     fn byte_code(&self) -> u16 {
         ENCRYPTION_CAPABILITIES_TAG
@@ -290,15 +308,21 @@ impl EncryptionCapabilities {
             ciphers: vec![connection.cipher_id()],
         }
     }
-    pub fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(&self, connection: SMBConnectionUpdate<R, W, S>) -> SMBResult<(SMBConnectionUpdate<R, W, S>, bool)> {
-        let mut ciphers = self.ciphers.clone();
-        ciphers.sort();
-        ciphers.reverse();
-        if let Some(cipher) = ciphers.first() {
-            Ok((connection.cipher_id(*cipher), true))
-        } else {
-            Ok((connection, true))
+    pub fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(&self, connection: SMBConnectionUpdate<R, W, S>, server: &S) -> SMBResult<(SMBConnectionUpdate<R, W, S>, bool)> {
+        if !server.encryption_supported() {
+            return Ok((connection, false));
         }
+        if self.ciphers.is_empty() {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        Ok((connection.cipher_id(self.select_cipher()), true))
+    }
+
+    pub fn select_cipher(&self) -> EncryptionCipher {
+        SERVER_CIPHER_PREFERENCE.iter()
+            .find(|cipher| self.ciphers.contains(cipher))
+            .copied()
+            .unwrap_or(EncryptionCipher::None)
     }
 }
 
@@ -370,11 +394,22 @@ impl NetnameNegotiateContextID {
     fn byte_code(&self) -> u16 {
         NETNAME_NEGOTIATE_CONTEXT_ID_TAG
     }
+
+    // The server never answers with a netname context, so this only records the name
+    pub fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(&self, connection: SMBConnectionUpdate<R, W, S>) -> SMBResult<(SMBConnectionUpdate<R, W, S>, bool)> {
+        let netname = self.netname.trim_end_matches('\0');
+        if netname.is_empty() {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        Ok((connection.server_name(netname.to_string()), false))
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, SMBFromBytes, SMBByteSize, SMBToBytes)]
 pub struct TransportCapabilities {
-    #[smb_direct(start = 0, length = 4)]
+    #[smb_skip(start = 0, length = 6)]
+    reserved: PhantomData<Vec<u8>>,
+    #[smb_direct(start(fixed = 6))]
     pub(crate) flags: TransportCapabilitiesFlags,
 }
 
@@ -395,12 +430,13 @@ impl TransportCapabilities {
     }
 
     fn from_connection_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(connection: &SMBConnection<R, W, S>) -> Self {
-        let flags = if connection.supports_chained_compression() {
+        let flags = if connection.accept_transport_security() {
             TransportCapabilitiesFlags::ACCEPT_TRANSPORT_LEVEL_SECURITY
         } else {
             TransportCapabilitiesFlags::empty()
         };
         Self {
+            reserved: PhantomData,
             flags,
         }
     }
@@ -518,4 +554,239 @@ impl PosixExtensions {
             posix_reserved: connection.posix_extension_payload().to_vec(),
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone)]
+pub struct UnknownNegotiateContext {
+    context_type: u16,
+    data: Vec<u8>,
+}
+
+impl UnknownNegotiateContext {
+    fn byte_code(&self) -> u16 {
+        self.context_type
+    }
+
+    pub fn context_type(&self) -> u16 {
+        self.context_type
+    }
+
+    // Input starts at DataLength, the same place the derived context bodies start
+    fn parse(context_type: u16, input: &[u8]) -> SMBParseResult<&[u8], Self> {
+        let (remaining, length) = u16::smb_from_bytes(input)?;
+        let data = remaining.get(4..4 + length as usize)
+            .ok_or(SMBError::payload_too_small(6 + length as usize, input.len()))?;
+        Ok((&remaining[4 + length as usize..], Self {
+            context_type,
+            data: data.to_vec(),
+        }))
+    }
+}
+
+impl SMBByteSize for UnknownNegotiateContext {
+    fn smb_byte_size(&self) -> usize {
+        6 + self.data.len()
+    }
+}
+
+impl SMBToBytes for UnknownNegotiateContext {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        [
+            &(self.data.len() as u16).smb_to_bytes()[0..],
+            &[0; 4],
+            &self.data,
+        ].concat()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::marker::PhantomData;
+    use std::sync::Arc;
+
+    use tokio::net::TcpStream;
+
+    use uuid::Uuid;
+
+    use smb_core::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::capabilities::Capabilities;
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::negotiate::context::{EncryptionCapabilities, EncryptionCipher, HashAlgorithm, NegotiateContext, NetnameNegotiateContextID, PreAuthIntegrityCapabilities, SigningAlgorithm, SigningCapabilities, TransportCapabilities, TransportCapabilitiesFlags, UnknownNegotiateContext};
+    use crate::protocol::body::negotiate::security_mode::NegotiateSecurityMode;
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::negotiate::{SMBNegotiateRequest, SMBNegotiateResponse};
+    use crate::server::connection::{Connection, SMBConnection, SMBConnectionUpdate};
+    use crate::socket::message_stream::SMBSocketConnection;
+    use crate::test_util::test_server;
+
+    fn capabilities(ciphers: Vec<EncryptionCipher>) -> EncryptionCapabilities {
+        EncryptionCapabilities {
+            reserved: PhantomData,
+            ciphers,
+        }
+    }
+
+    #[test]
+    fn selects_cipher_by_server_preference() {
+        assert_eq!(capabilities(vec![EncryptionCipher::AES256GCM, EncryptionCipher::AES128CCM]).select_cipher(), EncryptionCipher::AES128CCM);
+        assert_eq!(capabilities(vec![EncryptionCipher::AES256CCM, EncryptionCipher::AES128GCM]).select_cipher(), EncryptionCipher::AES128GCM);
+        assert_eq!(capabilities(vec![EncryptionCipher::AES256CCM]).select_cipher(), EncryptionCipher::AES256CCM);
+        assert_eq!(capabilities(vec![]).select_cipher(), EncryptionCipher::None);
+    }
+
+    fn netname(name: &str) -> NegotiateContext {
+        NegotiateContext::NetnameNegotiateContextID(NetnameNegotiateContextID {
+            reserved: PhantomData,
+            netname: name.into(),
+        })
+    }
+
+    fn transport(flags: TransportCapabilitiesFlags) -> NegotiateContext {
+        NegotiateContext::TransportCapabilities(TransportCapabilities {
+            reserved: PhantomData,
+            flags,
+        })
+    }
+
+    #[test]
+    fn netname_and_transport_contexts_round_trip() {
+        for context in [netname("fileserver.example.com"), transport(TransportCapabilitiesFlags::ACCEPT_TRANSPORT_LEVEL_SECURITY)] {
+            let bytes = context.smb_to_bytes();
+            assert_eq!(u16::from_le_bytes([bytes[2], bytes[3]]) as usize, bytes.len() - 8);
+            let (remaining, parsed) = NegotiateContext::smb_from_bytes(&bytes).unwrap();
+            assert!(remaining.is_empty());
+            assert_eq!(parsed, context);
+        }
+    }
+
+    #[tokio::test]
+    async fn netname_and_transport_contexts_update_the_connection() {
+        let (server, addr) = test_server().await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let mut connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        let server_rd = server.read().await;
+
+        let (update, respond) = netname("fileserver.example.com\0").validate_and_set_state(SMBConnectionUpdate::default(), &*server_rd).unwrap();
+        assert!(!respond);
+        let (update, respond) = transport(TransportCapabilitiesFlags::ACCEPT_TRANSPORT_LEVEL_SECURITY).validate_and_set_state(update, &*server_rd).unwrap();
+        assert!(respond);
+        connection.apply_update(update);
+
+        assert_eq!(connection.server_name(), "fileserver.example.com");
+        assert!(connection.accept_transport_security());
+        let response = NegotiateContext::from_connection_state(&connection, HashSet::from([0x05, 0x06]));
+        assert_eq!(response, vec![transport(TransportCapabilitiesFlags::ACCEPT_TRANSPORT_LEVEL_SECURITY)]);
+        let empty = netname("").validate_and_set_state(SMBConnectionUpdate::default(), &*server_rd)
+            .map(|(update, _)| connection.apply_update(update));
+        assert!(empty.is_err());
+    }
+
+    #[test]
+    fn context_vector_pads_between_elements_and_reparses() {
+        let preauth = NegotiateContext::PreAuthIntegrityCapabilities(PreAuthIntegrityCapabilities {
+            reserved: PhantomData,
+            hash_algorithms: vec![HashAlgorithm::SHA512],
+            salt: vec![1, 2, 3],
+        });
+        let signing = NegotiateContext::SigningCapabilities(SigningCapabilities {
+            reserved: PhantomData,
+            signing_algorithms: vec![SigningAlgorithm::AesCmac],
+        });
+        let mut request = SMBNegotiateRequest::new(NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED, Capabilities::empty(), Uuid::new_v4(), vec![SMBDialect::V3_1_1]);
+        request.negotiate_contexts = vec![preauth, signing];
+
+        let bytes = request.smb_to_bytes();
+        assert_eq!(bytes.len(), request.smb_byte_size());
+        // The fixed part and one dialect end at 38, so the 17 byte preauth context starts at 40
+        let context_offset = u32::from_le_bytes(bytes[28..32].try_into().unwrap()) as usize;
+        assert_eq!(context_offset - 64, 40);
+        assert_eq!(u16::from_le_bytes([bytes[32], bytes[33]]), 2);
+        assert_eq!(&bytes[40..42], &1_u16.to_le_bytes());
+        assert_eq!(&bytes[42..44], &9_u16.to_le_bytes());
+        // Padded out to 64 before the 12 byte signing context
+        assert_eq!(&bytes[57..64], &[0; 7]);
+        assert_eq!(&bytes[64..66], &8_u16.to_le_bytes());
+        assert_eq!(&bytes[66..68], &4_u16.to_le_bytes());
+        assert_eq!(bytes.len(), 76);
+
+        let (_, parsed) = SMBNegotiateRequest::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, request);
+    }
+
+    #[test]
+    fn unknown_context_types_are_skipped_by_length() {
+        let bytes = [0x77, 0x77, 3, 0, 0, 0, 0, 0, 0xA, 0xB, 0xC, 0xFF];
+        let (remaining, parsed) = NegotiateContext::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(remaining, &[0xFF]);
+        assert_eq!(parsed, NegotiateContext::Unknown(UnknownNegotiateContext { context_type: 0x7777, data: vec![0xA, 0xB, 0xC] }));
+        assert_eq!(parsed.byte_code(), 0x7777);
+        assert_eq!(parsed.smb_to_bytes(), &bytes[..11]);
+
+        let truncated = NegotiateContext::smb_from_bytes(&bytes[..9]);
+        assert!(truncated.is_err());
+    }
+
+    #[test]
+    fn unknown_contexts_between_known_ones_still_parse() {
+        let preauth = NegotiateContext::PreAuthIntegrityCapabilities(PreAuthIntegrityCapabilities {
+            reserved: PhantomData,
+            hash_algorithms: vec![HashAlgorithm::SHA512],
+            salt: vec![1, 2, 3],
+        });
+        let made_up = NegotiateContext::Unknown(UnknownNegotiateContext { context_type: 0x7777, data: vec![9; 5] });
+        let signing = NegotiateContext::SigningCapabilities(SigningCapabilities {
+            reserved: PhantomData,
+            signing_algorithms: vec![SigningAlgorithm::AesCmac],
+        });
+        let mut request = SMBNegotiateRequest::new(NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED, Capabilities::empty(), Uuid::new_v4(), vec![SMBDialect::V3_1_1]);
+        request.negotiate_contexts = vec![preauth, made_up, signing];
+
+        let bytes = request.smb_to_bytes();
+        let (_, parsed) = SMBNegotiateRequest::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(parsed, request);
+        let codes: Vec<u16> = parsed.negotiate_contexts.iter().map(NegotiateContext::byte_code).collect();
+        assert_eq!(codes, vec![0x01, 0x7777, 0x08]);
+    }
+
+    #[test]
+    fn response_byte_size_matches_serialized_length_with_aligned_contexts() {
+        // None of these contexts is a multiple of 8 long, so every one but the last is followed by padding
+        let negotiate_contexts = vec![
+            NegotiateContext::PreAuthIntegrityCapabilities(PreAuthIntegrityCapabilities {
+                reserved: PhantomData,
+                hash_algorithms: vec![HashAlgorithm::SHA512],
+                salt: vec![1, 2, 3],
+            }),
+            netname("a"),
+            NegotiateContext::SigningCapabilities(SigningCapabilities {
+                reserved: PhantomData,
+                signing_algorithms: vec![SigningAlgorithm::AesCmac],
+            }),
+            netname("fileserver"),
+        ];
+        let mut response = SMBNegotiateResponse {
+            security_mode: NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED,
+            dialect: SMBDialect::V3_1_1,
+            guid: Uuid::new_v4(),
+            capabilities: Capabilities::empty(),
+            max_transact_size: 65536,
+            max_read_size: 65536,
+            max_write_size: 65536,
+            system_time: FileTime::zero(),
+            server_start_time: FileTime::zero(),
+            buffer: Vec::new(),
+            negotiate_contexts,
+        };
+        // Walk the security buffer through every residue so the contexts start at each alignment
+        for buffer_len in 0..16 {
+            response.buffer = (0..buffer_len as u8).collect();
+            let bytes = response.smb_to_bytes();
+            assert_eq!(response.smb_byte_size(), bytes.len(), "size mismatch with a {} byte buffer", buffer_len);
+            let context_offset = u32::from_le_bytes(bytes[60..64].try_into().unwrap()) as usize;
+            assert_eq!(context_offset % 8, 0);
+        }
+    }
+}
@@ -2,12 +2,10 @@ use std::any::Any;
 use std::collections::HashSet;
 use std::marker::PhantomData;
 
-use digest::Digest;
 use serde::{Deserialize, Serialize};
-use sha2::Sha512;
 use uuid::Uuid;
 
-use smb_core::{SMBResult, SMBToBytes};
+use smb_core::SMBResult;
 use smb_core::error::SMBError;
 use smb_core::nt_status::NTStatus;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
@@ -26,7 +24,7 @@ use crate::util::auth::spnego::{SPNEGOToken, SPNEGOTokenInitBody};
 pub mod context;
 pub mod security_mode;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBByteSize, SMBToBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
 #[smb_byte_tag(value = 36)]
 pub struct SMBNegotiateRequest {
     #[smb_direct(start(fixed = 4))]
@@ -44,6 +42,17 @@ pub struct SMBNegotiateRequest {
 }
 
 impl SMBNegotiateRequest {
+    pub fn new(security_mode: NegotiateSecurityMode, capabilities: Capabilities, client_uuid: Uuid, dialects: Vec<SMBDialect>) -> Self {
+        Self {
+            security_mode,
+            capabilities,
+            client_uuid,
+            reserved: PhantomData,
+            dialects,
+            negotiate_contexts: Vec::new(),
+        }
+    }
+
     pub fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server>(&self, connection: &SMBConnection<R, W, S>, server: &S) -> SMBResult<(SMBConnectionUpdate<R, W, S>, HashSet<u16>)> {
         if connection.negotiate_dialect() != SMBDialect::default() {
             return Err(SMBError::response_error(NTStatus::AccessDenied));
@@ -63,7 +72,7 @@ impl SMBNegotiateRequest {
         // }
         let mut dialects = Vec::new();
         for dialect in self.dialects.iter() {
-            if *dialect != SMBDialect::V2_X_X {
+            if dialect.is_concrete() && *dialect >= server.min_dialect() {
                 dialects.push(*dialect)
             }
         }
@@ -74,49 +83,46 @@ impl SMBNegotiateRequest {
             security_mode |= NegotiateSecurityMode::NEGOTIATE_SIGNING_REQUIRED;
         }
 
+        // Highest dialect both sides speak; a client offering only the 2.??? wildcard or dialects we don't know has nothing in common with us
+        let dialect = *dialects.last().ok_or(SMBError::response_error(NTStatus::NotSupported))?;
+        let multi_credit = dialect.supports_multi_credit();
+
         let mut capabilities = Capabilities::empty();
-        if connection.supports_multi_credit() {
+        if multi_credit {
             capabilities |= Capabilities::LARGE_MTU;
         }
-        if connection.dialect() as u16 > 0x300 {
+        if dialect.is_smb3() {
             if server.multi_channel_capable() {
                 capabilities |= Capabilities::MULTI_CHANNEL;
             }
             if self.capabilities.contains(Capabilities::PERSISTENT_HANDLES) {
                 capabilities |= Capabilities::PERSISTENT_HANDLES;
             }
-            if connection.dialect() != SMBDialect::V3_1_1 && server.encryption_supported() && capabilities.contains(Capabilities::ENCRYPTION) {
+            // 3.1.1 negotiates encryption through its context instead of the capability bit
+            if dialect != SMBDialect::V3_1_1 && server.encryption_supported() && self.capabilities.contains(Capabilities::ENCRYPTION) {
                 capabilities |= Capabilities::ENCRYPTION;
             }
         }
-
-        // let dialect = *dialects.last().ok_or(SMBError::response_error(NTStatus::AccessDenied))?;
-        let dialect = SMBDialect::V2_1_0;
-        let preauth_value = if dialect == SMBDialect::V3_1_1 {
-            let mut sha = Sha512::default();
-            sha.update(&self.smb_to_bytes());
-            sha.finalize().to_vec()
-        } else {
-            Vec::new()
-        };
+        let default_io_size = dialect.default_max_io_size(multi_credit);
 
         update = update
             .dialect(dialect)
             .client_dialects(dialects)
             .client_capabilities(self.capabilities)
             .client_guid(self.client_uuid)
+            .server_guid(server.guid())
             .should_sign(self.security_mode.contains(NegotiateSecurityMode::NEGOTIATE_SIGNING_REQUIRED))
             .server_capabilites(capabilities)
-            .max_read_size(8388608)
-            .max_write_size(8388608)
-            .max_transact_size(8388608)
-            .preauth_integrity_hash_value(preauth_value)
+            .supports_multi_credit(multi_credit)
+            .max_read_size(server.max_read_size().unwrap_or(default_io_size))
+            .max_write_size(server.max_write_size().unwrap_or(default_io_size))
+            .max_transact_size(server.max_transact_size().unwrap_or(default_io_size))
             .server_security_mode(security_mode);
         Ok((update, received_ctxs))
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBToBytes, SMBByteSize, SMBFromBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBToBytes, SMBByteSize, SMBFromBytes)]
 #[smb_byte_tag(value = 65)]
 pub struct SMBNegotiateResponse {
     #[smb_direct(start(fixed = 2))]
@@ -148,25 +154,53 @@ pub struct SMBNegotiateResponse {
 }
 
 impl SMBNegotiateResponse {
-    pub fn legacy_response() -> Self {
+    pub fn dialect(&self) -> SMBDialect {
+        self.dialect
+    }
+
+    pub fn security_mode(&self) -> NegotiateSecurityMode {
+        self.security_mode
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    pub fn legacy_response<A: AuthProvider, S: Server>(server: &S, extended_security: bool) -> Self {
+        // Without extended security the client picks its own mechanism, so no SPNEGO hint is sent
+        let buffer = match extended_security {
+            true => SPNEGOToken::Init(SPNEGOTokenInitBody::<A>::new()).as_bytes(true),
+            false => Vec::new(),
+        };
+        let mut security_mode = NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED;
+        if server.require_message_signing() {
+            security_mode |= NegotiateSecurityMode::NEGOTIATE_SIGNING_REQUIRED;
+        }
+        let dialect = SMBDialect::V2_X_X;
+        let default_io_size = dialect.default_max_io_size(dialect.supports_multi_credit());
         Self {
-            security_mode: NegotiateSecurityMode::empty(),
-            dialect: SMBDialect::V2_X_X,
-            guid: Uuid::new_v4(),
+            security_mode,
+            dialect,
+            guid: server.guid(),
             capabilities: Capabilities::empty(),
-            max_transact_size: 100,
-            max_read_size: 100,
-            max_write_size: 100,
-            system_time: FileTime::now(),
-            server_start_time: FileTime::default(),
-            buffer: vec![],
+            max_transact_size: server.max_transact_size().unwrap_or(default_io_size),
+            max_read_size: server.max_read_size().unwrap_or(default_io_size),
+            max_write_size: server.max_write_size().unwrap_or(default_io_size),
+            system_time: server.clock().now(),
+            server_start_time: server.start_time(),
+            buffer,
             negotiate_contexts: Vec::new(),
         }
     }
 
     pub fn from_connection_state<A: AuthProvider, R: SMBReadStream, W: SMBWriteStream, S: Server>(connection: &SMBConnection<R, W, S>, server: &S, negotiate_contexts: HashSet<u16>) -> Self {
         let buffer = SPNEGOToken::Init(SPNEGOTokenInitBody::<A>::new()).as_bytes(true);
-        let negotiate_contexts = NegotiateContext::from_connection_state(connection, negotiate_contexts);
+        // Negotiate contexts only exist on the wire for 3.1.1
+        let negotiate_contexts = if connection.dialect() == SMBDialect::V3_1_1 {
+            NegotiateContext::from_connection_state(connection, negotiate_contexts)
+        } else {
+            Vec::new()
+        };
         Self {
             security_mode: connection.server_security_mode(),
             dialect: connection.dialect(),
@@ -176,10 +210,307 @@ impl SMBNegotiateResponse {
             max_transact_size: connection.max_transact_size(),
             max_read_size: connection.max_read_size(),
             max_write_size: connection.max_write_size(),
-            system_time: FileTime::now(),
-            server_start_time: FileTime::default(),
+            system_time: server.clock().now(),
+            server_start_time: server.start_time(),
             buffer,
             negotiate_contexts,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::marker::PhantomData;
+    use std::sync::Arc;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use uuid::Uuid;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::{SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::body::capabilities::Capabilities;
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::negotiate::context::EncryptionCipher;
+    use crate::protocol::body::negotiate::security_mode::NegotiateSecurityMode;
+    use crate::protocol::body::negotiate::{SMBNegotiateRequest, SMBNegotiateResponse};
+    use crate::server::{DefaultShare, Server, SMBClock, SMBServerBuilder};
+    use crate::server::connection::{Connection, SMBConnection};
+    use crate::socket::message_stream::SMBSocketConnection;
+    use crate::test_util::{serve, server_builder, test_server};
+    use crate::util::auth::AuthProvider;
+    use crate::util::auth::negotiate::NegotiateAuthProvider;
+    use crate::util::auth::negotiate::tests::{KERBEROS_OID, MS_KERBEROS_OID, TicketProvider};
+    use crate::util::auth::ntlm::NTLMAuthProvider;
+    use crate::util::auth::spnego::SPNEGOToken;
+
+    #[tokio::test]
+    async fn negotiated_parameters_are_readable() {
+        let (server, addr) = serve(server_builder().require_message_signing(true)).await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let mut connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+
+        let request = SMBNegotiateRequest {
+            security_mode: NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED,
+            capabilities: Capabilities::empty(),
+            client_uuid: Uuid::new_v4(),
+            reserved: PhantomData,
+            dialects: vec![SMBDialect::V2_0_2, SMBDialect::V2_1_0],
+            negotiate_contexts: vec![],
+        };
+        let server_rd = server.read().await;
+        let (update, _) = request.validate_and_set_state(&connection, &*server_rd).unwrap();
+        connection.apply_update(update);
+
+        assert_eq!(connection.dialect(), SMBDialect::V2_1_0);
+        assert_eq!(connection.client_guid(), request.client_uuid);
+        assert_eq!(connection.server_guid(), server_rd.guid());
+        assert!(connection.supports_multi_credit());
+        assert_eq!(connection.max_read_size(), 1048576);
+        assert_eq!(connection.max_write_size(), 1048576);
+        assert_eq!(connection.max_transact_size(), 1048576);
+        assert_eq!(connection.cipher_id(), EncryptionCipher::None);
+        assert!(connection.signing_required());
+        assert!(!connection.encryption_active());
+    }
+
+    fn read_u16(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([bytes[at], bytes[at + 1]]) as usize
+    }
+
+    fn read_u32(bytes: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(bytes[at..(at + 4)].try_into().unwrap()) as usize
+    }
+
+    // Follows MS-SMB2 2.2.4 to the letter: offsets are from the start of the SMB2 header and contexts are 8-byte aligned
+    fn strict_parse(body: &[u8]) -> (&[u8], Vec<(u16, &[u8])>) {
+        const HEADER_LEN: usize = 64;
+        assert_eq!(read_u16(body, 0), 65);
+        let buffer_offset = read_u16(body, 56);
+        let buffer_len = read_u16(body, 58);
+        assert!(buffer_offset >= HEADER_LEN + 64, "security buffer overlaps the fixed response");
+        let buffer_start = buffer_offset - HEADER_LEN;
+        let buffer = &body[buffer_start..(buffer_start + buffer_len)];
+
+        let context_offset = read_u32(body, 60);
+        let context_count = read_u16(body, 6);
+        assert_eq!(context_offset % 8, 0, "first negotiate context is misaligned");
+        let mut position = context_offset - HEADER_LEN;
+        assert!(position >= buffer_start + buffer_len, "negotiate contexts overlap the security buffer");
+        let mut contexts = Vec::new();
+        for idx in 0..context_count {
+            assert_eq!((position + HEADER_LEN) % 8, 0, "negotiate context {} is misaligned", idx);
+            let context_type = read_u16(body, position) as u16;
+            let data_len = read_u16(body, position + 2);
+            let data_end = position + 8 + data_len;
+            contexts.push((context_type, &body[(position + 8)..data_end]));
+            position = data_end.next_multiple_of(8);
+            if idx + 1 == context_count {
+                assert_eq!(data_end, body.len(), "trailing bytes after the last negotiate context");
+            }
+        }
+        (buffer, contexts)
+    }
+
+    #[tokio::test]
+    async fn serialized_negotiate_response_parses_strictly() {
+        let (server, addr) = test_server().await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let mut connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        let server_rd = server.read().await;
+        let (update, _) = negotiate_request(vec![SMBDialect::V3_1_1]).validate_and_set_state(&connection, &*server_rd).unwrap();
+        connection.apply_update(update);
+
+        let requested = HashSet::from([0x01, 0x02, 0x06, 0x08]);
+        let mut response = SMBNegotiateResponse::from_connection_state::<NTLMAuthProvider, _, _, _>(&connection, &*server_rd, requested);
+        assert!(!response.negotiate_contexts.is_empty());
+        for buffer_len in [0, 1, 7, 74] {
+            response.buffer = (0..buffer_len as u8).collect();
+            let body = response.smb_to_bytes();
+            let (buffer, contexts) = strict_parse(&body);
+            assert_eq!(buffer, response.buffer.as_slice());
+            assert_eq!(contexts.len(), response.negotiate_contexts.len());
+            for ((context_type, data), expected) in contexts.iter().zip(&response.negotiate_contexts) {
+                let expected_bytes = expected.smb_to_bytes();
+                assert_eq!(*context_type, expected.byte_code());
+                assert_eq!(*data, &expected_bytes[8..]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn legacy_response_offers_spnego_only_with_extended_security() {
+        let (server, _) = test_server().await;
+        let server_rd = server.read().await;
+
+        let extended = SMBNegotiateResponse::legacy_response::<NTLMAuthProvider, _>(&*server_rd, true);
+        assert_eq!(extended.dialect, SMBDialect::V2_X_X);
+        assert!(!extended.buffer.is_empty());
+
+        let plain = SMBNegotiateResponse::legacy_response::<NTLMAuthProvider, _>(&*server_rd, false);
+        assert_eq!(plain.dialect, SMBDialect::V2_X_X);
+        assert!(plain.buffer.is_empty());
+    }
+
+    #[tokio::test]
+    async fn negotiate_token_advertises_every_registered_mechanism() {
+        type Provider = NegotiateAuthProvider<TicketProvider, NTLMAuthProvider>;
+        let server = SMBServerBuilder::<_, TcpListener, Provider, DefaultShare<Provider>, _>::default()
+            .auth_provider(NegotiateAuthProvider::new(TicketProvider, NTLMAuthProvider::new(vec![], false)))
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build().unwrap();
+        let addr = {
+            let server_rd = server.read().await;
+            let listener = server_rd.local_listeners[0].lock().await;
+            listener.local_addr().unwrap()
+        };
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        let server_rd = server.read().await;
+
+        let response = SMBNegotiateResponse::from_connection_state::<Provider, _, _, _>(&connection, &*server_rd, HashSet::new());
+        let (_, token) = SPNEGOToken::<Provider>::parse(&response.buffer).unwrap();
+        let SPNEGOToken::Init(init) = token else {
+            panic!("Expected a NegTokenInit");
+        };
+        let expected = vec![MS_KERBEROS_OID.to_vec(), KERBEROS_OID.to_vec(), NTLMAuthProvider::get_oid()];
+        assert_eq!(init.mech_types(), Some(expected.as_slice()));
+
+        let legacy = SMBNegotiateResponse::legacy_response::<Provider, _>(&*server_rd, true);
+        assert_eq!(legacy.buffer, response.buffer);
+    }
+
+    #[derive(Debug)]
+    struct FixedClock(u64);
+
+    impl SMBClock for FixedClock {
+        fn now(&self) -> FileTime {
+            FileTime::from_unix(self.0)
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiate_response_reports_the_server_clock() {
+        let (server, addr) = serve(server_builder().clock(FixedClock(1_700_000_000))).await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        let server_rd = server.read().await;
+
+        let expected = FileTime::from_unix(1_700_000_000).as_bytes();
+        let response = SMBNegotiateResponse::from_connection_state::<NTLMAuthProvider, _, _, _>(&connection, &*server_rd, HashSet::new());
+        assert_eq!(&response.smb_to_bytes()[40..48], expected.as_slice());
+        assert_eq!(&response.smb_to_bytes()[48..56], FileTime::default().as_bytes().as_slice());
+
+        let legacy = SMBNegotiateResponse::legacy_response::<NTLMAuthProvider, _>(&*server_rd, true);
+        assert_eq!(&legacy.smb_to_bytes()[40..48], expected.as_slice());
+    }
+
+    #[tokio::test]
+    async fn configured_max_sizes_override_the_dialect_defaults() {
+        let builder = server_builder()
+            .max_read_size(131072)
+            .max_write_size(262144);
+        let (server, addr) = serve(builder).await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let mut connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+
+        let request = SMBNegotiateRequest {
+            security_mode: NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED,
+            capabilities: Capabilities::empty(),
+            client_uuid: Uuid::new_v4(),
+            reserved: PhantomData,
+            dialects: vec![SMBDialect::V2_1_0],
+            negotiate_contexts: vec![],
+        };
+        let server_rd = server.read().await;
+        let (update, _) = request.validate_and_set_state(&connection, &*server_rd).unwrap();
+        connection.apply_update(update);
+
+        assert_eq!(connection.max_read_size(), 131072);
+        assert_eq!(connection.max_write_size(), 262144);
+        assert_eq!(connection.max_transact_size(), 1048576);
+        assert!(connection.server_capabilities().contains(Capabilities::LARGE_MTU));
+    }
+
+    fn negotiate_request(dialects: Vec<SMBDialect>) -> SMBNegotiateRequest {
+        SMBNegotiateRequest {
+            security_mode: NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED,
+            capabilities: Capabilities::ENCRYPTION,
+            client_uuid: Uuid::new_v4(),
+            reserved: PhantomData,
+            dialects,
+            negotiate_contexts: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn negotiate_selects_the_highest_common_dialect() {
+        let (server, addr) = serve(server_builder().encryption_supported(true)).await;
+        let server_rd = server.read().await;
+        let requested = HashSet::from([0x01, 0x02]);
+        let negotiate = |dialects: Vec<SMBDialect>| {
+            let socket = std::net::TcpStream::connect(addr).unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let (read, write) = TcpStream::from_std(socket).unwrap().into_split();
+            let mut connection = SMBConnection::try_from((SMBSocketConnection::new("test".into(), read, write), Arc::downgrade(&server))).unwrap();
+            let result = negotiate_request(dialects).validate_and_set_state(&connection, &*server_rd);
+            result.map(|(update, _)| {
+                connection.apply_update(update);
+                SMBNegotiateResponse::from_connection_state::<NTLMAuthProvider, _, _, _>(&connection, &*server_rd, requested.clone())
+            })
+        };
+
+        let smb21 = negotiate(vec![SMBDialect::V2_1_0]).unwrap();
+        assert_eq!(smb21.dialect, SMBDialect::V2_1_0);
+        assert!(smb21.negotiate_contexts.is_empty());
+        assert!(!smb21.capabilities.contains(Capabilities::ENCRYPTION));
+
+        let smb30 = negotiate(vec![SMBDialect::V2_1_0, SMBDialect::V3_0_0]).unwrap();
+        assert_eq!(smb30.dialect, SMBDialect::V3_0_0);
+        assert!(smb30.negotiate_contexts.is_empty());
+        assert!(smb30.capabilities.contains(Capabilities::ENCRYPTION));
+
+        let smb311 = negotiate(vec![SMBDialect::V3_1_1, SMBDialect::V2_0_2, SMBDialect::V2_1_0, SMBDialect::V3_0_2]).unwrap();
+        assert_eq!(smb311.dialect, SMBDialect::V3_1_1);
+        assert_eq!(smb311.negotiate_contexts.len(), 2);
+        assert!(!smb311.capabilities.contains(Capabilities::ENCRYPTION));
+
+        let wildcard = negotiate(vec![SMBDialect::V2_X_X]);
+        assert!(matches!(wildcard, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+    }
+
+    #[test]
+    fn unrecognised_dialects_parse_alongside_known_ones() {
+        let request = SMBNegotiateRequest::new(NegotiateSecurityMode::NEGOTIATE_SIGNING_ENABLED, Capabilities::empty(), Uuid::new_v4(), vec![SMBDialect::V2_1_0, SMBDialect::V3_0_0, SMBDialect::V3_1_1]);
+        let mut bytes = request.smb_to_bytes();
+        // The second offered dialect becomes a made up 3.1.2
+        bytes[38..40].copy_from_slice(&0x0312_u16.to_le_bytes());
+        let (_, parsed) = SMBNegotiateRequest::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(parsed.dialects, vec![SMBDialect::V2_1_0, SMBDialect::Unknown, SMBDialect::V3_1_1]);
+    }
+
+    #[tokio::test]
+    async fn dialects_below_the_minimum_are_not_negotiated() {
+        let (server, addr) = serve(server_builder().min_dialect(SMBDialect::V3_0_0)).await;
+        let server_rd = server.read().await;
+        let socket = std::net::TcpStream::connect(addr).unwrap();
+        socket.set_nonblocking(true).unwrap();
+        let (read, write) = TcpStream::from_std(socket).unwrap().into_split();
+        let connection = SMBConnection::try_from((SMBSocketConnection::new("test".into(), read, write), Arc::downgrade(&server))).unwrap();
+
+        let old = negotiate_request(vec![SMBDialect::V2_0_2, SMBDialect::V2_1_0]).validate_and_set_state(&connection, &*server_rd);
+        assert!(matches!(old, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+        let (update, _) = negotiate_request(vec![SMBDialect::V2_1_0, SMBDialect::V3_0_2]).validate_and_set_state(&connection, &*server_rd).unwrap();
+        let mut connection = connection;
+        connection.apply_update(update);
+        assert_eq!(connection.dialect(), SMBDialect::V3_0_2);
+    }
+}
@@ -7,9 +7,9 @@ use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::oplock_break::oplock_level::SMBOplockLevel;
 
-mod oplock_level;
+pub mod oplock_level;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 24)]
 pub struct SMBOplockBreakContent {
     #[smb_direct(start(fixed = 2))]
@@ -22,4 +22,23 @@ pub struct SMBOplockBreakContent {
     file_id: SMBFileId,
 }
 
-pub type SMBOplockBreakAcknowledgement = SMBOplockBreakContent;
\ No newline at end of file
+pub type SMBOplockBreakAcknowledgement = SMBOplockBreakContent;
+
+impl SMBOplockBreakContent {
+    pub fn new(level: SMBOplockLevel, file_id: SMBFileId) -> Self {
+        Self {
+            level,
+            reserved: PhantomData,
+            reserved2: PhantomData,
+            file_id,
+        }
+    }
+
+    pub fn level(&self) -> SMBOplockLevel {
+        self.level
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+}
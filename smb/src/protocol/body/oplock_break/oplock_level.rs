@@ -3,10 +3,33 @@ use serde::{Deserialize, Serialize};
 
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
+use crate::protocol::body::create::oplock::SMBOplockLevel as SMBCreateOplockLevel;
+
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, TryFromPrimitive, SMBFromBytes, SMBByteSize, SMBToBytes)]
 pub enum SMBOplockLevel {
     None = 0x0,
     II = 0x1,
     Exclusive = 0x8,
-}
\ No newline at end of file
+}
+
+// A break only ever lands on level II or none; batch is reported as exclusive
+impl From<SMBCreateOplockLevel> for SMBOplockLevel {
+    fn from(value: SMBCreateOplockLevel) -> Self {
+        match value {
+            SMBCreateOplockLevel::None | SMBCreateOplockLevel::Lease => Self::None,
+            SMBCreateOplockLevel::II => Self::II,
+            SMBCreateOplockLevel::Exclusive | SMBCreateOplockLevel::Batch => Self::Exclusive,
+        }
+    }
+}
+
+impl From<SMBOplockLevel> for SMBCreateOplockLevel {
+    fn from(value: SMBOplockLevel) -> Self {
+        match value {
+            SMBOplockLevel::None => Self::None,
+            SMBOplockLevel::II => Self::II,
+            SMBOplockLevel::Exclusive => Self::Exclusive,
+        }
+    }
+}
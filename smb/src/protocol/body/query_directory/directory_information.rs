@@ -0,0 +1,277 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::{SMBFromBytes, SMBResult, SMBToBytes};
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+use crate::protocol::body::filetime::FileTime;
+use crate::protocol::body::query_directory::information_class::SMBInformationClass;
+use crate::server::share::SMBDirectoryEntry;
+
+// From MS-FSCC section 2.4, the next entry offset is filled in once the entries are laid out
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileDirectoryInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 8))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 56))]
+    file_attributes: SMBFileAttributes,
+    #[smb_buffer(offset(fixed = 64), length(inner(start = 60, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileFullDirectoryInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 8))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 56))]
+    file_attributes: SMBFileAttributes,
+    #[smb_direct(start(fixed = 64))]
+    ea_size: u32,
+    #[smb_buffer(offset(fixed = 68), length(inner(start = 60, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileIdFullDirectoryInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 8))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 56))]
+    file_attributes: SMBFileAttributes,
+    #[smb_direct(start(fixed = 64))]
+    ea_size: u32,
+    #[smb_skip(start = 68, length = 4)]
+    reserved: PhantomData<Vec<u8>>,
+    #[smb_direct(start(fixed = 72))]
+    file_id: u64,
+    #[smb_buffer(offset(fixed = 80), length(inner(start = 60, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileBothDirectoryInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 8))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 56))]
+    file_attributes: SMBFileAttributes,
+    #[smb_direct(start(fixed = 64))]
+    ea_size: u32,
+    // No 8.3 names are generated, so the short name length and buffer are left zeroed
+    #[smb_skip(start = 68, length = 26)]
+    short_name: PhantomData<Vec<u8>>,
+    #[smb_buffer(offset(fixed = 94), length(inner(start = 60, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileIdBothDirectoryInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_direct(start(fixed = 8))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 56))]
+    file_attributes: SMBFileAttributes,
+    #[smb_direct(start(fixed = 64))]
+    ea_size: u32,
+    #[smb_skip(start = 68, length = 28)]
+    short_name: PhantomData<Vec<u8>>,
+    #[smb_direct(start(fixed = 96))]
+    file_id: u64,
+    #[smb_buffer(offset(fixed = 104), length(inner(start = 60, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileNamesInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 4))]
+    file_index: u32,
+    #[smb_buffer(offset(fixed = 12), length(inner(start = 8, num_type = "u32")))]
+    file_name: Vec<u8>,
+}
+
+impl SMBFileNamesInformation {
+    pub fn file_name(&self) -> String {
+        let name = self.file_name.chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect::<Vec<u16>>();
+        String::from_utf16_lossy(&name)
+    }
+
+    // Follows each entry's NextEntryOffset through a QUERY_DIRECTORY output buffer
+    pub fn parse_list(buffer: &[u8]) -> SMBResult<Vec<Self>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        loop {
+            let entry = buffer.get(offset..)
+                .ok_or(SMBError::payload_too_small(offset, buffer.len()))?;
+            let (_, info) = Self::smb_from_bytes(entry)?;
+            let next = info.next_entry_offset as usize;
+            entries.push(info);
+            if next == 0 {
+                return Ok(entries);
+            }
+            offset += next;
+        }
+    }
+}
+
+impl SMBInformationClass {
+    pub fn encode_entry(&self, entry: &SMBDirectoryEntry, file_index: u32) -> SMBResult<Vec<u8>> {
+        let metadata = &entry.metadata;
+        let file_attributes = metadata.attributes;
+        let file_name = entry.name.encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let bytes = match self {
+            Self::FileDirectoryInformation => SMBFileDirectoryInformation {
+                next_entry_offset: 0,
+                file_index,
+                creation_time: metadata.creation_time.clone(),
+                last_access_time: metadata.last_access_time.clone(),
+                last_write_time: metadata.last_write_time.clone(),
+                change_time: metadata.last_modification_time.clone(),
+                end_of_file: metadata.actual_size,
+                allocation_size: metadata.allocated_size,
+                file_attributes,
+                file_name,
+            }.smb_to_bytes(),
+            Self::FileFullDirectoryInformation => SMBFileFullDirectoryInformation {
+                next_entry_offset: 0,
+                file_index,
+                creation_time: metadata.creation_time.clone(),
+                last_access_time: metadata.last_access_time.clone(),
+                last_write_time: metadata.last_write_time.clone(),
+                change_time: metadata.last_modification_time.clone(),
+                end_of_file: metadata.actual_size,
+                allocation_size: metadata.allocated_size,
+                file_attributes,
+                ea_size: 0,
+                file_name,
+            }.smb_to_bytes(),
+            Self::FileIdFullDirectoryInformation => SMBFileIdFullDirectoryInformation {
+                next_entry_offset: 0,
+                file_index,
+                creation_time: metadata.creation_time.clone(),
+                last_access_time: metadata.last_access_time.clone(),
+                last_write_time: metadata.last_write_time.clone(),
+                change_time: metadata.last_modification_time.clone(),
+                end_of_file: metadata.actual_size,
+                allocation_size: metadata.allocated_size,
+                file_attributes,
+                ea_size: 0,
+                reserved: PhantomData,
+                file_id: metadata.index_number,
+                file_name,
+            }.smb_to_bytes(),
+            Self::FileBothDirectoryInformation => SMBFileBothDirectoryInformation {
+                next_entry_offset: 0,
+                file_index,
+                creation_time: metadata.creation_time.clone(),
+                last_access_time: metadata.last_access_time.clone(),
+                last_write_time: metadata.last_write_time.clone(),
+                change_time: metadata.last_modification_time.clone(),
+                end_of_file: metadata.actual_size,
+                allocation_size: metadata.allocated_size,
+                file_attributes,
+                ea_size: 0,
+                short_name: PhantomData,
+                file_name,
+            }.smb_to_bytes(),
+            Self::FileIdBothDirectoryInformation => SMBFileIdBothDirectoryInformation {
+                next_entry_offset: 0,
+                file_index,
+                creation_time: metadata.creation_time.clone(),
+                last_access_time: metadata.last_access_time.clone(),
+                last_write_time: metadata.last_write_time.clone(),
+                change_time: metadata.last_modification_time.clone(),
+                end_of_file: metadata.actual_size,
+                allocation_size: metadata.allocated_size,
+                file_attributes,
+                ea_size: 0,
+                short_name: PhantomData,
+                file_id: metadata.index_number,
+                file_name,
+            }.smb_to_bytes(),
+            Self::FileNamesInformation => SMBFileNamesInformation {
+                next_entry_offset: 0,
+                file_index,
+                file_name,
+            }.smb_to_bytes(),
+            _ => return Err(SMBError::response_error(NTStatus::InvalidInfoClass)),
+        };
+        Ok(bytes)
+    }
+}
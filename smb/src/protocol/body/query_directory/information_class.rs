@@ -8,7 +8,7 @@ use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, SMBFromBytes, SMBToBytes, SMBByteSize, TryFromPrimitive, Serialize, Deserialize)]
 pub enum SMBInformationClass {
     FileDirectoryInformation = 0x1,
-    FullFileificateInformation = 0x2,
+    FileFullDirectoryInformation = 0x2,
     FileIdFullDirectoryInformation = 0x26,
     FileBothDirectoryInformation = 0x03,
     FileIdBothDirectoryInformation = 0x25,
@@ -16,4 +16,18 @@ pub enum SMBInformationClass {
     FileIdExtdDirectoryInformation = 0x3C,
     // Must never be used and ignored on receipt
     FileInformationClassReserved = 0x64,
-}
\ No newline at end of file
+}
+impl SMBInformationClass {
+    pub fn fixed_size(&self) -> usize {
+        match self {
+            Self::FileDirectoryInformation => 64,
+            Self::FileFullDirectoryInformation => 68,
+            Self::FileIdFullDirectoryInformation => 80,
+            Self::FileBothDirectoryInformation => 94,
+            Self::FileIdBothDirectoryInformation => 104,
+            Self::FileNamesInformation => 12,
+            Self::FileIdExtdDirectoryInformation => 88,
+            Self::FileInformationClassReserved => 0,
+        }
+    }
+}
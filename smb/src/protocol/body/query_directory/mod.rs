@@ -2,16 +2,24 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::error::buffer_too_small;
 use crate::protocol::body::query_directory::flags::SMBQueryDirectoryFlags;
 use crate::protocol::body::query_directory::information_class::SMBInformationClass;
+use crate::protocol::body::query_directory::search_pattern::matches_search_pattern;
+use crate::server::share::SMBDirectoryEntry;
 
-mod information_class;
-mod flags;
+pub mod information_class;
+pub mod flags;
+pub mod directory_information;
+pub mod search_pattern;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 33)]
 pub struct SMBQueryDirectoryRequest {
     #[smb_direct(start(fixed = 2))]
@@ -28,7 +36,98 @@ pub struct SMBQueryDirectoryRequest {
     search_pattern: String,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Default)]
+pub struct SMBDirectoryCursor {
+    pattern: Option<String>,
+    position: usize,
+}
+
+impl SMBQueryDirectoryRequest {
+    pub fn new(information_class: SMBInformationClass, flags: SMBQueryDirectoryFlags, file_id: SMBFileId, max_output_len: u32, search_pattern: &str) -> Self {
+        Self {
+            information_class,
+            flags,
+            file_index: 0,
+            file_id,
+            max_output_len,
+            search_pattern: search_pattern.into(),
+        }
+    }
+
+    pub fn information_class(&self) -> SMBInformationClass {
+        self.information_class
+    }
+
+    pub fn flags(&self) -> &SMBQueryDirectoryFlags {
+        &self.flags
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn search_pattern(&self) -> &str {
+        &self.search_pattern
+    }
+
+    pub fn enumerate(&self, cursor: &mut SMBDirectoryCursor, entries: &[SMBDirectoryEntry]) -> SMBResult<SMBQueryDirectoryResponse> {
+        let restarted = cursor.pattern.is_none()
+            || self.flags.intersects(SMBQueryDirectoryFlags::RESTART_SCANS | SMBQueryDirectoryFlags::REOPEN);
+        if restarted {
+            let pattern = match self.search_pattern.as_str() {
+                "" => "*",
+                pattern => pattern,
+            };
+            cursor.pattern = Some(pattern.into());
+            cursor.position = 0;
+        }
+        if self.flags.contains(SMBQueryDirectoryFlags::INDEX_SPECIFIED) {
+            cursor.position = self.file_index as usize + 1;
+        }
+        if (self.max_output_len as usize) < self.information_class.fixed_size() {
+            return Err(SMBError::response_error(NTStatus::InfoLengthMismatch));
+        }
+
+        let pattern = cursor.pattern.as_deref().unwrap_or("*");
+        let mut buffer = Vec::new();
+        let mut last_entry = None;
+        let mut position = cursor.position;
+        while let Some(entry) = entries.get(position) {
+            if !matches_search_pattern(pattern, &entry.name) {
+                position += 1;
+                continue;
+            }
+            let bytes = self.information_class.encode_entry(entry, position as u32)?;
+            let start = buffer.len().next_multiple_of(8);
+            if start + bytes.len() > self.max_output_len as usize {
+                if last_entry.is_none() {
+                    return Err(buffer_too_small(bytes.len()));
+                }
+                break;
+            }
+            if let Some(previous) = last_entry {
+                let next_entry_offset = (start - previous) as u32;
+                buffer[previous..(previous + 4)].copy_from_slice(&next_entry_offset.to_le_bytes());
+            }
+            buffer.resize(start, 0);
+            buffer.extend_from_slice(&bytes);
+            last_entry = Some(start);
+            position += 1;
+            if self.flags.contains(SMBQueryDirectoryFlags::RETURN_SINGLE_ENTRY) {
+                break;
+            }
+        }
+        cursor.position = position;
+
+        match last_entry {
+            Some(_) => Ok(SMBQueryDirectoryResponse::new(buffer)),
+            None if restarted => Err(SMBError::response_error(NTStatus::NoSuchFile)),
+            None => Err(SMBError::response_error(NTStatus::NoMoreFiles)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 9)]
 pub struct SMBQueryDirectoryResponse {
     #[smb_skip(start = 0, length = 8)]
@@ -36,4 +135,112 @@ pub struct SMBQueryDirectoryResponse {
     // TODO make this a file directory class https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-smb2/4f75351b-048c-4a0c-9ea3-addd55a71956
     #[smb_buffer(offset(inner(start = 2, num_type = "u16", subtract = 64)), length(inner(start = 4, num_type = "u32")))]
     buffer: Vec<u8>,
-}
\ No newline at end of file
+}
+
+impl SMBQueryDirectoryResponse {
+    pub fn new(buffer: Vec<u8>) -> Self {
+        Self {
+            output_info: PhantomData,
+            buffer,
+        }
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::query_directory::directory_information::SMBFileNamesInformation;
+    use crate::protocol::body::query_directory::flags::SMBQueryDirectoryFlags;
+    use crate::protocol::body::query_directory::information_class::SMBInformationClass;
+    use crate::protocol::body::query_directory::{SMBDirectoryCursor, SMBQueryDirectoryRequest};
+    use crate::server::share::{ResourceHandle, SharedResource};
+    use crate::test_util::{fs_share, TempDir};
+
+    fn query_request(flags: SMBQueryDirectoryFlags, max_output_len: u32) -> SMBQueryDirectoryRequest {
+        SMBQueryDirectoryRequest {
+            information_class: SMBInformationClass::FileNamesInformation,
+            flags,
+            file_index: 0,
+            file_id: SMBFileId { persistent: 0, volatile: 0 },
+            max_output_len,
+            search_pattern: "*".into(),
+        }
+    }
+
+    fn entry_names(buffer: &[u8]) -> Vec<String> {
+        SMBFileNamesInformation::parse_list(buffer).unwrap()
+            .iter()
+            .map(SMBFileNamesInformation::file_name)
+            .collect()
+    }
+
+    #[test]
+    fn pages_through_directory() {
+        let path = TempDir::new("query_directory");
+        for i in 0..5 {
+            fs::write(path.join(format!("file{}.txt", i)), b"data").unwrap();
+        }
+        let share = fs_share(&path);
+        let handle = share.handle_create("", SMBCreateDisposition::Open, true).unwrap();
+        let entries = handle.list_directory().unwrap();
+
+        let mut cursor = SMBDirectoryCursor::default();
+        let mut seen = Vec::new();
+        // Only two of the 30 byte entries fit per response once aligned
+        let request = query_request(SMBQueryDirectoryFlags::empty(), 64);
+        let result = loop {
+            match request.enumerate(&mut cursor, &entries) {
+                Ok(response) => seen.extend(entry_names(&response.buffer)),
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(result, SMBError::ResponseError(e) if e.status() == NTStatus::NoMoreFiles));
+        assert_eq!(seen.len(), 5);
+        assert_eq!(seen.iter().collect::<HashSet<_>>().len(), 5);
+
+        let restart = query_request(SMBQueryDirectoryFlags::RESTART_SCANS | SMBQueryDirectoryFlags::RETURN_SINGLE_ENTRY, 1024);
+        let response = restart.enumerate(&mut cursor, &entries).unwrap();
+        assert_eq!(entry_names(&response.buffer), vec!["file0.txt".to_string()]);
+    }
+
+    #[test]
+    fn unmatched_pattern_reports_no_such_file() {
+        let mut request = query_request(SMBQueryDirectoryFlags::empty(), 1024);
+        request.search_pattern = "missing.txt".into();
+        let result = request.enumerate(&mut SMBDirectoryCursor::default(), &[]);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NoSuchFile));
+    }
+
+    #[test]
+    fn first_entry_too_large_reports_required_size() {
+        let path = TempDir::new("query_directory_too_small");
+        fs::write(path.join("file0.txt"), b"data").unwrap();
+        let share = fs_share(&path);
+        let handle = share.handle_create("", SMBCreateDisposition::Open, true).unwrap();
+        let entries = handle.list_directory().unwrap();
+
+        // Room for the fixed part of a names entry but not the 9 character name after it
+        let request = query_request(SMBQueryDirectoryFlags::empty(), 16);
+        let result = request.enumerate(&mut SMBDirectoryCursor::default(), &entries);
+        let Err(SMBError::ResponseError(e)) = result else {
+            panic!("expected STATUS_BUFFER_TOO_SMALL");
+        };
+        assert_eq!(e.status(), NTStatus::BufferTooSmall);
+        assert_eq!(e.error_data(), &30_u32.to_le_bytes());
+
+        let retry = query_request(SMBQueryDirectoryFlags::empty(), 30);
+        let response = retry.enumerate(&mut SMBDirectoryCursor::default(), &entries).unwrap();
+        assert_eq!(entry_names(&response.buffer), vec!["file0.txt".to_string()]);
+    }
+}
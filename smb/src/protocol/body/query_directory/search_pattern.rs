@@ -0,0 +1,120 @@
+// Wildcards from MS-FSA section 2.1.4.4
+const DOS_STAR: char = '<';
+const DOS_QM: char = '>';
+const DOS_DOT: char = '"';
+
+pub fn matches_search_pattern(pattern: &str, name: &str) -> bool {
+    if pattern.is_empty() || pattern == "*" || pattern == "*.*" {
+        return true;
+    }
+    let pattern = dos_pattern(pattern);
+    let name = name.chars().collect::<Vec<char>>();
+    matches_from(&pattern, &name)
+}
+
+// Clients that don't pre-translate their patterns send the DOS forms (`*.`, `name.*`),
+// so rewrite them the same way Win32 does before handing them to the matcher
+fn dos_pattern(pattern: &str) -> Vec<char> {
+    let chars = pattern.chars().collect::<Vec<char>>();
+    chars.iter().enumerate().map(|(idx, c)| {
+        let next = chars.get(idx + 1);
+        match (c, next) {
+            ('*', Some('.')) => DOS_STAR,
+            ('.', Some('*')) => DOS_DOT,
+            ('.', None) if idx > 0 && chars[idx - 1] == '*' => DOS_DOT,
+            (c, _) => *c,
+        }
+    }).collect()
+}
+
+// Filled in from the end of the pattern backwards, matched[p][n] saying whether pattern[p..] matches name[n..].
+// Every cell only looks at later pattern positions, so runs of wildcards cost a table rather than backtracking
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    let width = name.len() + 1;
+    let mut matched = vec![false; (pattern.len() + 1) * width];
+    matched[pattern.len() * width + name.len()] = true;
+    let last_dot = name.iter().rposition(|c| *c == '.');
+    for p in (0..pattern.len()).rev() {
+        let rest = (p + 1) * width;
+        for n in (0..=name.len()).rev() {
+            let next = name.get(n);
+            matched[p * width + n] = match pattern[p] {
+                '*' => matched[rest + n] || (next.is_some() && matched[p * width + n + 1]),
+                '?' => next.is_some() && matched[rest + n + 1],
+                DOS_STAR => {
+                    let limit = match last_dot {
+                        Some(dot) if dot >= n => dot,
+                        _ => name.len(),
+                    };
+                    (n..=limit).any(|idx| matched[rest + idx])
+                },
+                DOS_QM => match next {
+                    None | Some('.') => {
+                        let skipped = p + pattern[p..].iter().take_while(|c| **c == DOS_QM).count();
+                        matched[skipped * width + n]
+                    },
+                    Some(_) => matched[rest + n + 1],
+                },
+                DOS_DOT => match next {
+                    Some('.') => matched[rest + n + 1],
+                    None => matched[rest + n],
+                    Some(_) => false,
+                },
+                literal => next.is_some_and(|c| c.to_lowercase().eq(literal.to_lowercase()))
+                    && matched[rest + n + 1],
+            };
+        }
+    }
+    matched[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::query_directory::search_pattern::matches_search_pattern;
+
+    #[test]
+    fn dos_wildcard_table() {
+        let cases = [
+            ("", "anything.txt", true),
+            ("*", "anything.txt", true),
+            ("*", "noext", true),
+            ("*.*", "noext", true),
+            ("*.txt", "notes.txt", true),
+            ("*.txt", "NOTES.TXT", true),
+            ("*.txt", "notes.txt.bak", false),
+            ("*.txt", "archive.tar.txt", true),
+            ("file?.dat", "file1.dat", true),
+            ("file?.dat", "file.dat", false),
+            ("file?.dat", "file12.dat", false),
+            ("*.", "noext", true),
+            ("*.", "has.ext", false),
+            ("readme.*", "readme", true),
+            ("readme.*", "readme.md", true),
+            ("readme.*", "readme2.md", false),
+            ("a*b", "ab", true),
+            ("a*b", "a.long.b", true),
+            ("a*b", "a.long.c", false),
+            ("<.txt", "a.b.txt", true),
+            ("ab>>", "ab", true),
+            ("ab>>.c", "ab.c", true),
+            ("ab>>.c", "abxy.c", true),
+            ("ab>>.c", "abxyz.c", false),
+            ("name\"", "name", true),
+            ("name\"", "name.", true),
+            ("name\"", "names", false),
+        ];
+        for (pattern, name, expected) in cases {
+            assert_eq!(matches_search_pattern(pattern, name), expected, "pattern {:?} against {:?}", pattern, name);
+        }
+    }
+
+    #[test]
+    fn long_wildcard_runs_fail_without_backtracking() {
+        let name = "a".repeat(200);
+        let pattern = "*a".repeat(60) + "b";
+        assert!(!matches_search_pattern(&pattern, &name));
+        let dos_pattern = "<a".repeat(60) + "b";
+        assert!(!matches_search_pattern(&dos_pattern, &name));
+        assert!(matches_search_pattern(&"*a".repeat(60), &name));
+    }
+}
@@ -0,0 +1,432 @@
+use std::marker::PhantomData;
+
+use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::{SMBFromBytes, SMBResult, SMBToBytes};
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+use crate::protocol::body::filetime::FileTime;
+use crate::server::open::Open;
+use crate::server::share::ResourceHandle;
+
+// From MS-FSCC section 2.4, only the classes the server can query or set are listed
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, TryFromPrimitive, Serialize, Deserialize)]
+pub enum SMBFileInformationClass {
+    FileBasicInformation = 0x04,
+    FileStandardInformation = 0x05,
+    FileInternalInformation = 0x06,
+    FileEaInformation = 0x07,
+    FileAccessInformation = 0x08,
+    FileNameInformation = 0x09,
+    FileDispositionInformation = 0x0D,
+    FilePositionInformation = 0x0E,
+    FileModeInformation = 0x10,
+    FileAlignmentInformation = 0x11,
+    FileAllInformation = 0x12,
+    FileNetworkOpenInformation = 0x22,
+    FileAttributeTagInformation = 0x23,
+}
+
+impl SMBFileInformationClass {
+    pub fn from_class(class: u8) -> SMBResult<Self> {
+        Self::try_from_primitive(class)
+            .map_err(|_| SMBError::response_error(NTStatus::InvalidInfoClass))
+    }
+}
+
+// A typed MS-FSCC information class. Adding a class means implementing this (and QueryFileInformation
+// if it can be queried) and registering it with SMBInfoRegistry
+pub trait FileInformation: SMBToBytes + SMBFromBytes + Sized {
+    const CLASS: u8;
+    // Size without any variable-length trailer, smaller output buffers can't hold the class at all
+    const FIXED_SIZE: usize;
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.smb_to_bytes()
+    }
+
+    fn from_bytes(bytes: &[u8]) -> SMBResult<Self> {
+        Self::smb_from_bytes(bytes)
+            .map(|(_, information)| information)
+            .map_err(|_| SMBError::response_error(NTStatus::InfoLengthMismatch))
+    }
+}
+
+pub trait QueryFileInformation: FileInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self>;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileBasicInformation {
+    #[smb_direct(start(fixed = 0))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 8))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    file_attributes: SMBFileAttributes,
+    #[smb_skip(start = 36, length = 4)]
+    reserved: PhantomData<Vec<u8>>,
+}
+
+impl FileInformation for SMBFileBasicInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileBasicInformation as u8;
+    const FIXED_SIZE: usize = 40;
+}
+
+impl QueryFileInformation for SMBFileBasicInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        let metadata = open.file_metadata()?;
+        Ok(Self {
+            creation_time: metadata.creation_time,
+            last_access_time: metadata.last_access_time,
+            last_write_time: metadata.last_write_time,
+            change_time: metadata.last_modification_time,
+            file_attributes: open.file_attributes(),
+            reserved: PhantomData,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileStandardInformation {
+    #[smb_direct(start(fixed = 0))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 8))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 16))]
+    number_of_links: u32,
+    #[smb_direct(start(fixed = 20))]
+    delete_pending: u8,
+    #[smb_direct(start(fixed = 21))]
+    directory: u8,
+    #[smb_skip(start = 22, length = 2)]
+    reserved: PhantomData<Vec<u8>>,
+}
+
+impl FileInformation for SMBFileStandardInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileStandardInformation as u8;
+    const FIXED_SIZE: usize = 24;
+}
+
+impl QueryFileInformation for SMBFileStandardInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        let metadata = open.file_metadata()?;
+        Ok(Self {
+            allocation_size: metadata.allocated_size,
+            end_of_file: metadata.actual_size,
+            number_of_links: 1,
+            delete_pending: open.delete_on_close() as u8,
+            directory: open.handle()?.is_directory() as u8,
+            reserved: PhantomData,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileInternalInformation {
+    #[smb_direct(start(fixed = 0))]
+    index_number: u64,
+}
+
+impl FileInformation for SMBFileInternalInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileInternalInformation as u8;
+    const FIXED_SIZE: usize = 8;
+}
+
+impl QueryFileInformation for SMBFileInternalInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        Ok(Self {
+            index_number: open.file_metadata()?.index_number,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileEaInformation {
+    #[smb_direct(start(fixed = 0))]
+    ea_size: u32,
+}
+
+impl FileInformation for SMBFileEaInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileEaInformation as u8;
+    const FIXED_SIZE: usize = 4;
+}
+
+impl QueryFileInformation for SMBFileEaInformation {
+    fn for_open<O: Open>(_open: &O) -> SMBResult<Self> {
+        Ok(Self { ea_size: 0 })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileAccessInformation {
+    #[smb_direct(start(fixed = 0))]
+    access_flags: u32,
+}
+
+impl FileInformation for SMBFileAccessInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileAccessInformation as u8;
+    const FIXED_SIZE: usize = 4;
+}
+
+impl QueryFileInformation for SMBFileAccessInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        Ok(Self {
+            access_flags: open.granted_access().raw(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[smb_buffer(offset(fixed = 4), length(inner(start = 0, num_type = "u32")))]
+pub struct SMBFileNameInformation {
+    file_name: Vec<u8>,
+}
+
+impl FileInformation for SMBFileNameInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileNameInformation as u8;
+    const FIXED_SIZE: usize = 4;
+}
+
+impl QueryFileInformation for SMBFileNameInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        Ok(Self {
+            file_name: open.file_name().encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFilePositionInformation {
+    #[smb_direct(start(fixed = 0))]
+    current_byte_offset: u64,
+}
+
+impl FileInformation for SMBFilePositionInformation {
+    const CLASS: u8 = SMBFileInformationClass::FilePositionInformation as u8;
+    const FIXED_SIZE: usize = 8;
+}
+
+impl QueryFileInformation for SMBFilePositionInformation {
+    fn for_open<O: Open>(_open: &O) -> SMBResult<Self> {
+        Ok(Self { current_byte_offset: 0 })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileModeInformation {
+    #[smb_direct(start(fixed = 0))]
+    mode: u32,
+}
+
+impl FileInformation for SMBFileModeInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileModeInformation as u8;
+    const FIXED_SIZE: usize = 4;
+}
+
+impl QueryFileInformation for SMBFileModeInformation {
+    fn for_open<O: Open>(_open: &O) -> SMBResult<Self> {
+        Ok(Self { mode: 0 })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileAlignmentInformation {
+    #[smb_direct(start(fixed = 0))]
+    alignment_requirement: u32,
+}
+
+impl FileInformation for SMBFileAlignmentInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileAlignmentInformation as u8;
+    const FIXED_SIZE: usize = 4;
+}
+
+impl QueryFileInformation for SMBFileAlignmentInformation {
+    fn for_open<O: Open>(_open: &O) -> SMBResult<Self> {
+        Ok(Self { alignment_requirement: 0 })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileAllInformation {
+    #[smb_direct(start(fixed = 0))]
+    basic_information: SMBFileBasicInformation,
+    #[smb_direct(start(fixed = 40))]
+    standard_information: SMBFileStandardInformation,
+    #[smb_direct(start(fixed = 64))]
+    internal_information: SMBFileInternalInformation,
+    #[smb_direct(start(fixed = 72))]
+    ea_information: SMBFileEaInformation,
+    #[smb_direct(start(fixed = 76))]
+    access_information: SMBFileAccessInformation,
+    #[smb_direct(start(fixed = 80))]
+    position_information: SMBFilePositionInformation,
+    #[smb_direct(start(fixed = 88))]
+    mode_information: SMBFileModeInformation,
+    #[smb_direct(start(fixed = 92))]
+    alignment_information: SMBFileAlignmentInformation,
+    #[smb_direct(start(fixed = 96))]
+    name_information: SMBFileNameInformation,
+}
+
+impl FileInformation for SMBFileAllInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileAllInformation as u8;
+    const FIXED_SIZE: usize = 100;
+}
+
+impl QueryFileInformation for SMBFileAllInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        Ok(Self {
+            basic_information: SMBFileBasicInformation::for_open(open)?,
+            standard_information: SMBFileStandardInformation::for_open(open)?,
+            internal_information: SMBFileInternalInformation::for_open(open)?,
+            ea_information: SMBFileEaInformation::for_open(open)?,
+            access_information: SMBFileAccessInformation::for_open(open)?,
+            position_information: SMBFilePositionInformation::for_open(open)?,
+            mode_information: SMBFileModeInformation::for_open(open)?,
+            alignment_information: SMBFileAlignmentInformation::for_open(open)?,
+            name_information: SMBFileNameInformation::for_open(open)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileNetworkOpenInformation {
+    #[smb_direct(start(fixed = 0))]
+    creation_time: FileTime,
+    #[smb_direct(start(fixed = 8))]
+    last_access_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    last_write_time: FileTime,
+    #[smb_direct(start(fixed = 24))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 32))]
+    allocation_size: u64,
+    #[smb_direct(start(fixed = 40))]
+    end_of_file: u64,
+    #[smb_direct(start(fixed = 48))]
+    file_attributes: SMBFileAttributes,
+    #[smb_skip(start = 52, length = 4)]
+    reserved: PhantomData<Vec<u8>>,
+}
+
+impl FileInformation for SMBFileNetworkOpenInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileNetworkOpenInformation as u8;
+    const FIXED_SIZE: usize = 56;
+}
+
+impl QueryFileInformation for SMBFileNetworkOpenInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        let metadata = open.file_metadata()?;
+        Ok(Self {
+            creation_time: metadata.creation_time,
+            last_access_time: metadata.last_access_time,
+            last_write_time: metadata.last_write_time,
+            change_time: metadata.last_modification_time,
+            allocation_size: metadata.allocated_size,
+            end_of_file: metadata.actual_size,
+            file_attributes: open.file_attributes(),
+            reserved: PhantomData,
+        })
+    }
+}
+
+// Lets clients probe for symlinks and mount points without opening them as reparse points
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileAttributeTagInformation {
+    #[smb_direct(start(fixed = 0))]
+    file_attributes: SMBFileAttributes,
+    #[smb_direct(start(fixed = 4))]
+    reparse_tag: u32,
+}
+
+impl FileInformation for SMBFileAttributeTagInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileAttributeTagInformation as u8;
+    const FIXED_SIZE: usize = 8;
+}
+
+impl QueryFileInformation for SMBFileAttributeTagInformation {
+    fn for_open<O: Open>(open: &O) -> SMBResult<Self> {
+        let metadata = open.file_metadata()?;
+        Ok(Self {
+            file_attributes: metadata.attributes,
+            reparse_tag: metadata.reparse_tag,
+        })
+    }
+}
+
+// Only ever set, carries the DeletePending flag for the open
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileDispositionInformation {
+    #[smb_direct(start(fixed = 0))]
+    delete_pending: u8,
+}
+
+impl SMBFileDispositionInformation {
+    pub fn delete_pending(&self) -> bool {
+        self.delete_pending != 0
+    }
+}
+
+impl FileInformation for SMBFileDispositionInformation {
+    const CLASS: u8 = SMBFileInformationClass::FileDispositionInformation as u8;
+    const FIXED_SIZE: usize = 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use crate::protocol::body::create::file_attributes::{IO_REPARSE_TAG_SYMLINK, SMBFileAttributes};
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::query_info::file_information::{FileInformation, SMBFileAttributeTagInformation, SMBFileBasicInformation, SMBFileDispositionInformation, SMBFileInformationClass, SMBFileNameInformation};
+
+    #[test]
+    fn encoded_sizes_match_fixed_sizes() {
+        let basic = SMBFileBasicInformation {
+            creation_time: FileTime::from_unix(0),
+            last_access_time: FileTime::from_unix(0),
+            last_write_time: FileTime::from_unix(0),
+            change_time: FileTime::from_unix(0),
+            file_attributes: SMBFileAttributes::NORMAL,
+            reserved: PhantomData,
+        };
+        assert_eq!(basic.to_bytes().len(), SMBFileBasicInformation::FIXED_SIZE);
+
+        let name = SMBFileNameInformation { file_name: "a.txt".encode_utf16().flat_map(u16::to_le_bytes).collect() };
+        let bytes = name.to_bytes();
+        assert_eq!(bytes.len(), SMBFileNameInformation::FIXED_SIZE + 10);
+        assert_eq!(&bytes[..4], &10_u32.to_le_bytes());
+    }
+
+    #[test]
+    fn attribute_tag_information_carries_the_reparse_tag() {
+        let info = SMBFileAttributeTagInformation {
+            file_attributes: SMBFileAttributes::REPARSE_POINT,
+            reparse_tag: IO_REPARSE_TAG_SYMLINK,
+        };
+        let bytes = info.to_bytes();
+        assert_eq!(bytes.len(), SMBFileAttributeTagInformation::FIXED_SIZE);
+        assert_eq!(&bytes[..4], &0x400_u32.to_le_bytes());
+        assert_eq!(&bytes[4..], &0xA000000C_u32.to_le_bytes());
+        assert_eq!(SMBFileInformationClass::from_class(0x23).unwrap(), SMBFileInformationClass::FileAttributeTagInformation);
+    }
+
+    #[test]
+    fn disposition_information_parses_and_needs_its_flag_byte() {
+        assert!(SMBFileDispositionInformation::from_bytes(&[1]).unwrap().delete_pending());
+        assert!(!SMBFileDispositionInformation::from_bytes(&[0]).unwrap().delete_pending());
+        assert!(SMBFileDispositionInformation::from_bytes(&[]).is_err());
+    }
+}
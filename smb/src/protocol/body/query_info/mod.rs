@@ -2,18 +2,29 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::{SMBFromBytes, SMBResult, SMBToBytes};
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::error::buffer_too_small;
 use crate::protocol::body::query_info::flags::SMBQueryInfoFlags;
 use crate::protocol::body::query_info::info_type::SMBInfoType;
+use crate::protocol::body::query_info::quota_information::{SMBFileQuotaInformation, SMBQueryQuotaInfo};
+use crate::protocol::body::query_info::registry::SMBInfoRegistry;
 use crate::protocol::body::query_info::security_information::SMBSecurityInformation;
+use crate::server::open::Open;
+use crate::server::share::SMBQuotaProvider;
 
 mod flags;
-mod info_type;
+pub mod info_type;
+pub mod file_information;
+pub mod quota_information;
+pub mod registry;
 mod security_information;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 41)]
 pub struct SMBQueryInfoRequest {
     #[smb_direct(start(fixed = 2))]
@@ -34,7 +45,79 @@ pub struct SMBQueryInfoRequest {
     buffer: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+impl SMBQueryInfoRequest {
+    pub fn info_type(&self) -> SMBInfoType {
+        self.info_type
+    }
+
+    pub fn file_info_class(&self) -> u8 {
+        self.file_info_class
+    }
+
+    pub fn output_buffer_length(&self) -> u32 {
+        self.output_buffer_length
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn query_open<O: Open>(&self, open: &O) -> SMBResult<(NTStatus, SMBQueryInfoResponse)> {
+        let registry = SMBInfoRegistry::file_classes();
+        let entry = registry.lookup(self.info_type, self.file_info_class)?;
+        let data = (entry.query)(open)?;
+        let (status, data) = self.fit_output(entry.fixed_size, data)?;
+        Ok((status, SMBQueryInfoResponse::new(data)))
+    }
+
+    pub fn query_quota(&self, provider: &dyn SMBQuotaProvider) -> SMBResult<(NTStatus, SMBQueryInfoResponse)> {
+        let (sids, return_single) = match self.buffer.is_empty() {
+            true => (Vec::new(), false),
+            false => {
+                let (_, info) = SMBQueryQuotaInfo::smb_from_bytes(&self.buffer)?;
+                (info.sids()?, info.return_single())
+            }
+        };
+        let mut quotas = provider.query_quotas(&sids)?;
+        if return_single {
+            quotas.truncate(1);
+        }
+        // Only whole entries go back, the last one that fits ending the chain, the way QUERY_DIRECTORY cuts its list
+        let mut end = 0_usize;
+        let mut fitting = 0;
+        for quota in &quotas {
+            let entry_end = end.next_multiple_of(8) + quota.smb_to_bytes().len();
+            if entry_end > self.output_buffer_length as usize {
+                break;
+            }
+            end = entry_end;
+            fitting += 1;
+        }
+        // Unlike a fixed class, a list that can't hold even its first entry tells the client how much to ask for
+        if let (0, Some(first)) = (fitting, quotas.first()) {
+            return Err(buffer_too_small(first.smb_to_bytes().len()));
+        }
+        let status = match fitting < quotas.len() {
+            true => NTStatus::BufferOverflow,
+            false => NTStatus::StatusSuccess,
+        };
+        Ok((status, SMBQueryInfoResponse::new(SMBFileQuotaInformation::encode_list(&quotas[..fitting]))))
+    }
+
+    pub fn fit_output(&self, fixed_size: usize, mut data: Vec<u8>) -> SMBResult<(NTStatus, Vec<u8>)> {
+        let max_len = self.output_buffer_length as usize;
+        if max_len < fixed_size {
+            return Err(SMBError::response_error(NTStatus::InfoLengthMismatch));
+        }
+        if data.len() > max_len {
+            data.truncate(max_len);
+            return Ok((NTStatus::BufferOverflow, data));
+        }
+        Ok((NTStatus::StatusSuccess, data))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 17)]
 pub struct SMBQueryInfoResponse {
     #[smb_skip(start = 2, length = 6)]
@@ -42,4 +125,140 @@ pub struct SMBQueryInfoResponse {
     // TODO make this a struct: https://learn.microsoft.com/en-us/openspecs/windows_protocols/ms-smb2/3b1b3598-a898-44ca-bfac-2dcae065247f
     #[smb_buffer(order = 0, offset(inner(start = 2, num_type = "u16", subtract = 64)), length(inner(start = 4, num_type = "u32")))]
     data: Vec<u8>,
-}
\ No newline at end of file
+}
+
+impl SMBQueryInfoResponse {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            reserved: PhantomData,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::{SMBResult, SMBToBytes};
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::query_info::file_information::{FileInformation, SMBFileBasicInformation, SMBFileInformationClass, SMBFileNameInformation, SMBFileStandardInformation};
+    use crate::protocol::body::query_info::flags::SMBQueryInfoFlags;
+    use crate::protocol::body::query_info::info_type::SMBInfoType;
+    use crate::protocol::body::query_info::security_information::SMBSecurityInformation;
+    use crate::protocol::body::query_info::{SMBQueryInfoRequest, SMBQueryInfoResponse};
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::query_info::quota_information::SMBFileQuotaInformation;
+    use crate::server::share::{SMBNoQuotaProvider, SMBQuotaProvider};
+
+    struct SingleQuotaProvider;
+
+    impl SMBQuotaProvider for SingleQuotaProvider {
+        fn query_quotas(&self, _sids: &[Vec<u8>]) -> SMBResult<Vec<SMBFileQuotaInformation>> {
+            Ok(vec![SMBFileQuotaInformation::new(vec![1; 16], FileTime::zero(), 10, 20, 30)])
+        }
+
+        fn set_quotas(&self, _quotas: Vec<SMBFileQuotaInformation>) -> SMBResult<()> {
+            Ok(())
+        }
+    }
+
+    struct TwoQuotaProvider;
+
+    impl SMBQuotaProvider for TwoQuotaProvider {
+        fn query_quotas(&self, _sids: &[Vec<u8>]) -> SMBResult<Vec<SMBFileQuotaInformation>> {
+            Ok(vec![
+                SMBFileQuotaInformation::new(vec![1; 16], FileTime::zero(), 10, 20, 30),
+                SMBFileQuotaInformation::new(vec![2; 16], FileTime::zero(), 40, 50, 60),
+            ])
+        }
+
+        fn set_quotas(&self, _quotas: Vec<SMBFileQuotaInformation>) -> SMBResult<()> {
+            Ok(())
+        }
+    }
+
+    fn query_request(class: SMBFileInformationClass, output_buffer_length: u32) -> SMBQueryInfoRequest {
+        SMBQueryInfoRequest {
+            info_type: SMBInfoType::File,
+            file_info_class: class as u8,
+            output_buffer_length,
+            reserved: PhantomData,
+            additional_information: SMBSecurityInformation::empty(),
+            flags: SMBQueryInfoFlags::empty(),
+            file_id: SMBFileId { persistent: 0, volatile: 0 },
+            buffer: vec![],
+        }
+    }
+
+    #[test]
+    fn buffer_smaller_than_fixed_class_is_length_mismatch() {
+        let request = query_request(SMBFileInformationClass::FileBasicInformation, 39);
+        let result = request.fit_output(SMBFileBasicInformation::FIXED_SIZE, vec![0; 40]);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InfoLengthMismatch));
+    }
+
+    #[test]
+    fn buffer_smaller_than_variable_class_overflows() {
+        let request = query_request(SMBFileInformationClass::FileNameInformation, 6);
+        let (status, data) = request.fit_output(SMBFileNameInformation::FIXED_SIZE, vec![1, 2, 3, 4, 5, 6, 7, 8]).unwrap();
+        assert_eq!(status, NTStatus::BufferOverflow);
+        assert_eq!(data, vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn buffer_large_enough_succeeds() {
+        let request = query_request(SMBFileInformationClass::FileStandardInformation, 64);
+        let (status, data) = request.fit_output(SMBFileStandardInformation::FIXED_SIZE, vec![0; 24]).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+        assert_eq!(data.len(), 24);
+    }
+
+    #[test]
+    fn stub_quota_provider_returns_empty_list() {
+        let mut request = query_request(SMBFileInformationClass::FileBasicInformation, 64);
+        request.info_type = SMBInfoType::Quota;
+        request.file_info_class = 0;
+        let (status, response) = request.query_quota(&SMBNoQuotaProvider).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+        assert_eq!(response, SMBQueryInfoResponse::new(vec![]));
+        assert_eq!(response.smb_to_bytes().len(), 8);
+    }
+
+    #[test]
+    fn quota_buffer_too_small_for_first_entry_reports_required_size() {
+        let mut request = query_request(SMBFileInformationClass::FileBasicInformation, 48);
+        request.info_type = SMBInfoType::Quota;
+        request.file_info_class = 0;
+        let result = request.query_quota(&SingleQuotaProvider);
+        // 40 fixed bytes plus the 16 byte SID
+        assert!(matches!(result, Err(SMBError::ResponseError(e))
+            if e.status() == NTStatus::BufferTooSmall && e.error_data() == 56_u32.to_le_bytes()));
+
+        request.output_buffer_length = 56;
+        let (status, _) = request.query_quota(&SingleQuotaProvider).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+    }
+
+    #[test]
+    fn quota_list_cut_short_keeps_only_whole_entries() {
+        let mut request = query_request(SMBFileInformationClass::FileBasicInformation, 100);
+        request.info_type = SMBInfoType::Quota;
+        request.file_info_class = 0;
+        let (status, response) = request.query_quota(&TwoQuotaProvider).unwrap();
+        assert_eq!(status, NTStatus::BufferOverflow);
+        let quotas = SMBFileQuotaInformation::parse_list(&response.data).unwrap();
+        assert_eq!(quotas, vec![SMBFileQuotaInformation::new(vec![1; 16], FileTime::zero(), 10, 20, 30)]);
+        // The one entry that fit ends the chain
+        assert_eq!(response.data.len(), 56);
+        assert_eq!(response.data[..4], [0; 4]);
+
+        request.output_buffer_length = 112;
+        let (status, response) = request.query_quota(&TwoQuotaProvider).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+        assert_eq!(SMBFileQuotaInformation::parse_list(&response.data).unwrap().len(), 2);
+    }
+}
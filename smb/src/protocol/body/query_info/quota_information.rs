@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+use smb_core::{SMBFromBytes, SMBResult, SMBToBytes};
+use smb_core::error::SMBError;
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+use crate::protocol::body::filetime::FileTime;
+
+// FILE_QUOTA_INFORMATION from MS-FSCC section 2.4.41
+#[derive(Debug, PartialEq, Eq, Clone, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileQuotaInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_direct(start(fixed = 8))]
+    change_time: FileTime,
+    #[smb_direct(start(fixed = 16))]
+    quota_used: u64,
+    #[smb_direct(start(fixed = 24))]
+    quota_threshold: u64,
+    #[smb_direct(start(fixed = 32))]
+    quota_limit: u64,
+    #[smb_buffer(offset(fixed = 40), length(inner(start = 4, num_type = "u32")))]
+    sid: Vec<u8>,
+}
+
+impl SMBFileQuotaInformation {
+    pub fn new(sid: Vec<u8>, change_time: FileTime, quota_used: u64, quota_threshold: u64, quota_limit: u64) -> Self {
+        Self {
+            next_entry_offset: 0,
+            change_time,
+            quota_used,
+            quota_threshold,
+            quota_limit,
+            sid,
+        }
+    }
+
+    pub fn sid(&self) -> &[u8] {
+        &self.sid
+    }
+
+    pub fn change_time(&self) -> &FileTime {
+        &self.change_time
+    }
+
+    pub fn quota_used(&self) -> u64 {
+        self.quota_used
+    }
+
+    pub fn quota_threshold(&self) -> u64 {
+        self.quota_threshold
+    }
+
+    pub fn quota_limit(&self) -> u64 {
+        self.quota_limit
+    }
+
+    pub fn encode_list(quotas: &[Self]) -> Vec<u8> {
+        encode_chain(quotas.iter().map(SMBToBytes::smb_to_bytes))
+    }
+
+    pub fn parse_list(buffer: &[u8]) -> SMBResult<Vec<Self>> {
+        parse_chain(buffer)
+    }
+}
+
+// FILE_GET_QUOTA_INFORMATION from MS-FSCC section 2.4.41.1, used to name the SIDs a query is interested in
+#[derive(Debug, PartialEq, Eq, Clone, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBFileGetQuotaInformation {
+    #[smb_direct(start(fixed = 0))]
+    next_entry_offset: u32,
+    #[smb_buffer(offset(fixed = 8), length(inner(start = 4, num_type = "u32")))]
+    sid: Vec<u8>,
+}
+
+impl SMBFileGetQuotaInformation {
+    pub fn new(sid: Vec<u8>) -> Self {
+        Self {
+            next_entry_offset: 0,
+            sid,
+        }
+    }
+
+    pub fn sid(&self) -> &[u8] {
+        &self.sid
+    }
+
+    pub fn encode_list(sids: &[Self]) -> Vec<u8> {
+        encode_chain(sids.iter().map(SMBToBytes::smb_to_bytes))
+    }
+
+    pub fn parse_list(buffer: &[u8]) -> SMBResult<Vec<Self>> {
+        parse_chain(buffer)
+    }
+}
+
+// SMB2_QUERY_QUOTA_INFO from MS-SMB2 section 2.2.37.1, carried in the QUERY_INFO input buffer
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+pub struct SMBQueryQuotaInfo {
+    #[smb_direct(start(fixed = 0))]
+    return_single: u8,
+    #[smb_direct(start(fixed = 1))]
+    restart_scan: u8,
+    #[smb_direct(start(fixed = 8))]
+    start_sid_length: u32,
+    #[smb_direct(start(fixed = 12))]
+    start_sid_offset: u32,
+    #[smb_buffer(offset(fixed = 16), length(inner(start = 4, num_type = "u32")))]
+    sid_list: Vec<u8>,
+}
+
+impl SMBQueryQuotaInfo {
+    pub fn return_single(&self) -> bool {
+        self.return_single != 0
+    }
+
+    pub fn restart_scan(&self) -> bool {
+        self.restart_scan != 0
+    }
+
+    pub fn sids(&self) -> SMBResult<Vec<Vec<u8>>> {
+        let sids = SMBFileGetQuotaInformation::parse_list(&self.sid_list)?
+            .into_iter()
+            .map(|info| info.sid)
+            .collect();
+        Ok(sids)
+    }
+}
+
+// Entries are 8-byte aligned and linked through the leading NextEntryOffset, which is 0 on the last one
+fn encode_chain<I: Iterator<Item=Vec<u8>>>(entries: I) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut last_entry: Option<usize> = None;
+    for bytes in entries {
+        let start = buffer.len().next_multiple_of(8);
+        if let Some(previous) = last_entry {
+            let next_entry_offset = (start - previous) as u32;
+            buffer[previous..(previous + 4)].copy_from_slice(&next_entry_offset.to_le_bytes());
+        }
+        buffer.resize(start, 0);
+        buffer.extend_from_slice(&bytes);
+        last_entry = Some(start);
+    }
+    buffer
+}
+
+fn parse_chain<T: SMBFromBytes>(buffer: &[u8]) -> SMBResult<Vec<T>> {
+    let mut entries = Vec::new();
+    let mut position = 0;
+    while position < buffer.len() {
+        let (_, entry) = T::smb_from_bytes(&buffer[position..])?;
+        let (_, next_entry_offset) = u32::smb_from_bytes(&buffer[position..])?;
+        entries.push(entry);
+        if next_entry_offset == 0 {
+            break;
+        }
+        if next_entry_offset % 8 != 0 {
+            return Err(SMBError::parse_error("Misaligned next entry offset"));
+        }
+        position += next_entry_offset as usize;
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::query_info::quota_information::{SMBFileGetQuotaInformation, SMBFileQuotaInformation};
+
+    // S-1-5-18 and S-1-5-21-1004336348-1177238915-682003330-512
+    const LOCAL_SYSTEM: [u8; 12] = [1, 1, 0, 0, 0, 0, 0, 5, 18, 0, 0, 0];
+    const DOMAIN_ADMINS: [u8; 28] = [
+        1, 5, 0, 0, 0, 0, 0, 5, 21, 0, 0, 0, 0xDC, 0xF4, 0xDC, 0x3B,
+        0x83, 0x3D, 0x2B, 0x46, 0x82, 0x8B, 0xA6, 0x28, 0x00, 0x02, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn round_trips_quota_list_with_mixed_sid_lengths() {
+        let quotas = vec![
+            SMBFileQuotaInformation::new(LOCAL_SYSTEM.to_vec(), FileTime::default(), 4096, 1 << 30, 2 << 30),
+            SMBFileQuotaInformation::new(DOMAIN_ADMINS.to_vec(), FileTime::default(), 0, u64::MAX, u64::MAX),
+        ];
+        let bytes = SMBFileQuotaInformation::encode_list(&quotas);
+
+        // 40 fixed bytes + 12 byte SID, padded to 56 before the second entry
+        assert_eq!(&bytes[0..4], &56_u32.to_le_bytes());
+        assert_eq!(&bytes[4..8], &12_u32.to_le_bytes());
+        assert_eq!(&bytes[56..60], &0_u32.to_le_bytes());
+        assert_eq!(&bytes[60..64], &28_u32.to_le_bytes());
+        assert_eq!(bytes.len(), 56 + 40 + 28);
+
+        let parsed = SMBFileQuotaInformation::parse_list(&bytes).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].sid(), LOCAL_SYSTEM);
+        assert_eq!(parsed[0].quota_used(), 4096);
+        assert_eq!(parsed[0].quota_limit(), 2 << 30);
+        assert_eq!(parsed[1].sid(), DOMAIN_ADMINS);
+        assert_eq!(parsed[1].quota_threshold(), u64::MAX);
+        assert_eq!(SMBFileQuotaInformation::encode_list(&parsed), bytes);
+    }
+
+    #[test]
+    fn round_trips_sid_list() {
+        let sids = vec![
+            SMBFileGetQuotaInformation::new(DOMAIN_ADMINS.to_vec()),
+            SMBFileGetQuotaInformation::new(LOCAL_SYSTEM.to_vec()),
+        ];
+        let bytes = SMBFileGetQuotaInformation::encode_list(&sids);
+        assert_eq!(&bytes[0..4], &40_u32.to_le_bytes());
+        let parsed = SMBFileGetQuotaInformation::parse_list(&bytes).unwrap();
+        assert_eq!(parsed.iter().map(|s| s.sid().to_vec()).collect::<Vec<_>>(), vec![DOMAIN_ADMINS.to_vec(), LOCAL_SYSTEM.to_vec()]);
+    }
+
+    #[test]
+    fn empty_quota_list_encodes_to_nothing() {
+        assert!(SMBFileQuotaInformation::encode_list(&[]).is_empty());
+        assert!(SMBFileQuotaInformation::parse_list(&[]).unwrap().is_empty());
+    }
+}
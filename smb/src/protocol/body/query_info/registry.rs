@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::protocol::body::query_info::file_information::{QueryFileInformation, SMBFileAccessInformation, SMBFileAlignmentInformation, SMBFileAllInformation, SMBFileAttributeTagInformation, SMBFileBasicInformation, SMBFileEaInformation, SMBFileInternalInformation, SMBFileModeInformation, SMBFileNameInformation, SMBFileNetworkOpenInformation, SMBFilePositionInformation, SMBFileStandardInformation};
+use crate::protocol::body::query_info::info_type::SMBInfoType;
+use crate::server::open::Open;
+
+pub type SMBInfoQuery<O> = fn(&O) -> SMBResult<Vec<u8>>;
+
+pub struct SMBInfoEntry<O> {
+    pub fixed_size: usize,
+    pub query: SMBInfoQuery<O>,
+}
+
+// Maps an (info type, class) pair from QUERY_INFO to the typed structure that answers it
+pub struct SMBInfoRegistry<O> {
+    entries: HashMap<(SMBInfoType, u8), SMBInfoEntry<O>>,
+}
+
+impl<O: Open> SMBInfoRegistry<O> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    // Every file class the server can answer for an open
+    pub fn file_classes() -> Self {
+        Self::new()
+            .register::<SMBFileBasicInformation>()
+            .register::<SMBFileStandardInformation>()
+            .register::<SMBFileInternalInformation>()
+            .register::<SMBFileEaInformation>()
+            .register::<SMBFileAccessInformation>()
+            .register::<SMBFileNameInformation>()
+            .register::<SMBFilePositionInformation>()
+            .register::<SMBFileModeInformation>()
+            .register::<SMBFileAlignmentInformation>()
+            .register::<SMBFileAllInformation>()
+            .register::<SMBFileNetworkOpenInformation>()
+            .register::<SMBFileAttributeTagInformation>()
+    }
+
+    pub fn register<T: QueryFileInformation>(mut self) -> Self {
+        self.entries.insert((SMBInfoType::File, T::CLASS), SMBInfoEntry {
+            fixed_size: T::FIXED_SIZE,
+            query: query_information::<O, T>,
+        });
+        self
+    }
+
+    // An unknown class of a known type is the client's mistake, a whole type we never registered isn't
+    pub fn lookup(&self, info_type: SMBInfoType, class: u8) -> SMBResult<&SMBInfoEntry<O>> {
+        if let Some(entry) = self.entries.get(&(info_type, class)) {
+            return Ok(entry);
+        }
+        match self.entries.keys().any(|(registered, _)| *registered == info_type) {
+            true => Err(SMBError::response_error(NTStatus::InvalidInfoClass)),
+            false => Err(SMBError::response_error(NTStatus::NotSupported)),
+        }
+    }
+}
+
+impl<O: Open> Default for SMBInfoRegistry<O> {
+    fn default() -> Self {
+        Self::file_classes()
+    }
+}
+
+fn query_information<O: Open, T: QueryFileInformation>(open: &O) -> SMBResult<Vec<u8>> {
+    Ok(T::for_open(open)?.to_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::options::SMBCreateOptions;
+    use crate::protocol::body::create::tests::create_request;
+    use crate::protocol::body::query_info::file_information::{FileInformation, SMBFileInformationClass, SMBFileInternalInformation, SMBFileStandardInformation};
+    use crate::protocol::body::query_info::info_type::SMBInfoType;
+    use crate::protocol::body::query_info::registry::SMBInfoRegistry;
+    use crate::server::open::{Open, SMBOpen};
+    use crate::server::share::recording::RecordingHandle;
+    use crate::server::share::ResourceHandle;
+    use crate::server::SMBServer;
+
+    type TestOpen = SMBOpen<SMBServer<&'static str>>;
+
+    fn open() -> TestOpen {
+        let handle: Box<dyn ResourceHandle> = Box::new(RecordingHandle::default());
+        Open::init(handle, &create_request("a.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty()))
+    }
+
+    #[test]
+    fn registered_classes_dispatch_to_their_structures() {
+        let registry = SMBInfoRegistry::<TestOpen>::file_classes();
+        let open = open();
+
+        let standard = registry.lookup(SMBInfoType::File, SMBFileInformationClass::FileStandardInformation as u8).unwrap();
+        assert_eq!(standard.fixed_size, SMBFileStandardInformation::FIXED_SIZE);
+        let bytes = (standard.query)(&open).unwrap();
+        assert_eq!(bytes.len(), 24);
+        // Recording handles are files, not directories
+        assert_eq!(bytes[21], 0);
+
+        let internal = registry.lookup(SMBInfoType::File, SMBFileInternalInformation::CLASS).unwrap();
+        assert_eq!((internal.query)(&open).unwrap(), vec![0; 8]);
+    }
+
+    #[test]
+    fn unknown_classes_and_types_are_refused() {
+        let registry = SMBInfoRegistry::<TestOpen>::file_classes();
+        let unknown_class = registry.lookup(SMBInfoType::File, 0x01);
+        assert!(matches!(unknown_class, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidInfoClass));
+        let unknown_type = registry.lookup(SMBInfoType::Security, 0);
+        assert!(matches!(unknown_type, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+    }
+}
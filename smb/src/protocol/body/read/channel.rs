@@ -1,6 +1,9 @@
 use num_enum::TryFromPrimitive;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 #[repr(u32)]
@@ -9,4 +12,46 @@ pub enum SMBRWChannel {
     None = 0x0,
     RdmaV1 = 0x1,
     RdmaV1Invalidate = 0x2,
+    RdmaTransform = 0x3,
+}
+
+impl SMBRWChannel {
+    pub fn is_rdma(&self) -> bool {
+        *self != SMBRWChannel::None
+    }
+
+    // An RDMA channel names buffer descriptors only an SMB Direct transport can use, so without one the
+    // request can't be serviced, MS-SMB2 3.3.5.12 and 3.3.5.13
+    pub fn validate(&self, rdma_supported: bool) -> SMBResult<()> {
+        if self.is_rdma() && !rdma_supported {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::SMBFromBytes;
+
+    use crate::protocol::body::read::channel::SMBRWChannel;
+
+    #[test]
+    fn rdma_channels_need_rdma_support() {
+        assert!(SMBRWChannel::None.validate(false).is_ok());
+        for channel in [SMBRWChannel::RdmaV1, SMBRWChannel::RdmaV1Invalidate, SMBRWChannel::RdmaTransform] {
+            let refused = channel.validate(false);
+            assert!(matches!(refused, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+            assert!(channel.validate(true).is_ok());
+        }
+    }
+
+    #[test]
+    fn every_channel_value_parses() {
+        for (value, channel) in [(0_u32, SMBRWChannel::None), (1, SMBRWChannel::RdmaV1), (2, SMBRWChannel::RdmaV1Invalidate), (3, SMBRWChannel::RdmaTransform)] {
+            assert_eq!(SMBRWChannel::smb_from_bytes(&value.to_le_bytes()).unwrap().1, channel);
+        }
+    }
 }
\ No newline at end of file
@@ -7,7 +7,7 @@ use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 use crate::util::flags_helper::{impl_smb_byte_size_for_bitflag, impl_smb_from_bytes_for_bitflag, impl_smb_to_bytes_for_bitflag};
 
 bitflags! {
-    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
     pub struct SMBReadRequestFlags: u8 {
         const UNBUFFERED = 0x01;
         const REQUEST_COMPRESSED = 0x02;
@@ -2,16 +2,21 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::read::channel::SMBRWChannel;
 use crate::protocol::body::read::flags::{SMBReadRequestFlags, SMBReadResponseFlags};
+use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::server::share::ResourceHandle;
 
 mod flags;
 pub mod channel;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 49)]
 pub struct SMBReadRequest {
     #[smb_direct(start(fixed = 3))]
@@ -32,7 +37,7 @@ pub struct SMBReadRequest {
     channel_information: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 17)]
 pub struct SMBReadResponse {
     #[smb_skip(start = 3, length = 1)]
@@ -41,6 +46,194 @@ pub struct SMBReadResponse {
     data_remaining: u32,
     #[smb_direct(start(fixed = 12))]
     flags: SMBReadResponseFlags,
+    // Laid out straight after the 16 byte fixed part, so DataOffset is always 0x50 from the header start
     #[smb_buffer(order = 0, offset(inner(start = 2, num_type = "u8", subtract = 64)), length(inner(start = 4, num_type = "u32")))]
     data: Vec<u8>,
-}
\ No newline at end of file
+}
+
+impl SMBReadRequest {
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn read_offset(&self) -> u64 {
+        self.read_offset
+    }
+
+    pub fn minimum_count(&self) -> u32 {
+        self.minimum_count
+    }
+
+    pub fn channel(&self) -> SMBRWChannel {
+        self.channel
+    }
+
+    // MS-SMB2 3.3.5.12: the length is bounded by the negotiated MaxReadSize before anything gets allocated,
+    // and the open itself has to have been granted read access
+    pub fn validate(&self, granted_access: &SMBAccessMask, max_read_size: u32) -> SMBResult<()> {
+        if self.read_length > max_read_size {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        if !granted_access.includes_read_data() {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
+        Ok(())
+    }
+
+    pub fn read_from<H: ResourceHandle + ?Sized>(&self, handle: &H) -> SMBResult<SMBReadResponse> {
+        self.response_for(handle.read_at(self.read_offset, self.read_length)?)
+    }
+
+    // The same read through the handle's async IO, so the server's runtime threads aren't held up by the disk
+    #[cfg(feature = "async")]
+    pub async fn read_from_async<H: ResourceHandle + ?Sized>(&self, handle: &H) -> SMBResult<SMBReadResponse> {
+        self.response_for(handle.read_at_async(self.read_offset, self.read_length).await?)
+    }
+
+    // MS-SMB2 3.3.5.12: reading nothing past the end of the file, or less than MinimumCount, fails with STATUS_END_OF_FILE
+    fn response_for(&self, data: Vec<u8>) -> SMBResult<SMBReadResponse> {
+        if (self.read_length > 0 && data.is_empty()) || (data.len() as u64) < self.minimum_count as u64 {
+            return Err(SMBError::response_error(NTStatus::EndOfFile));
+        }
+        Ok(SMBReadResponse::new(data))
+    }
+}
+
+impl SMBReadResponse {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self {
+            reserved: PhantomData,
+            data_remaining: 0,
+            flags: SMBReadResponseFlags::None,
+            data,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::{SMBFromBytes, SMBToBytes};
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::read::channel::SMBRWChannel;
+    use crate::protocol::body::read::flags::SMBReadRequestFlags;
+    use crate::protocol::body::read::{SMBReadRequest, SMBReadResponse};
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::{Message, SMBSyncMessage};
+    use crate::server::share::recording::RecordingHandle;
+    use crate::server::share::ResourceHandle;
+
+    fn read_request(read_offset: u64, read_length: u32, minimum_count: u32) -> SMBReadRequest {
+        SMBReadRequest {
+            flags: SMBReadRequestFlags::empty(),
+            read_length,
+            read_offset,
+            file_id: SMBFileId { persistent: 0, volatile: 0 },
+            minimum_count,
+            channel: SMBRWChannel::None,
+            remaining_bytes: 0,
+            channel_information: vec![],
+        }
+    }
+
+    fn ten_byte_file() -> RecordingHandle {
+        let handle = RecordingHandle::default();
+        handle.write_at(0, &(0..10).collect::<Vec<u8>>()).unwrap();
+        handle
+    }
+
+    #[test]
+    fn short_read_across_eof_returns_what_is_there() {
+        let handle = ten_byte_file();
+        assert_eq!(read_request(6, 8, 0).read_from(&handle).unwrap().data(), &[6, 7, 8, 9]);
+        assert_eq!(read_request(6, 8, 4).read_from(&handle).unwrap().data(), &[6, 7, 8, 9]);
+    }
+
+    #[test]
+    fn short_read_below_minimum_count_is_end_of_file() {
+        let handle = ten_byte_file();
+        for (offset, length, minimum_count) in [(6, 8, 5), (0, 4, 8)] {
+            let result = read_request(offset, length, minimum_count).read_from(&handle);
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::EndOfFile));
+        }
+    }
+
+    #[test]
+    fn read_at_or_past_eof_is_end_of_file() {
+        let handle = ten_byte_file();
+        for offset in [10, 20] {
+            let result = read_request(offset, 4, 0).read_from(&handle);
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::EndOfFile));
+        }
+        assert!(read_request(10, 0, 0).read_from(&handle).unwrap().data().is_empty());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_read_goes_through_the_handle_async_io() {
+        let handle = ten_byte_file();
+        assert_eq!(read_request(6, 8, 4).read_from_async(&handle).await.unwrap().data(), &[6, 7, 8, 9]);
+        let result = read_request(10, 4, 0).read_from_async(&handle).await;
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::EndOfFile));
+        assert_eq!(handle.async_io(), 2);
+    }
+
+    #[test]
+    fn oversized_or_unauthorised_reads_are_refused() {
+        let read = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        assert!(read_request(0, 65536, 0).validate(&read, 65536).is_ok());
+
+        let oversized = read_request(0, u32::MAX, 0).validate(&read, 65536);
+        assert!(matches!(oversized, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+
+        let write_only = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        let denied = read_request(0, 4, 0).validate(&write_only, 65536);
+        assert!(matches!(denied, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+    }
+
+    #[test]
+    fn maximum_allowed_open_can_read() {
+        let desired = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::MAXIMUM_ALLOWED);
+        let granted = SMBAccessMask::from_desired_access(&desired);
+        assert!(read_request(0, 4, 0).validate(&granted, 65536).is_ok());
+        assert_eq!(read_request(0, 4, 0).read_from(&ten_byte_file()).unwrap().data(), &[0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rdma_read_is_refused_without_rdma_support() {
+        let mut request = read_request(0, 4, 0);
+        request.channel = SMBRWChannel::RdmaV1;
+        let (_, parsed) = SMBReadRequest::smb_from_bytes(&request.smb_to_bytes()).unwrap();
+        assert_eq!(parsed.channel(), SMBRWChannel::RdmaV1);
+        let refused = parsed.channel().validate(false);
+        assert!(matches!(refused, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+        assert!(read_request(0, 4, 0).channel().validate(false).is_ok());
+    }
+
+    #[test]
+    fn response_data_sits_at_its_data_offset() {
+        let payload = (0..37).collect::<Vec<u8>>();
+        let response = SMBReadResponse::new(payload.clone());
+        let bytes = response.smb_to_bytes();
+        assert_eq!(bytes[2], 0x50);
+        assert_eq!(bytes.len(), 16 + payload.len());
+        assert_eq!(SMBReadResponse::smb_from_bytes(&bytes).unwrap().1, response);
+
+        // Read the way a client does: DataOffset and DataLength from the fixed part, relative to the header
+        let header = SMBSyncHeader::new(SMBCommandCode::Read, SMBFlags::SERVER_TO_REDIR, 0, 5, 1, 9, [0; 16]);
+        let message = SMBSyncMessage::new(header, SMBBody::ReadResponse(response)).as_bytes();
+        let data_offset = message[64 + 2] as usize;
+        let data_length = u32::from_le_bytes(message[(64 + 4)..(64 + 8)].try_into().unwrap()) as usize;
+        assert_eq!(&message[data_offset..(data_offset + data_length)], payload.as_slice());
+    }
+}
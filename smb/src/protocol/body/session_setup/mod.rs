@@ -1,10 +1,8 @@
 use std::collections::HashMap;
 
-use digest::Digest;
 use serde::{Deserialize, Serialize};
-use sha2::Sha512;
 
-use smb_core::{SMBResult, SMBToBytes};
+use smb_core::SMBResult;
 use smb_core::error::SMBError;
 use smb_core::nt_status::NTStatus;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
@@ -21,11 +19,12 @@ use crate::server::Server;
 use crate::server::session::{Session, SessionState};
 use crate::socket::message_stream::{SMBReadStream, SMBWriteStream};
 use crate::util::auth::AuthProvider;
+use crate::util::crypto::smb2::chain_preauth_hash;
 
 pub mod security_mode;
 pub mod flags;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBByteSize, SMBToBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
 #[smb_byte_tag(value = 25)]
 pub struct SMBSessionSetupRequest {
     #[smb_direct(start(fixed = 2))]
@@ -41,12 +40,31 @@ pub struct SMBSessionSetupRequest {
 }
 
 impl SMBSessionSetupRequest {
+    pub fn new(security_mode: SessionSetupSecurityMode, capabilities: Capabilities, buffer: Vec<u8>) -> Self {
+        Self {
+            flags: SMBSessionSetupFlags::empty(),
+            security_mode,
+            capabilities,
+            previous_session_id: 0,
+            buffer,
+        }
+    }
+
+    // Names the session a reconnecting client had before it lost its connection
+    pub fn with_previous_session_id(mut self, previous_session_id: u64) -> Self {
+        self.previous_session_id = previous_session_id;
+        self
+    }
+
     pub fn buffer(&self) -> &[u8] {
         &self.buffer
     }
     pub fn flags(&self) -> SMBSessionSetupFlags {
         self.flags
     }
+    pub fn previous_session_id(&self) -> u64 {
+        self.previous_session_id
+    }
     pub async fn validate_and_set_state<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=SMBConnection<R, W, S>>>(&self, connection: &SMBConnection<R, W, S>, server: &S, session: &S::Session, header: &SMBSyncHeader) -> SMBResult<SMBConnectionUpdate<R, W, S>> {
         let mut update = SMBConnectionUpdate::default();
         if server.encrypt_data() && (!server.unencrypted_access()
@@ -77,10 +95,7 @@ impl SMBSessionSetupRequest {
                 return Err(SMBError::response_error(NTStatus::NotSupported));
             }
             if connection.dialect() == SMBDialect::V3_1_1 && !connection.preauth_sessions().contains_key(&session.id()) {
-                let mut sha = Sha512::default();
-                sha.update(connection.preauth_integtiry_hash_value());
-                sha.update(&self.smb_to_bytes());
-                let bytes = sha.finalize().to_vec();
+                let bytes = chain_preauth_hash(connection.preauth_integtiry_hash_value(), connection.session_setup_request());
                 let preauth_session = SMBPreauthSession::new(session.id(), bytes);
                 update = update.preauth_session_table(HashMap::from([(session.id(), preauth_session)]));
             }
@@ -89,7 +104,7 @@ impl SMBSessionSetupRequest {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBToBytes, SMBFromBytes, SMBByteSize)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBToBytes, SMBFromBytes, SMBByteSize)]
 #[smb_byte_tag(value = 9)]
 pub struct SMBSessionSetupResponse {
     #[smb_direct(start(fixed = 2))]
@@ -106,8 +121,20 @@ impl SMBSessionSetupResponse {
         }
     }
 
-    pub fn from_session_state<S: Server>(session: &S::Session, buffer: Vec<u8>) -> Self {
+    pub fn session_flags(&self) -> SMBSessionFlags {
+        self.session_flags
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    // Session flags only mean something once the session is established, intermediate legs carry none
+    pub fn from_session_state<S: Server>(session: &S::Session, status: NTStatus, buffer: Vec<u8>) -> Self {
         let mut session_flags = SMBSessionFlags::empty();
+        if status != NTStatus::StatusSuccess {
+            return Self::new(session_flags, buffer);
+        }
         if session.guest() {
             session_flags |= SMBSessionFlags::IS_GUEST;
         }
@@ -117,16 +144,6 @@ impl SMBSessionSetupResponse {
         if session.encrypt_data() {
             session_flags |= SMBSessionFlags::ENCRYPT_DATA;
         }
-        Self {
-            session_flags,
-            buffer,
-        }
-    }
-
-    pub fn from_request(request: SMBSessionSetupRequest, token: Vec<u8>) -> Option<Self> {
-        Some(Self {
-            session_flags: SMBSessionFlags::empty(),
-            buffer: token,
-        })
+        Self::new(session_flags, buffer)
     }
 }
\ No newline at end of file
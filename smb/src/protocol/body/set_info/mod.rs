@@ -2,18 +2,26 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::query_info::file_information::{FileInformation, SMBFileDispositionInformation};
+use crate::protocol::body::query_info::quota_information::SMBFileQuotaInformation;
 use crate::protocol::body::set_info::info_type::SMBInfoType;
+use crate::server::share::SMBQuotaProvider;
 
-mod info_type;
+pub mod info_type;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 33)]
 pub struct SMBSetInfoRequest {
-    #[smb_direct(start(fixed = 3))]
+    #[smb_direct(start(fixed = 2))]
     info_type: SMBInfoType,
+    #[smb_direct(start(fixed = 3))]
+    file_info_class: u8,
     #[smb_skip(start = 10, length = 2)]
     reserved: PhantomData<Vec<u8>>,
     #[smb_direct(start(fixed = 12))]
@@ -24,11 +32,40 @@ pub struct SMBSetInfoRequest {
     buffer: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+impl SMBSetInfoRequest {
+    pub fn info_type(&self) -> SMBInfoType {
+        self.info_type
+    }
+
+    pub fn file_info_class(&self) -> u8 {
+        self.file_info_class
+    }
+
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    // Returns the requested DeletePending value when this is a FileDispositionInformation request
+    pub fn delete_pending(&self) -> Option<SMBResult<bool>> {
+        if self.info_type != SMBInfoType::File || self.file_info_class != SMBFileDispositionInformation::CLASS {
+            return None;
+        }
+        let pending = SMBFileDispositionInformation::from_bytes(&self.buffer)
+            .map(|information| information.delete_pending());
+        Some(pending)
+    }
+
+    pub fn set_quota(&self, provider: &dyn SMBQuotaProvider) -> SMBResult<SMBSetInfoResponse> {
+        if self.info_type != SMBInfoType::Quota {
+            return Err(SMBError::response_error(NTStatus::InvalidInfoClass));
+        }
+        provider.set_quotas(SMBFileQuotaInformation::parse_list(&self.buffer)?)?;
+        Ok(SMBSetInfoResponse)
+    }
+}
+
+// Only the StructureSize of 2, unlike the other empty bodies which carry two reserved bytes after it
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 2)]
-pub struct SMBSetInfoResponse {
-    #[smb_skip(start = 0, length = 1)]
-    reserved: PhantomData<Vec<u8>>,
-    #[smb_skip(start = 1, length = 1)]
-    reserved2: PhantomData<Vec<u8>>,
-}
\ No newline at end of file
+#[smb_skip(start = 0, length = 2)]
+pub struct SMBSetInfoResponse;
@@ -1,6 +1,9 @@
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBEnumFromBytes, SMBToBytes};
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBEnumFromBytes, SMBByteSize, SMBToBytes, Clone)]
@@ -41,6 +44,41 @@ impl SMBAccessMask {
         }
     }
 
+    // The write-type rights share their bit positions between the file and directory masks
+    pub fn includes_write(&self) -> bool {
+        let write = SMBFilePipePrinterAccessMask::FILE_WRITE_DATA | SMBFilePipePrinterAccessMask::FILE_APPEND_DATA
+            | SMBFilePipePrinterAccessMask::FILE_WRITE_EA | SMBFilePipePrinterAccessMask::FILE_DELETE_CHILD
+            | SMBFilePipePrinterAccessMask::FILE_WRITE_ATTRIBUTES | SMBFilePipePrinterAccessMask::DELETE
+            | SMBFilePipePrinterAccessMask::WRITE_DAC | SMBFilePipePrinterAccessMask::WRITE_OWNER
+            | SMBFilePipePrinterAccessMask::GENERIC_WRITE | SMBFilePipePrinterAccessMask::GENERIC_ALL;
+        self.raw() & write.bits() != 0
+    }
+
+    pub fn includes_read_data(&self) -> bool {
+        let read = SMBFilePipePrinterAccessMask::FILE_READ_DATA | SMBFilePipePrinterAccessMask::FILE_EXECUTE
+            | SMBFilePipePrinterAccessMask::GENERIC_READ | SMBFilePipePrinterAccessMask::GENERIC_EXECUTE
+            | SMBFilePipePrinterAccessMask::GENERIC_ALL;
+        self.raw() & read.bits() != 0
+    }
+
+    pub fn includes_write_data(&self) -> bool {
+        let write = SMBFilePipePrinterAccessMask::FILE_WRITE_DATA | SMBFilePipePrinterAccessMask::FILE_APPEND_DATA
+            | SMBFilePipePrinterAccessMask::GENERIC_WRITE | SMBFilePipePrinterAccessMask::GENERIC_ALL;
+        self.raw() & write.bits() != 0
+    }
+
+    // FILE_APPEND_DATA without any right to overwrite, so every write lands at the end of the file
+    pub fn is_append_only(&self) -> bool {
+        let overwrite = SMBFilePipePrinterAccessMask::FILE_WRITE_DATA | SMBFilePipePrinterAccessMask::GENERIC_WRITE
+            | SMBFilePipePrinterAccessMask::GENERIC_ALL;
+        self.raw() & SMBFilePipePrinterAccessMask::FILE_APPEND_DATA.bits() != 0 && self.raw() & overwrite.bits() == 0
+    }
+
+    pub fn includes_delete(&self) -> bool {
+        let delete = SMBFilePipePrinterAccessMask::DELETE | SMBFilePipePrinterAccessMask::GENERIC_ALL;
+        self.raw() & delete.bits() != 0
+    }
+
     pub fn access_no_connect_security(is_directory: bool) -> Self {
         match is_directory {
             true => Self::FilePipePrinter(SMBFilePipePrinterAccessMask::access_no_connect_security()),
@@ -51,20 +89,38 @@ impl SMBAccessMask {
     pub fn from_desired_access(desired: &SMBAccessMask) -> Self {
         let mut mask = desired.clone();
         if mask.includes_maximum_allowed() {
-            match mask {
-                SMBAccessMask::FilePipePrinter(mut x) => x |= SMBFilePipePrinterAccessMask::GENERIC_ALL,
-                SMBAccessMask::Directory(mut x) => x |= SMBDirectoryAccessMask::GENERIC_ALL
+            match &mut mask {
+                SMBAccessMask::FilePipePrinter(x) => *x |= SMBFilePipePrinterAccessMask::GENERIC_ALL,
+                SMBAccessMask::Directory(x) => *x |= SMBDirectoryAccessMask::GENERIC_ALL
             };
         }
 
         if mask.includes_access_system_security() {
-            match mask {
-                SMBAccessMask::FilePipePrinter(mut x) => x |= SMBFilePipePrinterAccessMask::ACCESS_SYSTEM_SECURITY,
-                SMBAccessMask::Directory(mut x) => x |= SMBDirectoryAccessMask::ACCESS_SYSTEM_SECURITY
+            match &mut mask {
+                SMBAccessMask::FilePipePrinter(x) => *x |= SMBFilePipePrinterAccessMask::ACCESS_SYSTEM_SECURITY,
+                SMBAccessMask::Directory(x) => *x |= SMBDirectoryAccessMask::ACCESS_SYSTEM_SECURITY
             };
         }
         mask
     }
+
+    // MS-SMB2 3.3.5.9: what's asked for has to be within the tree connect's MaximalAccess, generic rights checked
+    // as the file rights they stand for. MAXIMUM_ALLOWED takes whatever else the tree allows
+    pub fn granted_within(desired: &SMBAccessMask, maximal: &SMBAccessMask) -> SMBResult<Self> {
+        let maximal = SMBFilePipePrinterAccessMask::from_bits_truncate(maximal.raw()).with_generic_expanded();
+        let named = SMBFilePipePrinterAccessMask::from_bits_truncate(desired.raw()) - SMBFilePipePrinterAccessMask::MAXIMUM_ALLOWED;
+        if !maximal.contains(named.with_generic_expanded()) {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
+        let granted = match desired.includes_maximum_allowed() {
+            true => named | maximal,
+            false => named,
+        };
+        Ok(match desired {
+            SMBAccessMask::FilePipePrinter(_) => SMBAccessMask::FilePipePrinter(granted),
+            SMBAccessMask::Directory(_) => SMBAccessMask::Directory(SMBDirectoryAccessMask::from_bits_truncate(granted.bits())),
+        })
+    }
 }
 
 bitflags! {
@@ -106,6 +162,31 @@ impl SMBFilePipePrinterAccessMask {
             | Self::FILE_WRITE_ATTRIBUTES | Self::DELETE | Self::READ_CONTROL | Self::WRITE_DAC | Self::WRITE_OWNER
             | Self::SYNCHRONIZE
     }
+
+    // Everything that changes a file or its security, what a read-only share takes away
+    pub fn write_rights() -> Self {
+        Self::FILE_WRITE_DATA | Self::FILE_APPEND_DATA | Self::FILE_WRITE_EA | Self::FILE_DELETE_CHILD
+            | Self::FILE_WRITE_ATTRIBUTES | Self::DELETE | Self::WRITE_DAC | Self::WRITE_OWNER
+    }
+
+    // Generic rights swapped for the file rights they stand for, FILE_GENERIC_READ and friends
+    pub fn with_generic_expanded(self) -> Self {
+        let mut expanded = self - (Self::GENERIC_ALL | Self::GENERIC_READ | Self::GENERIC_WRITE | Self::GENERIC_EXECUTE);
+        if self.contains(Self::GENERIC_ALL) {
+            expanded |= Self::access_no_connect_security();
+        }
+        if self.contains(Self::GENERIC_READ) {
+            expanded |= Self::READ_CONTROL | Self::FILE_READ_DATA | Self::FILE_READ_ATTRIBUTES | Self::FILE_READ_EA | Self::SYNCHRONIZE;
+        }
+        if self.contains(Self::GENERIC_WRITE) {
+            expanded |= Self::READ_CONTROL | Self::FILE_WRITE_DATA | Self::FILE_WRITE_ATTRIBUTES | Self::FILE_WRITE_EA
+                | Self::FILE_APPEND_DATA | Self::SYNCHRONIZE;
+        }
+        if self.contains(Self::GENERIC_EXECUTE) {
+            expanded |= Self::READ_CONTROL | Self::FILE_READ_ATTRIBUTES | Self::FILE_EXECUTE | Self::SYNCHRONIZE;
+        }
+        expanded
+    }
 }
 
 bitflags! {
@@ -4,19 +4,28 @@ use serde::{Deserialize, Serialize};
 
 use smb_derive::{SMBByteSize, SMBEnumFromBytes, SMBFromBytes, SMBToBytes};
 
-use crate::protocol::body::tree_connect::context::SMBTreeConnectContext;
+use crate::protocol::body::tree_connect::context::{RemotedIdentity, SMBTreeConnectContext};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBEnumFromBytes, SMBByteSize, SMBToBytes)]
+// The buffer's form follows the request's flags: the low flags byte includes EXTENSION_PRESENT (0x4)
+// whenever the extension is there, whatever the other two flags are
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBEnumFromBytes, SMBByteSize, SMBToBytes)]
 pub enum SMBTreeConnectBuffer {
-    #[smb_discriminator(value = 0x0)]
+    #[smb_discriminator(value = 0x0, value = 0x1, value = 0x2, value = 0x3)]
     #[smb_string(order = 0, start(inner(start = 0, num_type = "u16", subtract = 68)), length(inner(start = 2, num_type = "u16")), underlying = "u16")]
     Path(String),
     #[smb_direct(start(fixed = 0))]
-    #[smb_discriminator(value = 0x1)]
+    #[smb_discriminator(value = 0x4, value = 0x5, value = 0x6, value = 0x7)]
     Extension(SMBTreeConnectExtension),
 }
 
 impl SMBTreeConnectBuffer {
+    pub fn extension(&self) -> Option<&SMBTreeConnectExtension> {
+        match self {
+            SMBTreeConnectBuffer::Path(_) => None,
+            SMBTreeConnectBuffer::Extension(extension) => Some(extension),
+        }
+    }
+
     pub fn share(&self) -> &str {
         let path_str = match self {
             SMBTreeConnectBuffer::Path(x) => x,
@@ -32,12 +41,31 @@ impl SMBTreeConnectBuffer {
     }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBByteSize, SMBFromBytes, SMBToBytes)]
+// MS-SMB2 2.2.9.1, parsed from the request's PathOffset field onwards. PathOffset still counts from the SMB2
+// header while TreeConnectContextOffset counts from the start of the request
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBByteSize, SMBFromBytes, SMBToBytes)]
 pub struct SMBTreeConnectExtension {
-    #[smb_skip(start = 12, length = 2)]
+    #[smb_skip(start = 10, length = 10)]
     reserved: PhantomData<Vec<u8>>,
-    #[smb_string(order = 1, start(inner(start = 2, num_type = "u16", subtract = 64)), length = "null_terminated", underlying = "u16")]
+    #[smb_string(order = 1, start(inner(start = 0, num_type = "u16", subtract = 68)), length(inner(start = 2, num_type = "u16")), underlying = "u16")]
     path_name: String,
-    #[smb_vector(order = 2, count(inner(start = 10, num_type = "u16")), offset(inner(start = 6, num_type = "u32", subtract = 64)))]
+    #[smb_vector(order = 2, count(inner(start = 8, num_type = "u16")), offset(inner(start = 4, num_type = "u32", subtract = 4)))]
     tree_connect_contexts: Vec<SMBTreeConnectContext>,
-}
\ No newline at end of file
+}
+
+impl SMBTreeConnectExtension {
+    pub fn path_name(&self) -> &str {
+        &self.path_name
+    }
+
+    pub fn contexts(&self) -> &[SMBTreeConnectContext] {
+        &self.tree_connect_contexts
+    }
+
+    pub fn remoted_identity(&self) -> Option<&RemotedIdentity> {
+        // RemotedIdentity is the only context defined, so the first one is it
+        self.tree_connect_contexts.first().map(|context| match context {
+            SMBTreeConnectContext::RemotedIdentity(identity) => identity,
+        })
+    }
+}
@@ -2,7 +2,7 @@ use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
 bitflags! {
-    #[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+    #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
     pub struct SMBTreeConnectCapabilities: u32 {
         const DFS                     = 0x008;
         const CONTINUOUS_AVAILABILITY = 0x010;
@@ -12,9 +12,12 @@ pub enum SMBTreeConnectContext {
     RemotedIdentity(RemotedIdentity),
 }
 
+// Every context is a ContextType, a DataLength and four reserved bytes ahead of its data, MS-SMB2 2.2.9.2
+const CONTEXT_HEADER_SIZE: usize = 8;
+
 impl SMBByteSize for SMBTreeConnectContext {
     fn smb_byte_size(&self) -> usize {
-        match self {
+        CONTEXT_HEADER_SIZE + match self {
             Self::RemotedIdentity(identity) => identity.smb_byte_size()
         }
     }
@@ -23,10 +26,15 @@ impl SMBByteSize for SMBTreeConnectContext {
 impl SMBFromBytes for SMBTreeConnectContext {
     fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
         let (remaining, ctx_type) = u16::smb_from_bytes(input)?;
+        let (_, data_length) = u16::smb_from_bytes(remaining)?;
+        let end = CONTEXT_HEADER_SIZE + data_length as usize;
+        let data = input.get(CONTEXT_HEADER_SIZE..end)
+            .ok_or(SMBError::payload_too_small(end, input.len()))?;
         match ctx_type {
             0x01 => {
-                let (remaining, identity) = RemotedIdentity::smb_from_bytes(input)?;
-                Ok((remaining, Self::RemotedIdentity(identity)))
+                // The identity's offsets count from the start of its own data
+                let (_, identity) = RemotedIdentity::smb_from_bytes(data)?;
+                Ok((&input[end..], Self::RemotedIdentity(identity)))
             },
             _ => Err(SMBError::parse_error("Invalid context type for tree connect context"))
         }
@@ -38,9 +46,10 @@ impl SMBToBytes for SMBTreeConnectContext {
         let (ctx_type, bytes) = match self {
             Self::RemotedIdentity(x) => (0x01_u16, x.smb_to_bytes()),
         };
-        let ctx_bytes = ctx_type.smb_to_bytes();
         [
-            ctx_bytes,
+            ctx_type.smb_to_bytes(),
+            (bytes.len() as u16).smb_to_bytes(),
+            vec![0; 4],
             bytes
         ].concat()
     }
@@ -74,6 +83,16 @@ pub struct RemotedIdentity {
     device_claims: BlobData,
 }
 
+impl RemotedIdentity {
+    pub fn user_sid(&self) -> &[u8] {
+        &self.user.sid_data.data
+    }
+
+    pub fn user_attributes(&self) -> &SidAttr {
+        &self.user.attr
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
 pub struct BlobData {
     #[smb_skip(start = 0, length = 2)]
@@ -6,9 +6,9 @@ use serde::{Deserialize, Serialize};
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask, SMBFilePipePrinterAccessMask};
-use crate::protocol::body::tree_connect::buffer::SMBTreeConnectBuffer;
+use crate::protocol::body::tree_connect::buffer::{SMBTreeConnectBuffer, SMBTreeConnectExtension};
 use crate::protocol::body::tree_connect::capabilities::SMBTreeConnectCapabilities;
-use crate::protocol::body::tree_connect::context::{LuidAttr, SidAttr};
+use crate::protocol::body::tree_connect::context::{LuidAttr, RemotedIdentity, SidAttr};
 use crate::protocol::body::tree_connect::flags::{SMBShareFlags, SMBTreeConnectFlags};
 use crate::server::share::{ResourceType, SharedResource};
 use crate::util::flags_helper::{impl_smb_byte_size_for_bitflag, impl_smb_from_bytes_for_bitflag, impl_smb_to_bytes_for_bitflag};
@@ -19,22 +19,42 @@ pub mod access_mask;
 pub mod flags;
 pub mod capabilities;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBByteSize, SMBFromBytes, SMBToBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBByteSize, SMBFromBytes, SMBToBytes)]
 #[smb_byte_tag(value = 09)]
 pub struct SMBTreeConnectRequest {
     #[smb_direct(start(fixed = 2))]
     flags: SMBTreeConnectFlags,
-    #[smb_enum(start(fixed = 4), discriminator(inner(start = 3, num_type = "u8")))]
+    #[smb_enum(start(fixed = 4), discriminator(inner(start = 2, num_type = "u8")))]
     buffer: SMBTreeConnectBuffer,
 }
 
 impl SMBTreeConnectRequest {
+    // The path is the full UNC name of the share, e.g. \\server\share
+    pub fn new(path: &str) -> Self {
+        Self {
+            flags: SMBTreeConnectFlags::empty(),
+            buffer: SMBTreeConnectBuffer::Path(path.into()),
+        }
+    }
+
     pub fn share(&self) -> &str {
         self.buffer.share()
     }
+
+    pub fn flags(&self) -> SMBTreeConnectFlags {
+        self.flags
+    }
+
+    pub fn extension(&self) -> Option<&SMBTreeConnectExtension> {
+        self.buffer.extension()
+    }
+
+    pub fn remoted_identity(&self) -> Option<&RemotedIdentity> {
+        self.extension().and_then(SMBTreeConnectExtension::remoted_identity)
+    }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBByteSize, SMBFromBytes, SMBToBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBByteSize, SMBFromBytes, SMBToBytes)]
 #[smb_byte_tag(value = 16)]
 pub struct SMBTreeConnectResponse {
     #[smb_direct(start(fixed = 2))]
@@ -71,7 +91,8 @@ impl SMBTreeConnectResponse {
             capabilities: SMBTreeConnectCapabilities::empty(),
         }
     }
-    pub fn for_share<S: SharedResource>(share: &S) -> Self {
+    // With no user, as before session setup finishes, only the share itself narrows the maximal access
+    pub fn for_share<S: SharedResource>(share: &S, uid: Option<&S::UserName>) -> Self {
         let share_type = match share.resource_type() {
             ResourceType::DISK => SMBShareType::Disk,
             ResourceType::IPC => SMBShareType::Pipe,
@@ -82,14 +103,29 @@ impl SMBTreeConnectResponse {
             share_type,
             reserved: Default::default(),
             share_flags,
-            capabilities: SMBTreeConnectCapabilities::empty(),
-            maximal_access: SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::from_bits_truncate(0x001f01ff)),
+            capabilities: share.capabilities(),
+            maximal_access: share.maximal_access(uid),
         }
     }
 
+    // A server that can't answer DFS referrals mustn't send clients looking for them
+    pub fn without_dfs(mut self) -> Self {
+        self.share_flags -= SMBShareFlags::DFS | SMBShareFlags::DFS_ROOT;
+        self.capabilities -= SMBTreeConnectCapabilities::DFS;
+        self
+    }
+
     pub fn access_mask(&self) -> &SMBAccessMask {
         &self.maximal_access
     }
+
+    pub fn share_flags(&self) -> SMBShareFlags {
+        self.share_flags
+    }
+
+    pub fn capabilities(&self) -> &SMBTreeConnectCapabilities {
+        &self.capabilities
+    }
 }
 
 #[repr(u8)]
@@ -133,3 +169,66 @@ impl_smb_from_bytes_for_bitflag! {
     SMBFilePipePrinterAccessMask
     SMBDirectoryAccessMask
 }
+
+#[cfg(test)]
+mod tests {
+    use smb_core::SMBFromBytes;
+
+    use crate::protocol::body::tree_connect::context::SidAttr;
+    use crate::protocol::body::tree_connect::flags::SMBTreeConnectFlags;
+    use crate::protocol::body::tree_connect::SMBTreeConnectRequest;
+
+    fn utf16(value: &str) -> Vec<u8> {
+        value.encode_utf16().flat_map(u16::to_le_bytes).collect()
+    }
+
+    #[test]
+    fn classic_requests_carry_only_the_path() {
+        let path = utf16("\\\\srv\\share");
+        // CLUSTER_RECONNECT alone doesn't change the buffer's form
+        let mut bytes = vec![9, 0, 1, 0, 72, 0, path.len() as u8, 0];
+        bytes.extend_from_slice(&path);
+
+        let (_, request) = SMBTreeConnectRequest::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(request.flags(), SMBTreeConnectFlags::CLUSTER_RECONNECT);
+        assert_eq!(request.share(), "share");
+        assert!(request.extension().is_none());
+        assert!(request.remoted_identity().is_none());
+    }
+
+    // A REMOTED_IDENTITY with a user SID attribute and every other array and blob left empty
+    fn remoted_identity() -> Vec<u8> {
+        let mut identity = vec![1, 0, 36, 0, 28, 0];
+        for _ in 0..11 {
+            identity.extend_from_slice(&[34, 0]);
+        }
+        identity.extend_from_slice(&[0, 0, 0x07, 0, 0, 0]);
+        identity.extend_from_slice(&[0, 0]);
+        identity
+    }
+
+    #[test]
+    fn extended_requests_carry_the_path_and_contexts() {
+        let path = utf16("\\\\srv\\share");
+        let identity = remoted_identity();
+        // PathOffset counts from the SMB2 header, the path sitting right after the 24 fixed bytes
+        let mut bytes = vec![9, 0, 0x04, 0, 88, 0, path.len() as u8, 0];
+        bytes.extend_from_slice(&48_u32.to_le_bytes());
+        bytes.extend_from_slice(&1_u16.to_le_bytes());
+        bytes.extend_from_slice(&[0; 10]);
+        bytes.extend_from_slice(&path);
+        bytes.resize(48, 0);
+        bytes.extend_from_slice(&[0x01, 0, identity.len() as u8, 0, 0, 0, 0, 0]);
+        bytes.extend_from_slice(&identity);
+
+        let (_, request) = SMBTreeConnectRequest::smb_from_bytes(&bytes).unwrap();
+        assert_eq!(request.flags(), SMBTreeConnectFlags::EXTENSION_PRESENT);
+        assert_eq!(request.share(), "share");
+        let extension = request.extension().unwrap();
+        assert_eq!(extension.path_name(), "\\\\srv\\share");
+        assert_eq!(extension.contexts().len(), 1);
+        let identity = request.remoted_identity().unwrap();
+        assert!(identity.user_sid().is_empty());
+        assert_eq!(identity.user_attributes(), &(SidAttr::GROUP_MANDATORY | SidAttr::GROUP_ENABLED_BY_DEFAULT | SidAttr::GROUP_ENABLED));
+    }
+}
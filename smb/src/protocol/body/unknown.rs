@@ -0,0 +1,34 @@
+use serde::{Deserialize, Serialize};
+
+use smb_core::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBToBytes};
+
+// The body of a command we have no structure for. Without a layout there's no telling where it ends, so
+// everything past the header is kept as is
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Default)]
+pub struct SMBUnknownBody {
+    data: Vec<u8>,
+}
+
+impl SMBUnknownBody {
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl SMBByteSize for SMBUnknownBody {
+    fn smb_byte_size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl SMBFromBytes for SMBUnknownBody {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        Ok((&input[input.len()..], Self { data: input.to_vec() }))
+    }
+}
+
+impl SMBToBytes for SMBUnknownBody {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+}
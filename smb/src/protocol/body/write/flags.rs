@@ -4,9 +4,10 @@ use serde::{Deserialize, Serialize};
 use crate::util::flags_helper::{impl_smb_byte_size_for_bitflag, impl_smb_from_bytes_for_bitflag, impl_smb_to_bytes_for_bitflag};
 
 bitflags! {
-    #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
     pub struct SMBWriteFlags: u8 {
         const WRITE_THROUGH = 0x01;
+        // Best-effort: the data is written straight to the backing handle, but no cache bypass is attempted
         const WRITE_UNBUFFERED = 0x02;
     }
 }
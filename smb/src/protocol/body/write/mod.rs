@@ -2,15 +2,23 @@ use std::marker::PhantomData;
 
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::read::channel::SMBRWChannel;
+use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
 use crate::protocol::body::write::flags::SMBWriteFlags;
+use crate::server::share::ResourceHandle;
 
-mod flags;
+pub mod flags;
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+// The offset a client sends to write at the current end of file, MS-SMB2 2.2.21
+pub const APPEND_TO_EOF: u64 = u64::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 49)]
 pub struct SMBWriteRequest {
     #[smb_direct(start(fixed = 4))]
@@ -31,7 +39,71 @@ pub struct SMBWriteRequest {
     data_to_write: Vec<u8>,
 }
 
-#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
+impl SMBWriteRequest {
+    pub fn file_id(&self) -> &SMBFileId {
+        &self.file_id
+    }
+
+    pub fn write_offset(&self) -> u64 {
+        self.write_offset
+    }
+
+    pub fn flags(&self) -> SMBWriteFlags {
+        self.flags
+    }
+
+    pub fn channel(&self) -> SMBRWChannel {
+        self.channel
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data_to_write
+    }
+
+    // MS-SMB2 3.3.5.13: the data is bounded by the negotiated MaxWriteSize, and the open itself has to have
+    // been granted write access
+    pub fn validate(&self, granted_access: &SMBAccessMask, max_write_size: u32) -> SMBResult<()> {
+        if self.data_to_write.len() > max_write_size as usize {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        if !granted_access.includes_write_data() {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
+        Ok(())
+    }
+
+    // Appends land at the current end of file, whatever offset the client sent
+    pub fn offset_for<H: ResourceHandle + ?Sized>(&self, handle: &H, granted_access: &SMBAccessMask) -> SMBResult<u64> {
+        if self.write_offset == APPEND_TO_EOF || granted_access.is_append_only() {
+            return Ok(handle.metadata()?.actual_size);
+        }
+        Ok(self.write_offset)
+    }
+
+    pub fn write_to<H: ResourceHandle + ?Sized>(&self, handle: &H, granted_access: &SMBAccessMask) -> SMBResult<u32> {
+        let offset = self.offset_for(handle, granted_access)?;
+        let written = handle.write_at(offset, &self.data_to_write)?;
+        self.written_through(handle, written)
+    }
+
+    // The same write through the handle's async IO, so the server's runtime threads aren't held up by the disk
+    #[cfg(feature = "async")]
+    pub async fn write_to_async<H: ResourceHandle + ?Sized>(&self, handle: &H, granted_access: &SMBAccessMask) -> SMBResult<u32> {
+        let offset = self.offset_for(handle, granted_access)?;
+        let written = handle.write_at_async(offset, &self.data_to_write).await?;
+        self.written_through(handle, written)
+    }
+
+    // WRITE_THROUGH wants the data on stable storage before the response goes out
+    fn written_through<H: ResourceHandle + ?Sized>(&self, handle: &H, written: u32) -> SMBResult<u32> {
+        if self.flags.contains(SMBWriteFlags::WRITE_THROUGH) {
+            handle.sync()?;
+        }
+        Ok(written)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, SMBByteSize, SMBToBytes, SMBFromBytes, Serialize, Deserialize)]
 #[smb_byte_tag(value = 17)]
 pub struct SMBWriteResponse {
     #[smb_skip(start = 2, length = 2)]
@@ -44,4 +116,122 @@ pub struct SMBWriteResponse {
     write_channel_info_offset: PhantomData<Vec<u8>>,
     #[smb_skip(start = 14, length = 2)]
     write_channel_info_len: PhantomData<Vec<u8>>,
-}
\ No newline at end of file
+}
+
+impl SMBWriteResponse {
+    pub fn new(bytes_written: u32) -> Self {
+        Self {
+            reserved: PhantomData,
+            bytes_written,
+            remaining_bytes: PhantomData,
+            write_channel_info_offset: PhantomData,
+            write_channel_info_len: PhantomData,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::read::channel::SMBRWChannel;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::protocol::body::write::flags::SMBWriteFlags;
+    use crate::protocol::body::write::{APPEND_TO_EOF, SMBWriteRequest};
+    use crate::server::share::recording::RecordingHandle;
+
+    fn access(mask: SMBFilePipePrinterAccessMask) -> SMBAccessMask {
+        SMBAccessMask::FilePipePrinter(mask)
+    }
+
+    pub(crate) fn write_request(flags: SMBWriteFlags) -> SMBWriteRequest {
+        SMBWriteRequest {
+            write_length: 4,
+            write_offset: 0,
+            file_id: SMBFileId { persistent: 0, volatile: 0 },
+            channel: SMBRWChannel::None,
+            remaining_bytes: 0,
+            flags,
+            channel_information: vec![],
+            data_to_write: vec![1, 2, 3, 4],
+        }
+    }
+
+    pub(crate) fn write_request_for(file_id: SMBFileId, data: Vec<u8>) -> SMBWriteRequest {
+        SMBWriteRequest {
+            write_length: data.len() as u32,
+            file_id,
+            data_to_write: data,
+            ..write_request(SMBWriteFlags::empty())
+        }
+    }
+
+    #[test]
+    fn oversized_or_unauthorised_writes_are_refused() {
+        let write = access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        assert!(write_request(SMBWriteFlags::empty()).validate(&write, 4).is_ok());
+
+        let oversized = write_request(SMBWriteFlags::empty()).validate(&write, 3);
+        assert!(matches!(oversized, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+
+        let read_only = access(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let denied = write_request(SMBWriteFlags::empty()).validate(&read_only, 65536);
+        assert!(matches!(denied, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+    }
+
+    #[test]
+    fn write_through_syncs_handle() {
+        let handle = RecordingHandle::default();
+        let written = write_request(SMBWriteFlags::WRITE_THROUGH).write_to(&handle, &access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA)).unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(handle.writes(), vec![(0, vec![1, 2, 3, 4])]);
+        assert_eq!(handle.syncs(), 1);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_write_goes_through_the_handle_async_io() {
+        let handle = RecordingHandle::default();
+        let written = write_request(SMBWriteFlags::WRITE_THROUGH).write_to_async(&handle, &access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA)).await.unwrap();
+        assert_eq!(written, 4);
+        assert_eq!(handle.writes(), vec![(0, vec![1, 2, 3, 4])]);
+        assert_eq!(handle.syncs(), 1);
+        assert_eq!(handle.async_io(), 1);
+    }
+
+    #[test]
+    fn buffered_write_does_not_sync_handle() {
+        let handle = RecordingHandle::default();
+        write_request(SMBWriteFlags::empty()).write_to(&handle, &access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA)).unwrap();
+        assert_eq!(handle.syncs(), 0);
+    }
+
+    #[test]
+    fn append_only_opens_write_sequentially_at_eof() {
+        let handle = RecordingHandle::default();
+        let append = access(SMBFilePipePrinterAccessMask::FILE_APPEND_DATA);
+        assert_eq!(write_request(SMBWriteFlags::empty()).write_to(&handle, &append).unwrap(), 4);
+        // The offset of 0 is ignored, the second write goes after the first
+        let mut second = write_request(SMBWriteFlags::empty());
+        second.data_to_write = vec![5, 6];
+        assert_eq!(second.write_to(&handle, &append).unwrap(), 2);
+        assert_eq!(handle.writes(), vec![(0, vec![1, 2, 3, 4]), (4, vec![5, 6])]);
+        assert_eq!(handle.contents(), vec![1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn end_of_file_offset_appends_with_full_write_access() {
+        let handle = RecordingHandle::default();
+        let write = access(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA | SMBFilePipePrinterAccessMask::FILE_APPEND_DATA);
+        write_request(SMBWriteFlags::empty()).write_to(&handle, &write).unwrap();
+        let mut overwrite = write_request(SMBWriteFlags::empty());
+        overwrite.write_offset = 2;
+        overwrite.write_to(&handle, &write).unwrap();
+        let mut append = write_request(SMBWriteFlags::empty());
+        append.write_offset = APPEND_TO_EOF;
+        append.write_to(&handle, &write).unwrap();
+        assert_eq!(handle.writes().iter().map(|(offset, _)| *offset).collect::<Vec<u64>>(), vec![0, 2, 6]);
+    }
+}
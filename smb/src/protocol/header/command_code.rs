@@ -1,10 +1,14 @@
-use num_enum::{IntoPrimitive, TryFromPrimitive};
+use num_enum::{FromPrimitive, IntoPrimitive, TryFromPrimitive};
 use serde::{Deserialize, Serialize};
 
+use smb_core::{SMBByteSize, SMBFromBytes, SMBParseResult, SMBToBytes};
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
+// Body discriminator shared by every command code we don't recognize, see SMBBody::UnknownCommand
+pub const UNKNOWN_COMMAND_DISCRIMINATOR: u64 = 0x998;
+
 #[repr(u16)]
-#[derive(Debug, Eq, PartialEq, TryFromPrimitive, IntoPrimitive, Serialize, Deserialize, Clone, Copy, SMBFromBytes, SMBByteSize, SMBToBytes)]
+#[derive(Debug, Eq, PartialEq, FromPrimitive, IntoPrimitive, Serialize, Deserialize, Clone, Copy)]
 pub enum SMBCommandCode {
     Negotiate = 0x0,
     SessionSetup,
@@ -25,12 +29,39 @@ pub enum SMBCommandCode {
     QueryInfo,
     SetInfo,
     OplockBreak,
-    LegacyNegotiate
+    // Never on the wire, it marks SMB1 negotiates upgraded to SMB2 and shares LegacyCommand's discriminator.
+    // 0x13 is left to SERVER_TO_CLIENT_NOTIFICATION, which we don't support
+    LegacyNegotiate = 0x999,
+    // Anything else still parses so the server can answer it with STATUS_NOT_IMPLEMENTED
+    #[num_enum(catch_all)]
+    Unknown(u16),
 }
 
 impl Into<u64> for SMBCommandCode {
     fn into(self) -> u64 {
-        self as u16 as u64
+        match self {
+            SMBCommandCode::Unknown(_) => UNKNOWN_COMMAND_DISCRIMINATOR,
+            known => u16::from(known) as u64,
+        }
+    }
+}
+
+impl SMBByteSize for SMBCommandCode {
+    fn smb_byte_size(&self) -> usize {
+        std::mem::size_of::<u16>()
+    }
+}
+
+impl SMBFromBytes for SMBCommandCode {
+    fn smb_from_bytes(input: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized {
+        let (remaining, code) = u16::smb_from_bytes(input)?;
+        Ok((remaining, Self::from(code)))
+    }
+}
+
+impl SMBToBytes for SMBCommandCode {
+    fn smb_to_bytes(&self) -> Vec<u8> {
+        u16::from(*self).smb_to_bytes()
     }
 }
 
@@ -114,4 +145,25 @@ impl Into<u64> for LegacySMBCommandCode {
     fn into(self) -> u64 {
         self as u8 as u64
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+    use crate::protocol::header::command_code::{SMBCommandCode, UNKNOWN_COMMAND_DISCRIMINATOR};
+
+    #[test]
+    fn unrecognized_codes_fall_back_to_unknown() {
+        let (remaining, known) = SMBCommandCode::smb_from_bytes(&[0x10, 0x00]).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(known, SMBCommandCode::QueryInfo);
+
+        let (_, unknown) = SMBCommandCode::smb_from_bytes(&[0x13, 0x00]).unwrap();
+        assert_eq!(unknown, SMBCommandCode::Unknown(0x13));
+        assert_eq!(unknown.smb_to_bytes(), vec![0x13, 0x00]);
+        assert_eq!(unknown.smb_byte_size(), 2);
+        assert_eq!(Into::<u64>::into(unknown), UNKNOWN_COMMAND_DISCRIMINATOR);
+        assert_eq!(Into::<u64>::into(SMBCommandCode::QueryInfo), 0x10);
+    }
+}
@@ -9,7 +9,7 @@ use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::byte_helper::{u16_to_bytes, u64_to_bytes};
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBByteSize, SMBToBytes)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
 pub struct SMBExtra {
     #[smb_direct(start(fixed = 0))]
     pid_high: u16,
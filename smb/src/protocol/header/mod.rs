@@ -1,11 +1,11 @@
 use std::cmp::min;
-use std::marker::PhantomData;
 
 use nom::error::ErrorKind;
 use nom::IResult;
 use serde::{Deserialize, Serialize};
 
 use smb_core::{SMBFromBytes, SMBToBytes};
+use smb_core::nt_status::NTStatus;
 use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
 
 use crate::protocol::header::command_code::{LegacySMBCommandCode, SMBCommandCode};
@@ -19,6 +19,12 @@ pub mod status;
 pub mod flags;
 pub mod flags2;
 pub mod extra;
+pub mod transform;
+
+// Where the 16 byte signature sits in the sync header
+pub const SIGNATURE_OFFSET: usize = 48;
+
+const DEFAULT_PROCESS_ID: u32 = 0xFEFF;
 
 pub enum SMBSender {
     Client = 0x0,
@@ -39,13 +45,21 @@ pub trait Header: SMBFromBytes + SMBToBytes {
     }
 
     fn sender(&self) -> SMBSender;
+
+    // Whether the body that follows is an ERROR response rather than the command's own
+    fn carries_error_body(&self) -> bool {
+        false
+    }
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBToBytes, SMBByteSize)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBToBytes, SMBByteSize)]
 #[smb_byte_tag(value = 0xFE, order = 0)]
 #[smb_string_tag(value = "SMB", order = 1)]
 #[smb_byte_tag(value = 64, order = 2)]
 pub struct SMBSyncHeader {
+    // Credits this request costs, zero from 2.0.2 clients is the same as one
+    #[smb_direct(start(fixed = 6))]
+    pub credit_charge: u16,
     #[smb_direct(start(fixed = 8))]
     pub channel_sequence: u32,
     #[smb_direct(start(fixed = 12))]
@@ -58,8 +72,9 @@ pub struct SMBSyncHeader {
     pub next_command: u32,
     #[smb_direct(start(fixed = 24))]
     pub message_id: u64,
-    #[smb_skip(start = 32, length = 4, value = "[0xFF, 0xFE, 0, 0]")]
-    pub reserved: PhantomData<[u8; 4]>,
+    // With ASYNC_COMMAND set, this and tree_id are the low and high halves of the AsyncId
+    #[smb_direct(start(fixed = 32))]
+    pub process_id: u32,
     #[smb_direct(start(fixed = 36))]
     pub tree_id: u32,
     #[smb_direct(start(fixed = 40))]
@@ -68,8 +83,8 @@ pub struct SMBSyncHeader {
     pub signature: [u8; 16],
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, SMBFromBytes, SMBByteSize, SMBToBytes)]
-#[smb_byte_tag(value = 0xFE)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBByteSize, SMBToBytes)]
+#[smb_byte_tag(value = 0xFF)]
 #[smb_string_tag("SMB")]
 pub struct LegacySMBHeader {
     #[smb_direct(start(fixed = 4))]
@@ -82,13 +97,13 @@ pub struct LegacySMBHeader {
     flags2: LegacySMBFlags2,
     #[smb_direct(start(fixed = 12))]
     extra: SMBExtra,
-    #[smb_direct(start(fixed = 22))]
-    tid: u16,
     #[smb_direct(start(fixed = 24))]
-    pid: u16,
+    tid: u16,
     #[smb_direct(start(fixed = 26))]
-    uid: u16,
+    pid: u16,
     #[smb_direct(start(fixed = 28))]
+    uid: u16,
+    #[smb_direct(start(fixed = 30))]
     mid: u16,
 }
 
@@ -106,6 +121,22 @@ impl Header for SMBSyncHeader {
             SMBSender::Client
         }
     }
+
+    fn carries_error_body(&self) -> bool {
+        if !matches!(self.sender(), SMBSender::Server) {
+            return false;
+        }
+        match NTStatus::try_from(self.channel_sequence) {
+            // STATUS_PENDING only comes on interim responses and those are ERROR bodies
+            Ok(NTStatus::StatusSuccess) => false,
+            Ok(NTStatus::MoreProcessingRequired) => self.command != SMBCommandCode::SessionSetup,
+            // Partial data still comes back in the command's response body
+            Ok(NTStatus::BufferOverflow) => !matches!(self.command,
+                SMBCommandCode::Read | SMBCommandCode::IOCTL | SMBCommandCode::QueryInfo
+                | SMBCommandCode::QueryDirectory | SMBCommandCode::ChangeNotify),
+            _ => true,
+        }
+    }
 }
 
 impl Header for LegacySMBHeader {
@@ -124,6 +155,12 @@ impl Header for LegacySMBHeader {
     }
 }
 
+impl LegacySMBHeader {
+    pub fn flags2(&self) -> LegacySMBFlags2 {
+        self.flags2
+    }
+}
+
 impl SMBSyncHeader {
     pub fn new(
         command: SMBCommandCode,
@@ -136,12 +173,13 @@ impl SMBSyncHeader {
     ) -> Self {
         SMBSyncHeader {
             command,
+            credit_charge: 0,
             channel_sequence: 0,
             credits: 0,
             flags,
             next_command,
             message_id,
-            reserved: PhantomData,
+            process_id: DEFAULT_PROCESS_ID,
             tree_id,
             session_id,
             signature,
@@ -153,11 +191,12 @@ impl SMBSyncHeader {
             LegacySMBCommandCode::Negotiate => Some(Self {
                 command: SMBCommandCode::LegacyNegotiate,
                 flags: SMBFlags::empty(),
+                credit_charge: 0,
                 channel_sequence: 0,
                 next_command: 0,
                 credits: 0,
                 message_id: legacy_header.mid as u64,
-                reserved: PhantomData,
+                process_id: DEFAULT_PROCESS_ID,
                 tree_id: legacy_header.tid as u32,
                 session_id: legacy_header.uid as u64,
                 signature: [0; 16],
@@ -170,20 +209,66 @@ impl SMBSyncHeader {
         Self {
             command: self.command,
             flags: SMBFlags::SERVER_TO_REDIR,
+            credit_charge: self.credit_charge,
             channel_sequence,
             next_command: 0,
             credits: self.credits,
             message_id: self.message_id,
-            reserved: PhantomData,
+            process_id: DEFAULT_PROCESS_ID,
             tree_id,
             session_id,
             signature: [0; 16],
         }
     }
 
+    pub fn is_interim_response(&self) -> bool {
+        self.flags.contains(SMBFlags::SERVER_TO_REDIR | SMBFlags::ASYNC_COMMAND)
+            && self.channel_sequence == NTStatus::Pending as u32
+    }
+
+    pub fn async_id(&self) -> Option<u64> {
+        self.flags.contains(SMBFlags::ASYNC_COMMAND)
+            .then_some(((self.tree_id as u64) << 32) | self.process_id as u64)
+    }
+
+    pub fn set_async_id(&mut self, async_id: u64) {
+        self.flags |= SMBFlags::ASYNC_COMMAND;
+        self.process_id = async_id as u32;
+        self.tree_id = (async_id >> 32) as u32;
+    }
+
+    // The (MessageId, AsyncId) pair a CANCEL carries to name the request it's cancelling
+    pub fn cancel_ids(&self) -> (u64, Option<u64>) {
+        (self.message_id, self.async_id())
+    }
+
     pub fn set_signature(&mut self, signature: &[u8]) {
         self.flags |= SMBFlags::SIGNED;
         self.signature[..min(16, signature.len())]
             .copy_from_slice(&signature[..min(16, signature.len())]);
     }
+
+    // The header with what signing adds taken back out, the signature and the SIGNED flag
+    fn unsigned(&self) -> Self {
+        let mut header = self.clone();
+        header.flags -= SMBFlags::SIGNED;
+        header.signature = [0; 16];
+        header
+    }
+
+    // Equal apart from signing, so a signed message still matches the one it was signed from
+    pub fn eq_ignoring_signature(&self, other: &Self) -> bool {
+        self.unsigned() == other.unsigned()
+    }
+
+    // Also leaves out the message id and credits, which a replayed request gets afresh
+    pub fn eq_ignoring_volatile(&self, other: &Self) -> bool {
+        let stable = |header: &Self| Self {
+            credit_charge: 0,
+            credits: 0,
+            message_id: 0,
+            ..header.unsigned()
+        };
+        stable(self) == stable(other)
+    }
 }
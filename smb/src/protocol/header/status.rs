@@ -14,13 +14,13 @@ use smb_core::error::SMBError;
 
 use crate::byte_helper::u16_to_bytes;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub enum SMBStatus {
     NTStatus(NTStatusCode),
     DosError(char, char, u16),
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct NTStatusCode {
     level: NTStatusLevel,
     facility: [u8; 2],
@@ -55,8 +55,7 @@ impl SMBStatus {
                         |(first, second, third)| Self::DosError(first.into(), second.into(), third),
                     )(bytes)
                 }
-            })?;
-        todo!()
+            })
     }
 }
 
@@ -0,0 +1,46 @@
+use std::marker::PhantomData;
+
+use serde::{Deserialize, Serialize};
+
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+// Everything after the signature is authenticated along with the encrypted message
+pub const TRANSFORM_AAD_OFFSET: usize = 20;
+
+const ENCRYPTED_FLAG: u16 = 0x0001;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, SMBFromBytes, SMBToBytes, SMBByteSize)]
+#[smb_byte_tag(value = 0xFD, order = 0)]
+#[smb_string_tag(value = "SMB", order = 1)]
+pub struct SMBTransformHeader {
+    #[smb_direct(start(fixed = 4))]
+    pub signature: [u8; 16],
+    #[smb_direct(start(fixed = 20))]
+    pub nonce: [u8; 16],
+    #[smb_direct(start(fixed = 36))]
+    pub original_message_size: u32,
+    #[smb_skip(start = 40, length = 2)]
+    reserved: PhantomData<Vec<u8>>,
+    // Flags for 3.1.1, EncryptionAlgorithm for 3.0.x; both use 0x0001
+    #[smb_direct(start(fixed = 42))]
+    pub flags: u16,
+    #[smb_direct(start(fixed = 44))]
+    pub session_id: u64,
+}
+
+impl SMBTransformHeader {
+    pub fn new(nonce: [u8; 16], original_message_size: u32, session_id: u64) -> Self {
+        Self {
+            signature: [0; 16],
+            nonce,
+            original_message_size,
+            reserved: PhantomData,
+            flags: ENCRYPTED_FLAG,
+            session_id,
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags == ENCRYPTED_FLAG
+    }
+}
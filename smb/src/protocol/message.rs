@@ -8,34 +8,53 @@ use hmac::Hmac;
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 
-use smb_core::{SMBParseResult, SMBResult};
+use smb_core::{SMBFromBytes, SMBParseResult, SMBResult, SMBToBytes};
 use smb_core::error::SMBError;
 
-use crate::byte_helper::u16_to_bytes;
 use crate::protocol::body::{Body, LegacySMBBody, SMBBody};
 use crate::protocol::body::negotiate::context::SigningAlgorithm;
-use crate::protocol::header::{Header, LegacySMBHeader, SMBSyncHeader};
+use crate::protocol::header::{Header, LegacySMBHeader, SIGNATURE_OFFSET, SMBSyncHeader};
+use crate::protocol::header::transform::SMBTransformHeader;
 
 pub type SMBSyncMessage = SMBMessage<SMBSyncHeader, SMBBody>;
 pub type SMBLegacyMessage = SMBMessage<LegacySMBHeader, LegacySMBBody>;
 
-#[derive(Serialize, Deserialize, PartialEq, Eq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
 pub struct SMBMessage<S: Header, T: Body<S>> {
     pub header: S,
     pub body: T,
+    // Whether the message reached us wrapped in a transform header
+    #[serde(skip)]
+    encrypted: bool,
 }
 
 impl<S: Header, T: Body<S>> SMBMessage<S, T> {
     pub fn new(header: S, body: T) -> Self {
         SMBMessage {
             header,
-            body
+            body,
+            encrypted: false,
         }
     }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    pub fn set_encrypted(&mut self, encrypted: bool) {
+        self.encrypted = encrypted;
+    }
 }
 
 pub trait Message {
+    // The SMB2 message alone, without any transport framing
     fn as_bytes(&self) -> Vec<u8>;
+
+    // The message as it goes on the wire, behind its transport length prefix
+    fn framed_bytes(&self) -> Vec<u8> {
+        with_transport_framing(self.as_bytes())
+    }
+
     fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> where Self: Sized;
 
     fn signature(&self, nonce: &[u8], key: &[u8], algorithm: SigningAlgorithm) -> SMBResult<Vec<u8>>;
@@ -43,26 +62,41 @@ pub trait Message {
 
 impl SMBMessage<SMBSyncHeader, SMBBody> {
     pub fn from_legacy(legacy_message: SMBMessage<LegacySMBHeader, LegacySMBBody>) -> Option<Self> {
+        let flags2 = legacy_message.header.flags2();
         let header = SMBSyncHeader::from_legacy_header(legacy_message.header)?;
-        let body = SMBBody::LegacyCommand(legacy_message.body);
-        Some(Self { header, body })
+        let body = SMBBody::LegacyCommand(legacy_message.body.with_flags2(flags2));
+        Some(Self::new(header, body))
+    }
+
+    // Header and body as they're signed: no transport framing and the signature field zeroed
+    pub fn signature_bytes_region(&self) -> Vec<u8> {
+        let mut bytes = [self.header.smb_to_bytes(), self.body.smb_to_bytes()].concat();
+        bytes[SIGNATURE_OFFSET..(SIGNATURE_OFFSET + 16)].fill(0);
+        bytes
+    }
+
+    // Only what goes on the wire is compared, not whether the message arrived encrypted
+    pub fn eq_ignoring_signature(&self, other: &Self) -> bool {
+        self.header.eq_ignoring_signature(&other.header) && self.body == other.body
+    }
+
+    pub fn eq_ignoring_volatile(&self, other: &Self) -> bool {
+        self.header.eq_ignoring_volatile(&other.header) && self.body == other.body
     }
 }
 
 impl<S: Header + Debug, T: Body<S>> Message for SMBMessage<S, T> {
     fn as_bytes(&self) -> Vec<u8> {
-        let smb2_message = [self.header.smb_to_bytes(), self.body.smb_to_bytes()].concat();
-        let mut len_bytes = u16_to_bytes(smb2_message.len() as u16);
-        len_bytes.reverse();
-        [[0, 0].to_vec(), len_bytes.to_vec(), smb2_message].concat()
+        [self.header.smb_to_bytes(), self.body.smb_to_bytes()].concat()
     }
 
     fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> {
         let (remaining, header) = S::smb_from_bytes(bytes)?;
         println!("header: {:?}", header);
-        let discriminator_code = (header.command_code().into()) | ((header.sender() as u64) << 16);
+        let discriminator_code = (header.command_code().into()) | ((header.sender() as u64) << 16)
+            | ((header.carries_error_body() as u64) << 17);
         let (remaining, body) = T::smb_enum_from_bytes(remaining, discriminator_code)?;
-        Ok((remaining, Self { header, body }))
+        Ok((remaining, Self::new(header, body)))
     }
 
     fn signature(&self, nonce: &[u8], key: &[u8], algorithm: SigningAlgorithm) -> SMBResult<Vec<u8>> {
@@ -94,4 +128,211 @@ impl<S: Header + Debug, T: Body<S>> Message for SMBMessage<S, T> {
         };
         Ok(res)
     }
-}
\ No newline at end of file
+}
+
+// A message as it came off the transport, kept with the bytes it arrived as. Signatures and the preauth
+// integrity hash are taken over those, not over the message serialized again. Sealed ones are opened by the
+// connection, only it knows the session keys
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum SMBFrame {
+    Plain(SMBSyncMessage, Vec<u8>),
+    Sealed(SMBEncryptedMessage),
+}
+
+// A message sealed under a transform header; the payload is the encrypted SMB2 message
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct SMBEncryptedMessage {
+    pub header: SMBTransformHeader,
+    pub payload: Vec<u8>,
+}
+
+impl SMBEncryptedMessage {
+    pub fn new(header: SMBTransformHeader, payload: Vec<u8>) -> Self {
+        Self { header, payload }
+    }
+}
+
+impl Message for SMBEncryptedMessage {
+    fn as_bytes(&self) -> Vec<u8> {
+        [self.header.smb_to_bytes(), self.payload.clone()].concat()
+    }
+
+    fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> {
+        let (remaining, header) = SMBTransformHeader::smb_from_bytes(bytes)?;
+        let length = header.original_message_size as usize;
+        let payload = remaining.get(..length)
+            .ok_or(SMBError::payload_too_small(length, remaining.len()))?;
+        Ok((&remaining[length..], Self::new(header, payload.to_vec())))
+    }
+
+    fn signature(&self, _nonce: &[u8], _key: &[u8], _algorithm: SigningAlgorithm) -> SMBResult<Vec<u8>> {
+        Err(SMBError::crypto_error("Encrypted messages are authenticated by their transform header"))
+    }
+}
+
+// Messages compounded into one transport frame, MS-SMB2 3.2.4.1.4 and 3.3.4.1.3. Every message but the
+// last is padded to 8 bytes and its NextCommand points at the one after it. Offsets are fixed when the
+// compound is built, so members have to be signed after that
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SMBCompoundMessage {
+    messages: Vec<SMBSyncMessage>,
+}
+
+impl SMBCompoundMessage {
+    pub fn new(mut messages: Vec<SMBSyncMessage>) -> Self {
+        let last = messages.len().saturating_sub(1);
+        for (idx, message) in messages.iter_mut().enumerate() {
+            message.header.next_command = 0;
+            if idx != last {
+                message.header.next_command = compound_aligned(message.as_bytes().len()) as u32;
+            }
+        }
+        Self { messages }
+    }
+
+    pub fn messages(&self) -> &[SMBSyncMessage] {
+        &self.messages
+    }
+
+    pub fn messages_mut(&mut self) -> &mut [SMBSyncMessage] {
+        &mut self.messages
+    }
+
+    pub fn into_messages(self) -> Vec<SMBSyncMessage> {
+        self.messages
+    }
+}
+
+impl Message for SMBCompoundMessage {
+    fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for message in &self.messages {
+            let start = bytes.len();
+            bytes.extend(message.as_bytes());
+            if message.header.next_command != 0 {
+                bytes.resize(start + message.header.next_command as usize, 0);
+            }
+        }
+        bytes
+    }
+
+    // Each member is cut at its NextCommand first, so a body that runs to the end of its input stops there
+    fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> {
+        let mut messages = Vec::new();
+        let mut remaining = bytes;
+        loop {
+            let (_, header) = SMBSyncHeader::smb_from_bytes(remaining)?;
+            let next_command = header.next_command as usize;
+            if next_command == 0 {
+                let (rest, message) = SMBSyncMessage::parse(remaining)?;
+                messages.push(message);
+                return Ok((rest, Self { messages }));
+            }
+            let member = remaining.get(..next_command)
+                .ok_or(SMBError::payload_too_small(next_command, remaining.len()))?;
+            let (_, message) = SMBSyncMessage::parse(member)?;
+            messages.push(message);
+            remaining = &remaining[next_command..];
+        }
+    }
+
+    fn signature(&self, _nonce: &[u8], _key: &[u8], _algorithm: SigningAlgorithm) -> SMBResult<Vec<u8>> {
+        Err(SMBError::crypto_error("Compounded messages are signed one by one"))
+    }
+}
+
+fn compound_aligned(length: usize) -> usize {
+    (length + 7) & !7
+}
+
+// Direct TCP (MS-SMB2 2.1): a zero byte then a 24-bit big-endian length. A NetBIOS session message
+// has the same shape, so this one prefix serves both transports
+fn with_transport_framing(smb2_message: Vec<u8>) -> Vec<u8> {
+    let length = (smb2_message.len() as u32).to_be_bytes();
+    [&[0], &length[1..], &smb2_message[..]].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::read::SMBReadResponse;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::body::empty::SMBEmpty;
+    use crate::protocol::message::{Message, SMBCompoundMessage, SMBSyncMessage};
+
+    fn read_response(len: usize) -> SMBSyncMessage {
+        let header = SMBSyncHeader::new(SMBCommandCode::Read, SMBFlags::SERVER_TO_REDIR, 0, 5, 1, 9, [0; 16]);
+        SMBSyncMessage::new(header, SMBBody::ReadResponse(SMBReadResponse::new(vec![7; len])))
+    }
+
+    #[test]
+    fn framed_length_matches_the_message() {
+        for len in [16, 70_000] {
+            let message = read_response(len);
+            let bytes = message.as_bytes();
+            let framed = message.framed_bytes();
+            // Lengths past u16::MAX need the full 24 bits of the prefix
+            assert_eq!(framed[0], 0);
+            assert_eq!(u32::from_be_bytes([0, framed[1], framed[2], framed[3]]) as usize, bytes.len());
+            assert_eq!(&framed[4..], bytes.as_slice());
+        }
+    }
+
+    #[test]
+    fn unknown_commands_parse_with_their_raw_body() {
+        let header = SMBSyncHeader::new(SMBCommandCode::Unknown(0x13), SMBFlags::empty(), 0, 4, 1, 9, [0; 16]);
+        let message = SMBSyncMessage::new(header, SMBBody::UnknownCommand(Default::default()));
+        let mut bytes = message.as_bytes();
+        bytes.extend_from_slice(&[8, 0, 1, 2, 3, 4, 5, 6]);
+
+        let (remaining, parsed) = SMBSyncMessage::parse(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed.header.command, SMBCommandCode::Unknown(0x13));
+        let SMBBody::UnknownCommand(body) = &parsed.body else {
+            panic!("expected an unknown command body, got {:?}", parsed.body);
+        };
+        assert_eq!(body.data(), &[8, 0, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(parsed.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn compounds_round_trip_through_their_offsets() {
+        let echo = SMBSyncHeader::new(SMBCommandCode::Echo, SMBFlags::SERVER_TO_REDIR, 0, 4, 0, 0, [0; 16]);
+        let compound = SMBCompoundMessage::new(vec![
+            SMBSyncMessage::new(echo, SMBBody::EchoResponse(SMBEmpty)),
+            read_response(3),
+        ]);
+        let bytes = compound.as_bytes();
+        // 68 bytes of echo padded to 72
+        assert_eq!(compound.messages()[0].header.next_command, 72);
+        assert_eq!(compound.messages()[1].header.next_command, 0);
+        assert_eq!(&bytes[72..76], &[0xFE, b'S', b'M', b'B']);
+
+        let (remaining, parsed) = SMBCompoundMessage::parse(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, compound);
+    }
+
+    #[test]
+    fn signing_is_ignored_when_asked() {
+        let unsigned = read_response(8);
+        let mut signed = unsigned.clone();
+        signed.header.set_signature(&[0xA5; 16]);
+        assert_ne!(signed, unsigned);
+        assert!(signed.eq_ignoring_signature(&unsigned));
+
+        let mut other_body = read_response(9);
+        other_body.header.set_signature(&[0xA5; 16]);
+        assert!(!other_body.eq_ignoring_signature(&unsigned));
+
+        let mut replayed = signed.clone();
+        replayed.header.message_id = 6;
+        replayed.header.credits = 32;
+        assert!(!replayed.eq_ignoring_signature(&unsigned));
+        assert!(replayed.eq_ignoring_volatile(&unsigned));
+        replayed.header.session_id = 10;
+        assert!(!replayed.eq_ignoring_volatile(&unsigned));
+    }
+}
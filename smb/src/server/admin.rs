@@ -0,0 +1,32 @@
+use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::dialect::SMBDialect;
+use crate::protocol::body::filetime::FileTime;
+use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::server::share::ResourceType;
+
+// A copy of one session's state for operators, taken without holding on to any of the server's locks
+#[derive(Debug, Clone, PartialEq)]
+pub struct SMBSessionInfo<UserName> {
+    pub session_id: u64,
+    pub user_name: Option<UserName>,
+    pub client_name: String,
+    pub dialect: SMBDialect,
+    pub creation_time: FileTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SMBOpenInfo {
+    pub global_id: u32,
+    pub file_id: SMBFileId,
+    pub path: String,
+    pub share_name: String,
+    pub granted_access: SMBAccessMask,
+    pub opened_at: FileTime,
+}
+
+// One entry of a NetrShareEnum-style listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SMBShareInfo {
+    pub name: String,
+    pub resource_type: ResourceType,
+}
@@ -1,32 +1,38 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::net::SocketAddr;
 use std::sync::{Arc, Weak};
+use std::time::Instant;
 
 use derive_builder::Builder;
-use digest::Digest;
-use sha2::Sha512;
 use tokio::sync::{Mutex, RwLock};
+use tokio::sync::mpsc;
 use tokio::sync::mpsc::Sender;
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
-use smb_core::{SMBResult, SMBToBytes};
+use smb_core::SMBResult;
 use smb_core::error::SMBError;
 use smb_core::nt_status::NTStatus;
 
 use crate::protocol::body::capabilities::Capabilities;
 use crate::protocol::body::create::SMBCreateRequest;
 use crate::protocol::body::dialect::SMBDialect;
+use crate::protocol::body::error::SMBErrorResponse;
 use crate::protocol::body::filetime::FileTime;
 use crate::protocol::body::negotiate::{SMBNegotiateRequest, SMBNegotiateResponse};
 use crate::protocol::body::negotiate::context::{CompressionAlgorithm, EncryptionCipher, HashAlgorithm, RDMATransformID, SigningAlgorithm};
 use crate::protocol::body::negotiate::security_mode::NegotiateSecurityMode;
 use crate::protocol::body::session_setup::flags::SMBSessionSetupFlags;
 use crate::protocol::body::session_setup::SMBSessionSetupRequest;
-use crate::protocol::body::SMBBody;
+use crate::protocol::body::{LegacySMBBody, SMBBody};
+use crate::protocol::header::command_code::SMBCommandCode;
+use crate::protocol::header::flags::SMBFlags;
 use crate::protocol::header::SMBSyncHeader;
-use crate::protocol::message::SMBMessage;
+use crate::protocol::message::{Message, SMBEncryptedMessage, SMBFrame, SMBMessage};
 use crate::server::{Server, SMBServerDiagnosticsUpdate};
+use crate::server::credits::SMBCreditWindow;
 use crate::server::message_handler::{NonEndingHandler, SMBHandlerState, SMBLockedMessageHandler, SMBLockedMessageHandlerBase, SMBMessageType};
 use crate::server::open::Open;
 use crate::server::preauth_session::SMBPreauthSession;
@@ -35,6 +41,24 @@ use crate::server::safe_locked_getter::{InnerGetter, SafeLockedGetter};
 use crate::server::session::Session;
 use crate::socket::message_stream::{SMBReadStream, SMBSocketConnection, SMBWriteStream};
 use crate::util::auth::{AuthMessage, AuthProvider};
+use crate::util::crypto::smb2::{chain_preauth_hash, decrypt_message, encrypt_message, sign_message, verify_signature};
+
+// Unsolicited messages waiting to go out before a slow client holds up whoever queued them
+const NOTIFICATION_QUEUE_LEN: usize = 16;
+
+fn check_request_signature(raw_request: &[u8], signing_key: &[u8], dialect: SMBDialect) -> SMBResult<()> {
+    if signing_key.is_empty() || verify_signature(raw_request, signing_key, dialect)? {
+        return Ok(());
+    }
+    Err(SMBError::response_error(NTStatus::AccessDenied))
+}
+
+// Requests that go unsigned even where signing is required, they either come before the session has a key or
+// (CANCEL) are never signed at all
+fn signing_exempt(request: &SMBMessageType) -> bool {
+    matches!(request.body, SMBBody::NegotiateRequest(_) | SMBBody::SessionSetupRequest(_) | SMBBody::CancelRequest(_)
+        | SMBBody::LegacyCommand(_) | SMBBody::UnknownCommand(_))
+}
 
 // use tokio::sync::Mutex;
 // use tokio_stream::StreamExt;
@@ -48,6 +72,8 @@ pub trait Connection: Send + Sync {
 
     fn should_sign(&self) -> bool;
     fn client_name(&self) -> &str;
+    fn peer_addr(&self) -> Option<SocketAddr>;
+    fn channel_bindings(&self) -> Option<&[u8]>;
     fn max_transact_size(&self) -> u32;
     fn max_write_size(&self) -> u32;
     fn max_read_size(&self) -> u32;
@@ -56,6 +82,7 @@ pub trait Connection: Send + Sync {
     fn transport_name(&self) -> &str;
 
     fn client_guid(&self) -> Uuid;
+    fn server_guid(&self) -> Uuid;
 
     fn server_capabilities(&self) -> Capabilities;
 
@@ -74,9 +101,42 @@ pub trait Connection: Send + Sync {
     fn rdma_transform_ids(&self) -> &Vec<RDMATransformID>;
     fn signing_algorithm_id(&self) -> SigningAlgorithm;
     fn accept_transport_security(&self) -> bool;
+    // The name the client dialed, from its SMB2_NETNAME_NEGOTIATE_CONTEXT_ID
+    fn server_name(&self) -> &str;
     fn preauth_sessions(&self) -> &HashMap<u64, SMBPreauthSession>;
+    // The SESSION_SETUP request being handled, as it arrived, for the session to fold into its preauth hash
+    fn session_setup_request(&self) -> &[u8];
 
     fn server_ref(&self) -> Weak<RwLock<Self::Server>>;
+    // Queues unsolicited messages, such as oplock breaks, for the connection's message loop to send
+    fn notification_sender(&self) -> Option<Sender<SMBMessageType>>;
+    // When a message last went either way on this connection, for idle scavenging and diagnostics
+    fn idle_since(&self) -> Instant;
+    fn remove_session(&mut self, session_id: u64) -> Option<Arc<RwLock<<Self::Server as Server>::Session>>>;
+
+    fn signing_required(&self) -> bool {
+        self.should_sign() || self.server_security_mode().contains(NegotiateSecurityMode::NEGOTIATE_SIGNING_REQUIRED)
+    }
+
+    // Only what was negotiated counts, the server merely supporting encryption doesn't turn it on
+    fn encryption_active(&self) -> bool {
+        self.cipher_id() != EncryptionCipher::None || self.encryption_capability_negotiated()
+    }
+
+    // SMB 3.0.x has no cipher negotiation, encryption is on once both sides set SMB2_GLOBAL_CAP_ENCRYPTION
+    fn encryption_capability_negotiated(&self) -> bool {
+        matches!(self.dialect(), SMBDialect::V3_0_0 | SMBDialect::V3_0_2)
+            && self.client_capabilities().contains(Capabilities::ENCRYPTION)
+            && self.server_capabilities().contains(Capabilities::ENCRYPTION)
+    }
+
+    // SMB 3.0.x never negotiates a cipher and always encrypts with AES-128-CCM
+    fn encryption_cipher(&self) -> EncryptionCipher {
+        match self.cipher_id() {
+            EncryptionCipher::None if self.encryption_active() => EncryptionCipher::AES128CCM,
+            cipher => cipher,
+        }
+    }
 }
 
 #[derive(Builder)]
@@ -91,15 +151,19 @@ pub struct SMBConnection<R: SMBReadStream, W: SMBWriteStream, S: Server> {
     dialect: SMBDialect,
     should_sign: bool,
     client_name: String,
+    peer_addr: Option<SocketAddr>,
+    channel_bindings: Option<Vec<u8>>,
     max_transact_size: u32,
     max_write_size: u32,
     max_read_size: u32,
     supports_multi_credit: bool,
+    credit_window: SMBCreditWindow,
     transport_name: String,
     session_table: HashMap<u64, Arc<RwLock<S::Session>>>,
     creation_time: FileTime,
     preauth_session_table: HashMap<u64, SMBPreauthSession>, // TODO
     client_guid: Uuid,
+    server_guid: Uuid,
     server_capabilites: Capabilities,
     client_security_mode: NegotiateSecurityMode,
     server_security_mode: NegotiateSecurityMode,
@@ -108,6 +172,7 @@ pub struct SMBConnection<R: SMBReadStream, W: SMBWriteStream, S: Server> {
     posix_extension_payload: Vec<u8>,
     // TODO
     preauth_integrity_hash_value: Vec<u8>, // TODO
+    session_setup_request: Vec<u8>,
     cipher_id: EncryptionCipher,
     client_dialects: Vec<SMBDialect>,
     compression_ids: Vec<CompressionAlgorithm>, // TODO ??
@@ -115,10 +180,24 @@ pub struct SMBConnection<R: SMBReadStream, W: SMBWriteStream, S: Server> {
     rdma_transform_ids: Vec<RDMATransformID>, // TODO ??
     signing_algorithm_id: SigningAlgorithm,
     accept_transport_security: bool,
+    server_name: String,
     underlying_stream: Arc<Mutex<SMBSocketConnection<R, W>>>,
+    notification_sender: Option<Sender<SMBMessageType>>,
+    // Set once the message loop starts, a child of the server's shutdown token
+    cancellation: Option<CancellationToken>,
+    last_activity: Instant,
+    // Requests answered with STATUS_PENDING, until their final response goes out as a notification
+    pending_responses: HashMap<u64, PendingResponse>,
     server: Weak<RwLock<S>>
 }
 
+// The final response is timed from when its request arrived, and signed or sealed as the request was
+struct PendingResponse {
+    received: Instant,
+    request_signed: bool,
+    request_encrypted: bool,
+}
+
 // Getters
 impl<R: SMBReadStream, W: SMBWriteStream, S: Server> Connection for SMBConnection<R, W, S> {
     type Server = S;
@@ -141,6 +220,14 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> Connection for SMBConnectio
     fn client_name(&self) -> &str {
         &self.client_name
     }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    fn channel_bindings(&self) -> Option<&[u8]> {
+        self.channel_bindings.as_deref()
+    }
     fn max_transact_size(&self) -> u32 {
         self.max_transact_size
     }
@@ -163,6 +250,10 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> Connection for SMBConnectio
         self.client_guid
     }
 
+    fn server_guid(&self) -> Uuid {
+        self.server_guid
+    }
+
     fn server_capabilities(&self) -> Capabilities {
         self.server_capabilites
     }
@@ -207,53 +298,296 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> Connection for SMBConnectio
         self.accept_transport_security
     }
 
+    fn server_name(&self) -> &str {
+        &self.server_name
+    }
+
     fn preauth_sessions(&self) -> &HashMap<u64, SMBPreauthSession> {
         &self.preauth_session_table
     }
 
+    fn session_setup_request(&self) -> &[u8] {
+        &self.session_setup_request
+    }
+
     fn server_ref(&self) -> Weak<RwLock<Self::Server>> {
         self.server.clone()
     }
+
+    fn notification_sender(&self) -> Option<Sender<SMBMessageType>> {
+        self.notification_sender.clone()
+    }
+
+    fn idle_since(&self) -> Instant {
+        self.last_activity
+    }
+
+    fn remove_session(&mut self, session_id: u64) -> Option<Arc<RwLock<S::Session>>> {
+        self.session_table.remove(&session_id)
+    }
 }
 
 impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnection<R, W, S>
     where Arc<RwLock<S::Session>>: SMBLockedMessageHandler {
-    pub async fn start_message_handler<A: AuthProvider>(stream: &mut SMBSocketConnection<R, W>, mut connection: Arc<RwLock<SMBConnection<R, W, S>>>, update_channel: Sender<SMBServerDiagnosticsUpdate>) -> SMBResult<()> {
+    pub async fn start_message_handler<A: AuthProvider>(stream: &mut SMBSocketConnection<R, W>, mut connection: Arc<RwLock<SMBConnection<R, W, S>>>, update_channel: Sender<SMBServerDiagnosticsUpdate>, shutdown: CancellationToken) -> SMBResult<()> {
         let (read, write) = stream.streams();
         println!("Start message handler");
         let mut messages = read.messages();
-        while let Some(message) = messages.next().await {
+        let (notification_sender, mut notifications) = mpsc::channel(NOTIFICATION_QUEUE_LEN);
+        {
+            let mut conn_wr = connection.write().await;
+            conn_wr.notification_sender = Some(notification_sender);
+            conn_wr.cancellation = Some(shutdown.clone());
+        }
+        // Shutdown is only observed between messages, so a request already being handled still gets its response.
+        // Dropping the wait for the next message is safe, a partly read one stays in the stream's own future
+        loop {
+            let message = tokio::select! {
+                message = messages.next() => message,
+                Some(mut notification) = notifications.recv() => {
+                    let pending = {
+                        let mut conn_wr = connection.write().await;
+                        conn_wr.last_activity = Instant::now();
+                        match notification.header.is_interim_response() {
+                            true => None,
+                            false => conn_wr.pending_responses.remove(&notification.header.message_id),
+                        }
+                    };
+                    // Oplock breaks go out as they are, a deferred request's final response like any other response
+                    let sent = match pending.as_ref() {
+                        Some(pending) => Self::send_response(&connection, write, pending.request_signed, pending.request_encrypted, &mut notification).await?,
+                        None => write.write_message(&notification).await?,
+                    };
+                    let mut update = SMBServerDiagnosticsUpdate::default().bytes_sent(sent as u64);
+                    if let Some(pending) = pending {
+                        update = update.response_time(Self::elapsed_millis(&connection, pending.received).await);
+                    }
+                    let _ = update_channel.send(update).await;
+                    continue;
+                },
+                _ = shutdown.cancelled() => None,
+            };
+            let (message, raw) = match message {
+                Some(SMBFrame::Plain(message, raw)) => (message, raw),
+                // MS-SMB2 3.3.5.2.1.1, a sealed request that can't be opened drops the connection
+                Some(SMBFrame::Sealed(sealed)) => match Self::unseal_request(&connection, &sealed).await {
+                    Ok(unsealed) => unsealed,
+                    Err(_) => break,
+                },
+                None => break,
+            };
             println!("Got message: {:?}", message);
-            let message = connection.handle_message(&message).await;
-            // let message = match message.header.command_code() {
-            //     SMBCommandCode::LegacyNegotiate => connection.handle_legacy_negotiate(),
-            //     SMBCommandCode::Negotiate => connection.handle_negotiate(&message).await,
-            //     SMBCommandCode::SessionSetup => connection.handle_session_setup(&server, message).await,
-            //     SMBCommandCode::LogOff => {
-            //         println!("got logoff");
-            //         break;
-            //     }
-            //     _ => connection.generic_message_handler(message).await
-            // };
-            println!("After handler: {:?}", message);
-            if let Ok(message) = message {
+            {
+                let mut conn_wr = connection.write().await;
+                conn_wr.last_activity = Instant::now();
+                if message.header.command == SMBCommandCode::SessionSetup {
+                    conn_wr.session_setup_request = raw.clone();
+                }
+            }
+            let received = Self::clock_instant(&connection).await;
+            let request_signed = message.header.flags.contains(SMBFlags::SIGNED);
+            let request_encrypted = message.is_encrypted();
+            let credit_request = message.header.credits;
+            let (charge, checked) = match Self::charge_request(&connection, &message).await {
+                Ok(charge) => (Some(charge), Self::verify_request(&connection, &message, &raw).await),
+                Err(err) => (None, Err(err)),
+            };
+            let handled = match checked {
+                Ok(()) => connection.handle_message(&message).await,
+                Err(err) => Err(err),
+            };
+            let response = match handled {
+                // Failed requests still get an answer, an ERROR body carrying the status
+                Err(SMBError::ResponseError(e)) => {
+                    let header = message.header.create_response_header(e.status() as u32, message.header.session_id, message.header.tree_id);
+                    let dialect = connection.read().await.dialect();
+                    Ok(SMBMessage::new(header, SMBBody::ErrorResponse(SMBErrorResponse::for_dialect(dialect, e.error_data().to_vec()))))
+                }
+                response => response,
+            };
+            println!("After handler: {:?}", response);
+            if let Ok(mut message) = response {
+                // A request turned away by its charge spent nothing and gets nothing back
+                message.header.credits = match charge {
+                    Some(charge) => connection.write().await.credit_window.grant(charge, credit_request),
+                    None => 0,
+                };
+                Self::update_preauth_hash(&connection, &raw, &message).await;
                 println!("Writing message {:?}", message);
-                let sent = write.write_message(&message).await?;
-                let _ = update_channel.send(SMBServerDiagnosticsUpdate::default().bytes_sent(sent as u64)).await;
+                let sent = Self::send_response(&connection, write, request_signed, request_encrypted, &mut message).await?;
+                let mut update = SMBServerDiagnosticsUpdate::default().bytes_sent(sent as u64);
+                {
+                    let mut conn_wr = connection.write().await;
+                    conn_wr.last_activity = Instant::now();
+                    // A deferred request is timed to its final response, which goes out as a notification
+                    if message.header.is_interim_response() {
+                        conn_wr.pending_responses.insert(message.header.message_id, PendingResponse { received, request_signed, request_encrypted });
+                    }
+                }
+                if !message.header.is_interim_response() {
+                    update = update.response_time(Self::elapsed_millis(&connection, received).await);
+                }
+                let _ = update_channel.send(update).await;
             }
-            // TODO handle error response (SMBError::ResponseError)
         }
 
         // Close streams on message parse finish (logoff)
         let _ = write.close_stream().await;
         Ok(())
     }
+
+    // Reads the server's clock, falling back to the system's once the server is gone
+    async fn clock_instant(connection: &Arc<RwLock<SMBConnection<R, W, S>>>) -> Instant {
+        let server = connection.read().await.server.upgrade();
+        match server {
+            Some(server) => server.read().await.clock().instant(),
+            None => Instant::now(),
+        }
+    }
+
+    async fn elapsed_millis(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, received: Instant) -> u32 {
+        let sent = Self::clock_instant(connection).await;
+        sent.saturating_duration_since(received).as_millis() as u32
+    }
+
+    // MS-SMB2 3.3.5.2.3 and 3.3.5.2.5: spends the request's MessageIds out of the credit window. CANCEL takes
+    // the MessageId of the request it cancels and spends nothing
+    async fn charge_request(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, request: &SMBMessageType) -> SMBResult<u16> {
+        if let SMBBody::CancelRequest(_) = request.body {
+            return Ok(0);
+        }
+        let mut conn_wr = connection.write().await;
+        let multi_credit = conn_wr.supports_multi_credit;
+        conn_wr.credit_window.charge(request.header.message_id, request.header.credit_charge, multi_credit)
+    }
+
+    // MS-SMB2 3.3.5.2.4: a signed request has to carry the signature of its session's key, taken over the bytes it
+    // arrived as, and where signing is required an unsigned one is refused. Sealed requests were already
+    // authenticated by decryption, and a session without a key yet has nothing to check against
+    async fn verify_request(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, request: &SMBMessageType, raw_request: &[u8]) -> SMBResult<()> {
+        if request.is_encrypted() {
+            return Ok(());
+        }
+        let (session, dialect, signing_required) = {
+            let conn_rd = connection.read().await;
+            (conn_rd.sessions().get(&request.header.session_id).cloned(), conn_rd.dialect(), conn_rd.signing_required())
+        };
+        let Some(session) = session else {
+            return Ok(());
+        };
+        let session_rd = session.read().await;
+        if session_rd.signing_key().is_empty() {
+            return Ok(());
+        }
+        if !request.header.flags.contains(SMBFlags::SIGNED) {
+            return match (signing_required || session_rd.signing_required()) && !signing_exempt(request) {
+                true => Err(SMBError::response_error(NTStatus::AccessDenied)),
+                false => Ok(()),
+            };
+        }
+        check_request_signature(raw_request, session_rd.signing_key(), dialect)
+    }
+
+    // MS-SMB2 3.3.5.4 and 3.3.5.5: on 3.1.1 the connection's preauth hash covers its NEGOTIATE request and
+    // response, and a session's carries on from there through every SESSION_SETUP response short of the last.
+    // The session folds in the requests itself, its keys come out of the hash as of the final one
+    async fn update_preauth_hash(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, raw_request: &[u8], response: &SMBMessageType) {
+        let mut conn_wr = connection.write().await;
+        if conn_wr.dialect() != SMBDialect::V3_1_1 {
+            return;
+        }
+        let status = response.header.channel_sequence;
+        match response.header.command {
+            SMBCommandCode::Negotiate if status == NTStatus::StatusSuccess as u32 => {
+                let request_hash = chain_preauth_hash(&[], raw_request);
+                conn_wr.preauth_integrity_hash_value = chain_preauth_hash(&request_hash, &response.as_bytes());
+            }
+            SMBCommandCode::SessionSetup if status == NTStatus::MoreProcessingRequired as u32 => {
+                let session = conn_wr.sessions().get(&response.header.session_id).cloned();
+                drop(conn_wr);
+                if let Some(session) = session {
+                    session.write().await.update_preauth_hash(&response.as_bytes());
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Responses are signed once the session has a key, whenever the client signed its request or signing is mandatory
+    async fn sign_response(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, request_signed: bool, response: &mut SMBMessageType) -> SMBResult<()> {
+        let (session, dialect) = {
+            let conn_rd = connection.read().await;
+            (conn_rd.sessions().get(&response.header.session_id).cloned(), conn_rd.dialect())
+        };
+        let Some(session) = session else {
+            return Ok(());
+        };
+        let session_rd = session.read().await;
+        if session_rd.signing_key().is_empty() || !(request_signed || session_rd.signing_required()) {
+            return Ok(());
+        }
+        sign_message(response, session_rd.signing_key(), dialect)
+    }
+
+    // MS-SMB2 3.3.5.2.1.1: a sealed request is opened with the decryption key of the session its transform
+    // header names, and what comes out has to belong to that same session. It's then handled as if it arrived
+    // in the clear but marked encrypted
+    async fn unseal_request(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, sealed: &SMBEncryptedMessage) -> SMBResult<(SMBMessageType, Vec<u8>)> {
+        let (session, cipher) = {
+            let conn_rd = connection.read().await;
+            (conn_rd.sessions().get(&sealed.header.session_id).cloned(), conn_rd.encryption_cipher())
+        };
+        let session = session.ok_or(SMBError::response_error(NTStatus::UserSessionDeleted))?;
+        let plaintext = decrypt_message(sealed, session.read().await.decryption_key(), cipher)?;
+        let (_, mut message) = SMBMessageType::parse(&plaintext)?;
+        if message.header.session_id != sealed.header.session_id {
+            return Err(SMBError::parse_error("Sealed request is for another session"));
+        }
+        message.set_encrypted(true);
+        Ok((message, plaintext))
+    }
+
+    // MS-SMB2 3.3.4.1.4: encrypted requests get encrypted responses, and sessions that settled on EncryptData get
+    // every response but the session setup itself sealed
+    async fn send_response(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, write: &mut W, request_signed: bool, request_encrypted: bool, response: &mut SMBMessageType) -> SMBResult<usize> {
+        match Self::encrypt_response(connection, request_encrypted, response).await? {
+            Some(encrypted) => write.write_message(&encrypted).await,
+            None => {
+                Self::sign_response(connection, request_signed, response).await?;
+                write.write_message(response).await
+            }
+        }
+    }
+
+    async fn encrypt_response(connection: &Arc<RwLock<SMBConnection<R, W, S>>>, request_encrypted: bool, response: &SMBMessageType) -> SMBResult<Option<SMBEncryptedMessage>> {
+        if response.header.command == SMBCommandCode::SessionSetup && !request_encrypted {
+            return Ok(None);
+        }
+        let (session, cipher) = {
+            let conn_rd = connection.read().await;
+            (conn_rd.sessions().get(&response.header.session_id).cloned(), conn_rd.encryption_cipher())
+        };
+        let Some(session) = session else {
+            return Ok(None);
+        };
+        let session_rd = session.read().await;
+        if !(request_encrypted || session_rd.encrypt_data()) || session_rd.encryption_key().is_empty() {
+            return Ok(None);
+        }
+        encrypt_message(response, session_rd.id(), session_rd.encryption_key(), cipher).map(Some)
+    }
 }
 
 impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnection<R, W, S> {
     pub fn underlying_socket(&self) -> Arc<Mutex<SMBSocketConnection<R, W>>> {
         self.underlying_stream.clone()
     }
+    // Stops this connection's message loop the way a server shutdown would, the rest of the server keeps going
+    pub fn disconnect(&self) {
+        if let Some(cancellation) = &self.cancellation {
+            cancellation.cancel();
+        }
+    }
     pub fn sessions(&self) -> &HashMap<u64, Arc<RwLock<S::Session>>> {
         &self.session_table
     }
@@ -309,6 +643,9 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnect
         if let Some(client_guid) = update.client_guid.take() {
             self.client_guid = client_guid;
         }
+        if let Some(server_guid) = update.server_guid.take() {
+            self.server_guid = server_guid;
+        }
         if let Some(server_capabilities) = update.server_capabilites.take() {
             self.server_capabilites = server_capabilities;
         }
@@ -348,6 +685,9 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnect
         if let Some(accept_transport_security) = update.accept_transport_security.take() {
             self.accept_transport_security = accept_transport_security;
         }
+        if let Some(server_name) = update.server_name.take() {
+            self.server_name = server_name;
+        }
         if let Some(posix_extension_payload) = update.posix_extension_payload.take() {
             self.posix_extension_payload = posix_extension_payload;
         }
@@ -366,6 +706,16 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> InnerGette
 
 
 impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnection<R, W, S> {
+    fn handle_legacy_negotiate<A: AuthProvider>(&self, server: &S, header: &SMBSyncHeader, request: &LegacySMBBody) -> SMBResult<SMBMessageType> {
+        if !request.offers_smb2() {
+            return Err(SMBError::response_error(NTStatus::NotSupported));
+        }
+        let mut resp_header = header.create_response_header(0x0, 0, 0);
+        resp_header.command = SMBCommandCode::Negotiate;
+        let resp_body = SMBNegotiateResponse::legacy_response::<A, S>(server, request.extended_security());
+        Ok(SMBMessage::new(resp_header, SMBBody::NegotiateResponse(resp_body)))
+    }
+
     fn handle_negotiate<A: AuthProvider>(&mut self, server: &S, header: &SMBSyncHeader, request: &SMBNegotiateRequest) -> SMBResult<SMBMessageType> {
         let (update, contexts) = request.validate_and_set_state(self, server)?;
         self.apply_update(update);
@@ -374,16 +724,15 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=Self>> SMBConnect
         Ok(SMBMessage::new(resp_header, SMBBody::NegotiateResponse(resp_body)))
     }
 
-    async fn handle_session_setup<F: FnOnce() -> Arc<RwLock<Self>>>(&mut self, server: &S, header: &SMBSyncHeader, request: &SMBSessionSetupRequest, get_locked: F) -> SMBResult<Arc<RwLock<S::Session>>> {
+    async fn handle_session_setup<F: FnOnce() -> Arc<RwLock<Self>>>(&mut self, server: &mut S, header: &SMBSyncHeader, request: &SMBSessionSetupRequest, get_locked: F) -> SMBResult<Arc<RwLock<S::Session>>> {
         let locked_conn = get_locked();
-        let mut sha = Sha512::default();
-        sha.update(self.preauth_integtiry_hash_value());
-        sha.update(&request.smb_to_bytes());
-        let preauth_val = sha.finalize().to_vec();
-        let session = S::Session::init(1, server.encrypt_data(), preauth_val, Arc::downgrade(&locked_conn), server.auth_provider().clone());
-        let id = session.id();
+        let preauth_val = self.preauth_integrity_hash_value.clone();
+        // Ids come from the global table so a reconnecting client's PreviousSessionId names exactly one session
+        let id = (1..u64::MAX).find(|id| !server.sessions().contains_key(id)).unwrap_or(0);
+        let session = S::Session::init(id, server.encrypt_data(), preauth_val, Arc::downgrade(&locked_conn), server.auth_provider().clone());
         let wrapped_session = Arc::new(RwLock::new(session));
         self.session_table.insert(id, wrapped_session.clone());
+        server.sessions_mut().insert(id, wrapped_session.clone());
         let unlocked = wrapped_session.read().await;
         let update = request.validate_and_set_state(self, server, &unlocked, header).await?;
         drop(unlocked);
@@ -418,11 +767,27 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=SMBConnection<R,
             read.get_session(&server_rd, &message.header, req.flags())
                 .ok()
         } else {
-            server_rd.sessions().get(&message.header.session_id)
-                .or(read.sessions().get(&message.header.session_id))
+            read.sessions().get(&message.header.session_id)
                 .map(Arc::clone)
         }
     }
+    // MS-SMB2 3.3.5.2.9: everything past logon has to name a session this connection still has
+    async fn validate_message(&self, message: &SMBMessageType) -> SMBResult<()> {
+        match &message.body {
+            SMBBody::NegotiateRequest(_) | SMBBody::SessionSetupRequest(_) | SMBBody::EchoRequest(_)
+                | SMBBody::CancelRequest(_) | SMBBody::LegacyCommand(_) | SMBBody::UnknownCommand(_) => Ok(()),
+            _ if self.read().await.sessions().contains_key(&message.header.session_id) => Ok(()),
+            _ => Err(SMBError::response_error(NTStatus::UserSessionDeleted)),
+        }
+    }
+
+    async fn handle_legacy_command(&mut self, header: &SMBSyncHeader, message: &LegacySMBBody) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let server = self.upper().await?;
+        let unlocked = server.read().await;
+        let message = self.read().await.handle_legacy_negotiate::<S::AuthProvider>(&unlocked, header, message)?;
+        Ok(SMBHandlerState::Finished(message))
+    }
+
     async fn handle_negotiate(&mut self, header: &SMBSyncHeader, message: &SMBNegotiateRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
         let server = self.upper().await?;
         let unlocked = server.read().await;
@@ -432,13 +797,13 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server<Connection=SMBConnection<R,
 
     async fn handle_session_setup(&mut self, header: &SMBSyncHeader, message: &SMBSessionSetupRequest) -> SMBResult<SMBHandlerState<Arc<RwLock<S::Session>>>> {
         let server = self.upper().await?;
-        let unlocked = server.read().await;
         let cloned_arc = self.clone();
         let get_locked = || {
             cloned_arc
         };
         if header.session_id == 0 {
-            let session = self.write().await.handle_session_setup(&unlocked, header, message, get_locked).await?;
+            let mut server_wr = server.write().await;
+            let session = self.write().await.handle_session_setup(&mut server_wr, header, message, get_locked).await?;
             Ok(SMBHandlerState::Next(Some(session)))
         } else {
             Ok(SMBHandlerState::Next(None))
@@ -467,6 +832,8 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> TryFrom<(SMBSocketConnectio
 
     fn try_from(value: (SMBSocketConnection<R, W>, Weak<RwLock<S>>)) -> Result<Self, Self::Error> {
         let client_name = value.0.name().to_string();
+        let peer_addr = value.0.peer_addr();
+        let channel_bindings = value.0.channel_bindings().map(<[u8]>::to_vec);
         Ok(Self {
             command_sequence_window: vec![],
             request_list: Default::default(),
@@ -476,15 +843,19 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> TryFrom<(SMBSocketConnectio
             dialect: Default::default(),
             should_sign: false,
             client_name,
+            peer_addr,
+            channel_bindings,
             max_transact_size: 0,
             max_write_size: 0,
             max_read_size: 0,
             supports_multi_credit: false,
+            credit_window: SMBCreditWindow::default(),
             transport_name: "".to_string(),
             session_table: Default::default(),
             creation_time: Default::default(),
             preauth_session_table: Default::default(),
             client_guid: Default::default(),
+            server_guid: Default::default(),
             server_capabilites: Capabilities::empty(),
             client_security_mode: NegotiateSecurityMode::empty(),
             server_security_mode: NegotiateSecurityMode::empty(),
@@ -492,6 +863,7 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> TryFrom<(SMBSocketConnectio
             preauth_integrity_hash_id: HashAlgorithm::SHA512,
             posix_extension_payload: vec![],
             preauth_integrity_hash_value: vec![],
+            session_setup_request: vec![],
             cipher_id: EncryptionCipher::None,
             client_dialects: vec![],
             compression_ids: vec![],
@@ -499,8 +871,764 @@ impl<R: SMBReadStream, W: SMBWriteStream, S: Server> TryFrom<(SMBSocketConnectio
             rdma_transform_ids: vec![],
             signing_algorithm_id: SigningAlgorithm::HmacSha256,
             accept_transport_security: false,
+            server_name: String::new(),
             underlying_stream: Arc::new(Mutex::new(value.0)),
+            notification_sender: None,
+            cancellation: None,
+            last_activity: Instant::now(),
+            pending_responses: HashMap::new(),
             server: value.1
         })
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::{Duration, Instant};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{mpsc, RwLock};
+    use tokio::task::{JoinHandle, LocalSet};
+    use tokio_stream::StreamExt;
+    use tokio_util::sync::CancellationToken;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::SMBToBytes;
+
+    use crate::client::SMBClient;
+    use crate::protocol::body::capabilities::Capabilities;
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::oplock::SMBOplockLevel;
+    use crate::protocol::body::create::options::SMBCreateOptions;
+    use crate::protocol::body::create::share_access::SMBShareAccess;
+    use crate::protocol::body::create::SMBCreateRequest;
+    use crate::protocol::body::create::tests::create_request;
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::empty::SMBEmpty;
+    use crate::protocol::body::filetime::FileTime;
+    use crate::protocol::body::negotiate::context::EncryptionCipher;
+    use crate::protocol::body::oplock_break::oplock_level::SMBOplockLevel as SMBBreakOplockLevel;
+    use crate::protocol::body::oplock_break::SMBOplockBreakContent;
+    use crate::protocol::body::query_directory::flags::SMBQueryDirectoryFlags;
+    use crate::protocol::body::query_directory::information_class::SMBInformationClass;
+    use crate::protocol::body::query_directory::SMBQueryDirectoryRequest;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::body::tests::legacy_negotiate_message;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::protocol::body::tree_connect::SMBTreeConnectRequest;
+    use crate::protocol::body::write::tests::write_request_for;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::flags2::LegacySMBFlags2;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::{Message, SMBEncryptedMessage, SMBMessage, SMBSyncMessage};
+    use crate::server::{DefaultShare, Server, SMBClock, StartSMBServer};
+    use crate::server::connection::{check_request_signature, Connection, SMBConnection, SMBConnectionUpdate};
+    use crate::server::open::Open;
+    use crate::server::session::Session;
+    use crate::server::share::file_system::SMBFileSystemShare;
+    use crate::socket::message_stream::SMBSocketConnection;
+    use crate::test_util::{read_frame, serve, server_builder, share_server_builder, TempDir, test_server, user_server_builder};
+    use crate::util::auth::ntlm::NTLMAuthProvider;
+    use crate::util::crypto::smb2::{chain_preauth_hash, decrypt_message, derive_signing_key, encrypt_message, generate_encryption_keys, sign_message};
+
+    #[tokio::test]
+    async fn peer_address_is_available_after_accept() {
+        let (server, addr) = test_server().await;
+        let listener = server.read().await.local_listeners[0].clone();
+        let client = TcpStream::connect(addr).await.unwrap();
+
+        let socket = listener.lock().await.connections().next().await.unwrap();
+        assert_eq!(socket.peer_addr(), Some(client.local_addr().unwrap()));
+
+        let connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        assert_eq!(connection.peer_addr(), Some(client.local_addr().unwrap()));
+        assert_eq!(connection.client_name(), client.local_addr().unwrap().to_string());
+    }
+
+    #[tokio::test]
+    async fn encryption_is_active_only_once_negotiated() {
+        let (server, addr) = test_server().await;
+        let (read, write) = TcpStream::connect(addr).await.unwrap().into_split();
+        let socket = SMBSocketConnection::new("test".into(), read, write);
+        let mut connection = SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap();
+        let negotiated = |dialect: SMBDialect, client: Capabilities, server: Capabilities, cipher: EncryptionCipher| SMBConnectionUpdate::default()
+            .dialect(dialect)
+            .client_capabilities(client)
+            .server_capabilites(server)
+            .cipher_id(cipher);
+
+        connection.apply_update(negotiated(SMBDialect::V3_0_2, Capabilities::empty(), Capabilities::ENCRYPTION, EncryptionCipher::None));
+        let client_without_capability = connection.encryption_active();
+        connection.apply_update(negotiated(SMBDialect::V3_1_1, Capabilities::ENCRYPTION, Capabilities::ENCRYPTION, EncryptionCipher::None));
+        let smb311_without_cipher = connection.encryption_active();
+        connection.apply_update(negotiated(SMBDialect::V3_0_2, Capabilities::ENCRYPTION, Capabilities::ENCRYPTION, EncryptionCipher::None));
+        let smb30_capability = connection.encryption_active();
+        let smb30_cipher = connection.encryption_cipher();
+        connection.apply_update(negotiated(SMBDialect::V3_1_1, Capabilities::empty(), Capabilities::empty(), EncryptionCipher::AES256GCM));
+        let smb311_cipher = connection.encryption_active();
+
+        assert!(!client_without_capability);
+        assert!(!smb311_without_cipher);
+        assert!(smb30_capability);
+        assert_eq!(smb30_cipher, EncryptionCipher::AES128CCM);
+        assert!(smb311_cipher);
+    }
+
+    #[test]
+    fn signed_requests_must_match_the_session_key() {
+        let key = [0x5A; 16];
+        let header = SMBSyncHeader::new(SMBCommandCode::Echo, SMBFlags::SIGNED, 0, 3, 0, 7, [0; 16]);
+        let mut request = SMBMessage::new(header, SMBBody::EchoRequest(SMBEmpty));
+        sign_message(&mut request, &key, SMBDialect::V3_0_2).unwrap();
+
+        let request = request.as_bytes();
+        let mut tampered = request.clone();
+        tampered[24] += 1;
+        let wrong_key = check_request_signature(&request, &[0x11; 16], SMBDialect::V3_0_2);
+
+        assert!(check_request_signature(&request, &key, SMBDialect::V3_0_2).is_ok());
+        assert!(matches!(check_request_signature(&tampered, &key, SMBDialect::V3_0_2), Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+        assert!(matches!(wrong_key, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+        assert!(check_request_signature(&tampered, &[], SMBDialect::V3_0_2).is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn unsigned_requests_are_refused_once_signing_is_required() {
+        let root = TempDir::new("signing_required");
+        let (server, addr) = serve(share_server_builder(&root).require_message_signing(true)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let refused = client.tree_connect("\\\\127.0.0.1\\test").await;
+        server.read().await.shutdown();
+        assert!(matches!(refused, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+    }
+
+    // Passes a client's first few exchanges through to the server, keeping each request and response as it
+    // went over the wire. The server's end is handed back too, the connection and its sessions last as long as it does
+    fn record_exchanges(relay: TcpListener, upstream: SocketAddr, exchanges: usize) -> JoinHandle<(Vec<(Vec<u8>, Vec<u8>)>, TcpStream)> {
+        tokio::spawn(async move {
+            let (mut client, _) = relay.accept().await.unwrap();
+            let mut server = TcpStream::connect(upstream).await.unwrap();
+            let mut recorded = Vec::new();
+            for _ in 0..exchanges {
+                let request = read_frame(&mut client).await;
+                server.write_all(&request).await.unwrap();
+                let response = read_frame(&mut server).await;
+                client.write_all(&response).await.unwrap();
+                recorded.push((request[4..].to_vec(), response[4..].to_vec()));
+            }
+            (recorded, server)
+        })
+    }
+
+    // The client's side of MS-SMB2 3.2.5.3.1 and 3.2.5.5, worked from the recorded bytes
+    #[tokio::test(flavor = "multi_thread")]
+    async fn session_keys_come_from_the_exchange_as_sent() {
+        let (server, addr) = serve(user_server_builder()).await;
+        server.clone().spawn();
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let recorded = record_exchanges(relay, addr, 3);
+
+        let mut client = SMBClient::connect(relay_addr).await.unwrap();
+        assert_eq!(client.negotiate(vec![SMBDialect::V3_1_1]).await.unwrap(), SMBDialect::V3_1_1);
+        client.authenticate("", "alice", "password").await.unwrap();
+        let (recorded, _upstream) = recorded.await.unwrap();
+        let session = server.read().await.sessions().get(&client.session_id()).cloned().unwrap();
+        let signing_key = session.read().await.signing_key().to_vec();
+        server.read().await.shutdown();
+
+        let (negotiate, negotiated) = &recorded[0];
+        let connection_hash = chain_preauth_hash(&chain_preauth_hash(&[], negotiate), negotiated);
+        let session_hash = [&recorded[1].0, &recorded[1].1, &recorded[2].0].into_iter()
+            .fold(connection_hash, |hash, message| chain_preauth_hash(&hash, message));
+        assert_eq!(signing_key, derive_signing_key(&client.session_key()[..16], SMBDialect::V3_1_1, &session_hash).unwrap());
+    }
+
+    // A framed SMB2 NEGOTIATE offering 2.0.2 and 2.1
+    fn negotiate_request() -> Vec<u8> {
+        let mut message = vec![0xFE, b'S', b'M', b'B', 64, 0];
+        message.resize(64, 0);
+        message[14] = 1;
+        message.extend_from_slice(&[36, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        message.extend_from_slice(&[0x11; 16]);
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&[0x02, 0x02, 0x10, 0x02]);
+        let mut framed = vec![0, 0];
+        framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        framed.extend(message);
+        framed
+    }
+
+    #[tokio::test]
+    async fn activity_timestamp_advances_with_each_message() {
+        let (server, addr) = test_server().await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let connection = loop {
+                if let Some(connection) = server.read().await.connection_list.values().next().and_then(|conn| conn.upgrade()) {
+                    break connection;
+                }
+                tokio::task::yield_now().await;
+            };
+            let before = connection.read().await.idle_since();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            client.write_all(&negotiate_request()).await.unwrap();
+            let mut header = [0_u8; 8];
+            client.read_exact(&mut header).await.unwrap();
+            let after = connection.read().await.idle_since();
+            (before, after)
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            (before, after) = client => assert!(after > before),
+        }
+    }
+
+    #[tokio::test]
+    async fn responses_grant_the_requested_credits() {
+        let (server, addr) = test_server().await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let mut request = negotiate_request();
+            // CreditRequest sits at offset 14 of the SMB2 header, past the 4 byte frame
+            request[18] = 16;
+            client.write_all(&request).await.unwrap();
+            let mut frame = [0_u8; 4];
+            client.read_exact(&mut frame).await.unwrap();
+            let mut response = vec![0_u8; u32::from_be_bytes(frame) as usize];
+            client.read_exact(&mut response).await.unwrap();
+            u16::from_le_bytes([response[14], response[15]])
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            granted = client => assert_eq!(granted, 16),
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_outside_the_credit_window_are_invalid() {
+        let (server, addr) = test_server().await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&negotiate_request()).await.unwrap();
+            read_frame(&mut client).await;
+            let mut statuses = Vec::new();
+            // MessageId sits at offset 24 of the SMB2 header and CreditCharge at 6. One credit came back, for
+            // MessageId 1, so 0 is spent, 2 was never granted and a charge of 2 is more than the client holds
+            for (message_id, credit_charge) in [(0_u64, 1_u16), (2, 1), (1, 2)] {
+                let mut request = negotiate_request();
+                request[28..36].copy_from_slice(&message_id.to_le_bytes());
+                request[10..12].copy_from_slice(&credit_charge.to_le_bytes());
+                client.write_all(&request).await.unwrap();
+                let response = read_frame(&mut client).await;
+                statuses.push(u32::from_le_bytes([response[12], response[13], response[14], response[15]]));
+            }
+            statuses
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            statuses = client => assert_eq!(statuses, vec![NTStatus::InvalidParameter as u32; 3]),
+        }
+    }
+
+    // A client that starts with an SMB1 NEGOTIATE offering "SMB 2.???" is answered in SMB2, with the wildcard
+    // dialect and the sizes the server was configured with
+    #[tokio::test]
+    async fn legacy_negotiates_get_the_wildcard_response() {
+        let (server, addr) = serve(server_builder().max_read_size(131072).max_write_size(262144)).await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let message = legacy_negotiate_message(LegacySMBFlags2::UNICODE_STRINGS | LegacySMBFlags2::EXTENDED_SECURITY);
+            let framed = [&(message.len() as u32).to_be_bytes()[..], &message].concat();
+            client.write_all(&framed).await.unwrap();
+            read_frame(&mut client).await
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            response = client => {
+                let (_, response) = SMBSyncMessage::parse(&response[4..]).unwrap();
+                let SMBBody::NegotiateResponse(negotiate) = &response.body else {
+                    panic!("expected a negotiate response, got {:?}", response.body);
+                };
+                assert_eq!(response.header.command, SMBCommandCode::Negotiate);
+                assert_eq!(response.header.channel_sequence, NTStatus::StatusSuccess as u32);
+                assert_eq!(negotiate.dialect(), SMBDialect::V2_X_X);
+                assert!(!negotiate.buffer().is_empty());
+                // MaxReadSize and MaxWriteSize sit at offsets 32 and 36 of the response body
+                let body = negotiate.smb_to_bytes();
+                assert_eq!(u32::from_le_bytes(body[32..36].try_into().unwrap()), 131072);
+                assert_eq!(u32::from_le_bytes(body[36..40].try_into().unwrap()), 262144);
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_commands_are_answered_with_not_implemented() {
+        let (server, addr) = test_server().await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            // SERVER_TO_CLIENT_NOTIFICATION (0x13) has no business coming from a client
+            let mut request = negotiate_request();
+            request[16] = 0x13;
+            client.write_all(&request).await.unwrap();
+            let mut frame = [0_u8; 4];
+            client.read_exact(&mut frame).await.unwrap();
+            let mut response = vec![0_u8; u32::from_be_bytes(frame) as usize];
+            client.read_exact(&mut response).await.unwrap();
+            let status = u32::from_le_bytes([response[8], response[9], response[10], response[11]]);
+            let command = u16::from_le_bytes([response[12], response[13]]);
+            (status, command)
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            (status, command) = client => {
+                assert_eq!(status, NTStatus::NotImplemented as u32);
+                assert_eq!(command, 0x13);
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn message_loop_ends_on_shutdown_without_a_client_message() {
+        // Spawned locally since the compiler can't prove the loop Send once the server's boxed
+        // share handles are spelled out, start() gets away with it by spawning from generic code
+        LocalSet::new().run_until(async {
+            let (server, addr) = test_server().await;
+            let listener = server.read().await.local_listeners[0].clone();
+            let server_wide = CancellationToken::new();
+            let mut clients = Vec::new();
+            let mut loops = Vec::new();
+            for _ in 0..2 {
+                clients.push(TcpStream::connect(addr).await.unwrap());
+                let socket = listener.lock().await.connections().next().await.unwrap();
+                let connection = Arc::new(RwLock::new(SMBConnection::try_from((socket, Arc::downgrade(&server))).unwrap()));
+                let stream = connection.read().await.underlying_socket();
+                let (updates, _) = mpsc::channel(1);
+                let shutdown = server_wide.child_token();
+                let running = connection.clone();
+                let handle = tokio::task::spawn_local(async move {
+                    let mut stream = stream.lock().await;
+                    SMBConnection::start_message_handler::<NTLMAuthProvider>(&mut stream, running, updates, shutdown).await
+                });
+                while connection.read().await.cancellation.is_none() {
+                    tokio::task::yield_now().await;
+                }
+                loops.push((connection, handle));
+            }
+            let (second, second_loop) = loops.pop().unwrap();
+            let (first, first_loop) = loops.pop().unwrap();
+
+            // Neither client ever sends anything, the loops are parked waiting on a message
+            first.read().await.disconnect();
+            tokio::time::timeout(Duration::from_secs(5), first_loop).await.unwrap().unwrap().unwrap();
+            assert!(!second_loop.is_finished());
+            assert!(!second.read().await.cancellation.as_ref().unwrap().is_cancelled());
+
+            server_wide.cancel();
+            tokio::time::timeout(Duration::from_secs(5), second_loop).await.unwrap().unwrap().unwrap();
+            for mut client in clients {
+                let mut rest = Vec::new();
+                client.read_to_end(&mut rest).await.unwrap();
+                assert!(rest.is_empty());
+            }
+        }).await;
+    }
+
+    // Every reading lands 5ms * n² past the start, so consecutive requests take longer and longer
+    #[derive(Debug)]
+    struct SteppingClock {
+        start: Instant,
+        readings: AtomicU32,
+    }
+
+    impl SMBClock for SteppingClock {
+        fn now(&self) -> FileTime {
+            FileTime::zero()
+        }
+
+        fn instant(&self) -> Instant {
+            let n = self.readings.fetch_add(1, Ordering::SeqCst) as u64;
+            self.start + Duration::from_millis(5 * n * n)
+        }
+    }
+
+    #[tokio::test]
+    async fn response_times_feed_the_average() {
+        let (server, addr) = serve(server_builder().clock(SteppingClock { start: Instant::now(), readings: AtomicU32::new(0) })).await;
+        let statistics = server.read().await.statistics.clone();
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            // The second NEGOTIATE is refused, but an error is still a response to time
+            for _ in 0..2 {
+                client.write_all(&negotiate_request()).await.unwrap();
+                let mut frame = [0_u8; 4];
+                client.read_exact(&mut frame).await.unwrap();
+                let mut response = vec![0_u8; u32::from_be_bytes(frame) as usize];
+                client.read_exact(&mut response).await.unwrap();
+            }
+            for _ in 0..100 {
+                if statistics.read().await.responses == 2 {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+            statistics.read().await.average_response()
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            // 5ms (0 to 5) then 25ms (20 to 45)
+            average = client => assert_eq!(average, 15),
+        }
+    }
+
+    // Passes the client's NEGOTIATE and both SESSION_SETUP legs through with SMB2_GLOBAL_CAP_ENCRYPTION added to
+    // what it offers, the test client never asks for encryption itself. The server's end is handed back once
+    // the session is up
+    fn offer_encryption(relay: TcpListener, upstream: SocketAddr) -> JoinHandle<TcpStream> {
+        tokio::spawn(async move {
+            let (mut client, _) = relay.accept().await.unwrap();
+            let mut server = TcpStream::connect(upstream).await.unwrap();
+            for exchange in 0..3 {
+                let mut request = read_frame(&mut client).await;
+                if exchange == 0 {
+                    // Capabilities sit 8 bytes into the NEGOTIATE body
+                    request[4 + 64 + 8] |= Capabilities::ENCRYPTION.bits() as u8;
+                }
+                server.write_all(&request).await.unwrap();
+                let response = read_frame(&mut server).await;
+                client.write_all(&response).await.unwrap();
+            }
+            server
+        })
+    }
+
+    // An SMB 3.0.2 session with encryption negotiated, driven by hand once the test client has set it up
+    struct SealedSession {
+        stream: TcpStream,
+        session_id: u64,
+        next_message_id: u64,
+        // What the server seals with and what it opens with
+        server_keys: (Vec<u8>, Vec<u8>),
+    }
+
+    impl SealedSession {
+        async fn open(addr: SocketAddr) -> Self {
+            let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let relay_addr = relay.local_addr().unwrap();
+            let upstream = offer_encryption(relay, addr);
+            let mut client = SMBClient::connect(relay_addr).await.unwrap();
+            client.negotiate(vec![SMBDialect::V3_0_2]).await.unwrap();
+            client.authenticate("", "alice", "password").await.unwrap();
+            let key = client.session_key();
+            Self {
+                stream: upstream.await.unwrap(),
+                session_id: client.session_id(),
+                next_message_id: 3,
+                server_keys: generate_encryption_keys(&key[..16], key, SMBDialect::V3_0_2, EncryptionCipher::AES128CCM, &[]).unwrap(),
+            }
+        }
+
+        fn request(&mut self, tree_id: u32, body: SMBBody) -> SMBSyncMessage {
+            let command = match &body {
+                SMBBody::TreeConnectRequest(_) => SMBCommandCode::TreeConnect,
+                SMBBody::QueryDirectoryRequest(_) => SMBCommandCode::QueryDirectory,
+                SMBBody::WriteRequest(_) => SMBCommandCode::Write,
+                SMBBody::OplockBreakAcknowledgement(_) => SMBCommandCode::OplockBreak,
+                _ => SMBCommandCode::Create,
+            };
+            let header = SMBSyncHeader::new(command, SMBFlags::empty(), 0, self.next_message_id, tree_id, self.session_id, [0; 16]);
+            self.next_message_id += 1;
+            SMBMessage::new(header, body)
+        }
+
+        fn seal(&self, request: &SMBSyncMessage) -> SMBEncryptedMessage {
+            encrypt_message(request, self.session_id, &self.server_keys.1, EncryptionCipher::AES128CCM).unwrap()
+        }
+
+        async fn send<T: Message>(&mut self, message: &T) {
+            self.stream.write_all(&message.framed_bytes()).await.unwrap();
+        }
+
+        // The response and whether it came back sealed
+        async fn response(&mut self) -> (SMBSyncMessage, bool) {
+            let frame = read_frame(&mut self.stream).await;
+            if frame[4] != 0xFD {
+                return (SMBSyncMessage::parse(&frame[4..]).unwrap().1, false);
+            }
+            let (_, sealed) = SMBEncryptedMessage::parse(&frame[4..]).unwrap();
+            let plaintext = decrypt_message(&sealed, &self.server_keys.0, EncryptionCipher::AES128CCM).unwrap();
+            (SMBSyncMessage::parse(&plaintext).unwrap().1, true)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn sealed_requests_reach_shares_that_require_encryption() {
+        let root = TempDir::new("sealed_requests");
+        let share: DefaultShare<NTLMAuthProvider> = SMBFileSystemShare::path("test".into(), root.to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL))
+            .with_encrypt_data(true)
+            .into();
+        let (server, addr) = serve(user_server_builder().encryption_supported(true).add_share("test", share)).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let (connected, sealed) = session.response().await;
+        assert!(sealed);
+        assert_eq!(connected.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        let tree_id = connected.header.tree_id;
+
+        let plain = session.request(tree_id, SMBBody::CreateRequest(create_request("file.txt", SMBCreateDisposition::OpenIf, SMBCreateOptions::empty())));
+        session.send(&plain).await;
+        let (refused, sealed) = session.response().await;
+        assert!(!sealed);
+        assert_eq!(refused.header.channel_sequence, NTStatus::AccessDenied as u32);
+
+        let create = session.request(tree_id, SMBBody::CreateRequest(create_request("file.txt", SMBCreateDisposition::OpenIf, SMBCreateOptions::empty())));
+        session.send(&session.seal(&create)).await;
+        let (created, sealed) = session.response().await;
+        server.read().await.shutdown();
+        assert!(sealed);
+        assert_eq!(created.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        assert!(root.join("file.txt").exists());
+    }
+
+    // A 3.x request's ChannelSequence shares its header field with the response's Status
+    #[tokio::test(flavor = "multi_thread")]
+    async fn query_directory_succeeds_whatever_the_channel_sequence() {
+        let root = TempDir::new("query_directory_status");
+        std::fs::write(root.join("file.txt"), b"").unwrap();
+        let (server, addr) = serve(share_server_builder(&root).encryption_supported(true)).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let tree_id = session.response().await.0.header.tree_id;
+
+        let open = session.request(tree_id, SMBBody::CreateRequest(create_request("", SMBCreateDisposition::Open, SMBCreateOptions::DIRECTORY_FILE)));
+        session.send(&session.seal(&open)).await;
+        let SMBBody::CreateResponse(opened) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+
+        let request = SMBQueryDirectoryRequest::new(SMBInformationClass::FileNamesInformation, SMBQueryDirectoryFlags::RESTART_SCANS, opened.file_id().clone(), 4096, "*");
+        let mut query = session.request(tree_id, SMBBody::QueryDirectoryRequest(request));
+        query.header.channel_sequence = 3;
+        session.send(&session.seal(&query)).await;
+        let (listed, _) = session.response().await;
+        server.read().await.shutdown();
+        assert_eq!(listed.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        assert!(matches!(listed.body, SMBBody::QueryDirectoryResponse(_)));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn writes_through_a_read_only_open_are_refused() {
+        let root = TempDir::new("read_only_write");
+        std::fs::write(root.join("file.txt"), b"original").unwrap();
+        let (server, addr) = serve(share_server_builder(&root).encryption_supported(true)).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let tree_id = session.response().await.0.header.tree_id;
+
+        let read_only = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let request = SMBCreateRequest::new("file.txt", read_only, SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        let open = session.request(tree_id, SMBBody::CreateRequest(request));
+        session.send(&session.seal(&open)).await;
+        let SMBBody::CreateResponse(opened) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+
+        let write = session.request(tree_id, SMBBody::WriteRequest(write_request_for(opened.file_id().clone(), b"changed!".to_vec())));
+        session.send(&session.seal(&write)).await;
+        let (refused, _) = session.response().await;
+        server.read().await.shutdown();
+        assert_eq!(refused.header.channel_sequence, NTStatus::AccessDenied as u32);
+        assert_eq!(std::fs::read(root.join("file.txt")).unwrap(), b"original");
+    }
+
+    // A user whose permissions only cover reading gets a read-only MaximalAccess, and no more than that on a create
+    #[tokio::test(flavor = "multi_thread")]
+    async fn read_only_users_cannot_open_for_writing() {
+        let root = TempDir::new("read_only_user");
+        std::fs::write(root.join("file.txt"), b"original").unwrap();
+        let builder = user_server_builder().encryption_supported(true)
+            .add_fs_share("test".into(), root.to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::GENERIC_READ), false);
+        let (server, addr) = serve(builder).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let tree_id = session.response().await.0.header.tree_id;
+
+        let create = |access: SMBFilePipePrinterAccessMask| {
+            SMBBody::CreateRequest(SMBCreateRequest::new("file.txt", SMBAccessMask::FilePipePrinter(access), SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::empty()))
+        };
+        let write_open = session.request(tree_id, create(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA));
+        session.send(&session.seal(&write_open)).await;
+        let (refused, _) = session.response().await;
+        assert_eq!(refused.header.channel_sequence, NTStatus::AccessDenied as u32);
+
+        // Asking for the most there is only gets what the tree allows, which doesn't take writes
+        let maximum_open = session.request(tree_id, create(SMBFilePipePrinterAccessMask::MAXIMUM_ALLOWED));
+        session.send(&session.seal(&maximum_open)).await;
+        let SMBBody::CreateResponse(opened) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+        let write = session.request(tree_id, SMBBody::WriteRequest(write_request_for(opened.file_id().clone(), b"changed!".to_vec())));
+        session.send(&session.seal(&write)).await;
+        let (refused, _) = session.response().await;
+        server.read().await.shutdown();
+        assert_eq!(refused.header.channel_sequence, NTStatus::AccessDenied as u32);
+        assert_eq!(std::fs::read(root.join("file.txt")).unwrap(), b"original");
+    }
+
+    // MS-SMB2 3.3.5.9: a create that breaks a batch oplock is answered STATUS_PENDING, and only opens once the holder
+    // acknowledges, with the final response sealed like the request was
+    #[tokio::test(flavor = "multi_thread")]
+    async fn creates_wait_for_the_batch_holder_to_acknowledge() {
+        let root = TempDir::new("batch_break");
+        std::fs::write(root.join("file.txt"), b"original").unwrap();
+        let (server, addr) = serve(share_server_builder(&root).encryption_supported(true)).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let tree_id = session.response().await.0.header.tree_id;
+
+        let read_write = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA | SMBFilePipePrinterAccessMask::FILE_WRITE_DATA);
+        let request = SMBCreateRequest::new("file.txt", read_write, SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::empty())
+            .with_oplock_level(SMBOplockLevel::Batch);
+        let holder = session.request(tree_id, SMBBody::CreateRequest(request));
+        session.send(&session.seal(&holder)).await;
+        let SMBBody::CreateResponse(held) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+
+        let read_only = SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::FILE_READ_DATA);
+        let request = SMBCreateRequest::new("file.txt", read_only, SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        let create = session.request(tree_id, SMBBody::CreateRequest(request));
+        session.send(&session.seal(&create)).await;
+        let (interim, _) = session.response().await;
+        let (notification, _) = session.response().await;
+        assert!(interim.header.is_interim_response());
+        assert_eq!(interim.header.async_id(), Some(create.header.message_id));
+        let SMBBody::OplockBreakAcknowledgement(broken) = notification.body else {
+            panic!("Expected an oplock break");
+        };
+        assert_eq!(broken.file_id(), held.file_id());
+        assert_eq!(broken.level(), SMBBreakOplockLevel::II);
+        // Nothing more comes until the holder answers
+        assert!(tokio::time::timeout(Duration::from_millis(200), session.response()).await.is_err());
+
+        let acknowledgement = session.request(tree_id, SMBBody::OplockBreakAcknowledgement(SMBOplockBreakContent::new(SMBBreakOplockLevel::II, held.file_id().clone())));
+        session.send(&session.seal(&acknowledgement)).await;
+        let mut responses = vec![session.response().await, session.response().await];
+        responses.sort_by_key(|(response, _)| response.header.message_id);
+        server.read().await.shutdown();
+        let [(created, sealed), (acknowledged, _)] = <[_; 2]>::try_from(responses).unwrap();
+        assert_eq!(acknowledged.header.message_id, acknowledgement.header.message_id);
+        assert_eq!(acknowledged.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        assert!(matches!(created.body, SMBBody::CreateResponse(_)));
+        assert_eq!(created.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        assert_eq!(created.header.async_id(), Some(create.header.message_id));
+        assert!(sealed);
+    }
+
+    // Data written through one open leaves a level II holder's cache stale, so it's broken to none without waiting
+    #[tokio::test(flavor = "multi_thread")]
+    async fn writes_break_level_ii_oplocks_to_none() {
+        let root = TempDir::new("level_ii_write");
+        std::fs::write(root.join("file.txt"), b"original").unwrap();
+        let (server, addr) = serve(share_server_builder(&root).encryption_supported(true)).await;
+        server.clone().spawn();
+        let mut session = SealedSession::open(addr).await;
+
+        let tree_connect = session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\test")));
+        session.send(&session.seal(&tree_connect)).await;
+        let tree_id = session.response().await.0.header.tree_id;
+
+        let create = |access: SMBFilePipePrinterAccessMask, oplock_level: SMBOplockLevel| {
+            let request = SMBCreateRequest::new("file.txt", SMBAccessMask::FilePipePrinter(access), SMBShareAccess::READ | SMBShareAccess::WRITE, SMBCreateDisposition::Open, SMBCreateOptions::empty());
+            SMBBody::CreateRequest(request.with_oplock_level(oplock_level))
+        };
+        let writer = session.request(tree_id, create(SMBFilePipePrinterAccessMask::FILE_WRITE_DATA, SMBOplockLevel::None));
+        session.send(&session.seal(&writer)).await;
+        let SMBBody::CreateResponse(writer) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+        let reader = session.request(tree_id, create(SMBFilePipePrinterAccessMask::FILE_READ_DATA, SMBOplockLevel::II));
+        session.send(&session.seal(&reader)).await;
+        let SMBBody::CreateResponse(reader) = session.response().await.0.body else {
+            panic!("Expected a create response");
+        };
+
+        let write = session.request(tree_id, SMBBody::WriteRequest(write_request_for(writer.file_id().clone(), b"changed!".to_vec())));
+        session.send(&session.seal(&write)).await;
+        let mut responses = vec![session.response().await.0, session.response().await.0];
+        responses.sort_by_key(|response| response.header.message_id);
+        let held = {
+            let server_rd = server.read().await;
+            let open = server_rd.opens().get(&(reader.file_id().persistent as u32)).unwrap().clone();
+            drop(server_rd);
+            let oplock_level = open.read().await.oplock_level();
+            oplock_level
+        };
+        server.read().await.shutdown();
+        let [written, notification] = <[_; 2]>::try_from(responses).unwrap();
+        assert_eq!(written.header.channel_sequence, NTStatus::StatusSuccess as u32);
+        let SMBBody::OplockBreakAcknowledgement(broken) = notification.body else {
+            panic!("Expected an oplock break");
+        };
+        assert_eq!(broken.file_id(), reader.file_id());
+        assert_eq!(broken.level(), SMBBreakOplockLevel::None);
+        assert_eq!(held, SMBOplockLevel::None);
+    }
+
+    // Whatever the server can't take as a sealed request for the session it names ends the connection
+    #[tokio::test(flavor = "multi_thread")]
+    async fn malformed_sealed_requests_drop_the_connection() {
+        let (server, addr) = serve(user_server_builder().encryption_supported(true)).await;
+        server.clone().spawn();
+        let tree_connect = |session: &mut SealedSession| session.request(0, SMBBody::TreeConnectRequest(SMBTreeConnectRequest::new("\\\\127.0.0.1\\IPC$")));
+
+        let mut tampered = SealedSession::open(addr).await;
+        let request = tree_connect(&mut tampered);
+        let mut sealed = tampered.seal(&request);
+        sealed.payload[0] ^= 1;
+        tampered.send(&sealed).await;
+
+        let mut unknown_session = SealedSession::open(addr).await;
+        let request = tree_connect(&mut unknown_session);
+        let mut sealed = unknown_session.seal(&request);
+        sealed.header.session_id += 100;
+        unknown_session.send(&sealed).await;
+
+        let mut other_session = SealedSession::open(addr).await;
+        let mut request = tree_connect(&mut other_session);
+        request.header.session_id += 100;
+        other_session.send(&other_session.seal(&request)).await;
+
+        for mut session in [tampered, unknown_session, other_session] {
+            let mut rest = Vec::new();
+            session.stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+        }
+        server.read().await.shutdown();
+    }
+}
@@ -0,0 +1,126 @@
+use std::cmp::{max, min};
+use std::collections::BTreeSet;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+// Most credits a client can hold at once, past this requests for more are only partly granted
+pub const MAX_OUTSTANDING_CREDITS: u32 = 512;
+
+// Tracks the MessageIds the client's credits let it use, the CommandSequenceWindow of MS-SMB2 3.3.1.2
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SMBCreditWindow {
+    available: BTreeSet<u64>,
+    next_message_id: u64,
+}
+
+impl Default for SMBCreditWindow {
+    // Every client starts with the single credit its NEGOTIATE spends
+    fn default() -> Self {
+        Self { available: BTreeSet::from([0]), next_message_id: 1 }
+    }
+}
+
+impl SMBCreditWindow {
+    pub fn outstanding(&self) -> u32 {
+        self.available.len() as u32
+    }
+
+    // MS-SMB2 3.3.5.2.3 and 3.3.5.2.5: the request's charge has to fit the client's credits and each MessageId
+    // it covers has to be in the window. Those ids are spent and the charge is handed back for grant()
+    pub fn charge(&mut self, message_id: u64, credit_charge: u16, multi_credit: bool) -> SMBResult<u16> {
+        let charge = match multi_credit {
+            true => max(credit_charge, 1),
+            false => 1,
+        };
+        let ids = message_id..message_id.saturating_add(charge as u64);
+        if charge as u32 > self.outstanding() || !ids.clone().all(|id| self.available.contains(&id)) {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        for id in ids {
+            self.available.remove(&id);
+        }
+        Ok(charge)
+    }
+
+    // Returns the CreditResponse for a reply to a request that spent `charge`. At least the charge comes
+    // back so the client's window never shrinks, more up to what it asked for while there's room
+    pub fn grant(&mut self, charge: u16, credit_request: u16) -> u16 {
+        let wanted = max(credit_request, charge) as u32;
+        let room = MAX_OUTSTANDING_CREDITS.saturating_sub(self.outstanding());
+        // A client left holding nothing could never send again
+        let granted = max(min(wanted, room), (self.available.is_empty()) as u32);
+        self.available.extend(self.next_message_id..self.next_message_id + granted as u64);
+        self.next_message_id += granted as u64;
+        granted as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::server::credits::{MAX_OUTSTANDING_CREDITS, SMBCreditWindow};
+
+    #[test]
+    fn grants_at_least_the_charge() {
+        let mut window = SMBCreditWindow::default();
+        let charge = window.charge(0, 0, false).unwrap();
+        assert_eq!(window.grant(charge, 0), 1);
+        assert_eq!(window.outstanding(), 1);
+        let charge = window.charge(1, 1, true).unwrap();
+        assert_eq!(window.grant(charge, 31), 31);
+        assert_eq!(window.outstanding(), 31);
+        // A large read charging 8 credits gets all 8 back even though it asked for fewer
+        let charge = window.charge(2, 8, true).unwrap();
+        assert_eq!(window.grant(charge, 1), 8);
+        assert_eq!(window.outstanding(), 31);
+    }
+
+    #[test]
+    fn charge_is_one_without_large_mtu() {
+        let mut window = SMBCreditWindow::default();
+        let charge = window.charge(0, 1, false).unwrap();
+        window.grant(charge, 10);
+        assert_eq!(window.charge(1, 8, false).unwrap(), 1);
+        window.grant(1, 0);
+        assert_eq!(window.outstanding(), 10);
+    }
+
+    #[test]
+    fn grants_stop_at_the_outstanding_limit() {
+        let mut window = SMBCreditWindow::default();
+        let charge = window.charge(0, 1, true).unwrap();
+        assert_eq!(window.grant(charge, u16::MAX) as u32, MAX_OUTSTANDING_CREDITS);
+        let charge = window.charge(1, 1, true).unwrap();
+        assert_eq!(window.grant(charge, u16::MAX), 1);
+        assert_eq!(window.outstanding(), MAX_OUTSTANDING_CREDITS);
+    }
+
+    #[test]
+    fn charges_past_the_credits_held_are_invalid() {
+        let mut window = SMBCreditWindow::default();
+        let charge = window.charge(0, 1, true).unwrap();
+        window.grant(charge, 4);
+        assert!(matches!(window.charge(1, 5, true), Err(SMBError::ResponseError(e)) if e.status() == NTStatus::InvalidParameter));
+        assert_eq!(window.outstanding(), 4);
+        assert_eq!(window.charge(1, 4, true).unwrap(), 4);
+    }
+
+    #[test]
+    fn message_ids_must_be_in_the_window() {
+        let mut window = SMBCreditWindow::default();
+        let charge = window.charge(0, 1, true).unwrap();
+        window.grant(charge, 2);
+        // Already spent, then past what was granted, then running off its end
+        assert!(window.charge(0, 1, true).is_err());
+        assert!(window.charge(3, 1, true).is_err());
+        assert!(window.charge(2, 2, true).is_err());
+        // Ids can be spent out of order
+        assert_eq!(window.charge(2, 1, true).unwrap(), 1);
+        assert_eq!(window.charge(1, 1, true).unwrap(), 1);
+        assert!(window.charge(1, 1, true).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use std::future::Future;
 
 use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::protocol::body::cancel::SMBCancelRequest;
@@ -19,7 +20,7 @@ use crate::protocol::body::query_info::SMBQueryInfoRequest;
 use crate::protocol::body::read::SMBReadRequest;
 use crate::protocol::body::session_setup::SMBSessionSetupRequest;
 use crate::protocol::body::set_info::SMBSetInfoRequest;
-use crate::protocol::body::SMBBody;
+use crate::protocol::body::{LegacySMBBody, SMBBody};
 use crate::protocol::body::tree_connect::SMBTreeConnectRequest;
 use crate::protocol::body::tree_disconnect::SMBTreeDisconnectRequest;
 use crate::protocol::body::write::SMBWriteRequest;
@@ -50,6 +51,7 @@ pub trait SMBLockedMessageHandlerBase {
     fn handle_message_inner(&mut self, message: &SMBMessageType) -> impl Future<Output=SMBResult<SMBHandlerState<Self::Inner>>> {
         println!("in inner handler for msg: {:?}", message);
         async {
+            self.validate_message(message).await?;
             match &message.body {
                 SMBBody::NegotiateRequest(req) => self.handle_negotiate(&message.header, req).await,
                 SMBBody::SessionSetupRequest(req) => self.handle_session_setup(&message.header, req).await,
@@ -69,12 +71,24 @@ pub trait SMBLockedMessageHandlerBase {
                 SMBBody::ChangeNotifyRequest(req) => self.handle_change_notify(&message.header, req).await,
                 SMBBody::QueryInfoRequest(req) => self.handle_query_info(&message.header, req).await,
                 SMBBody::SetInfoRequest(req) => self.handle_set_info(&message.header, req).await,
-                SMBBody::OplockBreakAcknowledgement(req) => self.handle_oplock_break(&message.header, req).await,
+                // A client's acknowledgement parses as the request-side OplockBreak variant
+                SMBBody::OplockBreak(req) => self.handle_oplock_break(&message.header, req).await,
+                SMBBody::LegacyCommand(req) => self.handle_legacy_command(&message.header, req).await,
+                SMBBody::UnknownCommand(_) => Err(SMBError::response_error(NTStatus::NotImplemented)),
                 _ => Err(SMBError::server_error("Command not implemented")),
             }
         }
     }
 
+    // Runs before the message is dispatched so a handler can turn it away regardless of its command
+    fn validate_message(&self, _message: &SMBMessageType) -> impl Future<Output=SMBResult<()>> {
+        async { Ok(()) }
+    }
+
+    fn handle_legacy_command(&mut self, _header: &SMBSyncHeader, _message: &LegacySMBBody) -> impl Future<Output=SMBResult<SMBHandlerState<Self::Inner>>> {
+        async { Ok(SMBHandlerState::Next(None)) }
+    }
+
     fn handle_negotiate(&mut self, header: &SMBSyncHeader, message: &SMBNegotiateRequest) -> impl Future<Output=SMBResult<SMBHandlerState<Self::Inner>>> {
         async { Ok(SMBHandlerState::Next(None)) }
     }
@@ -1,22 +1,29 @@
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{Debug, Formatter};
 use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Weak};
+use std::time::Instant;
 
 use derive_builder::Builder;
 use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
 use tokio::sync::{mpsc, Mutex, RwLock};
 use tokio_stream::StreamExt;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::protocol::body::dialect::SMBDialect;
 use crate::protocol::body::filetime::FileTime;
+use crate::server::admin::{SMBOpenInfo, SMBSessionInfo, SMBShareInfo};
 use crate::server::client::SMBClient;
 use crate::server::connection::{Connection, SMBConnection};
 use crate::server::lease::{Lease, SMBLease, SMBLeaseTable};
+use crate::server::network_interface::{SMBHostInterfaces, SMBInterfaceOverride, SMBInterfaceProvider};
 use crate::server::open::{Open, SMBOpen};
 use crate::server::safe_locked_getter::InnerGetter;
 use crate::server::session::{Session, SMBSession};
@@ -26,10 +33,14 @@ use crate::socket::listener::{SMBListener, SMBSocket};
 use crate::util::auth::{AuthContext, AuthProvider};
 use crate::util::auth::ntlm::NTLMAuthProvider;
 
+pub mod admin;
 pub mod client;
 pub mod channel;
 pub mod connection;
+pub mod credits;
 pub mod lease;
+pub mod network_interface;
+pub mod oplock;
 pub mod open;
 pub mod preauth_session;
 pub mod request;
@@ -49,7 +60,8 @@ pub trait Server: Send + Sync {
     type Handle: ResourceHandle;
     fn shares(&self) -> &HashMap<String, Arc<Self::Share>>;
     fn opens(&self) -> &HashMap<u32, Arc<RwLock<Self::Open>>>;
-    fn add_open(&mut self, open: Arc<RwLock<Self::Open>>) -> impl Future<Output=u32>;
+    fn add_open(&mut self, open: Arc<RwLock<Self::Open>>) -> impl Future<Output=u32> + Send;
+    fn remove_open(&mut self, global_id: u32) -> Option<Arc<RwLock<Self::Open>>>;
     fn sessions(&self) -> &HashMap<u64, Arc<RwLock<Self::Session>>>;
     fn sessions_mut(&mut self) -> &mut HashMap<u64, Arc<RwLock<Self::Session>>>;
     fn guid(&self) -> Uuid;
@@ -70,19 +82,36 @@ pub trait Server: Send + Sync {
     fn compression_supported(&self) -> bool;
     fn chained_compression_supported(&self) -> bool;
     fn rdma_transform_supported(&self) -> bool;
+    fn tree_connect_extension(&self) -> bool;
     fn disable_encryption_over_secure_transport(&self) -> bool;
+    fn min_dialect(&self) -> SMBDialect;
     fn auth_provider(&self) -> &Arc<Self::AuthProvider>;
+    fn start_time(&self) -> FileTime;
+    fn max_read_size(&self) -> Option<u32>;
+    fn max_write_size(&self) -> Option<u32>;
+    fn max_transact_size(&self) -> Option<u32>;
+    fn clock(&self) -> &dyn SMBClock;
+    fn interface_provider(&self) -> &dyn SMBInterfaceProvider;
+    fn interface_overrides(&self) -> &HashMap<IpAddr, SMBInterfaceOverride>;
 }
 
 pub trait StartSMBServer {
     fn start(&self) -> impl Future<Output=SMBResult<()>> + Send;
+
+    // Runs the server on its own task. Spawning start() on a concrete server type can trip the
+    // compiler's Send check over the boxed share handles, which it proves fine from here
+    fn spawn(self) -> JoinHandle<SMBResult<()>> where Self: Sized + Send + Sync + 'static {
+        tokio::spawn(async move { self.start().await })
+    }
 }
 
 type SMBConnectionType<Addr, L, A, S, H> = SMBConnection<<L as SMBSocket<Addr>>::ReadStream, <L as SMBSocket<Addr>>::WriteStream, SMBServer<Addr, L, A, S, H>>;
 
+type LockedSMBConnection<Addr, L, A, S, H> = Arc<RwLock<SMBConnectionType<Addr, L, A, S, H>>>;
 type LockedWeakSMBConnection<Addr, L, A, S, H> = Weak<RwLock<SMBConnectionType<Addr, L, A, S, H>>>;
 type SMBSessionType<Addr, L, A, S, H> = SMBSession<SMBServer<Addr, L, A, S, H>>;
 type SMBOpenType<Addr, L, A, S, H> = SMBOpen<SMBServer<Addr, L, A, S, H>>;
+type LockedSMBOpen<Addr, L, A, S, H> = Arc<RwLock<SMBOpenType<Addr, L, A, S, H>>>;
 type SMBLeaseType<Addr, L, A, S, H> = SMBLease<SMBServer<Addr, L, A, S, H>>;
 type UserName<Auth> = <<Auth as AuthProvider>::Context as AuthContext>::UserName;
 pub type DefaultShare<Auth> = Box<dyn SharedResource<UserName=<<Auth as AuthProvider>::Context as AuthContext>::UserName, Handle=DefaultHandle>>;
@@ -145,6 +174,9 @@ pub struct SMBServer<Addrs: Send + Sync, Listener: SMBSocket<Addrs> = TcpListene
     shared_vhd_supported: bool,
     #[builder(default = "SMBDialect::V3_1_1")]
     max_cluster_dialect: SMBDialect,
+    // Clients offering nothing at or above this dialect fail negotiation
+    #[builder(default = "SMBDialect::V2_0_2")]
+    min_dialect: SMBDialect,
     #[builder(default = "true")]
     tree_connect_extension: bool,
     #[builder(default = "true")]
@@ -161,7 +193,29 @@ pub struct SMBServer<Addrs: Send + Sync, Listener: SMBSocket<Addrs> = TcpListene
     chained_compression_supported: bool,
     #[builder(default = "true")]
     disable_encryption_over_secure_transport: bool,
-    local_listener: Arc<Mutex<SMBListener<Addrs, Listener>>>,
+    #[builder(default = "None", setter(strip_option))]
+    max_connections: Option<usize>,
+    // Left unset, these are picked from the negotiated dialect
+    #[builder(default = "None", setter(strip_option))]
+    max_read_size: Option<u32>,
+    #[builder(default = "None", setter(strip_option))]
+    max_write_size: Option<u32>,
+    #[builder(default = "None", setter(strip_option))]
+    max_transact_size: Option<u32>,
+    #[builder(default = "SMBConnectFilter::default()", setter(custom))]
+    connect_filter: SMBConnectFilter,
+    #[builder(default = "Arc::new(SMBSystemClock)", setter(custom))]
+    clock: Arc<dyn SMBClock>,
+    #[builder(default = "Arc::new(SMBHostInterfaces)", setter(custom))]
+    interface_provider: Arc<dyn SMBInterfaceProvider>,
+    // Keyed by the interface's address, applied to whatever the provider reports
+    #[builder(field(type = "HashMap<IpAddr, SMBInterfaceOverride>"))]
+    interface_overrides: HashMap<IpAddr, SMBInterfaceOverride>,
+    // Every connection's message loop runs on a child of this, so cancelling it stops them all
+    #[builder(default = "CancellationToken::new()", setter(skip))]
+    shutdown: CancellationToken,
+    #[builder(field(type = "Vec<Arc<Mutex<SMBListener<Addrs, Listener>>>>"))]
+    pub(crate) local_listeners: Vec<Arc<Mutex<SMBListener<Addrs, Listener>>>>,
     #[builder(setter(custom))]
     auth_provider: Arc<Auth>,
 }
@@ -196,6 +250,10 @@ impl<Addrs: Send + Sync, Listener: SMBSocket<Addrs>, Auth: AuthProvider, Share:
         0
     }
 
+    fn remove_open(&mut self, global_id: u32) -> Option<Arc<RwLock<Self::Open>>> {
+        self.open_table.remove(&global_id)
+    }
+
     fn sessions(&self) -> &HashMap<u64, Arc<RwLock<Self::Session>>> {
         &self.session_table
     }
@@ -276,24 +334,63 @@ impl<Addrs: Send + Sync, Listener: SMBSocket<Addrs>, Auth: AuthProvider, Share:
         self.rdma_transform_supported
     }
 
+    fn tree_connect_extension(&self) -> bool {
+        self.tree_connect_extension
+    }
+
     fn disable_encryption_over_secure_transport(&self) -> bool {
         self.disable_encryption_over_secure_transport
     }
 
+    fn min_dialect(&self) -> SMBDialect {
+        self.min_dialect
+    }
+
     fn auth_provider(&self) -> &Arc<Self::AuthProvider> {
         &self.auth_provider
     }
+
+    fn start_time(&self) -> FileTime {
+        self.start_time.clone()
+    }
+
+    fn max_read_size(&self) -> Option<u32> {
+        self.max_read_size
+    }
+
+    fn max_write_size(&self) -> Option<u32> {
+        self.max_write_size
+    }
+
+    fn max_transact_size(&self) -> Option<u32> {
+        self.max_transact_size
+    }
+
+    fn clock(&self) -> &dyn SMBClock {
+        self.clock.as_ref()
+    }
+
+    fn interface_provider(&self) -> &dyn SMBInterfaceProvider {
+        self.interface_provider.as_ref()
+    }
+
+    fn interface_overrides(&self) -> &HashMap<IpAddr, SMBInterfaceOverride> {
+        &self.interface_overrides
+    }
 }
 
 impl<Addrs: Send + Sync, Listener: SMBSocket<Addrs>, Auth: AuthProvider, Share: SharedResource<UserName=UserName<Auth>, Handle=Handle>, Handle: ResourceHandle> SMBServerBuilder<Addrs, Listener, Auth, Share, Handle> {
+    // Can be called more than once to accept connections on several addresses
     #[cfg(not(feature = "async"))]
-    pub fn listener_address(self, addr: Addrs) -> SMBResult<Self> {
-        Ok(self.local_listener(SMBListener::new(addr)?))
+    pub fn listener_address(mut self, addr: Addrs) -> SMBResult<Self> {
+        self.local_listeners.push(Arc::new(Mutex::new(SMBListener::new(addr)?)));
+        Ok(self)
     }
 
     #[cfg(feature = "async")]
-    pub async fn listener_address(self, addr: Addrs) -> SMBResult<Self> {
-        Ok(self.local_listener(Arc::new(Mutex::new(SMBListener::new(addr).await?))))
+    pub async fn listener_address(mut self, addr: Addrs) -> SMBResult<Self> {
+        self.local_listeners.push(Arc::new(Mutex::new(SMBListener::new(addr).await?)));
+        Ok(self)
     }
 
     pub fn auth_provider(mut self, provider: Auth) -> Self {
@@ -306,12 +403,111 @@ impl<Addrs: Send + Sync, Listener: SMBSocket<Addrs>, Auth: AuthProvider, Share:
         self
     }
 
+    pub fn connect_filter<F: Fn(SocketAddr) -> bool + Send + Sync + 'static>(mut self, filter: F) -> Self {
+        self.connect_filter = Some(SMBConnectFilter(Box::new(filter)));
+        self
+    }
+
+    pub fn clock<C: SMBClock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    pub fn interface_provider<P: SMBInterfaceProvider + 'static>(mut self, provider: P) -> Self {
+        self.interface_provider = Some(Arc::new(provider));
+        self
+    }
+
+    // Link speed can't be read portably, so servers that know better can say what an interface is capable of
+    pub fn interface_override(mut self, address: IpAddr, interface_override: SMBInterfaceOverride) -> Self {
+        self.interface_overrides.insert(address, interface_override);
+        self
+    }
+
+    // Turns on encryption support and requires it of every session, refusing clients that can't encrypt
+    pub fn require_encryption(mut self) -> Self {
+        self.encryption_supported = Some(true);
+        self.encrypt_data = Some(true);
+        self.unencrypted_access = Some(false);
+        self
+    }
+
+    // Unset flags fall back to the same defaults the builder attributes give them
+    fn validate_encryption(&self) -> SMBResult<()> {
+        let encryption_supported = self.encryption_supported.unwrap_or(false);
+        let unencrypted_access = self.unencrypted_access.unwrap_or(false);
+        if self.encrypt_data == Some(true) && !encryption_supported {
+            return Err(SMBError::server_error("encrypt_data was set without encryption_supported, so no session could ever be encrypted"));
+        }
+        if self.encrypt_data.unwrap_or(true) && unencrypted_access && !encryption_supported {
+            return Err(SMBError::server_error("encrypt_data and unencrypted_access are both set without encryption_supported, so every session would run unencrypted"));
+        }
+        Ok(())
+    }
+
+    // Shares aren't checked since they can still be added once the server is running
+    fn validate(&self) -> SMBResult<()> {
+        if self.local_listeners.is_empty() {
+            return Err(SMBError::server_error("No listener address was given"));
+        }
+        let min_dialect = self.min_dialect.unwrap_or(SMBDialect::V2_0_2);
+        let max_dialect = self.max_cluster_dialect.unwrap_or(SMBDialect::V3_1_1);
+        if max_dialect < min_dialect {
+            return Err(SMBError::server_error(format!("max_cluster_dialect {:?} is below min_dialect {:?}, so no dialect could be negotiated", max_dialect, min_dialect)));
+        }
+        // There's no compression transform to back the negotiate context yet
+        if self.compression_supported == Some(true) || self.chained_compression_supported == Some(true) {
+            return Err(SMBError::server_error("compression_supported was set but this build has no compression support"));
+        }
+        self.validate_encryption()
+    }
+
     pub fn build(self) -> SMBResult<Arc<RwLock<SMBServer<Addrs, Listener, Auth, Share, Handle>>>> {
+        self.validate()?;
         let server = self.build_inner().map_err(SMBError::server_error)?;
         Ok(Arc::new(RwLock::new(server)))
     }
 }
 
+pub struct SMBConnectFilter(Box<dyn Fn(SocketAddr) -> bool + Send + Sync>);
+
+impl SMBConnectFilter {
+    pub fn allows(&self, peer_addr: SocketAddr) -> bool {
+        (self.0)(peer_addr)
+    }
+}
+
+impl Default for SMBConnectFilter {
+    fn default() -> Self {
+        Self(Box::new(|_| true))
+    }
+}
+
+impl Debug for SMBConnectFilter {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SMBConnectFilter {{}}")
+    }
+}
+
+// Source of the times the server reports, swappable so responses can be pinned in tests
+pub trait SMBClock: Debug + Send + Sync {
+    fn now(&self) -> FileTime;
+
+    // Monotonic time for measuring intervals like response latency, which wall clock time can't do reliably
+    fn instant(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SMBSystemClock;
+
+impl SMBClock for SMBSystemClock {
+    fn now(&self) -> FileTime {
+        FileTime::now()
+    }
+}
+
 #[derive(Debug, Default)]
 pub enum HashLevel {
     #[default]
@@ -334,6 +530,133 @@ impl<Addrs: Send + Sync, Listener: SMBSocket<Addrs>, Auth: AuthProvider + 'stati
     pub fn remove_share(&mut self, name: &str) {
         self.share_list.remove(name);
     }
+
+    // The shares a NetrShareEnum or `net view` should show, hidden ones left out
+    pub fn share_listing(&self) -> Vec<SMBShareInfo> {
+        let mut shares: Vec<SMBShareInfo> = self.share_list.values()
+            .filter(|share| !share.hidden())
+            .map(|share| SMBShareInfo {
+                name: share.name().to_string(),
+                resource_type: share.resource_type(),
+            })
+            .collect();
+        shares.sort_by(|a, b| a.name.cmp(&b.name));
+        shares
+    }
+
+    // The sessions as they stand now, for a `net session`-style listing
+    pub async fn session_snapshots(&self) -> Vec<SMBSessionInfo<UserName<Auth>>> where UserName<Auth>: Clone {
+        let mut snapshots = Vec::new();
+        for session in self.session_table.values() {
+            let session_rd = session.read().await;
+            let mut snapshot = SMBSessionInfo {
+                session_id: session_rd.id(),
+                user_name: session_rd.user_name().cloned(),
+                client_name: String::new(),
+                dialect: SMBDialect::default(),
+                creation_time: session_rd.creation_time(),
+            };
+            let connection = session_rd.connection().upgrade();
+            drop(session_rd);
+            if let Some(connection) = connection {
+                let conn_rd = connection.read().await;
+                snapshot.client_name = conn_rd.client_name().to_string();
+                snapshot.dialect = conn_rd.dialect();
+            }
+            snapshots.push(snapshot);
+        }
+        snapshots
+    }
+
+    pub async fn open_snapshots(&self) -> Vec<SMBOpenInfo> {
+        let mut snapshots = Vec::new();
+        for (global_id, open) in self.open_table.iter() {
+            let open_rd = open.read().await;
+            let Ok(handle) = open_rd.handle() else {
+                continue;
+            };
+            snapshots.push(SMBOpenInfo {
+                global_id: *global_id,
+                file_id: open_rd.file_id(),
+                path: handle.path().to_string(),
+                share_name: open_rd.share_name().to_string(),
+                granted_access: open_rd.granted_access().clone(),
+                opened_at: open_rd.opened_at(),
+            });
+        }
+        snapshots
+    }
+
+    // Evicts a session: its opens are closed and anything still sent on it gets STATUS_USER_SESSION_DELETED
+    pub async fn close_session(&mut self, session_id: u64) -> SMBResult<()> {
+        let session = self.session_table.remove(&session_id)
+            .ok_or(SMBError::response_error(NTStatus::UserSessionDeleted))?;
+        let (opens, connection) = session.write().await.expire();
+        for open in opens.iter() {
+            let global_id = open.read().await.file_id().persistent;
+            self.open_table.remove(&(global_id as u32));
+        }
+        // The connection may be mid-request and waiting on this server, so only detach now if that won't block;
+        // otherwise the expired session turns the request away itself
+        if let Some(connection) = connection {
+            if let Ok(mut conn_wr) = connection.try_write() {
+                conn_wr.remove_session(session_id);
+            }
+        }
+        Self::close_handles(opens).await
+    }
+
+    // MS-SMB2 3.3.7.1: a connection's sessions don't outlive it, and neither do the opens they hold
+    async fn release_connection(&mut self, connection: &LockedSMBConnection<Addrs, Listener, Auth, Share, Handle>) -> SMBResult<()> {
+        let sessions = connection.read().await.sessions().clone();
+        for (session_id, session) in sessions {
+            // An id a reconnect already took away may since have gone to another connection's session
+            if self.session_table.get(&session_id).is_some_and(|other| Arc::ptr_eq(other, &session)) {
+                self.close_session(session_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Closes a single open out from under its session, the next request on its file id gets STATUS_FILE_CLOSED
+    pub async fn close_open(&mut self, global_id: u32) -> SMBResult<()> {
+        let open = self.open_table.remove(&global_id)
+            .ok_or(SMBError::response_error(NTStatus::FileClosed))?;
+        for session in self.session_table.values() {
+            let mut session_wr = session.write().await;
+            let session_open_id = session_wr.open_table().iter()
+                .find(|(_, other)| Arc::ptr_eq(other, &open))
+                .map(|(id, _)| *id);
+            if let Some(id) = session_open_id {
+                session_wr.remove_open(id);
+            }
+        }
+        Self::close_handles(vec![open]).await
+    }
+
+    // An in-flight request still holding one of the opens gets STATUS_FILE_CLOSED once its handle is gone
+    async fn close_handles(opens: Vec<LockedSMBOpen<Addrs, Listener, Auth, Share, Handle>>) -> SMBResult<()> {
+        for open in opens {
+            if let Some(handle) = open.write().await.take_handle() {
+                Box::new(handle).close()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Stops accepting connections and lets each connection finish the request it's handling before `start` returns
+    pub fn shutdown(&self) {
+        self.shutdown.cancel();
+    }
+
+    async fn clear_tables(&mut self) {
+        self.session_table.clear();
+        self.connection_list.clear();
+        let opens = self.open_table.drain()
+            .map(|(_, open)| open)
+            .collect();
+        let _ = Self::close_handles(opens).await;
+    }
 }
 
 impl<
@@ -343,8 +666,9 @@ impl<
     Share: SharedResource<UserName=UserName<Auth>, Handle=Handle> + From<SMBFileSystemShare<UserName<Auth>, Handle>>,
     Handle: ResourceHandle + 'static + From<SMBFileSystemHandle> + TryInto<SMBFileSystemHandle>
 > SMBServerBuilder<Addrs, Listener, Auth, Share, Handle> {
-    pub fn add_fs_share(mut self, name: String, path: String, connect_allowed: ConnectAllowed<UserName<Auth>>, file_perms: FilePerms<UserName<Auth>>) -> Self {
-        let share = SMBFileSystemShare::path(name.clone(), path, connect_allowed, file_perms);
+    pub fn add_fs_share(mut self, name: String, path: String, connect_allowed: ConnectAllowed<UserName<Auth>>, file_perms: FilePerms<UserName<Auth>>, read_only: bool) -> Self {
+        let share = SMBFileSystemShare::path(name.clone(), path, connect_allowed, file_perms)
+            .with_read_only(read_only);
         self.add_share(name, share.into())
     }
 }
@@ -360,11 +684,48 @@ impl<Addrs: Send + Sync + 'static, Listener: SMBSocket<Addrs> + 'static, Auth: A
                 diagnostics.write().await.update(update);
             }
         });
-        let listener = {
-            self.read().await.local_listener.clone()
+        let listeners = {
+            self.read().await.local_listeners.clone()
         };
-        while let Some(connection) = listener.lock().await.connections().next().await {
+        let max_connections = {
+            self.read().await.max_connections
+        };
+        let shutdown = self.read().await.shutdown.clone();
+        // Each listener accepts on its own task and funnels its connections into the one loop below
+        let (accepted_tx, mut accepted) = mpsc::channel(listeners.len());
+        let acceptors = listeners.into_iter().map(|listener| {
+            let accepted_tx = accepted_tx.clone();
+            tokio::spawn(async move {
+                while let Some(connection) = listener.lock().await.connections().next().await {
+                    if accepted_tx.send(connection).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        }).collect::<Vec<JoinHandle<()>>>();
+        drop(accepted_tx);
+        let mut handlers = Vec::new();
+        loop {
+            let connection = tokio::select! {
+                connection = accepted.recv() => connection,
+                _ = shutdown.cancelled() => None,
+            };
+            let Some(connection) = connection else {
+                break;
+            };
             println!("got connection");
+            if let Some(peer_addr) = connection.peer_addr() {
+                if !self.read().await.connect_filter.allows(peer_addr) {
+                    continue;
+                }
+            }
+            if let Some(max_connections) = max_connections {
+                let mut server = self.write().await;
+                server.connection_list.retain(|_, conn| conn.strong_count() > 0);
+                if server.connection_list.len() >= max_connections {
+                    continue;
+                }
+            }
             let smb_connection = SMBConnection::try_from((connection, Arc::downgrade(self)))?;
             let name = smb_connection.client_name().to_string();
             let socket = smb_connection.underlying_socket();
@@ -373,12 +734,23 @@ impl<Addrs: Send + Sync + 'static, Listener: SMBSocket<Addrs> + 'static, Auth: A
                 self.write().await.connection_list.insert(name, Arc::downgrade(&wrapped_connection));
             }
             let update_channel = rx.clone();
-            tokio::spawn(async move {
+            let connection_shutdown = shutdown.child_token();
+            handlers.retain(|handler: &JoinHandle<()>| !handler.is_finished());
+            let server = self.clone();
+            handlers.push(tokio::spawn(async move {
                 let mut stream = socket.lock().await;
-                let _ = SMBConnection::start_message_handler::<Auth>(&mut stream, wrapped_connection, update_channel).await;
-            });
+                let _ = SMBConnection::start_message_handler::<Auth>(&mut stream, wrapped_connection.clone(), update_channel, connection_shutdown).await;
+                let _ = server.write().await.release_connection(&wrapped_connection).await;
+            }));
         }
 
+        for acceptor in acceptors {
+            acceptor.abort();
+        }
+        for handler in handlers {
+            let _ = handler.await;
+        }
+        self.write().await.clear_tables().await;
         Ok(())
     }
 }
@@ -398,9 +770,15 @@ pub struct SMBServerDiagnostics {
     system_errors: u32,
     bytes_sent: u64,
     bytes_received: u64,
+    // Updates carry a single response's latency in milliseconds, folded into the running average here
+    #[builder(setter(name = "response_time"))]
     average_response: u32,
     request_buffer_need: u32,
     big_buffer_need: u32,
+    #[builder(setter(skip))]
+    responses: u64,
+    #[builder(setter(skip))]
+    total_response_time: u64,
 }
 
 impl SMBServerDiagnostics {
@@ -416,6 +794,11 @@ impl SMBServerDiagnostics {
         self.bytes_sent += size;
     }
 
+    // Mean time in milliseconds between a request arriving and its final response going out
+    pub fn average_response(&self) -> u32 {
+        self.average_response
+    }
+
     pub fn update(&mut self, update: SMBServerDiagnosticsUpdate) {
         if let Some(start) = update.start {
             self.start = start;
@@ -453,8 +836,10 @@ impl SMBServerDiagnostics {
         if let Some(bytes_received) = update.bytes_received {
             self.bytes_received += bytes_received;
         }
-        if let Some(average_response) = update.average_response {
-            self.average_response = average_response;
+        if let Some(response_time) = update.average_response {
+            self.responses += 1;
+            self.total_response_time += response_time as u64;
+            self.average_response = (self.total_response_time / self.responses) as u32;
         }
         if let Some(request_buffer_need) = update.request_buffer_need {
             self.request_buffer_need += request_buffer_need;
@@ -463,4 +848,367 @@ impl SMBServerDiagnostics {
             self.big_buffer_need += big_buffer_need;
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpSocket, TcpStream};
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::client::SMBClient;
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask};
+    use crate::server::{Server, SMBServerDiagnostics, SMBServerDiagnosticsUpdate, StartSMBServer};
+    use crate::server::open::Open;
+    use crate::test_util::{serve, server_builder, share_server_builder, TempDir, test_server, user_server_builder};
+
+    #[test]
+    fn response_times_fold_into_a_running_average() {
+        let mut diagnostics = SMBServerDiagnostics::new();
+        assert_eq!(diagnostics.average_response(), 0);
+        diagnostics.update(SMBServerDiagnosticsUpdate::default().response_time(10));
+        assert_eq!(diagnostics.average_response(), 10);
+        diagnostics.update(SMBServerDiagnosticsUpdate::default().response_time(30).bytes_sent(64));
+        assert_eq!(diagnostics.average_response(), 20);
+        // Updates without a latency leave the average alone
+        diagnostics.update(SMBServerDiagnosticsUpdate::default().bytes_sent(64));
+        assert_eq!(diagnostics.average_response(), 20);
+    }
+
+    #[tokio::test]
+    async fn refuses_connections_over_limit() {
+        let (server, addr) = serve(server_builder().max_connections(2)).await;
+        let clients = tokio::spawn(async move {
+            let first = TcpStream::connect(addr).await.unwrap();
+            let second = TcpStream::connect(addr).await.unwrap();
+            let mut third = TcpStream::connect(addr).await.unwrap();
+            let mut buffer = [0_u8; 8];
+            let read = third.read(&mut buffer).await.unwrap();
+            drop((first, second));
+            read
+        });
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            read = clients => assert_eq!(read.unwrap(), 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_filter_drops_rejected_peers() {
+        let rejected_socket = TcpSocket::new_v4().unwrap();
+        rejected_socket.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let rejected_addr = rejected_socket.local_addr().unwrap();
+        let (server, addr) = serve(server_builder().connect_filter(move |peer| peer != rejected_addr)).await;
+        let clients = async {
+            let mut rejected = rejected_socket.connect(addr).await.unwrap();
+            let mut buffer = [0_u8; 8];
+            let read = rejected.read(&mut buffer).await.unwrap();
+            let allowed = TcpStream::connect(addr).await.unwrap();
+            while server.read().await.connection_list.is_empty() {
+                tokio::task::yield_now().await;
+            }
+            (read, allowed.local_addr().unwrap())
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            (read, allowed_addr) = clients => {
+                assert_eq!(read, 0);
+                let server_rd = server.read().await;
+                assert_eq!(server_rd.connection_list.len(), 1);
+                assert!(server_rd.connection_list.contains_key(&allowed_addr.to_string()));
+                assert!(!server_rd.connection_list.contains_key(&rejected_addr.to_string()));
+            },
+        }
+    }
+
+    // A framed SMB2 NEGOTIATE offering 2.0.2 and 2.1
+    fn negotiate_request() -> Vec<u8> {
+        let mut message = vec![0xFE, b'S', b'M', b'B', 64, 0];
+        message.resize(64, 0);
+        message[14] = 1;
+        message.extend_from_slice(&[36, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+        message.extend_from_slice(&[0x11; 16]);
+        message.extend_from_slice(&[0; 8]);
+        message.extend_from_slice(&[0x02, 0x02, 0x10, 0x02]);
+        let mut framed = vec![0, 0];
+        framed.extend_from_slice(&(message.len() as u16).to_be_bytes());
+        framed.extend(message);
+        framed
+    }
+
+    #[tokio::test]
+    async fn shutdown_stops_accepting_and_clears_tables() {
+        let (server, addr) = test_server().await;
+        let client = async {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&negotiate_request()).await.unwrap();
+            let mut header = [0_u8; 8];
+            client.read_exact(&mut header).await.unwrap();
+            server.read().await.shutdown();
+            (client, header)
+        };
+        let (result, (mut client, header)) = tokio::join!(server.start(), client);
+        result.unwrap();
+
+        assert_eq!(&header[4..8], &[0xFE, b'S', b'M', b'B']);
+        let mut rest = Vec::new();
+        client.read_to_end(&mut rest).await.unwrap();
+        assert!(!rest.is_empty());
+        let server_rd = server.read().await;
+        assert!(server_rd.connection_list.is_empty());
+        assert!(server_rd.session_table.is_empty());
+        assert!(server_rd.open_table.is_empty());
+    }
+
+    #[tokio::test]
+    async fn accepts_on_every_listener() {
+        let (server, _) = serve(server_builder().listener_address("127.0.0.1:0").await.unwrap()).await;
+        let mut addrs = Vec::new();
+        for listener in server.read().await.local_listeners.iter() {
+            addrs.push(listener.lock().await.local_addr().unwrap());
+        }
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+        let clients = async {
+            let mut clients = Vec::new();
+            for addr in &addrs {
+                clients.push(TcpStream::connect(addr).await.unwrap());
+            }
+            while server.read().await.connection_list.len() < 2 {
+                tokio::task::yield_now().await;
+            }
+            clients
+        };
+        tokio::select! {
+            _ = server.start() => panic!("server stopped accepting connections"),
+            clients = clients => {
+                let server_rd = server.read().await;
+                for client in clients {
+                    assert!(server_rd.connection_list.contains_key(&client.local_addr().unwrap().to_string()));
+                }
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn building_without_a_listener_fails() {
+        let result = server_builder().build();
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn require_encryption_sets_a_consistent_configuration() {
+        let (server, _) = serve(server_builder().require_encryption()).await;
+        let server_rd = server.read().await;
+        assert!(server_rd.encryption_supported());
+        assert!(server_rd.encrypt_data());
+        assert!(!server_rd.unencrypted_access());
+    }
+
+    #[tokio::test]
+    async fn encrypt_data_without_encryption_support_is_rejected() {
+        let result = server_builder()
+            .encrypt_data(true)
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build();
+        assert!(matches!(result, Err(SMBError::ServerError(_))));
+    }
+
+    #[tokio::test]
+    async fn encrypt_data_with_unencrypted_access_needs_encryption_support() {
+        let result = server_builder()
+            .unencrypted_access(true)
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build();
+        assert!(matches!(result, Err(SMBError::ServerError(_))));
+
+        let result = server_builder()
+            .unencrypted_access(true)
+            .encryption_supported(true)
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build();
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn inverted_dialect_range_is_rejected() {
+        let result = server_builder()
+            .min_dialect(SMBDialect::V3_0_0)
+            .max_cluster_dialect(SMBDialect::V2_1_0)
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build();
+        assert!(matches!(result, Err(SMBError::ServerError(_))));
+    }
+
+    #[tokio::test]
+    async fn compression_without_support_is_rejected() {
+        let result = server_builder()
+            .compression_supported(true)
+            .listener_address("127.0.0.1:0").await.unwrap()
+            .build();
+        assert!(matches!(result, Err(SMBError::ServerError(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn snapshots_list_sessions_and_opens() {
+        let root = TempDir::new("admin_snapshots");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = client.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        let file_id = client.open_directory(tree_id, "docs").await.unwrap();
+
+        let (sessions, opens) = {
+            let server_rd = server.read().await;
+            (server_rd.session_snapshots().await, server_rd.open_snapshots().await)
+        };
+        server.read().await.shutdown();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, client.session_id());
+        assert_eq!(sessions[0].user_name.as_deref(), Some("alice"));
+        assert_eq!(sessions[0].dialect, SMBDialect::V2_1_0);
+        assert!(!sessions[0].client_name.is_empty());
+        assert_eq!(opens.len(), 1);
+        assert_eq!(opens[0].file_id, file_id);
+        assert_eq!(opens[0].share_name, "test");
+        assert!(opens[0].path.ends_with("docs"));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn closing_a_session_drops_its_opens_and_fails_later_requests() {
+        let root = TempDir::new("admin_close_session");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = client.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        let file_id = client.open_directory(tree_id, "docs").await.unwrap();
+
+        server.write().await.close_session(client.session_id()).await.unwrap();
+        {
+            let server_rd = server.read().await;
+            assert!(server_rd.session_table.is_empty());
+            assert!(server_rd.open_table.is_empty());
+        }
+        let result = client.close(tree_id, &file_id).await;
+        let missing = server.write().await.close_session(client.session_id()).await;
+        server.read().await.shutdown();
+
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::UserSessionDeleted));
+        assert!(matches!(missing, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::UserSessionDeleted));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn disconnecting_drops_the_connection_sessions_and_their_opens() {
+        let root = TempDir::new("disconnect_sessions");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = client.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        client.open_directory(tree_id, "docs").await.unwrap();
+        let session_id = client.session_id();
+        assert!(server.read().await.session_table.contains_key(&session_id));
+
+        drop(client);
+        let released = tokio::time::timeout(Duration::from_secs(5), async {
+            while server.read().await.session_table.contains_key(&session_id) {
+                tokio::task::yield_now().await;
+            }
+        }).await;
+        let opens = server.read().await.open_table.len();
+        server.read().await.shutdown();
+
+        assert!(released.is_ok());
+        assert_eq!(opens, 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn close_succeeds_while_a_request_still_holds_the_open() {
+        let root = TempDir::new("close_held_open");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = client.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        let file_id = client.open_directory(tree_id, "docs").await.unwrap();
+        // Stands in for a request that's still working with the open when the close arrives
+        let held = server.read().await.open_table.get(&(file_id.persistent as u32)).cloned().unwrap();
+
+        let result = client.close(tree_id, &file_id).await;
+        let closed = held.read().await.handle().is_err();
+        server.read().await.shutdown();
+
+        assert!(result.is_ok());
+        assert!(closed);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn file_ids_need_both_halves_to_name_the_open() {
+        let root = TempDir::new("mismatched_file_id");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("other")).unwrap();
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = client.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        let docs = client.open_directory(tree_id, "docs").await.unwrap();
+        let other = client.open_directory(tree_id, "other").await.unwrap();
+        let mismatched = SMBFileId { persistent: other.persistent, volatile: docs.volatile };
+
+        let result = client.close(tree_id, &mismatched).await;
+        let remaining = server.read().await.open_table.len();
+        let closed = (client.close(tree_id, &docs).await, client.close(tree_id, &other).await);
+        server.read().await.shutdown();
+
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::FileClosed));
+        assert_eq!(remaining, 2);
+        assert!(closed.0.is_ok() && closed.1.is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn hidden_shares_are_connectable_but_not_listed() {
+        let root = TempDir::new("hidden_share");
+        let path = root.to_string_lossy().into_owned();
+        let builder = user_server_builder()
+            .add_fs_share("test".into(), path.clone(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false)
+            .add_fs_share("admin$".into(), path, |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false);
+        let (server, addr) = serve(builder).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let hidden = client.tree_connect("\\\\127.0.0.1\\admin$").await;
+        let listing = server.read().await.share_listing();
+        server.read().await.shutdown();
+
+        assert!(hidden.is_ok());
+        let names: Vec<&str> = listing.iter().map(|share| share.name.as_str()).collect();
+        assert_eq!(names, vec!["test"]);
+    }
+}
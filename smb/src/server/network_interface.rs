@@ -0,0 +1,184 @@
+use std::fmt::Debug;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::protocol::body::ioctl::network_interface::{SMBInterfaceCapabilities, SMBNetworkInterfaceInfo};
+use crate::server::Server;
+
+// Where FSCTL_QUERY_NETWORK_INTERFACE_INFO gets its answer, swappable so a server can advertise a fixed list
+// or tests can stub one
+pub trait SMBInterfaceProvider: Debug + Send + Sync {
+    fn interfaces(&self) -> SMBResult<Vec<SMBNetworkInterfaceInfo>>;
+}
+
+// The host's own interfaces. Enumerating them needs the host-interfaces feature, without it there's nothing
+// to advertise
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SMBHostInterfaces;
+
+impl SMBInterfaceProvider for SMBHostInterfaces {
+    #[cfg(feature = "host-interfaces")]
+    fn interfaces(&self) -> SMBResult<Vec<SMBNetworkInterfaceInfo>> {
+        use std::net::SocketAddr;
+
+        let interfaces = if_addrs::get_if_addrs()
+            .map_err(|e| SMBError::server_error(format!("Couldn't list network interfaces: {}", e)))?;
+        let advertised = interfaces.into_iter()
+            .filter(|interface| !interface.is_loopback())
+            .map(|interface| SMBNetworkInterfaceInfo::new(
+                interface.index.unwrap_or(0),
+                SMBInterfaceCapabilities::empty(),
+                host_link_speed(&interface.name),
+                SocketAddr::new(interface.ip(), 0),
+            ))
+            .collect();
+        Ok(advertised)
+    }
+
+    #[cfg(not(feature = "host-interfaces"))]
+    fn interfaces(&self) -> SMBResult<Vec<SMBNetworkInterfaceInfo>> {
+        Ok(Vec::new())
+    }
+}
+
+// What an interface is advertised at when nothing better is known, in bits per second
+pub const DEFAULT_LINK_SPEED: u64 = 1_000_000_000;
+
+// Linux reports the speed in Mb/s, anywhere else (or on links that don't say) we assume gigabit
+#[cfg(feature = "host-interfaces")]
+fn host_link_speed(name: &str) -> u64 {
+    std::fs::read_to_string(format!("/sys/class/net/{}/speed", name)).ok()
+        .and_then(|speed| speed.trim().parse::<i64>().ok())
+        .filter(|speed| *speed > 0)
+        .map_or(DEFAULT_LINK_SPEED, |speed| speed as u64 * 1_000_000)
+}
+
+// A fixed list, for servers whose clients should only be pointed at some of the host's addresses
+#[derive(Debug, Default, Clone)]
+pub struct SMBStaticInterfaces(pub Vec<SMBNetworkInterfaceInfo>);
+
+impl SMBInterfaceProvider for SMBStaticInterfaces {
+    fn interfaces(&self) -> SMBResult<Vec<SMBNetworkInterfaceInfo>> {
+        Ok(self.0.clone())
+    }
+}
+
+// Replaces what was reported for one interface, clients weigh channels by these when picking where to connect.
+// Anything left unset keeps the provider's value
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SMBInterfaceOverride {
+    link_speed: Option<u64>,
+    capabilities: Option<SMBInterfaceCapabilities>,
+}
+
+impl SMBInterfaceOverride {
+    pub fn with_link_speed(mut self, link_speed: u64) -> Self {
+        self.link_speed = Some(link_speed);
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: SMBInterfaceCapabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    fn apply(&self, interface: SMBNetworkInterfaceInfo) -> SMBNetworkInterfaceInfo {
+        let link_speed = self.link_speed.unwrap_or(interface.link_speed());
+        let capabilities = self.capabilities.unwrap_or(interface.capabilities());
+        interface.with_link_speed(link_speed).with_capabilities(capabilities)
+    }
+}
+
+// MS-SMB2 3.3.5.15.11, only a multichannel server tells clients where else to connect
+pub fn advertised_interfaces<S: Server>(server: &S) -> SMBResult<Vec<SMBNetworkInterfaceInfo>> {
+    if !server.multi_channel_capable() {
+        return Err(SMBError::response_error(NTStatus::NotSupported));
+    }
+    let overrides = server.interface_overrides();
+    let interfaces = server.interface_provider().interfaces()?
+        .into_iter()
+        .map(|interface| match overrides.get(&interface.address().ip()) {
+            Some(interface_override) => interface_override.apply(interface),
+            None => interface,
+        })
+        .collect::<Vec<SMBNetworkInterfaceInfo>>();
+    match interfaces.is_empty() {
+        true => Err(SMBError::response_error(NTStatus::NotSupported)),
+        false => Ok(interfaces),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, SocketAddr};
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
+    use crate::protocol::body::ioctl::network_interface::{SMBInterfaceCapabilities, SMBNetworkInterfaceInfo};
+    use crate::protocol::body::ioctl::SMBIoCtlRequest;
+    use crate::server::network_interface::{advertised_interfaces, DEFAULT_LINK_SPEED, SMBInterfaceOverride, SMBStaticInterfaces};
+    use crate::test_util::{serve, server_builder, TestServer};
+
+    fn stub_interfaces() -> Vec<SMBNetworkInterfaceInfo> {
+        vec![
+            SMBNetworkInterfaceInfo::new(3, SMBInterfaceCapabilities::RSS_CAPABLE, 10_000_000_000, "10.0.0.5:0".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(4, SMBInterfaceCapabilities::empty(), 1_000_000_000, "[fd00::5]:0".parse::<SocketAddr>().unwrap()),
+        ]
+    }
+
+    async fn server_with(multi_channel_capable: bool, interfaces: Vec<SMBNetworkInterfaceInfo>) -> TestServer {
+        let builder = server_builder()
+            .multi_channel_capable(multi_channel_capable)
+            .interface_provider(SMBStaticInterfaces(interfaces));
+        serve(builder).await.0
+    }
+
+    #[tokio::test]
+    async fn multichannel_server_advertises_its_interfaces() {
+        let server = server_with(true, stub_interfaces()).await;
+        assert_eq!(advertised_interfaces(&*server.read().await).unwrap(), stub_interfaces());
+    }
+
+    #[tokio::test]
+    async fn interfaces_are_refused_without_multichannel() {
+        let server = server_with(false, stub_interfaces()).await;
+        assert!(matches!(advertised_interfaces(&*server.read().await), Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+        let server = server_with(true, Vec::new()).await;
+        assert!(matches!(advertised_interfaces(&*server.read().await), Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+    }
+
+    #[tokio::test]
+    async fn configured_speed_and_capabilities_reach_the_response() {
+        let detected = vec![
+            SMBNetworkInterfaceInfo::new(3, SMBInterfaceCapabilities::empty(), DEFAULT_LINK_SPEED, "10.0.0.5:0".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(4, SMBInterfaceCapabilities::empty(), DEFAULT_LINK_SPEED, "10.0.1.5:0".parse::<SocketAddr>().unwrap()),
+            SMBNetworkInterfaceInfo::new(5, SMBInterfaceCapabilities::RSS_CAPABLE, DEFAULT_LINK_SPEED, "[fd00::5]:0".parse::<SocketAddr>().unwrap()),
+        ];
+        let builder = server_builder()
+            .multi_channel_capable(true)
+            .interface_provider(SMBStaticInterfaces(detected))
+            .interface_override("10.0.0.5".parse::<IpAddr>().unwrap(), SMBInterfaceOverride::default()
+                .with_link_speed(25_000_000_000)
+                .with_capabilities(SMBInterfaceCapabilities::RSS_CAPABLE | SMBInterfaceCapabilities::RDMA_CAPABLE))
+            .interface_override("fd00::5".parse::<IpAddr>().unwrap(), SMBInterfaceOverride::default().with_link_speed(10_000_000_000));
+        let (server, _) = serve(builder).await;
+
+        let interfaces = advertised_interfaces(&*server.read().await).unwrap();
+        let request = SMBIoCtlRequest::new(SMBIoCtlCode::QueryNetworkInterfaceInfo, SMBFileId { persistent: u64::MAX, volatile: u64::MAX }, Vec::new(), 1024);
+        let response = request.network_interfaces_response(&interfaces).unwrap();
+        let reported = SMBNetworkInterfaceInfo::decode_list(response.output()).unwrap();
+        let summary = reported.iter()
+            .map(|interface| (interface.if_index(), interface.link_speed(), interface.capabilities()))
+            .collect::<Vec<_>>();
+        assert_eq!(summary, vec![
+            (3, 25_000_000_000, SMBInterfaceCapabilities::RSS_CAPABLE | SMBInterfaceCapabilities::RDMA_CAPABLE),
+            (4, DEFAULT_LINK_SPEED, SMBInterfaceCapabilities::empty()),
+            (5, 10_000_000_000, SMBInterfaceCapabilities::RSS_CAPABLE),
+        ]);
+    }
+}
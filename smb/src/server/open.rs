@@ -1,16 +1,27 @@
 use std::fmt::{Debug, Formatter, Pointer};
+use std::future::Future;
 
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
 use uuid::Uuid;
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::protocol::body::create::file_attributes::SMBFileAttributes;
 use crate::protocol::body::create::file_id::SMBFileId;
 use crate::protocol::body::create::oplock::SMBOplockLevel;
 use crate::protocol::body::create::options::SMBCreateOptions;
+use crate::protocol::body::create::share_access::SMBShareAccess;
 use crate::protocol::body::create::SMBCreateRequest;
+use crate::protocol::body::filetime::FileTime;
+use crate::protocol::body::ioctl::copy_chunk::SMBResumeKey;
+use crate::protocol::body::query_directory::SMBDirectoryCursor;
 use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
 use crate::server::lease::SMBLease;
+use crate::server::message_handler::SMBMessageType;
+use crate::server::oplock::oplock_break_notification;
 use crate::server::Server;
 use crate::server::share::{ResourceHandle, SMBFileMetadata};
 use crate::server::tree_connect::SMBTreeConnect;
@@ -21,34 +32,63 @@ pub trait Open: Send + Sync {
     fn init(underlying: <Self::Server as Server>::Handle, request: &SMBCreateRequest) -> Self;
     fn set_session_id(&mut self, session_id: u32);
     fn set_global_id(&mut self, global_id: u32);
+    fn share_name(&self) -> &str;
+    fn set_share_name(&mut self, share_name: String);
+    fn opened_at(&self) -> FileTime;
     fn oplock_level(&self) -> SMBOplockLevel;
+    fn set_oplock_level(&mut self, oplock_level: SMBOplockLevel);
+    // Where break notifications for this open go, the channel of the connection that created it
+    fn set_notification_sender(&mut self, sender: Option<Sender<SMBMessageType>>);
+    fn break_oplock(&mut self, level: SMBOplockLevel);
+    fn acknowledge_oplock_break(&mut self, level: SMBOplockLevel) -> SMBResult<()>;
+    // Resolves once a break sent to this open has been acknowledged, or there was none to wait on
+    fn oplock_break_settled(&self) -> impl Future<Output=()> + Send + 'static;
+    // MS-SMB2 3.3.2.1: a holder that never acknowledged its break loses the oplock outright
+    fn expire_oplock_break(&mut self);
     fn file_attributes(&self) -> SMBFileAttributes;
     fn file_id(&self) -> SMBFileId;
     fn file_metadata(&self) -> SMBResult<SMBFileMetadata>;
+    fn granted_access(&self) -> &SMBAccessMask;
+    // What the tree connect let the create have out of its DesiredAccess
+    fn set_granted_access(&mut self, granted_access: SMBAccessMask);
+    fn share_access(&self) -> SMBShareAccess;
+    fn directory_cursor_mut(&mut self) -> &mut SMBDirectoryCursor;
+    fn handle(&self) -> SMBResult<&<Self::Server as Server>::Handle>;
+    // Whoever closes the open first takes the handle, anything still holding the open then sees STATUS_FILE_CLOSED
+    fn take_handle(&mut self) -> Option<<Self::Server as Server>::Handle>;
+    fn delete_on_close(&self) -> bool;
+    fn set_delete_on_close(&mut self, delete_on_close: bool);
+    // Names this open as the source of a server-side copy, MS-SMB2 3.3.5.15.5
+    fn resume_key(&self) -> SMBResumeKey;
 }
 
 pub struct SMBOpen<S: Server> {
     file_share_id: u32,
+    share_name: String,
+    opened_at: FileTime,
     session_id: u32,
     global_id: u32,
     session: Option<S::Session>,
     tree_connect: Option<SMBTreeConnect<S>>,
     granted_access: SMBAccessMask,
+    share_access: SMBShareAccess,
     oplock_level: SMBOplockLevel,
-    oplock_state: SMBOplockState,
-    oplock_timeout: u64,
+    oplock_state: watch::Sender<SMBOplockState>,
+    notification_sender: Option<Sender<SMBMessageType>>,
     is_durable: bool,
     durable_open_timeout: u64,
     durable_open_scavenger_timeout: u64,
     durable_owner: u64,
-    underlying: S::Handle,
+    underlying: Option<S::Handle>,
     current_ea_index: u32,
     current_quota_index: u32,
+    directory_cursor: SMBDirectoryCursor,
     lock_count: u32,
     path_name: String,
-    resume_key: u32,
+    resume_key: SMBResumeKey,
     file_name: String,
     create_options: SMBCreateOptions,
+    delete_on_close: bool,
     file_attributes: SMBFileAttributes,
     client_guid: Uuid,
     lease: Option<SMBLease<S>>,
@@ -77,26 +117,31 @@ impl<S: Server> Open for SMBOpen<S> {
         let path_name = underlying.path().into();
         Self {
             file_share_id: 0,
+            share_name: String::new(),
+            opened_at: FileTime::now(),
             session_id: 0,
             global_id: 0,
             session: None,
             tree_connect: None,
             granted_access: SMBAccessMask::from_desired_access(request.desired_access()),
+            share_access: request.share_access(),
             oplock_level: SMBOplockLevel::None,
-            oplock_state: SMBOplockState::None,
-            oplock_timeout: 0,
+            oplock_state: watch::channel(SMBOplockState::None).0,
+            notification_sender: None,
             is_durable: false,
             durable_open_timeout: 0,
             durable_open_scavenger_timeout: 0,
             durable_owner: 0,
-            underlying,
+            underlying: Some(underlying),
             current_ea_index: 1,
             current_quota_index: 1,
+            directory_cursor: SMBDirectoryCursor::default(),
             lock_count: 0,
             path_name,
-            resume_key: 0,
+            resume_key: SMBResumeKey::new(rand::random()),
             file_name: request.file_name().into(),
             create_options: request.options(),
+            delete_on_close: request.options().contains(SMBCreateOptions::DELETE_ON_CLOSE),
             file_attributes: request.attributes(),
             client_guid: Default::default(),
             lease: None,
@@ -124,33 +169,142 @@ impl<S: Server> Open for SMBOpen<S> {
         self.global_id = global_id;
     }
 
+    fn share_name(&self) -> &str {
+        &self.share_name
+    }
+
+    fn set_share_name(&mut self, share_name: String) {
+        self.share_name = share_name;
+    }
+
+    fn opened_at(&self) -> FileTime {
+        self.opened_at.clone()
+    }
+
     fn oplock_level(&self) -> SMBOplockLevel {
         self.oplock_level
     }
 
+    fn set_oplock_level(&mut self, oplock_level: SMBOplockLevel) {
+        self.oplock_level = oplock_level;
+        self.oplock_state.send_replace(match oplock_level {
+            SMBOplockLevel::None => SMBOplockState::None,
+            _ => SMBOplockState::Held,
+        });
+    }
+
+    fn set_notification_sender(&mut self, sender: Option<Sender<SMBMessageType>>) {
+        self.notification_sender = sender;
+    }
+
+    fn break_oplock(&mut self, level: SMBOplockLevel) {
+        // The holder has already been told, it answers the first break before it can be sent another
+        if let SMBOplockState::Breaking(_) = *self.oplock_state.borrow() {
+            return;
+        }
+        // A break to none from level II isn't acknowledged, the client just drops its cache
+        if self.oplock_level == SMBOplockLevel::II {
+            self.set_oplock_level(level);
+        } else {
+            self.oplock_state.send_replace(SMBOplockState::Breaking(level));
+        }
+        if let Some(sender) = self.notification_sender.as_ref() {
+            let _ = sender.try_send(oplock_break_notification(self.file_id(), level));
+        }
+    }
+
+    fn acknowledge_oplock_break(&mut self, level: SMBOplockLevel) -> SMBResult<()> {
+        let SMBOplockState::Breaking(break_to) = *self.oplock_state.borrow() else {
+            return Err(SMBError::response_error(NTStatus::InvalidOplockProtocol));
+        };
+        if level > break_to {
+            return Err(SMBError::response_error(NTStatus::InvalidOplockProtocol));
+        }
+        self.set_oplock_level(level);
+        Ok(())
+    }
+
+    fn oplock_break_settled(&self) -> impl Future<Output=()> + Send + 'static {
+        let mut state = self.oplock_state.subscribe();
+        async move {
+            let _ = state.wait_for(|state| !matches!(state, SMBOplockState::Breaking(_))).await;
+        }
+    }
+
+    fn expire_oplock_break(&mut self) {
+        let breaking = matches!(*self.oplock_state.borrow(), SMBOplockState::Breaking(_));
+        if breaking {
+            self.set_oplock_level(SMBOplockLevel::None);
+        }
+    }
+
+    // What's on disk wins over the attributes the create asked for
     fn file_attributes(&self) -> SMBFileAttributes {
-        self.file_attributes
+        self.handle()
+            .and_then(|handle| handle.metadata())
+            .map(|metadata| metadata.attributes)
+            .unwrap_or(self.file_attributes)
     }
 
     fn file_id(&self) -> SMBFileId {
         SMBFileId {
-            persistent: 0,
-            volatile: 0,
+            persistent: self.global_id as u64,
+            volatile: self.session_id as u64,
         }
     }
 
     fn file_metadata(&self) -> SMBResult<SMBFileMetadata> {
-        return self.underlying.metadata()
+        self.handle()?.metadata()
+    }
+
+    fn granted_access(&self) -> &SMBAccessMask {
+        &self.granted_access
+    }
+
+    fn set_granted_access(&mut self, granted_access: SMBAccessMask) {
+        self.granted_access = granted_access;
+    }
+
+    fn share_access(&self) -> SMBShareAccess {
+        self.share_access
+    }
+
+    fn directory_cursor_mut(&mut self) -> &mut SMBDirectoryCursor {
+        &mut self.directory_cursor
+    }
+
+    fn handle(&self) -> SMBResult<&S::Handle> {
+        self.underlying.as_ref()
+            .ok_or(SMBError::response_error(NTStatus::FileClosed))
+    }
+
+    fn take_handle(&mut self) -> Option<S::Handle> {
+        // A closed open holds no oplock, so nothing waiting on its break is left hanging
+        self.set_oplock_level(SMBOplockLevel::None);
+        self.underlying.take()
+    }
+
+    fn delete_on_close(&self) -> bool {
+        self.delete_on_close
+    }
+
+    fn set_delete_on_close(&mut self, delete_on_close: bool) {
+        self.delete_on_close = delete_on_close;
+    }
+
+    fn resume_key(&self) -> SMBResumeKey {
+        self.resume_key
     }
 }
 // TODO: From MS-FSCC section 2.6
 #[derive(Debug)]
 struct FileAttributes;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SMBOplockState {
     Held,
-    Breaking,
+    // Waiting on the client to acknowledge a break to this level
+    Breaking(SMBOplockLevel),
     None
 }
 
@@ -164,14 +318,17 @@ impl<S: Server> Debug for SMBOpen<S> where S: Debug, S::Session: Debug, S::Handl
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("SMBOpen")
             .field("file_share_id", &self.file_share_id)
+            .field("share_name", &self.share_name)
+            .field("opened_at", &self.opened_at)
             .field("session_id", &self.session_id)
             .field("global_id", &self.global_id)
             .field("session", &self.session)
             .field("tree_connect", &self.tree_connect)
             .field("granted_access", &self.granted_access)
+            .field("share_access", &self.share_access)
             .field("oplock_level", &self.oplock_level)
-            .field("oplock_state", &self.oplock_state)
-            .field("oplock_timeout", &self.oplock_timeout)
+            .field("oplock_state", &*self.oplock_state.borrow())
+            .field("notification_sender", &self.notification_sender)
             .field("is_durable", &self.is_durable)
             .field("durable_open_timeout", &self.durable_open_timeout)
             .field("durable_open_scavenger_timeout", &self.durable_open_scavenger_timeout)
@@ -179,11 +336,13 @@ impl<S: Server> Debug for SMBOpen<S> where S: Debug, S::Session: Debug, S::Handl
             .field("underlying", &self.underlying)
             .field("current_ea_index", &self.current_ea_index)
             .field("current_quota_index", &self.current_quota_index)
+            .field("directory_cursor", &self.directory_cursor)
             .field("lock_count", &self.lock_count)
             .field("path_name", &self.path_name)
             .field("resume_key", &self.resume_key)
             .field("file_name", &self.file_name)
             .field("create_options", &self.create_options)
+            .field("delete_on_close", &self.delete_on_close)
             .field("file_attributes", &self.file_attributes)
             .field("client_guid", &self.client_guid)
             .field("lease", &self.lease)
@@ -0,0 +1,92 @@
+use std::time::Duration;
+
+use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::create::oplock::SMBOplockLevel;
+use crate::protocol::body::oplock_break::SMBOplockBreakContent;
+use crate::protocol::body::SMBBody;
+use crate::protocol::header::command_code::SMBCommandCode;
+use crate::protocol::header::flags::SMBFlags;
+use crate::protocol::header::SMBSyncHeader;
+use crate::protocol::message::SMBMessage;
+use crate::server::message_handler::SMBMessageType;
+
+// Unsolicited notifications go out with this MessageId, MS-SMB2 3.3.4.6
+const NOTIFICATION_MESSAGE_ID: u64 = u64::MAX;
+
+// MS-SMB2 3.3.2.1: how long a holder gets to acknowledge a break, Windows waits 35 seconds
+pub const OPLOCK_BREAK_TIMEOUT: Duration = Duration::from_secs(35);
+
+// Exclusive and batch oplocks need the file to themselves, anything else can only share at level II.
+// Leases aren't supported, so a lease request gets no oplock at all
+pub fn granted_oplock_level(requested: SMBOplockLevel, directory: bool, other_opens: bool) -> SMBOplockLevel {
+    match requested {
+        SMBOplockLevel::None | SMBOplockLevel::Lease => SMBOplockLevel::None,
+        _ if directory => SMBOplockLevel::None,
+        _ if other_opens => SMBOplockLevel::II,
+        requested => requested,
+    }
+}
+
+// What an existing open's oplock drops to when another open of the same file arrives, if anything
+pub fn oplock_break_level(held: SMBOplockLevel, incoming_writes: bool) -> Option<SMBOplockLevel> {
+    match held {
+        SMBOplockLevel::Exclusive | SMBOplockLevel::Batch if incoming_writes => Some(SMBOplockLevel::None),
+        SMBOplockLevel::Exclusive | SMBOplockLevel::Batch => Some(SMBOplockLevel::II),
+        SMBOplockLevel::II if incoming_writes => Some(SMBOplockLevel::None),
+        _ => None,
+    }
+}
+
+pub fn oplock_break_notification(file_id: SMBFileId, level: SMBOplockLevel) -> SMBMessageType {
+    let header = SMBSyncHeader::new(SMBCommandCode::OplockBreak, SMBFlags::SERVER_TO_REDIR, 0, NOTIFICATION_MESSAGE_ID, 0, 0, [0; 16]);
+    // Server-sent oplock break bodies parse as the acknowledgement variant
+    SMBMessage::new(header, SMBBody::OplockBreakAcknowledgement(SMBOplockBreakContent::new(level.into(), file_id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::create::file_id::SMBFileId;
+    use crate::protocol::body::create::oplock::SMBOplockLevel;
+    use crate::protocol::body::oplock_break::oplock_level::SMBOplockLevel as SMBBreakOplockLevel;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::message::{Message, SMBSyncMessage};
+    use crate::server::oplock::{granted_oplock_level, oplock_break_level, oplock_break_notification};
+
+    #[test]
+    fn first_opener_gets_exclusive_and_second_breaks_it() {
+        let first = granted_oplock_level(SMBOplockLevel::Exclusive, false, false);
+        assert_eq!(first, SMBOplockLevel::Exclusive);
+
+        let second = granted_oplock_level(SMBOplockLevel::Exclusive, false, true);
+        assert_eq!(second, SMBOplockLevel::II);
+        assert_eq!(oplock_break_level(first, false), Some(SMBOplockLevel::II));
+        assert_eq!(oplock_break_level(first, true), Some(SMBOplockLevel::None));
+    }
+
+    #[test]
+    fn level_ii_only_breaks_for_writers() {
+        assert_eq!(oplock_break_level(SMBOplockLevel::II, false), None);
+        assert_eq!(oplock_break_level(SMBOplockLevel::II, true), Some(SMBOplockLevel::None));
+        assert_eq!(oplock_break_level(SMBOplockLevel::None, true), None);
+    }
+
+    #[test]
+    fn directories_and_leases_get_no_oplock() {
+        assert_eq!(granted_oplock_level(SMBOplockLevel::Batch, true, false), SMBOplockLevel::None);
+        assert_eq!(granted_oplock_level(SMBOplockLevel::Lease, false, false), SMBOplockLevel::None);
+        assert_eq!(granted_oplock_level(SMBOplockLevel::Batch, false, false), SMBOplockLevel::Batch);
+    }
+
+    #[test]
+    fn break_notification_parses_as_a_server_message() {
+        let file_id = SMBFileId { persistent: 4, volatile: 2 };
+        let bytes = oplock_break_notification(file_id.clone(), SMBOplockLevel::II).as_bytes();
+        let (_, parsed) = SMBSyncMessage::parse(&bytes).unwrap();
+        assert_eq!(parsed.header.message_id, u64::MAX);
+        let SMBBody::OplockBreakAcknowledgement(content) = parsed.body else {
+            panic!("expected an oplock break body");
+        };
+        assert_eq!(content.level(), SMBBreakOplockLevel::II);
+        assert_eq!(content.file_id(), &file_id);
+    }
+}
@@ -18,11 +18,12 @@ use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::protocol::body::dialect::SMBDialect;
+use crate::protocol::body::filetime::FileTime;
 use crate::protocol::body::negotiate::context::EncryptionCipher;
-use crate::protocol::body::negotiate::context::EncryptionCipher::AES256CCM;
 use crate::protocol::body::session_setup::{SMBSessionSetupRequest, SMBSessionSetupResponse};
 use crate::protocol::body::SMBBody;
 use crate::protocol::body::tree_connect::{SMBTreeConnectRequest, SMBTreeConnectResponse};
+use crate::protocol::body::tree_connect::flags::SMBShareFlags;
 use crate::protocol::header::{Header, SMBSyncHeader};
 use crate::protocol::message::{Message, SMBMessage};
 use crate::server::connection::Connection;
@@ -30,12 +31,15 @@ use crate::server::message_handler::{NonEndingHandler, SMBHandlerState, SMBLocke
 use crate::server::open::Open;
 use crate::server::safe_locked_getter::InnerGetter;
 use crate::server::Server;
+use crate::server::share::{ResourceHandle, SharedResource};
 use crate::server::tree_connect::SMBTreeConnect;
 use crate::util::auth::{AuthContext, AuthProvider};
 use crate::util::auth::spnego::{SPNEGOToken, SPNEGOTokenResponseBody};
 use crate::util::crypto::sp800_108::derive_key;
+use crate::util::crypto::smb2::{chain_preauth_hash, derive_signing_key, generate_encryption_keys};
 
 type SMBMessageType = SMBMessage<SMBSyncHeader, SMBBody>;
+type ExpiredSession<S> = (Vec<Arc<RwLock<<S as Server>::Open>>>, Option<Arc<RwLock<<S as Server>::Connection>>>);
 
 const OUTPUT_SIZE_128: usize = 128;
 const OUTPUT_SIZE_256: usize = 256;
@@ -50,11 +54,21 @@ pub trait Session<C: Connection, A: AuthProvider, O: Open>: Send + Sync {
     fn state(&self) -> SessionState;
     fn anonymous(&self) -> bool;
     fn guest(&self) -> bool;
+    // None until the client has authenticated
+    fn user_name(&self) -> Option<&<A::Context as AuthContext>::UserName>;
+    fn creation_time(&self) -> FileTime;
     fn security_context_mut(&mut self) -> &mut A::Context;
     fn provider(&self) -> &Arc<A>;
     fn encrypt_data(&self) -> bool;
+    fn signing_required(&self) -> bool;
+    fn signing_key(&self) -> &[u8];
+    fn encryption_key(&self) -> &[u8];
+    fn decryption_key(&self) -> &[u8];
+    // Folds a SESSION_SETUP message into the session's preauth integrity hash
+    fn update_preauth_hash(&mut self, message: &[u8]);
     fn open_table(&self) -> &HashMap<u32, Arc<RwLock<O>>>;
-    fn add_open(&mut self, open: Arc<RwLock<O>>) -> impl Future<Output=()>;
+    fn add_open(&mut self, open: Arc<RwLock<O>>) -> impl Future<Output=()> + Send;
+    fn remove_open(&mut self, id: u32) -> Option<Arc<RwLock<O>>>;
 }
 
 #[derive(Builder)]
@@ -74,7 +88,7 @@ pub struct SMBSession<S: Server> {
     expiration_time: u64,
     connection: Weak<RwLock<S::Connection>>,
     global_id: u32,
-    creation_time: u64,
+    creation_time: FileTime,
     idle_time: u64,
     user_name: String,
     // channel_list: HashMap<u64, SMBChannel<R, W, S>>,
@@ -112,11 +126,13 @@ impl<S: Server> SMBSession<S> {
         let conn = self.get_connection()?;
         let conn_rd = conn.read().await;
         let dialect = conn_rd.dialect();
-        let cipher = conn_rd.cipher_id();
+        let cipher = conn_rd.encryption_cipher();
+        let encryption_active = conn_rd.encryption_active();
         drop(conn_rd);
+        // Seeded with the server's EncryptData at init, settled now that the session is authenticated
+        self.encrypt_data = session_requires_encryption(self.encrypt_data, dialect, encryption_active, self.is_anonymous, self.is_guest);
         self.set_session_key();
-        self.generate_keys(dialect, cipher);
-        Ok(())
+        self.generate_keys(dialect, cipher)
     }
     fn set_session_key(&mut self) {
         // Set session_key to first 16 bytes of full key (or right padded if full key is less)
@@ -124,37 +140,21 @@ impl<S: Server> SMBSession<S> {
             self.session_key[num] = self.full_session_key[num];
         }
     }
-    fn generate_keys(&mut self, dialect: SMBDialect, cipher_id: EncryptionCipher) {
+    fn generate_keys(&mut self, dialect: SMBDialect, cipher_id: EncryptionCipher) -> SMBResult<()> {
         println!("in keygen");
-        let key_length = match cipher_id {
-            EncryptionCipher::AES256GCM | AES256CCM => 32,
-            _ => 16
-        };
-
-        let signing_key_len = 16;
-
-        let smb_sign_bytes = [
-            "SmbSign".as_bytes(),
-            &[0],
-        ].concat();
-
-        let (signing_key_label, signing_key_context): (&str, &[u8]) = match dialect {
-            SMBDialect::V3_1_1 => ("SMBSigningKey", &self.preauth_integrity_hash_value),
-            _ => ("SMB2AESCMAC", &smb_sign_bytes),
-        };
-        self.signing_key = match dialect {
-            SMBDialect::V3_0_0 | SMBDialect::V3_1_1 => generate_key(&self.session_key, signing_key_label, signing_key_context, signing_key_len),
-            _ => self.session_key.clone().to_vec(),
-        };
+        self.signing_key = derive_signing_key(&self.session_key, dialect, &self.preauth_integrity_hash_value)?;
 
         println!("skey: {:02x?}, signing key: {:02x?}", self.session_key, self.signing_key);
 
-        let (application_key_label, application_key_context): (&str, &[u8]) = match dialect {
-            SMBDialect::V3_1_1 => ("SMBAppKey", &self.preauth_integrity_hash_value),
-            _ => ("SMB2APP", "SmbRpc".as_bytes())
-        };
-        self.application_key = generate_key(&self.session_key, application_key_label, application_key_context, key_length);
+        self.application_key = application_key(&self.session_key, dialect, &self.preauth_integrity_hash_value);
         println!("signing: {:?}, application: {:?}", self.signing_key, self.application_key);
+
+        if dialect.is_smb3() && cipher_id != EncryptionCipher::None {
+            let (encryption_key, decryption_key) = generate_encryption_keys(&self.session_key, &self.full_session_key, dialect, cipher_id, &self.preauth_integrity_hash_value)?;
+            self.encryption_key = encryption_key;
+            self.decryption_key = decryption_key;
+        }
+        Ok(())
     }
     fn get_next_map_id<V>(map: &HashMap<u32, V>) -> u32 {
         for i in 1..u32::MAX {
@@ -166,6 +166,80 @@ impl<S: Server> SMBSession<S> {
     }
 }
 
+impl<S: Server<Session=SMBSession<S>>> SMBSession<S> {
+    // Cuts the session off from its trees and opens, handing back the opens to close and the connection to detach from
+    pub(crate) fn expire(&mut self) -> ExpiredSession<S> {
+        self.state = SessionState::Expired;
+        self.tree_connect_table.clear();
+        let opens = self.open_table.drain().map(|(_, open)| open).collect();
+        (opens, self.connection.upgrade())
+    }
+
+    // MS-SMB2 3.3.5.5.3: a session whose authentication fails is dropped from both tables, the client starts over
+    async fn discard_failed_session(&self) -> SMBResult<()> {
+        let conn = self.get_connection()?;
+        let server = conn.read().await.server_ref().upgrade()
+            .ok_or(SMBError::server_error("Server not found for connection"))?;
+        server.write().await.sessions_mut().remove(&self.session_id);
+        conn.write().await.remove_session(self.session_id);
+        Ok(())
+    }
+
+    // MS-SMB2 3.3.5.5.3: a reconnecting user's old session goes away along with its opens; another user's is left alone
+    async fn expire_previous_session(&self, previous_session_id: u64) -> SMBResult<()> {
+        if previous_session_id == 0 || previous_session_id == self.session_id {
+            return Ok(());
+        }
+        let conn = self.get_connection()?;
+        let server = conn.read().await.server_ref().upgrade()
+            .ok_or(SMBError::server_error("Server not found for connection"))?;
+        let mut server_wr = server.write().await;
+        let Some(previous) = server_wr.sessions().get(&previous_session_id).map(Arc::clone) else {
+            return Ok(());
+        };
+        let mut previous_wr = previous.write().await;
+        let same_user = match (self.security_context.user_name(), previous_wr.security_context.user_name()) {
+            (Ok(user), Ok(previous_user)) => user == previous_user,
+            _ => false,
+        };
+        if !same_user {
+            return Ok(());
+        }
+        server_wr.sessions_mut().remove(&previous_session_id);
+        let (opens, previous_conn) = previous_wr.expire();
+        drop(previous_wr);
+        for open in opens.iter() {
+            let global_id = open.read().await.file_id().persistent;
+            server_wr.remove_open(global_id as u32);
+        }
+        drop(server_wr);
+
+        if let Some(previous_conn) = previous_conn {
+            previous_conn.write().await.remove_session(previous_session_id);
+        }
+        for open in opens {
+            if let Some(handle) = open.write().await.take_handle() {
+                Box::new(handle).close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// MS-SMB2 3.3.5.5.3: only authenticated users on an SMB 3.x connection that can encrypt get Session.EncryptData
+fn session_requires_encryption(encrypt_data: bool, dialect: SMBDialect, encryption_active: bool, anonymous: bool, guest: bool) -> bool {
+    encrypt_data && dialect.is_smb3() && encryption_active && !anonymous && !guest
+}
+
+// MS-SMB2 3.3.5.5.3: 128 bits whatever cipher the connection settled on
+fn application_key(session_key: &[u8], dialect: SMBDialect, preauth_integrity_hash_value: &[u8]) -> Vec<u8> {
+    let (label, context): (&str, &[u8]) = match dialect {
+        SMBDialect::V3_1_1 => ("SMBAppKey", preauth_integrity_hash_value),
+        _ => ("SMB2APP", "SmbRpc".as_bytes())
+    };
+    generate_key(session_key, label, context, 16)
+}
+
 fn generate_key(secure_key: &[u8], label: &str, context: &[u8], output_len: usize) -> Vec<u8> {
     println!("key len: {:?}, label: {:02x?}, ctx: {:02x?}", secure_key.len(), label, context);
     let mac = <Hmac<Sha256>>::new_from_slice(secure_key)
@@ -188,23 +262,51 @@ impl<S: Server<Session=SMBSession<S>>> SMBLockedMessageHandlerBase for Arc<RwLoc
             .map(Arc::clone)
     }
 
+    // A request that picked up the session before it was torn down still has to fail
+    async fn validate_message(&self, _message: &SMBMessageType) -> SMBResult<()> {
+        match self.read().await.state {
+            SessionState::Expired => Err(SMBError::response_error(NTStatus::UserSessionDeleted)),
+            _ => Ok(()),
+        }
+    }
+
     async fn handle_session_setup(&mut self, header: &SMBSyncHeader, request: &SMBSessionSetupRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
         let buffer = request.buffer();
         let (_, token) = SPNEGOToken::<S::AuthProvider>::parse(buffer)?;
         let mut session_write = self.write().await;
+        let channel_bindings = {
+            let conn = session_write.get_connection()?;
+            let conn_rd = conn.read().await;
+            if conn_rd.dialect() == SMBDialect::V3_1_1 {
+                session_write.update_preauth_hash(conn_rd.session_setup_request());
+            }
+            conn_rd.channel_bindings().map(<[u8]>::to_vec)
+        };
         let provider = session_write.provider.clone();
         let ctx = session_write.security_context_mut();
+        ctx.set_channel_bindings(channel_bindings);
         let (status, msg) = token.get_message(provider.as_ref(), ctx)?;
+        if status != NTStatus::StatusSuccess && status != NTStatus::MoreProcessingRequired {
+            drop(session_write);
+            self.read().await.discard_failed_session().await?;
+            return Err(SMBError::response_error(status));
+        }
         if status == NTStatus::StatusSuccess {
             let session_key = ctx.session_key().to_vec();
             session_write.handle_successful_setup(session_key).await?;
             println!("session key: {:02x?}", session_write.session_key);
         }
         drop(session_write);
-        let response = SPNEGOTokenResponseBody::<S::AuthProvider>::new(status, msg);
+        if status == NTStatus::StatusSuccess {
+            self.read().await.expire_previous_session(request.previous_session_id()).await?;
+        }
+        let mut response = SPNEGOTokenResponseBody::<S::AuthProvider>::new(status, msg);
+        if let Some(mech) = token.negotiated_mech() {
+            response = response.with_supported_mech(mech);
+        }
         let (id, session_setup) = {
             let session_read = self.read().await;
-            let resp = SMBSessionSetupResponse::from_session_state::<S>(&session_read, response.as_bytes());
+            let resp = SMBSessionSetupResponse::from_session_state::<S>(&session_read, status, response.as_bytes());
             (session_read.id(), resp)
         };
         let header = header.create_response_header(status as u32, id, 0);
@@ -229,10 +331,30 @@ impl<S: Server<Session=SMBSession<S>>> SMBLockedMessageHandlerBase for Arc<RwLoc
             return Err(SMBError::response_error(NTStatus::BadNetworkName))
         }
         let share = share.unwrap();
-        let response = SMBTreeConnectResponse::for_share(share.deref());
+        // Each share decides who may connect. Anonymous sessions have no name to check, whether they get
+        // this far at all is down to the server's anonymous access setting
+        if let Some(uid) = self_rd.user_name() {
+            if !share.connect_allowed(uid) {
+                return Err(SMBError::response_error(NTStatus::AccessDenied));
+            }
+        }
+        share.check_encryption(conn_rd.encryption_active())?;
+        // MS-SMB2 3.3.5.7: the extended form is 3.1.1 only, and a remoted identity is ignored on shares
+        // that don't allow identity remoting
+        if request.extension().is_some() && (!server_rd.tree_connect_extension() || conn_rd.dialect() != SMBDialect::V3_1_1) {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        let remoted_identity = request.remoted_identity()
+            .filter(|_| share.flags().contains(SMBShareFlags::IDENTITY_REMOTING))
+            .cloned();
+        let mut response = SMBTreeConnectResponse::for_share(share.deref(), self_rd.user_name());
+        if !server_rd.dfs_capable() {
+            response = response.without_dfs();
+        }
         let tree_id = SMBSession::<S>::get_next_map_id(&self_rd.tree_connect_table);
-        let tree_connect = SMBTreeConnect::init(tree_id, Arc::downgrade(self), share.clone(), response.access_mask().clone());
-        let header = SMBSyncHeader::create_response_header(&header, 0, self_rd.id(), 1);
+        let tree_connect = SMBTreeConnect::init(tree_id, Arc::downgrade(self), share.clone(), response.access_mask().clone())
+            .with_remoted_identity(remoted_identity);
+        let header = header.create_response_header(0, self_rd.id(), tree_id);
         drop(self_rd);
         let mut self_wr = self.write().await;
         self_wr.tree_connect_table.insert(tree_id, Arc::new(tree_connect));
@@ -273,7 +395,7 @@ impl<S: Server<Session=Self>> Session<S::Connection, S::AuthProvider, S::Open> f
             expiration_time: 0,
             connection: conn,
             global_id: 0,
-            creation_time: 0,
+            creation_time: FileTime::now(),
             idle_time: 0,
             user_name: "".to_string(),
             encrypt_data,
@@ -315,6 +437,14 @@ impl<S: Server<Session=Self>> Session<S::Connection, S::AuthProvider, S::Open> f
         self.is_guest
     }
 
+    fn user_name(&self) -> Option<&<<S::AuthProvider as AuthProvider>::Context as AuthContext>::UserName> {
+        self.security_context.user_name().ok()
+    }
+
+    fn creation_time(&self) -> FileTime {
+        self.creation_time.clone()
+    }
+
     fn security_context_mut(&mut self) -> &mut <S::AuthProvider as AuthProvider>::Context {
         &mut self.security_context
     }
@@ -327,6 +457,26 @@ impl<S: Server<Session=Self>> Session<S::Connection, S::AuthProvider, S::Open> f
         self.encrypt_data
     }
 
+    fn signing_required(&self) -> bool {
+        self.signing_required
+    }
+
+    fn signing_key(&self) -> &[u8] {
+        &self.signing_key
+    }
+
+    fn encryption_key(&self) -> &[u8] {
+        &self.encryption_key
+    }
+
+    fn decryption_key(&self) -> &[u8] {
+        &self.decryption_key
+    }
+
+    fn update_preauth_hash(&mut self, message: &[u8]) {
+        self.preauth_integrity_hash_value = chain_preauth_hash(&self.preauth_integrity_hash_value, message);
+    }
+
     fn open_table(&self) -> &HashMap<u32, Arc<RwLock<S::Open>>> {
         &self.open_table
     }
@@ -338,4 +488,178 @@ impl<S: Server<Session=Self>> Session<S::Connection, S::AuthProvider, S::Open> f
         drop(open_wr);
         self.open_table.insert(id, open);
     }
-}
\ No newline at end of file
+
+    fn remove_open(&mut self, id: u32) -> Option<Arc<RwLock<S::Open>>> {
+        self.open_table.remove(&id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::client::SMBClient;
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask};
+    use crate::protocol::body::tree_connect::capabilities::SMBTreeConnectCapabilities;
+    use crate::protocol::body::tree_connect::flags::SMBShareFlags;
+    use crate::server::{Server, StartSMBServer};
+    use crate::server::session::{application_key, Session, session_requires_encryption, SessionState};
+    use crate::server::share::file_system::SMBFileSystemShare;
+    use crate::server::share::{ResourceHandle, SMBShareDfs};
+    use crate::test_util::{serve, share_server_builder, TempDir, user_server_builder};
+    use crate::util::auth::ntlm::NTLMAuthProvider;
+    use crate::util::auth::User;
+
+    #[test]
+    fn authenticated_smb3_session_requires_encryption() {
+        assert!(session_requires_encryption(true, SMBDialect::V3_1_1, true, false, false));
+        assert!(session_requires_encryption(true, SMBDialect::V3_0_0, true, false, false));
+    }
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|idx| u8::from_str_radix(&hex[idx..(idx + 2)], 16).unwrap()).collect()
+    }
+
+    // The inputs of the 3.1.1 example the key derivation tests in util::crypto::smb2 check against
+    #[test]
+    fn application_key_stays_128_bits() {
+        let session_key = unhex("270e1ba896585eeb7af3472d3b4c75a7");
+        let preauth = unhex("0dd13628cc3ed218ef9df9772d436d0887ab9814bfae63a80aa845f36909db7928622dddad522d9751640a459762c5a9d6bb084cbb3ce6bdadef5d5bce3c6c01");
+        assert_eq!(application_key(&session_key, SMBDialect::V3_1_1, &preauth), unhex("6d7ad7954e9ec61e907b4d473dc178ff"));
+    }
+
+    #[test]
+    fn session_without_usable_encryption_stays_plaintext() {
+        assert!(!session_requires_encryption(false, SMBDialect::V3_1_1, true, false, false));
+        assert!(!session_requires_encryption(true, SMBDialect::V2_1_0, false, false, false));
+        assert!(!session_requires_encryption(true, SMBDialect::V3_1_1, false, false, false));
+        assert!(!session_requires_encryption(true, SMBDialect::V3_1_1, true, true, false));
+        assert!(!session_requires_encryption(true, SMBDialect::V3_1_1, true, false, true));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reconnect_tears_down_the_previous_session_of_the_same_user() {
+        let root = TempDir::new("previous_session");
+        let users = vec![User::new("alice", "password"), User::new("bob", "password")];
+        let (server, addr) = serve(share_server_builder(&root).auth_provider(NTLMAuthProvider::new(users, false))).await;
+        server.clone().spawn();
+
+        let mut first = SMBClient::connect(addr).await.unwrap();
+        first.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        first.authenticate("", "alice", "password").await.unwrap();
+        let tree_id = first.tree_connect("\\\\127.0.0.1\\test").await.unwrap();
+        first.open_directory(tree_id, "").await.unwrap();
+        assert_eq!(server.read().await.opens().len(), 1);
+
+        let mut second = SMBClient::connect(addr).await.unwrap();
+        second.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        second.reconnect(first.session_id(), "", "alice", "password").await.unwrap();
+        {
+            let server_rd = server.read().await;
+            assert_ne!(second.session_id(), first.session_id());
+            assert!(!server_rd.sessions().contains_key(&first.session_id()));
+            assert!(server_rd.sessions().contains_key(&second.session_id()));
+            assert!(server_rd.opens().is_empty());
+        }
+
+        let mut other_user = SMBClient::connect(addr).await.unwrap();
+        other_user.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        other_user.reconnect(second.session_id(), "", "bob", "password").await.unwrap();
+        assert!(server.read().await.sessions().contains_key(&second.session_id()));
+
+        server.read().await.shutdown();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn tree_connect_honours_the_share_connect_check() {
+        let root = TempDir::new("connect_allowed");
+        let path = root.to_string_lossy().into_owned();
+        let builder = user_server_builder()
+            .add_fs_share("open".into(), path.clone(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false)
+            .add_fs_share("bob_only".into(), path, |user| user == "bob", |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false);
+        let (server, addr) = serve(builder).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let allowed = client.tree_connect("\\\\127.0.0.1\\open").await;
+        let refused = client.tree_connect("\\\\127.0.0.1\\bob_only").await;
+
+        server.read().await.shutdown();
+        assert!(allowed.is_ok());
+        assert!(matches!(refused, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn ntlm_legs_end_in_an_authenticated_session() {
+        let root = TempDir::new("ntlm_legs");
+        let (server, addr) = serve(share_server_builder(&root)).await;
+        server.clone().spawn();
+
+        // The client bails unless NEGOTIATE comes back as MORE_PROCESSING_REQUIRED with a CHALLENGE
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        {
+            let server_rd = server.read().await;
+            let session = server_rd.sessions().get(&client.session_id()).unwrap().read().await;
+            assert_eq!(session.state(), SessionState::Valid);
+            assert_eq!(session.user_name().map(String::as_str), Some("alice"));
+            assert!(!session.anonymous());
+        }
+
+        let mut wrong = SMBClient::connect(addr).await.unwrap();
+        wrong.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        let refused = wrong.authenticate("", "alice", "wrong").await;
+        let sessions = server.read().await.sessions().len();
+
+        server.read().await.shutdown();
+        assert!(matches!(refused, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::LogonFailure));
+        assert_eq!(sessions, 1);
+    }
+
+    // Connects to a DFS root share and a plain one, returning what each tree connect response advertised
+    async fn dfs_tree_connects(dfs_capable: bool) -> Vec<(SMBShareFlags, SMBTreeConnectCapabilities)> {
+        let root = TempDir::new(&format!("dfs_{}", dfs_capable));
+        let path = root.to_string_lossy().into_owned();
+        let dfs_root = SMBFileSystemShare::<String, Box<dyn ResourceHandle>>::path("dfs".into(), path.clone(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL))
+            .with_dfs(SMBShareDfs::Root);
+        let builder = user_server_builder()
+            .dfs_capable(dfs_capable)
+            .add_share("dfs", dfs_root.into())
+            .add_fs_share("plain".into(), path, |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false);
+        let (server, addr) = serve(builder).await;
+        server.clone().spawn();
+
+        let mut client = SMBClient::connect(addr).await.unwrap();
+        client.negotiate(vec![SMBDialect::V2_1_0]).await.unwrap();
+        client.authenticate("", "alice", "password").await.unwrap();
+        let mut advertised = Vec::new();
+        for share in ["dfs", "plain"] {
+            let (_, response) = client.tree_connect_response(&format!("\\\\127.0.0.1\\{}", share)).await.unwrap();
+            advertised.push((response.share_flags(), response.capabilities().clone()));
+        }
+
+        server.read().await.shutdown();
+        advertised
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dfs_root_share_is_advertised_on_tree_connect() {
+        let advertised = dfs_tree_connects(true).await;
+        let (root_flags, root_capabilities) = &advertised[0];
+        assert!(root_flags.contains(SMBShareFlags::DFS | SMBShareFlags::DFS_ROOT));
+        assert_eq!(root_capabilities, &SMBTreeConnectCapabilities::DFS);
+        let (plain_flags, plain_capabilities) = &advertised[1];
+        assert!(!plain_flags.intersects(SMBShareFlags::DFS | SMBShareFlags::DFS_ROOT));
+        assert_eq!(plain_capabilities, &SMBTreeConnectCapabilities::empty());
+
+        for (flags, capabilities) in dfs_tree_connects(false).await {
+            assert!(!flags.intersects(SMBShareFlags::DFS | SMBShareFlags::DFS_ROOT));
+            assert!(!capabilities.contains(SMBTreeConnectCapabilities::DFS));
+        }
+    }
+}
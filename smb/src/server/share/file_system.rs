@@ -2,17 +2,26 @@ use std::any::Any;
 use std::fmt::{Debug, Formatter};
 use std::fs;
 use std::fs::{File, OpenOptions, ReadDir};
+use std::io;
+use std::io::ErrorKind;
 use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use smb_core::error::SMBError;
-use smb_core::SMBResult;
+use smb_core::nt_status::NTStatus;
+use smb_core::{SMBResult, SMBToBytes};
 
 use crate::protocol::body::create::disposition::SMBCreateDisposition;
+use crate::protocol::body::create::file_attributes::{IO_REPARSE_TAG_SYMLINK, SMBFileAttributes};
+use crate::protocol::body::error::SMBSymbolicLinkErrorResponse;
 use crate::protocol::body::filetime::FileTime;
 use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::protocol::body::tree_connect::capabilities::SMBTreeConnectCapabilities;
 use crate::protocol::body::tree_connect::flags::SMBShareFlags;
-use crate::server::share::{ConnectAllowed, FilePerms, ResourceHandle, ResourceType, SharedResource, SMBFileMetadata};
+#[cfg(feature = "async")]
+use crate::server::share::HandleIO;
+use crate::server::share::{ConnectAllowed, FilePerms, ResourceHandle, ResourceType, SharedResource, SMBDirectoryEntry, SMBFileMetadata, SMBShareDfs};
 
 #[derive(Debug)]
 pub struct SMBFileSystemHandle {
@@ -57,6 +66,20 @@ impl ResourceHandle for SMBFileSystemHandle {
         Ok(())
     }
 
+    fn delete(self: Box<Self>) -> SMBResult<()> {
+        let Self { path, resource } = *self;
+        let is_directory = matches!(resource, SMBFileSystemResourceHandle::Directory(_));
+        drop(resource);
+        let removed = match is_directory {
+            true => fs::remove_dir(&path),
+            false => fs::remove_file(&path),
+        };
+        removed.map_err(|err| match err.kind() {
+            ErrorKind::DirectoryNotEmpty => SMBError::response_error(NTStatus::DirectoryNotEmpty),
+            _ => SMBError::io_error(err),
+        })
+    }
+
     fn is_directory(&self) -> bool {
         match &self.resource {
             SMBFileSystemResourceHandle::File(_) => false,
@@ -71,27 +94,155 @@ impl ResourceHandle for SMBFileSystemHandle {
     fn metadata(&self) -> SMBResult<SMBFileMetadata> {
         let metadata = fs::metadata(&self.path())
             .map_err(|err| SMBError::server_error(format!("Failed to get metadata for path: {}, error: {}", self.path(), err)))?;
-        let time_transform = |time: SystemTime| {
-            time.duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-        };
-        Ok(SMBFileMetadata {
-            creation_time: FileTime::from_unix(metadata.created().map(time_transform).unwrap_or(0)),
-            last_access_time: FileTime::from_unix(metadata.accessed().map(time_transform).unwrap_or(0)),
-            last_write_time: FileTime::from_unix(metadata.modified().map(time_transform).unwrap_or(0)),
-            last_modification_time: FileTime::from_unix(metadata.modified().map(time_transform).unwrap_or(0)),
-            allocated_size: metadata.len(),
-            actual_size: metadata.len(),
+        let is_symlink = fs::symlink_metadata(self.path())
+            .is_ok_and(|link| link.file_type().is_symlink());
+        let name = Path::new(self.path()).file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(file_metadata(&metadata, &name, is_symlink))
+    }
+
+    fn read_at(&self, offset: u64, length: u32) -> SMBResult<Vec<u8>> {
+        read_file_at(self.file()?, offset, length)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> SMBResult<u32> {
+        write_file_at(self.file()?, offset, data)
+    }
+
+    fn sync(&self) -> SMBResult<()> {
+        match &self.resource {
+            SMBFileSystemResourceHandle::File(file) => file.sync_all().map_err(SMBError::io_error),
+            SMBFileSystemResourceHandle::Directory(_) => Ok(())
+        }
+    }
+
+    fn list_directory(&self) -> SMBResult<Vec<SMBDirectoryEntry>> {
+        if !self.is_directory() {
+            return Err(SMBError::response_error(NTStatus::InvalidParameter));
+        }
+        let mut entries = fs::read_dir(&self.path)?
+            .map(|entry| {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                Ok(SMBDirectoryEntry {
+                    is_directory: metadata.is_dir(),
+                    metadata: file_metadata(&metadata, &name, entry.file_type()?.is_symlink()),
+                    name,
+                })
+            })
+            .collect::<SMBResult<Vec<SMBDirectoryEntry>>>()?;
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    // The file is cloned so the blocking IO can run on tokio's blocking pool rather than stall a worker
+    #[cfg(feature = "async")]
+    fn read_at_async(&self, offset: u64, length: u32) -> HandleIO<'_, Vec<u8>> {
+        let file = self.file().and_then(|file| file.try_clone().map_err(SMBError::io_error));
+        Box::pin(async move {
+            let file = file?;
+            tokio::task::spawn_blocking(move || read_file_at(&file, offset, length)).await
+                .map_err(|e| SMBError::server_error(format!("Read task failed: {}", e)))?
+        })
+    }
+
+    #[cfg(feature = "async")]
+    fn write_at_async<'a>(&'a self, offset: u64, data: &'a [u8]) -> HandleIO<'a, u32> {
+        let file = self.file().and_then(|file| file.try_clone().map_err(SMBError::io_error));
+        let data = data.to_vec();
+        Box::pin(async move {
+            let file = file?;
+            tokio::task::spawn_blocking(move || write_file_at(&file, offset, &data)).await
+                .map_err(|e| SMBError::server_error(format!("Write task failed: {}", e)))?
         })
     }
 }
 
+impl SMBFileSystemHandle {
+    fn file(&self) -> SMBResult<&File> {
+        match &self.resource {
+            SMBFileSystemResourceHandle::File(file) => Ok(file),
+            SMBFileSystemResourceHandle::Directory(_) => Err(SMBError::response_error(NTStatus::InvalidParameter))
+        }
+    }
+}
+
+fn read_file_at(file: &File, offset: u64, length: u32) -> SMBResult<Vec<u8>> {
+    let mut data = vec![0; length as usize];
+    let mut filled = 0;
+    while filled < data.len() {
+        #[cfg(unix)]
+        let read = std::os::unix::fs::FileExt::read_at(file, &mut data[filled..], offset + filled as u64);
+        #[cfg(windows)]
+        let read = std::os::windows::fs::FileExt::seek_read(file, &mut data[filled..], offset + filled as u64);
+        match read {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(SMBError::io_error(err)),
+        }
+    }
+    data.truncate(filled);
+    Ok(data)
+}
+
+// A single positional write may come back short, so keep going until all of it is down
+fn write_file_at(file: &File, offset: u64, data: &[u8]) -> SMBResult<u32> {
+    let mut written = 0;
+    while written < data.len() {
+        #[cfg(unix)]
+        let write = std::os::unix::fs::FileExt::write_at(file, &data[written..], offset + written as u64);
+        #[cfg(windows)]
+        let write = std::os::windows::fs::FileExt::seek_write(file, &data[written..], offset + written as u64);
+        match write {
+            Ok(0) => return Err(SMBError::io_error(io::Error::from(ErrorKind::WriteZero))),
+            Ok(count) => written += count,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(SMBError::io_error(err)),
+        }
+    }
+    Ok(written as u32)
+}
+
+// Symlinks surface as reparse points so clients can tell them apart from what they point at
+fn file_metadata(metadata: &fs::Metadata, name: &str, is_symlink: bool) -> SMBFileMetadata {
+    let attributes = SMBFileAttributes::from_metadata(metadata, name, is_symlink);
+    let time_transform = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    };
+    SMBFileMetadata {
+        creation_time: FileTime::from_unix(metadata.created().map(time_transform).unwrap_or(0)),
+        last_access_time: FileTime::from_unix(metadata.accessed().map(time_transform).unwrap_or(0)),
+        last_write_time: FileTime::from_unix(metadata.modified().map(time_transform).unwrap_or(0)),
+        last_modification_time: FileTime::from_unix(metadata.modified().map(time_transform).unwrap_or(0)),
+        allocated_size: metadata.len(),
+        actual_size: metadata.len(),
+        index_number: index_number(metadata),
+        attributes,
+        reparse_tag: if is_symlink { IO_REPARSE_TAG_SYMLINK } else { 0 },
+    }
+}
+
+#[cfg(unix)]
+fn index_number(metadata: &fs::Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+// The Windows file index is only exposed through an unstable std API, so other platforms report no index
+#[cfg(not(unix))]
+fn index_number(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
 impl SMBFileSystemResourceHandle {
-    fn file(path: &str, disposition: SMBCreateDisposition) -> SMBResult<Self> {
+    fn file(path: &str, disposition: SMBCreateDisposition, read_only: bool) -> SMBResult<Self> {
         let mut options = OpenOptions::new();
         options.read(true)
-            .write(true);
+            .write(!read_only);
         match disposition {
             SMBCreateDisposition::Supersede => options
                 .truncate(true)
@@ -107,16 +258,15 @@ impl SMBFileSystemResourceHandle {
                 .truncate(true)
                 .create(false),
             SMBCreateDisposition::OverwriteIf => options
-                .truncate(false)
+                .truncate(true)
                 .create(true)
         };
-        let file = options.open(path).map_err(SMBError::io_error)?;
+        let file = options.open(path)?;
         Ok(Self::File(file))
     }
 
     fn directory(path: &str) -> SMBResult<Self> {
-        let res = std::fs::read_dir(path)
-            .map_err(SMBError::io_error)?;
+        let res = std::fs::read_dir(path)?;
         Ok(Self::Directory(res))
     }
 }
@@ -128,7 +278,7 @@ pub struct SMBFileSystemShare<UserName: Send + Sync, Handle: TryFrom<SMBFileSyst
     connect_security: ConnectAllowed<UserName>,
     file_security: FilePerms<UserName>,
     csc_flags: SMBShareFlags,
-    dfs_enabled: bool,
+    dfs: SMBShareDfs,
     do_access_based_directory_enumeration: bool,
     allow_namespace_caching: bool,
     force_shared_delete: bool,
@@ -144,6 +294,10 @@ pub struct SMBFileSystemShare<UserName: Send + Sync, Handle: TryFrom<SMBFileSyst
     encrypt_data: bool,
     supports_identity_remoting: bool,
     compress_data: bool,
+    read_only: bool,
+    case_insensitive: bool,
+    // Left unset, a trailing $ decides
+    hidden: Option<bool>,
     user_name_type: PhantomData<UserName>,
     handle_phantom: PhantomData<Handle>,
 }
@@ -161,18 +315,34 @@ impl<UserName: Send + Sync, Handle: From<SMBFileSystemHandle> + ResourceHandle +
     }
 
     fn flags(&self) -> SMBShareFlags {
-        self.csc_flags
+        let mut flags = self.csc_flags;
+        flags |= self.dfs.share_flags();
+        flags.set(SMBShareFlags::ACCESS_BASED_DIRECTORY_ENUM, self.do_access_based_directory_enumeration);
+        flags.set(SMBShareFlags::ALLOW_NAMESPACE_CACHING, self.allow_namespace_caching);
+        flags.set(SMBShareFlags::FORCE_SHARED_DELETE, self.force_shared_delete);
+        flags.set(SMBShareFlags::RESTRICT_EXCLUSIVE_OPENS, self.restrict_exclusive_options);
+        flags.set(SMBShareFlags::FORCE_LEVEL_II_OPLOCK, self.force_level_2_oplock);
+        flags.set(SMBShareFlags::ENCRYPT_DATA, self.encrypt_data);
+        flags.set(SMBShareFlags::COMPRESS_DATA, self.compress_data);
+        flags
+    }
+
+    fn capabilities(&self) -> SMBTreeConnectCapabilities {
+        let mut capabilities = SMBTreeConnectCapabilities::empty();
+        capabilities.set(SMBTreeConnectCapabilities::DFS, self.dfs != SMBShareDfs::None);
+        capabilities.set(SMBTreeConnectCapabilities::CONTINUOUS_AVAILABILITY, self.continuously_available);
+        capabilities
     }
 
     fn handle_create(&self, path: &str, disposition: SMBCreateDisposition, directory: bool) -> SMBResult<Handle> {
-        let path = format!("{}/{}", self.local_path, path);
+        let path = self.resolve_path(path)?.to_string_lossy().into_owned();
         let resource = match directory {
             true => SMBFileSystemResourceHandle::directory(&path),
-            false => SMBFileSystemResourceHandle::file(&path, disposition)
+            false => SMBFileSystemResourceHandle::file(&path, disposition, self.read_only)
         }?;
         let handle = SMBFileSystemHandle {
             resource,
-            path: path.into(),
+            path,
         };
         println!("Created fs handle: {:?}", handle);
         Ok(handle.into())
@@ -185,9 +355,118 @@ impl<UserName: Send + Sync, Handle: From<SMBFileSystemHandle> + ResourceHandle +
     fn resource_perms(&self, uid: &Self::UserName) -> SMBAccessMask {
         (self.file_security)(uid)
     }
+
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn hidden(&self) -> bool {
+        self.hidden.unwrap_or_else(|| self.name.ends_with('$'))
+    }
+
+    fn existing_is_directory(&self, path: &str) -> Option<bool> {
+        let path = self.resolve_path(path).ok()?;
+        fs::metadata(path).ok()
+            .map(|metadata| metadata.is_dir())
+    }
+
+    fn handle_path(&self, path: &str) -> SMBResult<String> {
+        Ok(self.resolve_path(path)?.to_string_lossy().into_owned())
+    }
+
+    fn check_symlinks(&self, path: &str) -> SMBResult<()> {
+        let components = self.resolve_components(path)?;
+        let mut current = PathBuf::from(format!("{}/", self.local_path));
+        for (idx, component) in components.iter().enumerate() {
+            current.push(component);
+            // A missing component is left for handle_create to report
+            let Ok(metadata) = fs::symlink_metadata(&current) else {
+                return Ok(());
+            };
+            if !metadata.file_type().is_symlink() {
+                continue;
+            }
+            let target = fs::read_link(&current)?;
+            // Each remaining component counts with its leading separator, in UTF-16 bytes
+            let unparsed_path_length = components[(idx + 1)..].iter()
+                .map(|remaining| (remaining.encode_utf16().count() + 1) * 2)
+                .sum::<usize>();
+            let error = SMBSymbolicLinkErrorResponse::new(unparsed_path_length as u16, &target.to_string_lossy(), target.is_relative());
+            return Err(SMBError::response_error_with_data(NTStatus::StoppedOnSymlink, error.smb_to_bytes()));
+        }
+        Ok(())
+    }
+}
+
+// Splits a client path on either separator and folds away `.` and `..`, refusing any path that climbs out of the share.
+// A leading separator still means the share root, never the server's
+fn normalized_components(path: &str) -> SMBResult<Vec<&str>> {
+    if path.contains('\0') {
+        return Err(SMBError::response_error(NTStatus::ObjectNameInvalid));
+    }
+    let mut components = Vec::new();
+    for component in path.split(['\\', '/']) {
+        match component {
+            "" | "." => {},
+            ".." => if components.pop().is_none() {
+                return Err(SMBError::response_error(NTStatus::ObjectPathNotFound));
+            },
+            component => components.push(component),
+        }
+    }
+    Ok(components)
+}
+
+// The entry in `directory` whose name matches `component` ignoring case. Names that differ only in case are
+// settled by byte order, so the same request always lands on the same file
+fn case_insensitive_match(directory: &Path, component: &str) -> Option<String> {
+    let wanted = component.to_lowercase();
+    fs::read_dir(directory).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.to_lowercase() == wanted)
+        .min()
 }
 
 impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> SMBFileSystemShare<UserName, Handle> {
+    // The on-disk name of each component, which only differs from what the client sent on a case-insensitive share
+    fn resolve_components(&self, path: &str) -> SMBResult<Vec<String>> {
+        let mut current = PathBuf::from(format!("{}/", self.local_path));
+        let mut resolved = Vec::new();
+        for component in normalized_components(path)? {
+            let name = match self.case_insensitive && fs::symlink_metadata(current.join(component)).is_err() {
+                true => case_insensitive_match(&current, component).unwrap_or_else(|| component.to_string()),
+                false => component.to_string(),
+            };
+            current.push(&name);
+            resolved.push(name);
+        }
+        Ok(resolved)
+    }
+
+    fn resolve_path(&self, path: &str) -> SMBResult<PathBuf> {
+        let mut resolved = PathBuf::from(format!("{}/", self.local_path));
+        resolved.extend(self.resolve_components(path)?);
+        self.check_within_share(&resolved)?;
+        Ok(resolved)
+    }
+
+    // Folding `..` away can't see a symlink on the way that leads back out of the share, so as much of the path
+    // as exists is canonicalized and has to land under the canonical share root. A dangling link is refused
+    // outright since creating through it would put the file wherever it points
+    fn check_within_share(&self, resolved: &Path) -> SMBResult<()> {
+        let root = fs::canonicalize(format!("{}/", self.local_path))?;
+        for ancestor in resolved.ancestors() {
+            match fs::canonicalize(ancestor) {
+                Ok(canonical) if canonical.starts_with(&root) => return Ok(()),
+                Ok(_) => break,
+                Err(_) if fs::symlink_metadata(ancestor).is_ok() => break,
+                Err(_) => continue,
+            }
+        }
+        Err(SMBError::response_error(NTStatus::ObjectPathNotFound))
+    }
+
     pub fn root(name: String, connect_security: ConnectAllowed<UserName>, file_security: FilePerms<UserName>) -> Self {
         Self::path(name, "".into(), connect_security, file_security)
     }
@@ -199,7 +478,7 @@ impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> SMBFileSystemS
             connect_security,
             file_security,
             csc_flags: SMBShareFlags::default(),
-            dfs_enabled: false,
+            dfs: SMBShareDfs::None,
             do_access_based_directory_enumeration: false,
             allow_namespace_caching: false,
             force_shared_delete: false,
@@ -211,14 +490,55 @@ impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> SMBFileSystemS
             hash_enabled: true,
             snapshot_list: vec![],
             ca_timeout: 1000,
-            continuously_available: true,
-            encrypt_data: true,
+            continuously_available: false,
+            encrypt_data: false,
             supports_identity_remoting: true,
             compress_data: false,
+            read_only: false,
+            case_insensitive: false,
+            hidden: None,
             user_name_type: PhantomData,
             handle_phantom: PhantomData
         }
     }
+
+    pub fn with_read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // Lets Windows clients open `File.TXT` for an on-disk `file.txt`
+    pub fn with_case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    // Only the client-side caching bits of `caching` are kept, see MS-SMB2 2.2.10
+    pub fn with_caching(mut self, caching: SMBShareFlags) -> Self {
+        self.csc_flags = caching & SMBShareFlags::NO_CACHING;
+        self
+    }
+
+    pub fn with_encrypt_data(mut self, encrypt_data: bool) -> Self {
+        self.encrypt_data = encrypt_data;
+        self
+    }
+
+    pub fn with_continuously_available(mut self, continuously_available: bool) -> Self {
+        self.continuously_available = continuously_available;
+        self
+    }
+
+    pub fn with_hidden(mut self, hidden: bool) -> Self {
+        self.hidden = Some(hidden);
+        self
+    }
+
+    // Only advertised on servers that are DFS capable
+    pub fn with_dfs(mut self, dfs: SMBShareDfs) -> Self {
+        self.dfs = dfs;
+        self
+    }
 }
 
 impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> Debug for SMBFileSystemShare<UserName, Handle> {
@@ -228,7 +548,7 @@ impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> Debug for SMBF
             .field("server_name", &self.server_name)
             .field("local_path", &self.local_path)
             .field("csc_flags", &self.csc_flags)
-            .field("dfs_enabled", &self.dfs_enabled)
+            .field("dfs", &self.dfs)
             .field("do_access_based_directory_enumeration", &self.do_access_based_directory_enumeration)
             .field("allow_namespace_caching", &self.allow_namespace_caching)
             .field("force_shared_delete", &self.force_shared_delete)
@@ -244,6 +564,391 @@ impl<UserName: Send + Sync, Handle: TryFrom<SMBFileSystemHandle>> Debug for SMBF
             .field("encrypt_data", &self.encrypt_data)
             .field("supports_identity_remoting", &self.supports_identity_remoting)
             .field("compress_data", &self.compress_data)
+            .field("read_only", &self.read_only)
+            .field("case_insensitive", &self.case_insensitive)
             .finish()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use crate::protocol::body::create::action::SMBCreateAction;
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::file_attributes::{IO_REPARSE_TAG_SYMLINK, SMBFileAttributes};
+    use crate::protocol::body::error::SMBSymbolicLinkErrorResponse;
+    use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+    use crate::protocol::body::tree_connect::capabilities::SMBTreeConnectCapabilities;
+    use crate::protocol::body::tree_connect::flags::SMBShareFlags;
+    use crate::protocol::body::tree_connect::SMBTreeConnectResponse;
+    use crate::server::share::file_system::{SMBFileSystemHandle, SMBFileSystemShare};
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::SMBFromBytes;
+
+    use crate::server::share::{ResourceHandle, SharedResource, SMBShareDfs};
+    use crate::test_util::{fs_share, TempDir};
+
+    #[cfg(unix)]
+    #[test]
+    fn index_number_is_stable_across_opens() {
+        let path = TempDir::new("index_number");
+        fs::write(path.join("first.txt"), b"data").unwrap();
+        fs::write(path.join("second.txt"), b"data").unwrap();
+        let share = fs_share(&path);
+
+        let first = share.handle_create("first.txt", SMBCreateDisposition::Open, false).unwrap();
+        let reopened = share.handle_create("first.txt", SMBCreateDisposition::Open, false).unwrap();
+        let second = share.handle_create("second.txt", SMBCreateDisposition::Open, false).unwrap();
+        let first_index = first.metadata().unwrap().index_number;
+        let reopened_index = reopened.metadata().unwrap().index_number;
+        let second_index = second.metadata().unwrap().index_number;
+
+        assert_ne!(first_index, 0);
+        assert_eq!(first_index, reopened_index);
+        assert_ne!(first_index, second_index);
+    }
+
+    #[test]
+    fn read_only_share_opens_files_without_write_access() {
+        let path = TempDir::new("read_only");
+        fs::write(path.join("file.txt"), b"data").unwrap();
+        let share = fs_share(&path)
+            .with_read_only(true);
+
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Open, false).unwrap();
+        let metadata = handle.metadata().unwrap();
+        let written = handle.write_at(0, b"new");
+        let writable = share.check_writable();
+
+        assert_eq!(metadata.actual_size, 4);
+        assert!(written.is_err());
+        assert!(matches!(writable, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::MediaWriteProtected));
+    }
+
+    #[test]
+    fn delete_removes_the_backing_file_after_close() {
+        let path = TempDir::new("delete_on_close");
+        fs::create_dir_all(path.join("full")).unwrap();
+        fs::write(path.join("full").join("child.txt"), b"data").unwrap();
+        let share = fs_share(&path);
+
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Create, false).unwrap();
+        assert!(path.join("file.txt").exists());
+        share.delete(handle).unwrap();
+        let file_exists = path.join("file.txt").exists();
+
+        let directory = share.handle_create("full", SMBCreateDisposition::Open, true).unwrap();
+        let result = share.delete(directory);
+        let directory_exists = path.join("full").exists();
+
+        assert!(!file_exists);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::DirectoryNotEmpty));
+        assert!(directory_exists);
+    }
+
+    #[test]
+    fn handle_path_matches_the_created_handle() {
+        let path = TempDir::new("handle_path");
+        fs::write(path.join("file.txt"), b"data").unwrap();
+        let share = fs_share(&path);
+
+        // Resolving the name alone mustn't touch the file, the sharing check runs before any truncation
+        let expected = share.handle_path("file.txt").unwrap();
+        let untouched = fs::read(path.join("file.txt")).unwrap();
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Open, false).unwrap();
+        let created = handle.path().to_string();
+        share.close(handle).unwrap();
+
+        assert_eq!(untouched, b"data");
+        assert_eq!(expected, created);
+    }
+
+    #[test]
+    fn read_at_stops_at_end_of_file() {
+        let path = TempDir::new("read_at");
+        fs::write(path.join("file.txt"), b"0123456789").unwrap();
+        let share = fs_share(&path);
+
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Open, false).unwrap();
+        let within = handle.read_at(2, 4).unwrap();
+        let across = handle.read_at(6, 8).unwrap();
+        let past = handle.read_at(12, 4).unwrap();
+
+        assert_eq!(within, b"2345");
+        assert_eq!(across, b"6789");
+        assert!(past.is_empty());
+    }
+
+    #[test]
+    fn write_at_puts_down_the_whole_buffer() {
+        let path = TempDir::new("write_at");
+        let share = fs_share(&path);
+        let data: Vec<u8> = (0..4 * 1024 * 1024).map(|idx| idx as u8).collect();
+
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Create, false).unwrap();
+        let written = handle.write_at(16, &data).unwrap();
+        share.close(handle).unwrap();
+        let contents = fs::read(path.join("file.txt")).unwrap();
+
+        assert_eq!(written as usize, data.len());
+        assert_eq!(&contents[16..], &data[..]);
+    }
+
+    #[test]
+    fn create_action_follows_disposition_and_pre_existence() {
+        use SMBCreateAction::*;
+        use SMBCreateDisposition::*;
+        // The expected action, or status, and the file's size afterwards. The existing file holds four bytes
+        let matrix = [
+            (Supersede, true, Ok(Superseded), 0),
+            (Supersede, false, Ok(Created), 0),
+            (Open, true, Ok(Opened), 4),
+            (Open, false, Err(NTStatus::ObjectNameNotFound), 0),
+            (Create, true, Err(NTStatus::ObjectNameCollision), 4),
+            (Create, false, Ok(Created), 0),
+            (OpenIf, true, Ok(Opened), 4),
+            (OpenIf, false, Ok(Created), 0),
+            (Overwrite, true, Ok(Overwritten), 0),
+            (Overwrite, false, Err(NTStatus::ObjectNameNotFound), 0),
+            (OverwriteIf, true, Ok(Overwritten), 0),
+            (OverwriteIf, false, Ok(Created), 0),
+        ];
+        let path = TempDir::new("create_action");
+        for (disposition, exists, expected, size) in matrix {
+            if exists {
+                fs::write(path.join("file.txt"), b"data").unwrap();
+            }
+            let share = fs_share(&path);
+
+            let action = disposition.action(share.existing_is_directory("file.txt").is_some());
+            if action.is_ok() {
+                share.handle_create("file.txt", disposition, false).unwrap();
+            }
+            let actual_size = fs::metadata(path.join("file.txt")).map(|metadata| metadata.len()).unwrap_or(0);
+            let _ = fs::remove_file(path.join("file.txt"));
+
+            match expected {
+                Ok(expected) => assert_eq!(action.unwrap(), expected, "{:?} with the file {}", disposition, exists),
+                Err(status) => assert!(matches!(action, Err(SMBError::ResponseError(ref e)) if e.status() == status), "{:?} with the file {}", disposition, exists),
+            }
+            assert_eq!(actual_size, size, "{:?} with the file {}", disposition, exists);
+        }
+    }
+
+    #[test]
+    fn tree_connect_response_reflects_share_config() {
+        let share = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_caching(SMBShareFlags::AUTO_CACHING)
+            .with_encrypt_data(true)
+            .with_continuously_available(true);
+
+        let response = SMBTreeConnectResponse::for_share(&share, None);
+        assert_eq!(response.share_flags(), SMBShareFlags::AUTO_CACHING | SMBShareFlags::ENCRYPT_DATA);
+        assert_eq!(response.capabilities(), &SMBTreeConnectCapabilities::CONTINUOUS_AVAILABILITY);
+
+        let plain = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_caching(SMBShareFlags::NO_CACHING);
+        let response = SMBTreeConnectResponse::for_share(&plain, None);
+        assert_eq!(response.share_flags(), SMBShareFlags::NO_CACHING);
+        assert_eq!(response.capabilities(), &SMBTreeConnectCapabilities::empty());
+
+        let link = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_dfs(SMBShareDfs::Link);
+        let response = SMBTreeConnectResponse::for_share(&link, None);
+        assert_eq!(response.share_flags(), SMBShareFlags::DFS);
+        assert_eq!(response.capabilities(), &SMBTreeConnectCapabilities::DFS);
+        let response = response.without_dfs();
+        assert_eq!(response.share_flags(), SMBShareFlags::MANUAL_CACHING);
+        assert_eq!(response.capabilities(), &SMBTreeConnectCapabilities::empty());
+    }
+
+    #[test]
+    fn maximal_access_is_narrowed_to_the_user() {
+        let reader = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::FilePipePrinter(SMBFilePipePrinterAccessMask::GENERIC_READ));
+        let response = SMBTreeConnectResponse::for_share(&reader, Some(&()));
+        let read = SMBFilePipePrinterAccessMask::READ_CONTROL | SMBFilePipePrinterAccessMask::FILE_READ_DATA
+            | SMBFilePipePrinterAccessMask::FILE_READ_ATTRIBUTES | SMBFilePipePrinterAccessMask::FILE_READ_EA
+            | SMBFilePipePrinterAccessMask::SYNCHRONIZE;
+        assert_eq!(response.access_mask(), &SMBAccessMask::FilePipePrinter(read));
+        assert!(!response.access_mask().includes_write());
+        // Before we know who's asking only the share's own limits apply
+        assert_eq!(SMBTreeConnectResponse::for_share(&reader, None).access_mask().raw(), 0x001F01FF);
+
+        let read_only = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_read_only(true);
+        let response = SMBTreeConnectResponse::for_share(&read_only, Some(&()));
+        assert_eq!(response.access_mask().raw(), 0x001200A9);
+        assert!(!response.access_mask().includes_write());
+    }
+
+    #[test]
+    fn hidden_follows_the_dollar_suffix_unless_set() {
+        let share = |name: &str| SMBFileSystemShare::<(), SMBFileSystemHandle>::root(name.into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true));
+        assert!(share("C$").hidden());
+        assert!(!share("public").hidden());
+        assert!(share("public").with_hidden(true).hidden());
+        assert!(!share("C$").with_hidden(false).hidden());
+    }
+
+    #[test]
+    fn encrypted_share_needs_negotiated_encryption() {
+        let share = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_encrypt_data(true);
+
+        assert!(share.check_encryption(true).is_ok());
+        let result = share.check_encryption(false);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+
+        let plain = SMBFileSystemShare::<(), SMBFileSystemHandle>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true));
+        assert!(plain.check_encryption(false).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_reported_as_reparse_points() {
+        let path = TempDir::new("symlink");
+        fs::write(path.join("target.txt"), b"data").unwrap();
+        std::os::unix::fs::symlink(path.join("target.txt"), path.join("link.txt")).unwrap();
+        let share = fs_share(&path);
+
+        let link = share.handle_create("link.txt", SMBCreateDisposition::Open, false).unwrap().metadata().unwrap();
+        let target = share.handle_create("target.txt", SMBCreateDisposition::Open, false).unwrap().metadata().unwrap();
+        let dir = share.handle_create("", SMBCreateDisposition::Open, true).unwrap();
+        let listed = dir.list_directory().unwrap();
+
+        assert!(link.attributes.contains(SMBFileAttributes::REPARSE_POINT));
+        assert_eq!(link.reparse_tag, IO_REPARSE_TAG_SYMLINK);
+        assert_eq!(target.attributes, SMBFileAttributes::ARCHIVE);
+        assert_eq!(target.reparse_tag, 0);
+        let listed_link = listed.iter().find(|entry| entry.name == "link.txt").unwrap();
+        assert_eq!(listed_link.metadata.reparse_tag, IO_REPARSE_TAG_SYMLINK);
+        let listed_target = listed.iter().find(|entry| entry.name == "target.txt").unwrap();
+        assert!(!listed_target.metadata.attributes.contains(SMBFileAttributes::REPARSE_POINT));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_through_a_symlink_stop_with_the_link_target() {
+        let path = TempDir::new("stopped_on_symlink");
+        fs::create_dir_all(path.join("dir")).unwrap();
+        fs::write(path.join("dir").join("inner.txt"), b"data").unwrap();
+        std::os::unix::fs::symlink("dir", path.join("link")).unwrap();
+        let share = fs_share(&path);
+
+        let plain = share.check_symlinks("dir\\inner.txt");
+        let missing = share.check_symlinks("missing\\inner.txt");
+        let through_link = share.check_symlinks("link\\inner.txt");
+
+        assert!(plain.is_ok());
+        assert!(missing.is_ok());
+        let Err(SMBError::ResponseError(e)) = through_link else {
+            panic!("expected the create to stop on the symlink");
+        };
+        assert_eq!(e.status(), NTStatus::StoppedOnSymlink);
+        let (_, link) = SMBSymbolicLinkErrorResponse::smb_from_bytes(e.error_data()).unwrap();
+        // "\inner.txt" is 10 UTF-16 code units
+        assert_eq!(link.unparsed_path_length(), 20);
+        assert_eq!(link.substitute_name(), "dir");
+        assert_eq!(link.print_name(), "dir");
+        assert!(link.relative());
+    }
+
+    #[test]
+    fn paths_cannot_climb_out_of_the_share() {
+        let path = TempDir::new("traversal");
+        fs::create_dir_all(path.join("share").join("docs")).unwrap();
+        fs::write(path.join("secret.txt"), b"secret").unwrap();
+        fs::write(path.join("share").join("docs").join("inner.txt"), b"inner").unwrap();
+        let share = fs_share(&path.join("share"));
+
+        let escapes = ["..\\secret.txt", "../secret.txt", "docs\\..\\..\\secret.txt", "\\..\\secret.txt", "docs/./../../secret.txt"]
+            .map(|escape| share.handle_create(escape, SMBCreateDisposition::Open, false).map(|_| ()));
+        let symlink_check = share.check_symlinks("..\\secret.txt");
+        let nul = share.handle_create("docs\0inner.txt", SMBCreateDisposition::Open, false).map(|_| ());
+        let nested = share.handle_create("docs\\inner.txt", SMBCreateDisposition::Open, false).map(|handle| handle.path().to_string());
+        let folded = share.handle_create("docs\\..\\docs/inner.txt", SMBCreateDisposition::Open, false).map(|handle| handle.path().to_string());
+        let absolute = share.handle_create("\\docs\\inner.txt", SMBCreateDisposition::Open, false).map(|handle| handle.path().to_string());
+
+        for result in escapes {
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::ObjectPathNotFound));
+        }
+        assert!(matches!(symlink_check, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::ObjectPathNotFound));
+        assert!(matches!(nul, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::ObjectNameInvalid));
+        let expected = path.join("share").join("docs").join("inner.txt");
+        for resolved in [nested, folded, absolute] {
+            assert_eq!(Path::new(&resolved.unwrap()), expected);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_lead_out_of_the_share() {
+        let path = TempDir::new("escaping_symlink");
+        fs::create_dir_all(path.join("share").join("docs")).unwrap();
+        fs::create_dir_all(path.join("outside")).unwrap();
+        fs::write(path.join("outside").join("secret.txt"), b"secret").unwrap();
+        std::os::unix::fs::symlink(path.join("outside"), path.join("share").join("out")).unwrap();
+        std::os::unix::fs::symlink("../outside/secret.txt", path.join("share").join("leak.txt")).unwrap();
+        std::os::unix::fs::symlink(path.join("outside").join("new.txt"), path.join("share").join("dangling.txt")).unwrap();
+        std::os::unix::fs::symlink("docs", path.join("share").join("inside")).unwrap();
+        let share = fs_share(&path.join("share"));
+
+        // None of these go through check_symlinks, the same as a create with FILE_OPEN_REPARSE_POINT
+        let through_directory = share.handle_create("out\\secret.txt", SMBCreateDisposition::Open, false).map(|_| ());
+        let created_through_directory = share.handle_create("out\\new.txt", SMBCreateDisposition::Create, false).map(|_| ());
+        let final_link = share.handle_create("leak.txt", SMBCreateDisposition::Open, false).map(|_| ());
+        let dangling = share.handle_create("dangling.txt", SMBCreateDisposition::Create, false).map(|_| ());
+        let inside = share.handle_create("inside\\new.txt", SMBCreateDisposition::Create, false).map(|_| ());
+
+        for result in [through_directory, created_through_directory, final_link, dangling] {
+            assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::ObjectPathNotFound));
+        }
+        assert_eq!(share.existing_is_directory("out"), None);
+        assert!(!path.join("outside").join("new.txt").exists());
+        assert!(inside.is_ok());
+        assert!(path.join("share").join("docs").join("new.txt").exists());
+    }
+
+    // Other platforms default to case-insensitive file systems, where the case-sensitive open would succeed too
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn case_insensitive_share_finds_differently_cased_names() {
+        let path = TempDir::new("case_insensitive");
+        fs::create_dir_all(path.join("Docs")).unwrap();
+        fs::write(path.join("Docs").join("file.txt"), b"data").unwrap();
+        let share = || fs_share(&path);
+
+        let sensitive = share().handle_create("DOCS\\File.TXT", SMBCreateDisposition::Open, false).map(|_| ());
+        let insensitive = share().with_case_insensitive(true);
+        let opened = insensitive.handle_create("DOCS\\File.TXT", SMBCreateDisposition::Open, false).map(|handle| handle.path().to_string());
+        let directory = insensitive.existing_is_directory("docs");
+        // A create keeps the client's spelling when nothing matches
+        let created = insensitive.handle_create("docs\\New.txt", SMBCreateDisposition::Create, false).map(|handle| handle.path().to_string());
+
+        assert!(sensitive.is_err());
+        assert_eq!(Path::new(&opened.unwrap()), path.join("Docs").join("file.txt"));
+        assert_eq!(directory, Some(true));
+        assert_eq!(Path::new(&created.unwrap()), path.join("Docs").join("New.txt"));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn ambiguous_case_matches_pick_the_same_entry_every_time() {
+        let path = TempDir::new("case_ambiguous");
+        fs::write(path.join("readme.txt"), b"lower").unwrap();
+        fs::write(path.join("README.txt"), b"upper").unwrap();
+        let share = fs_share(&path)
+            .with_case_insensitive(true);
+
+        let exact = share.handle_create("readme.txt", SMBCreateDisposition::Open, false).unwrap().read_at(0, 5).unwrap();
+        let first = share.handle_create("ReadMe.txt", SMBCreateDisposition::Open, false).unwrap().read_at(0, 5).unwrap();
+        let second = share.handle_create("ReadMe.TXT", SMBCreateDisposition::Open, false).unwrap().read_at(0, 5).unwrap();
+
+        assert_eq!(exact, b"lower");
+        assert_eq!(first, b"upper");
+        assert_eq!(second, b"upper");
+    }
+}
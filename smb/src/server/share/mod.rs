@@ -1,28 +1,63 @@
 use std::any::Any;
 use std::fmt::Debug;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use std::pin::Pin;
 
 use bitflags::bitflags;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::protocol::body::create::disposition::SMBCreateDisposition;
+use crate::protocol::body::create::INVALID_NAME_CHARACTERS;
+use crate::protocol::body::create::file_attributes::SMBFileAttributes;
 use crate::protocol::body::filetime::FileTime;
-use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::protocol::body::query_info::quota_information::SMBFileQuotaInformation;
+use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBFilePipePrinterAccessMask};
+use crate::protocol::body::tree_connect::capabilities::SMBTreeConnectCapabilities;
 use crate::protocol::body::tree_connect::flags::SMBShareFlags;
 use crate::protocol::body::tree_connect::SMBShareType;
 
 pub mod file_system;
+pub mod named_pipe;
+#[cfg(test)]
+pub(crate) mod recording;
 
 pub type ConnectAllowed<UserName> = fn(&UserName) -> bool;
 pub type FilePerms<UserName> = fn(&UserName) -> SMBAccessMask;
+// Boxed so the async IO stays callable through a `dyn ResourceHandle`
+#[cfg(feature = "async")]
+pub type HandleIO<'a, T> = Pin<Box<dyn Future<Output=SMBResult<T>> + Send + 'a>>;
 
 pub trait ResourceHandle: Send + Sync {
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
     fn close(self: Box<Self>) -> SMBResult<()>;
+    // Closes the handle and removes the backing file, used once the last delete-on-close open goes away
+    fn delete(self: Box<Self>) -> SMBResult<()>;
     fn is_directory(&self) -> bool;
     fn path(&self) -> &str;
     fn metadata(&self) -> SMBResult<SMBFileMetadata>;
+    // Returns fewer than length bytes only when the read runs into the end of the file
+    fn read_at(&self, offset: u64, length: u32) -> SMBResult<Vec<u8>>;
+    fn write_at(&self, offset: u64, data: &[u8]) -> SMBResult<u32>;
+    fn sync(&self) -> SMBResult<()>;
+    fn list_directory(&self) -> SMBResult<Vec<SMBDirectoryEntry>>;
+
+    // The same IO for callers on the runtime. By default the blocking call runs in place, handles that can
+    // move it off the runtime's threads override these
+    #[cfg(feature = "async")]
+    fn read_at_async(&self, offset: u64, length: u32) -> HandleIO<'_, Vec<u8>> {
+        Box::pin(async move { self.read_at(offset, length) })
+    }
+
+    #[cfg(feature = "async")]
+    fn write_at_async<'a>(&'a self, offset: u64, data: &'a [u8]) -> HandleIO<'a, u32> {
+        Box::pin(async move { self.write_at(offset, data) })
+    }
 }
 
 pub struct SMBFileMetadata {
@@ -32,6 +67,17 @@ pub struct SMBFileMetadata {
     pub last_modification_time: FileTime,
     pub allocated_size: u64,
     pub actual_size: u64,
+    // Stable per-file identifier reported as the index number, so clients can spot hardlinks and renames
+    pub index_number: u64,
+    pub attributes: SMBFileAttributes,
+    // IO_REPARSE_TAG_* when attributes has REPARSE_POINT, zero otherwise
+    pub reparse_tag: u32,
+}
+
+pub struct SMBDirectoryEntry {
+    pub name: String,
+    pub is_directory: bool,
+    pub metadata: SMBFileMetadata,
 }
 
 impl<H: ?Sized + ResourceHandle + 'static> ResourceHandle for Box<H> {
@@ -43,6 +89,10 @@ impl<H: ?Sized + ResourceHandle + 'static> ResourceHandle for Box<H> {
         H::close(*self)
     }
 
+    fn delete(self: Box<Self>) -> SMBResult<()> {
+        H::delete(*self)
+    }
+
     fn is_directory(&self) -> bool {
         H::is_directory(self)
     }
@@ -54,6 +104,32 @@ impl<H: ?Sized + ResourceHandle + 'static> ResourceHandle for Box<H> {
     fn metadata(&self) -> SMBResult<SMBFileMetadata> {
         H::metadata(self)
     }
+
+    fn read_at(&self, offset: u64, length: u32) -> SMBResult<Vec<u8>> {
+        H::read_at(self, offset, length)
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> SMBResult<u32> {
+        H::write_at(self, offset, data)
+    }
+
+    #[cfg(feature = "async")]
+    fn read_at_async(&self, offset: u64, length: u32) -> HandleIO<'_, Vec<u8>> {
+        H::read_at_async(self, offset, length)
+    }
+
+    #[cfg(feature = "async")]
+    fn write_at_async<'a>(&'a self, offset: u64, data: &'a [u8]) -> HandleIO<'a, u32> {
+        H::write_at_async(self, offset, data)
+    }
+
+    fn sync(&self) -> SMBResult<()> {
+        H::sync(self)
+    }
+
+    fn list_directory(&self) -> SMBResult<Vec<SMBDirectoryEntry>> {
+        H::list_directory(self)
+    }
 }
 
 pub trait SharedResource: Send + Sync {
@@ -66,9 +142,104 @@ pub trait SharedResource: Send + Sync {
     fn close(&self, handle: Self::Handle) -> SMBResult<()> {
         Box::new(handle).close()
     }
+    fn delete(&self, handle: Self::Handle) -> SMBResult<()> {
+        Box::new(handle).delete()
+    }
     fn connect_allowed(&self, uid: &Self::UserName) -> bool;
 
     fn resource_perms(&self, uid: &Self::UserName) -> SMBAccessMask;
+
+    // What the resource type allows, narrowed to the user's own permissions when we know who they are and
+    // to read rights on a read-only share. This is the MaximalAccess a tree connect hands back
+    fn maximal_access(&self, uid: Option<&Self::UserName>) -> SMBAccessMask {
+        let mut access = match self.resource_type() {
+            ResourceType::DISK | ResourceType::IPC => SMBFilePipePrinterAccessMask::access_no_connect_security(),
+            _ => SMBFilePipePrinterAccessMask::FILE_WRITE_DATA | SMBFilePipePrinterAccessMask::FILE_APPEND_DATA
+                | SMBFilePipePrinterAccessMask::FILE_READ_ATTRIBUTES | SMBFilePipePrinterAccessMask::READ_CONTROL
+                | SMBFilePipePrinterAccessMask::SYNCHRONIZE,
+        };
+        if let Some(uid) = uid {
+            access &= SMBFilePipePrinterAccessMask::from_bits_truncate(self.resource_perms(uid).raw()).with_generic_expanded();
+        }
+        if self.read_only() {
+            access -= SMBFilePipePrinterAccessMask::write_rights();
+        }
+        SMBAccessMask::FilePipePrinter(access)
+    }
+
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    // Hidden shares, C$ and ADMIN$ style, can be connected to by name but are left out of share listings
+    fn hidden(&self) -> bool {
+        self.name().ends_with('$')
+    }
+
+    fn check_writable(&self) -> SMBResult<()> {
+        match self.read_only() {
+            true => Err(SMBError::response_error(NTStatus::MediaWriteProtected)),
+            false => Ok(()),
+        }
+    }
+
+    fn capabilities(&self) -> SMBTreeConnectCapabilities {
+        SMBTreeConnectCapabilities::empty()
+    }
+
+    fn encrypt_data(&self) -> bool {
+        self.flags().contains(SMBShareFlags::ENCRYPT_DATA)
+    }
+
+    // A share that requires encryption can't be reached over a connection that never negotiated it
+    fn check_encryption(&self, encryption_negotiated: bool) -> SMBResult<()> {
+        match self.encrypt_data() && !encryption_negotiated {
+            true => Err(SMBError::response_error(NTStatus::AccessDenied)),
+            false => Ok(()),
+        }
+    }
+
+    fn quota_provider(&self) -> &dyn SMBQuotaProvider {
+        &SMBNoQuotaProvider
+    }
+
+    // Fails with STATUS_STOPPED_ON_SYMLINK when a component of the path is a symbolic link
+    fn check_symlinks(&self, _path: &str) -> SMBResult<()> {
+        Ok(())
+    }
+
+    // Whether something already at the path is a directory, None when nothing is there yet
+    fn existing_is_directory(&self, _path: &str) -> Option<bool> {
+        None
+    }
+
+    // The path a handle created for this name would report, so opens can be compared before one exists
+    fn handle_path(&self, path: &str) -> SMBResult<String> {
+        Ok(path.to_string())
+    }
+
+    // Names containing any of these are refused at create time, a share over a more permissive store can allow more
+    fn invalid_name_characters(&self) -> &[char] {
+        &INVALID_NAME_CHARACTERS
+    }
+}
+
+pub trait SMBQuotaProvider: Send + Sync {
+    // An empty SID list asks for every quota entry the provider knows about
+    fn query_quotas(&self, sids: &[Vec<u8>]) -> SMBResult<Vec<SMBFileQuotaInformation>>;
+    fn set_quotas(&self, quotas: Vec<SMBFileQuotaInformation>) -> SMBResult<()>;
+}
+
+pub struct SMBNoQuotaProvider;
+
+impl SMBQuotaProvider for SMBNoQuotaProvider {
+    fn query_quotas(&self, _sids: &[Vec<u8>]) -> SMBResult<Vec<SMBFileQuotaInformation>> {
+        Ok(Vec::new())
+    }
+
+    fn set_quotas(&self, _quotas: Vec<SMBFileQuotaInformation>) -> SMBResult<()> {
+        Err(SMBError::response_error(NTStatus::NotSupported))
+    }
 }
 
 impl<T: ?Sized + SharedResource> SharedResource for Box<T> {
@@ -95,6 +266,10 @@ impl<T: ?Sized + SharedResource> SharedResource for Box<T> {
         T::close(self, handle)
     }
 
+    fn delete(&self, handle: Self::Handle) -> SMBResult<()> {
+        T::delete(self, handle)
+    }
+
     fn connect_allowed(&self, uid: &Self::UserName) -> bool {
         T::connect_allowed(self, uid)
     }
@@ -102,6 +277,46 @@ impl<T: ?Sized + SharedResource> SharedResource for Box<T> {
     fn resource_perms(&self, uid: &Self::UserName) -> SMBAccessMask {
         T::resource_perms(self, uid)
     }
+
+    fn maximal_access(&self, uid: Option<&Self::UserName>) -> SMBAccessMask {
+        T::maximal_access(self, uid)
+    }
+
+    fn read_only(&self) -> bool {
+        T::read_only(self)
+    }
+
+    fn hidden(&self) -> bool {
+        T::hidden(self)
+    }
+
+    fn capabilities(&self) -> SMBTreeConnectCapabilities {
+        T::capabilities(self)
+    }
+
+    fn encrypt_data(&self) -> bool {
+        T::encrypt_data(self)
+    }
+
+    fn quota_provider(&self) -> &dyn SMBQuotaProvider {
+        T::quota_provider(self)
+    }
+
+    fn check_symlinks(&self, path: &str) -> SMBResult<()> {
+        T::check_symlinks(self, path)
+    }
+
+    fn existing_is_directory(&self, path: &str) -> Option<bool> {
+        T::existing_is_directory(self, path)
+    }
+
+    fn handle_path(&self, path: &str) -> SMBResult<String> {
+        T::handle_path(self, path)
+    }
+
+    fn invalid_name_characters(&self) -> &[char] {
+        T::invalid_name_characters(self)
+    }
 }
 
 bitflags! {
@@ -119,6 +334,27 @@ bitflags! {
         const TEMPORARY = 0x40000000;
     }
 }
+
+// Where a share sits in a DFS namespace. A root holds the namespace, a link is a share the namespace points
+// clients at
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum SMBShareDfs {
+    #[default]
+    None,
+    Link,
+    Root,
+}
+
+impl SMBShareDfs {
+    pub fn share_flags(&self) -> SMBShareFlags {
+        match self {
+            SMBShareDfs::None => SMBShareFlags::empty(),
+            SMBShareDfs::Link => SMBShareFlags::DFS,
+            SMBShareDfs::Root => SMBShareFlags::DFS | SMBShareFlags::DFS_ROOT,
+        }
+    }
+}
+
 impl From<SMBShareType> for ResourceType {
     fn from(value: SMBShareType) -> Self {
         match value {
@@ -127,4 +363,82 @@ impl From<SMBShareType> for ResourceType {
             SMBShareType::Print => ResourceType::PRINT_QUEUE
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::server::share::file_system::SMBFileSystemHandle;
+    use crate::server::share::recording::RecordingHandle;
+    use crate::server::share::{ResourceHandle, SharedResource};
+    use crate::test_util::{fs_share, TempDir};
+
+    fn file_handle(name: &str) -> (TempDir, SMBFileSystemHandle) {
+        let path = TempDir::new(&format!("positional_{}", name));
+        let share = fs_share(&path);
+        let handle = share.handle_create("file.txt", SMBCreateDisposition::Create, false).unwrap();
+        (path, handle)
+    }
+
+    // Writes out of order and past the end, the gap reading back as zeroes, then reads across every edge
+    fn positional_io(handle: &dyn ResourceHandle) {
+        assert_eq!(handle.write_at(4, b"4567").unwrap(), 4);
+        assert_eq!(handle.write_at(0, b"0123").unwrap(), 4);
+        assert_eq!(handle.write_at(12, b"cd").unwrap(), 2);
+        assert_eq!(handle.write_at(2, b"ab").unwrap(), 2);
+
+        assert_eq!(handle.read_at(0, 14).unwrap(), b"01ab4567\0\0\0\0cd");
+        assert_eq!(handle.read_at(3, 3).unwrap(), b"b45");
+        assert_eq!(handle.read_at(8, 4).unwrap(), [0; 4]);
+        assert_eq!(handle.read_at(10, 16).unwrap(), b"\0\0cd");
+        assert!(handle.read_at(14, 4).unwrap().is_empty());
+        assert!(handle.read_at(100, 4).unwrap().is_empty());
+        assert!(handle.read_at(5, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn file_system_handle_reads_and_writes_at_offsets() {
+        let (_path, handle) = file_handle("sync");
+        let boxed: Box<dyn ResourceHandle> = handle.into();
+        positional_io(boxed.as_ref());
+        drop(boxed);
+    }
+
+    #[test]
+    fn recording_handle_reads_and_writes_at_offsets() {
+        let boxed: Box<dyn ResourceHandle> = Box::new(RecordingHandle::default());
+        positional_io(boxed.as_ref());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn async_io_matches_the_blocking_calls() {
+        let (path, handle) = file_handle("async");
+        assert_eq!(handle.write_at_async(3, b"345").await.unwrap(), 3);
+        assert_eq!(handle.write_at_async(0, b"012").await.unwrap(), 3);
+        let data = handle.read_at_async(1, 8).await.unwrap();
+        let blocking = handle.read_at(1, 8).unwrap();
+        let directory = fs_share(&path)
+            .handle_create("", SMBCreateDisposition::Open, true).unwrap()
+            .read_at_async(0, 4).await;
+
+        assert_eq!(data, b"12345");
+        assert_eq!(data, blocking);
+        assert!(directory.is_err());
+
+        let recording = RecordingHandle::default();
+        assert_eq!(recording.write_at_async(2, b"ab").await.unwrap(), 2);
+        assert_eq!(recording.read_at_async(0, 8).await.unwrap(), b"\0\0ab");
+    }
+
+    // The server holds its handles as `Box<dyn ResourceHandle>`, a handle's own async IO has to be reached through that
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn boxed_handles_keep_their_async_io() {
+        let boxed: Box<dyn ResourceHandle> = Box::new(RecordingHandle::default());
+        assert_eq!(ResourceHandle::write_at_async(&boxed, 0, b"ab").await.unwrap(), 2);
+        assert_eq!(ResourceHandle::read_at_async(&boxed, 0, 2).await.unwrap(), b"ab");
+
+        let recording = boxed.into_any().downcast::<RecordingHandle>().unwrap();
+        assert_eq!(recording.async_io(), 2);
+    }
+}
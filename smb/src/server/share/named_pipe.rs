@@ -0,0 +1,224 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+use crate::protocol::body::filetime::FileTime;
+use crate::server::share::{ResourceHandle, SMBDirectoryEntry, SMBFileMetadata};
+use crate::util::rpc::{NCA_OP_RANGE_ERROR, NDR_TRANSFER_SYNTAX, RPC_HEADER_SIZE, RPC_MAX_FRAGMENT, rpc_fault, RPCBind, RPCBindAck, RPCBindRejectReason, RPCBindResult, RPCHeader, RPCPacketType, RPCRequest, RPCSyntaxId};
+
+static NEXT_ASSOC_GROUP: AtomicU32 = AtomicU32::new(1);
+
+// A named pipe opened on IPC$. Writes carry DCE/RPC PDUs from the client, reads hand back whatever the
+// RPC responder has queued for it. Pipes are a byte stream, so offsets are ignored both ways
+#[derive(Debug)]
+pub struct SMBNamedPipeHandle {
+    name: String,
+    interfaces: Vec<RPCSyntaxId>,
+    incoming: Mutex<Vec<u8>>,
+    pending: Mutex<Vec<u8>>,
+}
+
+impl SMBNamedPipeHandle {
+    pub fn new<S: Into<String>>(name: S, interfaces: Vec<RPCSyntaxId>) -> Self {
+        Self {
+            name: name.into(),
+            interfaces,
+            incoming: Mutex::new(Vec::new()),
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn pending_len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    fn respond(&self, pdu: &[u8]) -> SMBResult<Vec<u8>> {
+        let (body, header) = RPCHeader::parse(pdu)
+            .map_err(|_| SMBError::response_error(NTStatus::InvalidParameter))?;
+        match header.packet_type() {
+            Ok(RPCPacketType::Bind) => {
+                let (_, bind) = RPCBind::parse(body)
+                    .map_err(|_| SMBError::response_error(NTStatus::InvalidParameter))?;
+                Ok(self.bind_ack(&bind).as_bytes(header.call_id))
+            },
+            // No interface operations are implemented yet, so every call faults
+            Ok(RPCPacketType::Request) => {
+                let (_, request) = RPCRequest::parse(body)
+                    .map_err(|_| SMBError::response_error(NTStatus::InvalidParameter))?;
+                Ok(rpc_fault(header.call_id, request.context_id, NCA_OP_RANGE_ERROR))
+            },
+            _ => Err(SMBError::response_error(NTStatus::InvalidParameter)),
+        }
+    }
+
+    fn bind_ack(&self, bind: &RPCBind) -> RPCBindAck {
+        let results = bind.contexts.iter().map(|context| {
+            if !self.interfaces.contains(&context.abstract_syntax) {
+                RPCBindResult::rejected(RPCBindRejectReason::AbstractSyntaxNotSupported)
+            } else if !context.transfer_syntaxes.contains(&NDR_TRANSFER_SYNTAX) {
+                RPCBindResult::rejected(RPCBindRejectReason::TransferSyntaxesNotSupported)
+            } else {
+                RPCBindResult::accepted(NDR_TRANSFER_SYNTAX)
+            }
+        }).collect();
+        let assoc_group_id = match bind.assoc_group_id {
+            0 => NEXT_ASSOC_GROUP.fetch_add(1, Ordering::Relaxed),
+            existing => existing,
+        };
+        RPCBindAck {
+            max_xmit_frag: bind.max_xmit_frag.min(RPC_MAX_FRAGMENT),
+            max_recv_frag: bind.max_recv_frag.min(RPC_MAX_FRAGMENT),
+            assoc_group_id,
+            secondary_address: format!("\\PIPE\\{}", self.name),
+            results,
+        }
+    }
+}
+
+impl ResourceHandle for SMBNamedPipeHandle {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn close(self: Box<Self>) -> SMBResult<()> {
+        Ok(())
+    }
+
+    fn delete(self: Box<Self>) -> SMBResult<()> {
+        Err(SMBError::response_error(NTStatus::AccessDenied))
+    }
+
+    fn is_directory(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> SMBResult<SMBFileMetadata> {
+        Ok(SMBFileMetadata {
+            creation_time: FileTime::zero(),
+            last_access_time: FileTime::zero(),
+            last_write_time: FileTime::zero(),
+            last_modification_time: FileTime::zero(),
+            allocated_size: 0,
+            actual_size: self.pending_len() as u64,
+            index_number: 0,
+            attributes: SMBFileAttributes::NORMAL,
+            reparse_tag: 0,
+        })
+    }
+
+    // Hands back up to length bytes of queued responses, anything left over waits for the next read
+    fn read_at(&self, _offset: u64, length: u32) -> SMBResult<Vec<u8>> {
+        let mut pending = self.pending.lock().unwrap();
+        let end = std::cmp::min(length as usize, pending.len());
+        Ok(pending.drain(..end).collect())
+    }
+
+    // PDUs can arrive split across writes, so only complete fragments are handed to the responder
+    fn write_at(&self, _offset: u64, data: &[u8]) -> SMBResult<u32> {
+        let mut incoming = self.incoming.lock().unwrap();
+        incoming.extend_from_slice(data);
+        while incoming.len() >= RPC_HEADER_SIZE {
+            let (_, header) = RPCHeader::parse(&incoming)
+                .map_err(|_| SMBError::response_error(NTStatus::InvalidParameter))?;
+            let frag_length = header.frag_length as usize;
+            if frag_length < RPC_HEADER_SIZE {
+                incoming.clear();
+                return Err(SMBError::response_error(NTStatus::InvalidParameter));
+            }
+            if incoming.len() < frag_length {
+                break;
+            }
+            let pdu: Vec<u8> = incoming.drain(..frag_length).collect();
+            let response = self.respond(&pdu)?;
+            self.pending.lock().unwrap().extend_from_slice(&response);
+        }
+        Ok(data.len() as u32)
+    }
+
+    fn sync(&self) -> SMBResult<()> {
+        Ok(())
+    }
+
+    fn list_directory(&self) -> SMBResult<Vec<SMBDirectoryEntry>> {
+        Err(SMBError::response_error(NTStatus::InvalidParameter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::uuid;
+
+    use crate::server::share::named_pipe::SMBNamedPipeHandle;
+    use crate::server::share::ResourceHandle;
+    use crate::util::rpc::{NDR_TRANSFER_SYNTAX, RPC_HEADER_SIZE, RPCBind, RPCBindAck, RPCBindResultCode, RPCContextElement, RPCHeader, RPCPacketType, RPCSyntaxId};
+
+    const SRVSVC: RPCSyntaxId = RPCSyntaxId { uuid: uuid!("4b324fc8-1670-01d3-1278-5a47bf6ee188"), version: 3 };
+
+    fn bind_request(abstract_syntax: RPCSyntaxId) -> Vec<u8> {
+        RPCBind {
+            max_xmit_frag: 0xFFFF,
+            max_recv_frag: 0xFFFF,
+            assoc_group_id: 0,
+            contexts: vec![RPCContextElement {
+                context_id: 0,
+                abstract_syntax,
+                transfer_syntaxes: vec![NDR_TRANSFER_SYNTAX],
+            }],
+        }.as_bytes(3)
+    }
+
+    #[test]
+    fn writing_a_bind_queues_a_bind_ack() {
+        let pipe = SMBNamedPipeHandle::new("srvsvc", vec![SRVSVC]);
+        let request = bind_request(SRVSVC);
+        assert_eq!(pipe.write_at(0, &request).unwrap() as usize, request.len());
+
+        let response = pipe.read_at(0, 4096).unwrap();
+        let (body, header) = RPCHeader::parse(&response).unwrap();
+        assert_eq!(header.packet_type(), Ok(RPCPacketType::BindAck));
+        assert_eq!(header.call_id, 3);
+        assert_eq!(header.frag_length as usize, response.len());
+        let (_, ack) = RPCBindAck::parse(body).unwrap();
+        assert_eq!(ack.secondary_address, "\\PIPE\\srvsvc");
+        assert_eq!(ack.max_xmit_frag, 4280);
+        assert_ne!(ack.assoc_group_id, 0);
+        assert_eq!(ack.results.len(), 1);
+        assert_eq!(ack.results[0].result, RPCBindResultCode::Acceptance as u16);
+        assert_eq!(ack.results[0].transfer_syntax, NDR_TRANSFER_SYNTAX);
+        assert_eq!(pipe.read_at(0, 4096).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn split_writes_and_short_reads_keep_the_stream_intact() {
+        let pipe = SMBNamedPipeHandle::new("srvsvc", vec![SRVSVC]);
+        let request = bind_request(SRVSVC);
+        pipe.write_at(0, &request[..10]).unwrap();
+        assert_eq!(pipe.pending_len(), 0);
+        pipe.write_at(10, &request[10..]).unwrap();
+
+        let mut response = pipe.read_at(0, RPC_HEADER_SIZE as u32).unwrap();
+        assert_eq!(response.len(), RPC_HEADER_SIZE);
+        response.extend(pipe.read_at(0, 4096).unwrap());
+        let (_, header) = RPCHeader::parse(&response).unwrap();
+        assert_eq!(header.frag_length as usize, response.len());
+    }
+
+    #[test]
+    fn unknown_interfaces_are_rejected_in_the_ack() {
+        let pipe = SMBNamedPipeHandle::new("srvsvc", vec![SRVSVC]);
+        let other = RPCSyntaxId { uuid: uuid!("12345778-1234-abcd-ef00-0123456789ab"), version: 0 };
+        pipe.write_at(0, &bind_request(other)).unwrap();
+        let response = pipe.read_at(0, 4096).unwrap();
+        let (_, ack) = RPCBindAck::parse(&response[RPC_HEADER_SIZE..]).unwrap();
+        assert_eq!(ack.results[0].result, RPCBindResultCode::ProviderRejection as u16);
+    }
+}
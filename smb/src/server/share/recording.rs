@@ -0,0 +1,117 @@
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_core::SMBResult;
+
+use crate::protocol::body::create::file_attributes::SMBFileAttributes;
+use crate::protocol::body::filetime::FileTime;
+#[cfg(feature = "async")]
+use crate::server::share::HandleIO;
+use crate::server::share::{ResourceHandle, SMBDirectoryEntry, SMBFileMetadata};
+
+#[derive(Debug, Default)]
+pub struct RecordingHandle {
+    writes: Mutex<Vec<(u64, Vec<u8>)>>,
+    syncs: AtomicUsize,
+    async_io: AtomicUsize,
+}
+
+impl RecordingHandle {
+    pub fn writes(&self) -> Vec<(u64, Vec<u8>)> {
+        self.writes.lock().unwrap().clone()
+    }
+
+    pub fn syncs(&self) -> usize {
+        self.syncs.load(Ordering::SeqCst)
+    }
+
+    // How many reads and writes came in through the async calls
+    pub fn async_io(&self) -> usize {
+        self.async_io.load(Ordering::SeqCst)
+    }
+
+    // Replays the recorded writes in order to get the file as it now stands
+    pub fn contents(&self) -> Vec<u8> {
+        let mut contents = Vec::new();
+        for (write_offset, data) in self.writes.lock().unwrap().iter() {
+            let end = *write_offset as usize + data.len();
+            if contents.len() < end {
+                contents.resize(end, 0);
+            }
+            contents[(*write_offset as usize)..end].copy_from_slice(data);
+        }
+        contents
+    }
+}
+
+impl ResourceHandle for RecordingHandle {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn close(self: Box<Self>) -> SMBResult<()> {
+        Ok(())
+    }
+
+    fn delete(self: Box<Self>) -> SMBResult<()> {
+        Ok(())
+    }
+
+    fn is_directory(&self) -> bool {
+        false
+    }
+
+    fn path(&self) -> &str {
+        ""
+    }
+
+    fn metadata(&self) -> SMBResult<SMBFileMetadata> {
+        Ok(SMBFileMetadata {
+            creation_time: FileTime::zero(),
+            last_access_time: FileTime::zero(),
+            last_write_time: FileTime::zero(),
+            last_modification_time: FileTime::zero(),
+            allocated_size: 0,
+            actual_size: self.contents().len() as u64,
+            index_number: 0,
+            attributes: SMBFileAttributes::NORMAL,
+            reparse_tag: 0,
+        })
+    }
+
+    fn read_at(&self, offset: u64, length: u32) -> SMBResult<Vec<u8>> {
+        let contents = self.contents();
+        let start = std::cmp::min(offset as usize, contents.len());
+        let end = std::cmp::min(start + length as usize, contents.len());
+        Ok(contents[start..end].to_vec())
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> SMBResult<u32> {
+        self.writes.lock().unwrap().push((offset, data.to_vec()));
+        Ok(data.len() as u32)
+    }
+
+    #[cfg(feature = "async")]
+    fn read_at_async(&self, offset: u64, length: u32) -> HandleIO<'_, Vec<u8>> {
+        self.async_io.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move { self.read_at(offset, length) })
+    }
+
+    #[cfg(feature = "async")]
+    fn write_at_async<'a>(&'a self, offset: u64, data: &'a [u8]) -> HandleIO<'a, u32> {
+        self.async_io.fetch_add(1, Ordering::SeqCst);
+        Box::pin(async move { self.write_at(offset, data) })
+    }
+
+    fn sync(&self) -> SMBResult<()> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn list_directory(&self) -> SMBResult<Vec<SMBDirectoryEntry>> {
+        Err(SMBError::response_error(NTStatus::InvalidParameter))
+    }
+}
@@ -1,24 +1,49 @@
 use std::fmt::Debug;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use std::sync::{Arc, Weak};
 
 use tokio::sync::RwLock;
 
-use smb_core::{SMBByteSize, SMBResult};
+use smb_core::{SMBByteSize, SMBResult, SMBToBytes};
 use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
 
+use crate::protocol::body::close::{SMBCloseRequest, SMBCloseResponse};
 use crate::protocol::body::create::{SMBCreateRequest, SMBCreateResponse};
+use crate::protocol::body::create::file_id::SMBFileId;
+use crate::protocol::body::create::oplock::SMBOplockLevel;
+use crate::protocol::body::create::options::SMBCreateOptions;
+use crate::protocol::body::empty::SMBEmpty;
+use crate::protocol::body::error::SMBErrorResponse;
 use crate::protocol::body::filetime::FileTime;
+use crate::protocol::body::flush::SMBFlushRequest;
+use crate::protocol::body::ioctl::copy_chunk::{SMBCopyChunkLimits, SMBResumeKey, SMBSrvCopyChunkCopy, SMBSrvCopyChunkResponse};
+use crate::protocol::body::ioctl::ctl_code::SMBIoCtlCode;
+use crate::protocol::body::ioctl::network_interface::SMBNetworkInterfaceInfo;
+use crate::protocol::body::ioctl::{SMBIoCtlRequest, SMBIoCtlResponse};
+use crate::protocol::body::oplock_break::{SMBOplockBreakAcknowledgement, SMBOplockBreakContent};
+use crate::protocol::body::query_directory::SMBQueryDirectoryRequest;
+use crate::protocol::body::read::SMBReadRequest;
+use crate::protocol::body::read::channel::SMBRWChannel;
+use crate::protocol::body::query_info::info_type::SMBInfoType;
+use crate::protocol::body::query_info::SMBQueryInfoRequest;
+use crate::protocol::body::set_info::info_type::SMBInfoType as SetInfoType;
+use crate::protocol::body::set_info::{SMBSetInfoRequest, SMBSetInfoResponse};
 use crate::protocol::body::SMBBody;
 use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+use crate::protocol::body::tree_connect::context::RemotedIdentity;
+use crate::protocol::body::write::{SMBWriteRequest, SMBWriteResponse};
 use crate::protocol::header::SMBSyncHeader;
 use crate::protocol::message::SMBMessage;
+use crate::server::connection::Connection;
 use crate::server::message_handler::{SMBHandlerState, SMBLockedMessageHandler, SMBLockedMessageHandlerBase, SMBMessageType};
+use crate::server::network_interface::advertised_interfaces;
 use crate::server::open::Open;
+use crate::server::oplock::{granted_oplock_level, oplock_break_level, OPLOCK_BREAK_TIMEOUT};
 use crate::server::safe_locked_getter::SafeLockedGetter;
 use crate::server::Server;
 use crate::server::session::Session;
-use crate::server::share::SharedResource;
+use crate::server::share::{ResourceHandle, SharedResource};
 
 #[derive(Debug)]
 pub struct SMBTreeConnect<S: Server> {
@@ -29,7 +54,8 @@ pub struct SMBTreeConnect<S: Server> {
     // tree_global_id: u64,
     creation_time: FileTime,
     maximal_access: SMBAccessMask,
-    remoted_identity_security_context: Vec<u8> // TODO
+    // The client's own identity from a REMOTED_IDENTITY context, on shares that allow identity remoting
+    remoted_identity: Option<RemotedIdentity>,
 }
 
 impl<S: Server> SMBTreeConnect<S> {
@@ -41,35 +67,506 @@ impl<S: Server> SMBTreeConnect<S> {
             open_count: 0,
             creation_time: FileTime::now(),
             maximal_access,
-            remoted_identity_security_context: vec![],
+            remoted_identity: None,
         }
     }
+
+    pub fn with_remoted_identity(mut self, remoted_identity: Option<RemotedIdentity>) -> Self {
+        self.remoted_identity = remoted_identity;
+        self
+    }
+
+    pub fn remoted_identity(&self) -> Option<&RemotedIdentity> {
+        self.remoted_identity.as_ref()
+    }
+
+    // MS-SMB2 3.3.5.9: exclusive and batch holders of the file are told to break before the create goes any
+    // further, what comes back are the opens it has to wait on
+    async fn break_exclusive_oplocks(&self, message: &SMBCreateRequest) -> SMBResult<Vec<Arc<RwLock<S::Open>>>> {
+        let (path, _, _) = message.validate(self.share.deref())?;
+        let granted_access = SMBAccessMask::granted_within(message.desired_access(), &self.maximal_access)?;
+        let Ok(handle_path) = self.share.handle_path(path) else {
+            return Ok(Vec::new());
+        };
+        let server = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?
+            .upper().await?
+            .upper().await?;
+        let server_rd = server.read().await;
+        let mut breaking = Vec::new();
+        for other in server_rd.opens().values() {
+            let mut other_wr = other.write().await;
+            if !other_wr.handle().is_ok_and(|other| other.path() == handle_path) {
+                continue;
+            }
+            if !matches!(other_wr.oplock_level(), SMBOplockLevel::Exclusive | SMBOplockLevel::Batch) {
+                continue;
+            }
+            if let Some(level) = oplock_break_level(other_wr.oplock_level(), granted_access.includes_write()) {
+                other_wr.break_oplock(level);
+            }
+            breaking.push(other.clone());
+        }
+        Ok(breaking)
+    }
+
+    // Data written through one open leaves what the others cached stale, so their level II oplocks drop to none
+    async fn break_level_ii_oplocks(&self, writer: &Arc<RwLock<S::Open>>) -> SMBResult<()> {
+        let path = writer.read().await.handle()?.path().to_string();
+        let server = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?
+            .upper().await?
+            .upper().await?;
+        let server_rd = server.read().await;
+        for other in server_rd.opens().values() {
+            if Arc::ptr_eq(other, writer) {
+                continue;
+            }
+            let mut other_wr = other.write().await;
+            if other_wr.oplock_level() == SMBOplockLevel::II && other_wr.handle().is_ok_and(|other| other.path() == path) {
+                other_wr.break_oplock(SMBOplockLevel::None);
+            }
+        }
+        Ok(())
+    }
+
+    async fn open_file(&self, header: &SMBSyncHeader, message: &SMBCreateRequest) -> SMBResult<SMBMessageType> {
+        let (path, disposition, directory) = message.validate(self.share.deref())?;
+        let granted_access = SMBAccessMask::granted_within(message.desired_access(), &self.maximal_access)?;
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let notification_sender = connection.read().await.notification_sender();
+        let server = connection.upper().await?;
+        // Without FILE_OPEN_REPARSE_POINT the client is expected to follow links itself
+        if !message.options().contains(SMBCreateOptions::OPEN_REPARSE_POINT) {
+            self.share.check_symlinks(path)?;
+        }
+        message.validate_name(self.share.invalid_name_characters())?;
+        let existing = self.share.existing_is_directory(path);
+        if let Some(is_directory) = existing {
+            message.validate_file_type(is_directory)?;
+        }
+        let action = disposition.action(existing.is_some())?;
+        let handle_path = self.share.handle_path(path)?;
+        let mut server_wr = server.write().await;
+        // Every other open of the same file has to be compatible with this one's access and share mode,
+        // checked before the handle exists since creating it can already truncate or overwrite the file
+        for other in server_wr.opens().values() {
+            let other_rd = other.read().await;
+            if !other_rd.handle().is_ok_and(|other| other.path() == handle_path) {
+                continue;
+            }
+            message.share_access().check_sharing(&granted_access, other_rd.share_access(), other_rd.granted_access())?;
+        }
+        let handle = self.share.handle_create(path, disposition, directory)?;
+        // Exclusive and batch holders were waited on already, a level II holder just drops to none without answering
+        let incoming_writes = granted_access.includes_write();
+        let mut other_opens = false;
+        for other in server_wr.opens().values() {
+            let mut other_wr = other.write().await;
+            if !other_wr.handle().is_ok_and(|other| other.path() == handle.path()) {
+                continue;
+            }
+            other_opens = true;
+            if let Some(level) = oplock_break_level(other_wr.oplock_level(), incoming_writes) {
+                other_wr.break_oplock(level);
+            }
+        }
+        let mut open_raw: S::Open = Open::init(handle, message);
+        open_raw.set_granted_access(granted_access);
+        open_raw.set_oplock_level(granted_oplock_level(message.requested_oplock_level(), directory, other_opens));
+        open_raw.set_notification_sender(notification_sender);
+        open_raw.set_share_name(self.share.name().to_string());
+        let open = Arc::new(RwLock::new(open_raw));
+        server_wr.add_open(open.clone()).await;
+        drop(server_wr);
+        session.write().await.add_open(open.clone()).await;
+        let response = SMBBody::CreateResponse(SMBCreateResponse::for_open::<S>(open.read().await.deref(), action)?);
+        println!("In tree connect create");
+        let header = header.create_response_header(header.channel_sequence, header.session_id, header.tree_id);
+        println!("Creat resp bs: {}", response.smb_byte_size());
+        Ok(SMBMessage::new(header, response))
+    }
+
+    async fn open_for(&self, file_id: &SMBFileId) -> SMBResult<Arc<RwLock<S::Open>>> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let open = session.read().await.open_table().get(&(file_id.volatile as u32))
+            .map(Arc::clone)
+            .ok_or(SMBError::response_error(NTStatus::FileClosed))?;
+        // The volatile half only picks the open out of the session's table, the persistent half has to agree
+        if open.read().await.file_id().persistent != file_id.persistent {
+            return Err(SMBError::response_error(NTStatus::FileClosed));
+        }
+        Ok(open)
+    }
+
+    async fn check_channel(&self, channel: SMBRWChannel) -> SMBResult<()> {
+        if !channel.is_rdma() {
+            return Ok(());
+        }
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let server = connection.upper().await?;
+        let rdma_supported = server.read().await.rdma_transform_supported();
+        channel.validate(rdma_supported)
+    }
+
+    async fn max_read_size(&self) -> SMBResult<u32> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let max_read_size = connection.read().await.max_read_size();
+        Ok(max_read_size)
+    }
+
+    async fn max_write_size(&self) -> SMBResult<u32> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let max_write_size = connection.read().await.max_write_size();
+        Ok(max_write_size)
+    }
+
+    // The source of a server-side copy is named by its resume key and can be any open on the server
+    async fn open_for_resume_key(&self, resume_key: &SMBResumeKey) -> SMBResult<Arc<RwLock<S::Open>>> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let server = connection.upper().await?;
+        let server_rd = server.read().await;
+        for open in server_rd.opens().values() {
+            if open.read().await.resume_key() == *resume_key {
+                return Ok(open.clone());
+            }
+        }
+        Err(SMBError::response_error(NTStatus::ObjectNameNotFound))
+    }
+
+    async fn network_interfaces(&self) -> SMBResult<Vec<SMBNetworkInterfaceInfo>> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let server = connection.upper().await?;
+        let interfaces = advertised_interfaces(server.read().await.deref())?;
+        Ok(interfaces)
+    }
+
+    async fn copy_chunk_limits(&self) -> SMBResult<SMBCopyChunkLimits> {
+        let session = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?;
+        let connection = session.upper().await?;
+        let server = connection.upper().await?;
+        let limits = SMBCopyChunkLimits::for_server(server.read().await.deref());
+        Ok(limits)
+    }
+
+    // MS-SMB2 3.3.5.15.6, the source has to be readable and the target writable. Plain COPYCHUNK also wants
+    // to read the target, COPYCHUNK_WRITE exists for targets opened without that right
+    async fn copy_chunks(&self, ctl_code: SMBIoCtlCode, target: &Arc<RwLock<S::Open>>, request: &SMBSrvCopyChunkCopy) -> SMBResult<(NTStatus, SMBSrvCopyChunkResponse)> {
+        self.share.check_writable()?;
+        let source = self.open_for_resume_key(request.source_key()).await?;
+        let limits = self.copy_chunk_limits().await?;
+        let source_rd = source.read().await;
+        if !source_rd.granted_access().includes_read_data() {
+            return Err(SMBError::response_error(NTStatus::AccessDenied));
+        }
+        let check_target = |open: &S::Open| {
+            let access = open.granted_access();
+            match access.includes_write_data() && (ctl_code == SMBIoCtlCode::SrvCopyChunkWrite || access.includes_read_data()) {
+                true => Ok(()),
+                false => Err(SMBError::response_error(NTStatus::AccessDenied)),
+            }
+        };
+        // Copying within one file only takes its lock once
+        if Arc::ptr_eq(&source, target) {
+            check_target(source_rd.deref())?;
+            return request.copy(source_rd.handle()?, source_rd.handle()?, &limits);
+        }
+        let target_rd = target.read().await;
+        check_target(target_rd.deref())?;
+        request.copy(source_rd.handle()?, target_rd.handle()?, &limits)
+    }
+}
+
+// FileDispositionInformation handling from MS-FSA section 2.1.5.15.3
+fn set_delete_pending<O: Open>(open: &mut O, pending: bool) -> SMBResult<()> {
+    if pending && !open.granted_access().includes_delete() {
+        return Err(SMBError::response_error(NTStatus::AccessDenied));
+    }
+    if pending && open.handle()?.is_directory() && !open.handle()?.list_directory()?.is_empty() {
+        return Err(SMBError::response_error(NTStatus::DirectoryNotEmpty));
+    }
+    open.set_delete_on_close(pending);
+    Ok(())
 }
 
-impl<S: Server> SMBLockedMessageHandlerBase for Arc<SMBTreeConnect<S>> {
+impl<S: Server + 'static> SMBLockedMessageHandlerBase for Arc<SMBTreeConnect<S>> {
     type Inner = ();
 
     async fn inner(&self, message: &SMBMessageType) -> Option<Self::Inner> {
         Some(())
     }
 
+    // Once the share requires encryption, plaintext requests on its tree are refused
+    async fn validate_message(&self, message: &SMBMessageType) -> SMBResult<()> {
+        self.share.check_encryption(message.is_encrypted())
+    }
+
     async fn handle_create(&mut self, header: &SMBSyncHeader, message: &SMBCreateRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
-        let (path, disposition, directory) = message.validate(self.share.deref())?;
-        let handle = self.share.handle_create(path, disposition, directory)?;
-        let open_raw = Open::init(handle, message);
-        let response = SMBBody::CreateResponse(SMBCreateResponse::for_open::<S>(&open_raw)?);
-        let open = Arc::new(RwLock::new(open_raw));
+        let breaking = self.break_exclusive_oplocks(message).await?;
+        if breaking.is_empty() {
+            return Ok(SMBHandlerState::Finished(self.open_file(header, message).await?));
+        }
+        // MS-SMB2 3.3.5.9: the create goes pending until every holder acknowledges its break or runs out of time
+        let connection = self.session.upgrade()
+            .ok_or(SMBError::server_error("No Session Found"))?
+            .upper().await?;
+        let (dialect, notification_sender) = {
+            let connection_rd = connection.read().await;
+            (connection_rd.dialect(), connection_rd.notification_sender())
+        };
+        let notification_sender = notification_sender
+            .ok_or(SMBError::server_error("No notification channel for the pending create"))?;
+        // MessageIds are unique on a connection, so they double as AsyncIds
+        let async_id = header.message_id;
+        let mut interim_header = header.create_response_header(NTStatus::Pending as u32, header.session_id, header.tree_id);
+        interim_header.set_async_id(async_id);
+        let mut settled = Vec::with_capacity(breaking.len());
+        for open in breaking.iter() {
+            settled.push(open.read().await.oplock_break_settled());
+        }
+        let (tree, header, message) = (self.clone(), header.clone(), message.clone());
+        tokio::spawn(async move {
+            let acknowledged = tokio::time::timeout(OPLOCK_BREAK_TIMEOUT, async {
+                for settled in settled {
+                    settled.await;
+                }
+            }).await;
+            if acknowledged.is_err() {
+                for open in breaking.iter() {
+                    open.write().await.expire_oplock_break();
+                }
+            }
+            let mut response = match tree.open_file(&header, &message).await {
+                Ok(response) => response,
+                Err(SMBError::ResponseError(e)) => {
+                    let header = header.create_response_header(e.status() as u32, header.session_id, header.tree_id);
+                    SMBMessage::new(header, SMBBody::ErrorResponse(SMBErrorResponse::for_dialect(dialect, e.error_data().to_vec())))
+                },
+                Err(_) => return,
+            };
+            response.header.set_async_id(async_id);
+            let _ = notification_sender.send(response).await;
+        });
+        Ok(SMBHandlerState::Finished(SMBMessage::new(interim_header, SMBBody::ErrorResponse(SMBErrorResponse::new(Vec::new())))))
+    }
+
+    async fn handle_close(&mut self, header: &SMBSyncHeader, message: &SMBCloseRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let file_id = message.file_id();
+        let open = self.open_for(file_id).await?;
         let session = self.session.upgrade()
             .ok_or(SMBError::server_error("No Session Found"))?;
-        session.write().await.add_open(open.clone()).await;
+        let file_id = open.read().await.file_id();
+        session.write().await.remove_open(file_id.volatile as u32);
         let server = session.upper().await?
             .upper().await?;
-        server.write().await.add_open(open).await;
-        println!("In tree connect create");
-        let header = header.create_response_header(header.channel_sequence, header.session_id, header.tree_id);
-        println!("Creat resp bs: {}", response.smb_byte_size());
+        let mut server_wr = server.write().await;
+        server_wr.remove_open(file_id.persistent as u32);
+
+        let (response, delete_on_close, path) = {
+            let open_rd = open.read().await;
+            let response = SMBCloseResponse::for_open(open_rd.deref(), message.post_query_attributes())?;
+            (response, open_rd.delete_on_close(), open_rd.handle()?.path().to_string())
+        };
+        // The file is only removed once its last open goes away, so hand the pending delete to any that remain
+        let mut last_open = true;
+        if delete_on_close {
+            for other in server_wr.opens().values() {
+                let mut other_wr = other.write().await;
+                if other_wr.handle().is_ok_and(|other| other.path() == path) {
+                    other_wr.set_delete_on_close(true);
+                    last_open = false;
+                }
+            }
+        }
+        drop(server_wr);
+
+        // Requests still holding the open find it closed rather than keeping the file alive
+        let handle = open.write().await.take_handle()
+            .ok_or(SMBError::response_error(NTStatus::FileClosed))?;
+        match delete_on_close && last_open {
+            true => self.share.delete(handle)?,
+            false => self.share.close(handle)?,
+        }
+        let response = SMBBody::CloseResponse(response);
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_flush(&mut self, header: &SMBSyncHeader, message: &SMBFlushRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let open = self.open_for(message.file_id()).await?;
+        let open_rd = open.read().await;
+        message.flush(open_rd.handle()?, open_rd.granted_access())?;
+        let response = SMBBody::FlushResponse(SMBEmpty);
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_read(&mut self, header: &SMBSyncHeader, message: &SMBReadRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        self.check_channel(message.channel()).await?;
+        let max_read_size = self.max_read_size().await?;
+        let open = self.open_for(message.file_id()).await?;
+        let open_rd = open.read().await;
+        message.validate(open_rd.granted_access(), max_read_size)?;
+        let response = SMBBody::ReadResponse(message.read_from_async(open_rd.handle()?).await?);
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_write(&mut self, header: &SMBSyncHeader, message: &SMBWriteRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        self.share.check_writable()?;
+        self.check_channel(message.channel()).await?;
+        let max_write_size = self.max_write_size().await?;
+        let open = self.open_for(message.file_id()).await?;
+        message.validate(open.read().await.granted_access(), max_write_size)?;
+        self.break_level_ii_oplocks(&open).await?;
+        // Held for writing so concurrent appends can't both see the same end of file
+        let open_wr = open.write().await;
+        let bytes_written = message.write_to_async(open_wr.handle()?, open_wr.granted_access()).await?;
+        let response = SMBBody::WriteResponse(SMBWriteResponse::new(bytes_written));
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_ioctl(&mut self, header: &SMBSyncHeader, message: &SMBIoCtlRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let ctl_code = message.ctl_code()?;
+        let (status, response) = match ctl_code {
+            SMBIoCtlCode::SrvRequestResumeKey => {
+                let open = self.open_for(message.file_id()).await?;
+                let resume_key = open.read().await.resume_key();
+                (NTStatus::StatusSuccess, message.resume_key_response(resume_key)?)
+            },
+            SMBIoCtlCode::SrvCopyChunk | SMBIoCtlCode::SrvCopyChunkWrite => {
+                let request = message.copy_chunk_request()?;
+                let target = self.open_for(message.file_id()).await?;
+                let (status, copied) = self.copy_chunks(ctl_code, &target, &request).await?;
+                (status, SMBIoCtlResponse::new(message, copied.smb_to_bytes()))
+            },
+            SMBIoCtlCode::QueryNetworkInterfaceInfo => {
+                let interfaces = self.network_interfaces().await?;
+                (NTStatus::StatusSuccess, message.network_interfaces_response(&interfaces)?)
+            },
+            _ => return Err(SMBError::response_error(NTStatus::NotSupported)),
+        };
+        let header = header.create_response_header(status as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, SMBBody::IoCtlResponse(response))))
+    }
+
+    async fn handle_oplock_break(&mut self, header: &SMBSyncHeader, message: &SMBOplockBreakAcknowledgement) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let open = self.open_for(message.file_id()).await?;
+        open.write().await.acknowledge_oplock_break(message.level().into())?;
+        let response = SMBOplockBreakContent::new(message.level(), message.file_id().clone());
+        let header = header.create_response_header(0, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, SMBBody::OplockBreakAcknowledgement(response))))
+    }
+
+    async fn handle_query_directory(&mut self, header: &SMBSyncHeader, message: &SMBQueryDirectoryRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let open = self.open_for(message.file_id()).await?;
+        let mut open_wr = open.write().await;
+        let entries = open_wr.handle()?.list_directory()?;
+        let response = SMBBody::QueryDirectoryResponse(message.enumerate(open_wr.directory_cursor_mut(), &entries)?);
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_query_info(&mut self, header: &SMBSyncHeader, message: &SMBQueryInfoRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        let open = self.open_for(message.file_id()).await?;
+        let (status, response) = match message.info_type() {
+            SMBInfoType::Quota => message.query_quota(self.share.quota_provider())?,
+            _ => message.query_open(open.read().await.deref())?,
+        };
+        let response = SMBBody::QueryInfoResponse(response);
+        let header = header.create_response_header(status as u32, header.session_id, header.tree_id);
+        Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
+    }
+
+    async fn handle_set_info(&mut self, header: &SMBSyncHeader, message: &SMBSetInfoRequest) -> SMBResult<SMBHandlerState<Self::Inner>> {
+        self.share.check_writable()?;
+        let open = self.open_for(message.file_id()).await?;
+        let response = match (message.info_type(), message.delete_pending()) {
+            (_, Some(pending)) => {
+                set_delete_pending(open.write().await.deref_mut(), pending?)?;
+                SMBSetInfoResponse
+            },
+            (SetInfoType::Quota, None) => message.set_quota(self.share.quota_provider())?,
+            _ => return Err(SMBError::response_error(NTStatus::NotSupported)),
+        };
+        let response = SMBBody::SetInfoResponse(response);
+        let header = header.create_response_header(NTStatus::StatusSuccess as u32, header.session_id, header.tree_id);
         Ok(SMBHandlerState::Finished(SMBMessage::new(header, response)))
     }
 }
 
-impl<S: Server> SMBLockedMessageHandler for Arc<SMBTreeConnect<S>> {}
\ No newline at end of file
+impl<S: Server + 'static> SMBLockedMessageHandler for Arc<SMBTreeConnect<S>> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Weak};
+
+    use tokio::net::TcpListener;
+
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+
+    use crate::protocol::body::create::disposition::SMBCreateDisposition;
+    use crate::protocol::body::create::options::SMBCreateOptions;
+    use crate::protocol::body::create::tests::create_request;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::body::tree_connect::access_mask::SMBAccessMask;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::SMBMessage;
+    use crate::server::{DefaultShare, SMBServer};
+    use crate::server::message_handler::{SMBLockedMessageHandler, SMBLockedMessageHandlerBase};
+    use crate::server::share::ResourceHandle;
+    use crate::server::share::file_system::SMBFileSystemShare;
+    use crate::server::tree_connect::SMBTreeConnect;
+    use crate::util::auth::ntlm::NTLMAuthProvider;
+
+    type TestServer = SMBServer<&'static str, TcpListener, NTLMAuthProvider>;
+
+    fn tree_for(encrypt_data: bool) -> Arc<SMBTreeConnect<TestServer>> {
+        let share: DefaultShare<NTLMAuthProvider> = SMBFileSystemShare::<String, Box<dyn ResourceHandle>>::root("test".into(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+            .with_encrypt_data(encrypt_data)
+            .into();
+        Arc::new(SMBTreeConnect::init(1, Weak::new(), Arc::new(share), SMBAccessMask::access_no_connect_security(true)))
+    }
+
+    fn create_message() -> SMBMessage<SMBSyncHeader, SMBBody> {
+        let header = SMBSyncHeader::new(SMBCommandCode::Create, SMBFlags::empty(), 0, 4, 1, 9, [0; 16]);
+        let request = create_request("file.txt", SMBCreateDisposition::Open, SMBCreateOptions::empty());
+        SMBMessage::new(header, SMBBody::CreateRequest(request))
+    }
+
+    #[tokio::test]
+    async fn plaintext_create_on_encrypted_tree_is_denied() {
+        let mut tree = tree_for(true);
+        let mut message = create_message();
+
+        let result = tree.handle_message(&message).await;
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::AccessDenied));
+
+        message.set_encrypted(true);
+        assert!(tree.validate_message(&message).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn plaintext_requests_reach_unencrypted_trees() {
+        let tree = tree_for(false);
+        assert!(tree.validate_message(&create_message()).await.is_ok());
+    }
+}
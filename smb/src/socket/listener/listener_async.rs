@@ -25,7 +25,7 @@ impl<T> SMBSocket<T> for TcpListener where T: ToSocketAddrs + Send + Sync {
         match self.accept().await {
             Ok((stream, addr)) => {
                 let (read, write) = stream.into_split();
-                Ok(SMBSocketConnection::new(addr.to_string(), read, write))
+                Ok(SMBSocketConnection::new(addr.to_string(), read, write).with_peer_addr(addr))
             }
             Err(e) => Err(SMBError::io_error(e))
         }
@@ -21,8 +21,8 @@ impl<T> SMBSocket<T> for TcpListener where T: ToSocketAddrs + Send + Sync {
     fn new_connection(&self) -> SMBResult<SMBSocketConnection<Self::ReadStream, Self::WriteStream>> {
         match self.accept() {
             Ok((read, addr)) => {
-                let write = read.try_clone().map_err(SMBError::io_error)?;
-                Ok(SMBSocketConnection::new(addr.to_string(), read, write))
+                let write = read.try_clone()?;
+                Ok(SMBSocketConnection::new(addr.to_string(), read, write).with_peer_addr(addr))
             }
             Err(e) => Err(SMBError::io_error(e))
         }
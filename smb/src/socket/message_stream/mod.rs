@@ -1,4 +1,5 @@
 use std::future::Future;
+use std::net::SocketAddr;
 
 use tokio_util::sync::ReusableBoxFuture;
 
@@ -7,7 +8,7 @@ use smb_core::error::SMBError;
 
 use crate::protocol::body::{LegacySMBBody, SMBBody};
 use crate::protocol::header::{LegacySMBHeader, SMBSyncHeader};
-use crate::protocol::message::{Message, SMBMessage};
+use crate::protocol::message::{Message, SMBEncryptedMessage, SMBFrame, SMBMessage, SMBSyncMessage};
 
 // use crate::socket::message_stream::stream_async::SMBMessageStream;
 
@@ -18,31 +19,68 @@ mod stream_async;
 
 pub trait SMBReadStream: SMBStream {
     #[cfg(feature = "async")]
-    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> impl Future<Output=SMBParseResult<&[u8], SMBMessage<SMBSyncHeader, SMBBody>>> + Send;
+    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> impl Future<Output=SMBParseResult<&[u8], SMBFrame>> + Send;
 
     #[cfg(not(feature = "async"))]
-    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&[u8], SMBMessage<SMBSyncHeader, SMBBody>>;
+    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&[u8], SMBFrame>;
     #[cfg(not(feature = "async"))]
     fn messages(&mut self) -> SMBMessageIterator<Self> where Self: Sized;
 
     #[cfg(feature = "async")]
     fn messages(&mut self) -> SMBMessageStream<Self> where Self: Sized;
-    fn read_message_inner(buffer: &[u8]) -> SMBParseResult<&[u8], SMBMessage<SMBSyncHeader, SMBBody>> {
-        println!("in inner read");
-        if let Some(pos) = buffer.iter().position(|x| *x == b'S') {
-            println!("found s at pos: {}", pos);
-            if buffer[(pos)..].starts_with(b"SMB") {
-                println!("found smb");
-                let result = SMBMessage::<SMBSyncHeader, SMBBody>::parse(&buffer[(pos - 1)..]);
-                return if result.is_err() {
-                    let (remaining, legacy_msg) = SMBMessage::<LegacySMBHeader, LegacySMBBody>::parse(&buffer[(pos - 1)..])?;
-                    Ok((remaining, SMBMessage::<SMBSyncHeader, SMBBody>::from_legacy(legacy_msg).ok_or(SMBError::parse_error("Invalid legacy body"))?))
-                } else {
-                    result
-                };
+    // MS-SMB2 2.1: Direct TCP puts a zero byte and a 24-bit big-endian length ahead of each message. A compound's
+    // later messages share their first's frame, so the rest of it is framed again where the next one starts
+    fn read_message_inner(buffer: &mut [u8]) -> SMBParseResult<&[u8], SMBFrame> {
+        if buffer.is_empty() {
+            return Err(SMBError::parse_error("No message in the buffer"));
+        }
+        let Some(prefix) = buffer.first_chunk::<4>() else {
+            return Err(SMBError::payload_too_small(4_usize, buffer.len()));
+        };
+        if prefix[0] != 0 {
+            return Err(SMBError::parse_error("Transport frame doesn't start with a zero byte"));
+        }
+        let length = u32::from_be_bytes(*prefix) as usize;
+        let frame_end = 4 + length;
+        if buffer.len() < frame_end {
+            return Err(SMBError::payload_too_small(frame_end, buffer.len()));
+        }
+        let message_bytes = &buffer[4..frame_end];
+        if message_bytes.first() == Some(&0xFD) {
+            let (_, sealed) = SMBEncryptedMessage::parse(message_bytes)?;
+            return Ok((&buffer[frame_end..], SMBFrame::Sealed(sealed)));
+        }
+        let (remaining, message) = match SMBMessage::<SMBSyncHeader, SMBBody>::parse(message_bytes) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                let (remaining, legacy_msg) = SMBMessage::<LegacySMBHeader, LegacySMBBody>::parse(message_bytes)?;
+                (remaining, SMBMessage::<SMBSyncHeader, SMBBody>::from_legacy(legacy_msg).ok_or(SMBError::parse_error("Invalid legacy body"))?)
             }
+        };
+        let parsed = length - remaining.len();
+        let next_command = message.header.next_command as usize;
+        if next_command == 0 {
+            let raw = message_bytes.to_vec();
+            return Ok((&buffer[frame_end..], SMBFrame::Plain(message, raw)));
         }
-        Err(SMBError::parse_error("Unknown error occurred while parsing message"))
+        if next_command < parsed.max(4) || next_command >= length {
+            return Err(SMBError::parse_error("NextCommand points outside its message's frame"));
+        }
+        // A compounded request is signed up to where the next one starts, padding included
+        let raw = message_bytes[..next_command].to_vec();
+        let next_start = 4 + next_command;
+        let next_length = (frame_end - next_start) as u32;
+        buffer[(next_start - 4)..next_start].copy_from_slice(&next_length.to_be_bytes());
+        Ok((&buffer[(next_start - 4)..], SMBFrame::Plain(message, raw)))
+    }
+}
+
+// Whether the buffer holds the whole of its first transport frame, or enough of it to tell it isn't one
+#[cfg(not(feature = "async"))]
+fn frame_buffered(buffer: &[u8]) -> bool {
+    match buffer.first_chunk::<4>() {
+        Some(prefix) => prefix[0] != 0 || buffer.len() >= 4 + u32::from_be_bytes(*prefix) as usize,
+        None => false,
     }
 }
 
@@ -52,6 +90,13 @@ pub trait SMBWriteStream: SMBStream {
 
     #[cfg(not(feature = "async"))]
     fn write_message<T: Message>(&mut self, message: &T) -> SMBResult<usize>;
+
+    // Compounded responses go out as one frame in a single write so nothing can land between them
+    #[cfg(feature = "async")]
+    fn write_compound(&mut self, messages: Vec<SMBSyncMessage>) -> impl Future<Output=SMBResult<usize>> + Send;
+
+    #[cfg(not(feature = "async"))]
+    fn write_compound(&mut self, messages: Vec<SMBSyncMessage>) -> SMBResult<usize>;
 }
 
 pub trait SMBStream: Send + Sync {
@@ -79,14 +124,37 @@ impl<'a, R: SMBReadStream> SMBMessageIterator<'a, R> {
     }
 }
 
+// The frame that answers a request, with the credits granted on the interim responses that came ahead of it,
+// MS-SMB2 3.2.5.1.5. A sealed frame is handed back as it arrived, only its reader has the keys to open it
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct SMBResponseFrame {
+    pub frame: SMBFrame,
+    pub interim_credits: u16,
+}
+
+impl SMBResponseFrame {
+    // Interim responses come in the clear, anything sealed may be the final one or not and goes to the caller
+    pub(crate) fn from_frame(frame: SMBFrame, interim_credits: &mut u16) -> Option<Self> {
+        match &frame {
+            SMBFrame::Plain(message, _) if message.header.is_interim_response() => {
+                *interim_credits = interim_credits.saturating_add(message.header.credits);
+                None
+            },
+            _ => Some(Self { frame, interim_credits: *interim_credits }),
+        }
+    }
+}
+
 #[cfg(feature = "async")]
 pub struct SMBMessageStream<'a, T: SMBReadStream> {
-    pub(crate) inner: ReusableBoxFuture<'a, (SMBResult<SMBMessage<SMBSyncHeader, SMBBody>>, SMBMessageIterator<'a, T>)>,
+    pub(crate) inner: ReusableBoxFuture<'a, (SMBResult<SMBFrame>, SMBMessageIterator<'a, T>)>,
 }
 
 #[derive(Debug)]
 pub struct SMBSocketConnection<R: SMBReadStream, W: SMBWriteStream> {
     name: String,
+    peer_addr: Option<SocketAddr>,
+    channel_bindings: Option<Vec<u8>>,
     read_stream: R,
     write_stream: W,
 }
@@ -95,11 +163,24 @@ impl<R: SMBReadStream, W: SMBWriteStream> SMBSocketConnection<R, W> {
     pub fn new(name: String, read_stream: R, write_stream: W) -> Self {
         Self {
             name,
+            peer_addr: None,
+            channel_bindings: None,
             read_stream,
             write_stream,
         }
     }
 
+    pub fn with_peer_addr(mut self, peer_addr: SocketAddr) -> Self {
+        self.peer_addr = Some(peer_addr);
+        self
+    }
+
+    // Channel binding application data of a secure transport (e.g. "tls-server-end-point:" and the certificate hash)
+    pub fn with_channel_bindings(mut self, channel_bindings: Vec<u8>) -> Self {
+        self.channel_bindings = Some(channel_bindings);
+        self
+    }
+
     pub fn messages(&mut self) -> SMBMessageIterator<R> {
         SMBMessageIterator::new(self.read())
     }
@@ -108,6 +189,14 @@ impl<R: SMBReadStream, W: SMBWriteStream> SMBSocketConnection<R, W> {
         &self.name
     }
 
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub fn channel_bindings(&self) -> Option<&[u8]> {
+        self.channel_bindings.as_deref()
+    }
+
     pub fn read(&mut self) -> &mut R {
         &mut self.read_stream
     }
@@ -3,18 +3,16 @@ use std::pin::Pin;
 use std::task::{Context, Poll, ready};
 
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio_stream::Stream;
+use tokio_stream::{Stream, StreamExt};
 use tokio_util::sync::ReusableBoxFuture;
 
 use smb_core::{SMBParseResult, SMBResult};
 use smb_core::error::SMBError;
 
-use crate::protocol::body::SMBBody;
-use crate::protocol::header::SMBSyncHeader;
-use crate::protocol::message::{Message, SMBMessage};
-use crate::socket::message_stream::{SMBMessageIterator, SMBMessageStream, SMBReadStream, SMBSocketConnection, SMBStream, SMBWriteStream};
+use crate::protocol::message::{Message, SMBCompoundMessage, SMBFrame, SMBSyncMessage};
+use crate::socket::message_stream::{SMBMessageIterator, SMBMessageStream, SMBReadStream, SMBResponseFrame, SMBSocketConnection, SMBStream, SMBWriteStream};
 
-async fn make_future<T: SMBReadStream>(mut iterator: SMBMessageIterator<'_, T>) -> (SMBResult<SMBMessage<SMBSyncHeader, SMBBody>>, SMBMessageIterator<'_, T>) {
+async fn make_future<T: SMBReadStream>(mut iterator: SMBMessageIterator<'_, T>) -> (SMBResult<SMBFrame>, SMBMessageIterator<'_, T>) {
     let res = loop {
         match iterator.reader.read_message(&mut iterator.buffer).await {
             Ok(msg) => break Ok(msg),
@@ -47,23 +45,43 @@ impl<'a, T: SMBReadStream> SMBMessageStream<'a, T> {
     }
 }
 
+impl<'a, T: SMBReadStream> SMBMessageStream<'a, T> {
+    pub async fn next_response(&mut self) -> Option<SMBResponseFrame> {
+        let mut interim_credits = 0;
+        while let Some(frame) = self.next().await {
+            if let Some(response) = SMBResponseFrame::from_frame(frame, &mut interim_credits) {
+                return Some(response);
+            }
+        }
+        None
+    }
+}
+
 impl<Writer> SMBWriteStream for Writer where Writer: AsyncWriteExt + Unpin + Send + Sync + SMBStream {
     async fn write_message<T: Message + Sync>(&mut self, message: &T) -> SMBResult<usize> {
-        let bytes = message.as_bytes();
-        self.write_all(&bytes).await.map_err(SMBError::io_error)?;
+        let bytes = message.framed_bytes();
+        self.write_all(&bytes).await?;
         Ok(bytes.len())
     }
+
+    async fn write_compound(&mut self, messages: Vec<SMBSyncMessage>) -> SMBResult<usize> {
+        self.write_message(&SMBCompoundMessage::new(messages)).await
+    }
 }
 
 impl<Reader> SMBReadStream for Reader where Reader: AsyncReadExt + Unpin + Send + Sync + SMBStream {
-    async fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&'a [u8], SMBMessage<SMBSyncHeader, SMBBody>> {
+    async fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&'a [u8], SMBFrame> {
         println!("read called w/ existing buffer: {:02x?}", existing);
         if let Ok((remaining, res)) = Self::read_message_inner(existing) {
-            return Ok((&existing[(existing.len() - remaining.len())..], res));
+            let remaining = remaining.len();
+            return Ok((&existing[(existing.len() - remaining)..], res));
         }
         let mut buffer = [0u8; 512];
-        if let Ok(read) = self.read(&mut buffer).await {
-            existing.extend_from_slice(&buffer[..read]);
+        match self.read(&mut buffer).await {
+            // The peer is gone, whatever part of a frame is left won't be finished
+            Ok(0) if !existing.is_empty() => return Err(SMBError::parse_error("Stream ended partway through a frame")),
+            Ok(read) => existing.extend_from_slice(&buffer[..read]),
+            Err(_) => {}
         }
         Self::read_message_inner(existing)
     }
@@ -80,7 +98,7 @@ impl<R: SMBReadStream, W: SMBWriteStream> SMBSocketConnection<R, W> {
 }
 
 impl<'a, R: SMBReadStream> Stream for SMBMessageStream<'a, R> {
-    type Item = SMBMessage<SMBSyncHeader, SMBBody>;
+    type Item = SMBFrame;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let (res, iterator) = ready!(self.inner.poll(cx));
@@ -90,4 +108,131 @@ impl<'a, R: SMBReadStream> Stream for SMBMessageStream<'a, R> {
             Err(_) => Poll::Ready(None),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, DuplexStream, duplex};
+    use tokio_stream::StreamExt;
+
+    use smb_core::nt_status::NTStatus;
+    use smb_core::SMBResult;
+    use smb_core::error::SMBError;
+
+    use crate::protocol::body::empty::SMBEmpty;
+    use crate::protocol::body::error::SMBErrorResponse;
+    use crate::protocol::body::negotiate::context::EncryptionCipher;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::protocol::header::flags::SMBFlags;
+    use crate::protocol::header::SMBSyncHeader;
+    use crate::protocol::message::{Message, SMBCompoundMessage, SMBFrame, SMBMessage};
+    use crate::socket::message_stream::{SMBReadStream, SMBStream, SMBWriteStream};
+    use crate::util::crypto::smb2::encrypt_message;
+
+    impl SMBStream for DuplexStream {
+        async fn close_stream(&mut self) -> SMBResult<()> {
+            Ok(())
+        }
+    }
+
+    fn echo_response(flags: SMBFlags, status: NTStatus) -> SMBMessage<SMBSyncHeader, SMBBody> {
+        let mut header = SMBSyncHeader::new(SMBCommandCode::Echo, flags, 0, 7, 0, 0, [0; 16]);
+        header.channel_sequence = status as u32;
+        SMBMessage::new(header, SMBBody::EchoResponse(SMBEmpty))
+    }
+
+    // Scripted rather than served: no server handler goes async, so nothing in the tree sends a STATUS_PENDING
+    // interim response ahead of the final one
+    #[tokio::test]
+    async fn next_response_skips_interim_response_but_keeps_its_credits() {
+        let (mut server, mut client) = duplex(1024);
+        let mut interim = echo_response(SMBFlags::SERVER_TO_REDIR | SMBFlags::ASYNC_COMMAND, NTStatus::Pending);
+        interim.header.credits = 3;
+        interim.body = SMBBody::ErrorResponse(SMBErrorResponse::new(Vec::new()));
+        let last = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess);
+        server.write_message(&interim).await.unwrap();
+        server.write_message(&last).await.unwrap();
+
+        let mut messages = client.messages();
+        let response = messages.next_response().await.unwrap();
+        assert!(matches!(response.frame, SMBFrame::Plain(message, _) if message == last));
+        assert_eq!(response.interim_credits, 3);
+    }
+
+    #[tokio::test]
+    async fn next_response_hands_back_sealed_frames() {
+        let (mut server, mut client) = duplex(1024);
+        let sealed = encrypt_message(&echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess), 1, &[0x11; 16], EncryptionCipher::AES128CCM).unwrap();
+        server.write_message(&sealed).await.unwrap();
+
+        let mut messages = client.messages();
+        let response = messages.next_response().await.unwrap();
+        assert_eq!(response.frame, SMBFrame::Sealed(sealed));
+        assert_eq!(response.interim_credits, 0);
+    }
+
+    #[test]
+    fn read_message_inner_takes_the_frame_from_its_length_prefix() {
+        let bytes = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess).framed_bytes();
+        let mut trailing = [bytes.as_slice(), &[0xAA; 3]].concat();
+        let (remaining, _) = DuplexStream::read_message_inner(&mut trailing).unwrap();
+        assert_eq!(remaining, &[0xAA; 3]);
+
+        assert!(matches!(DuplexStream::read_message_inner(&mut bytes[..bytes.len() - 1].to_vec()), Err(SMBError::PayloadTooSmall(_))));
+        assert!(matches!(DuplexStream::read_message_inner(&mut bytes[..3].to_vec()), Err(SMBError::PayloadTooSmall(_))));
+        assert!(DuplexStream::read_message_inner(&mut bytes[4..].to_vec()).is_err());
+        assert!(DuplexStream::read_message_inner(&mut [&[0xFF], &bytes[1..]].concat()).is_err());
+        assert!(DuplexStream::read_message_inner(&mut Vec::new()).is_err());
+    }
+
+    #[tokio::test]
+    async fn length_prefix_holding_an_s_byte_still_frames() {
+        let (mut server, mut client) = duplex(1024);
+        let mut message = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::AccessDenied);
+        message.body = SMBBody::ErrorResponse(SMBErrorResponse::new(vec![0; 11]));
+        let bytes = message.framed_bytes();
+        assert_eq!(bytes[..4], [0, 0, 0, b'S']);
+        server.write_message(&message).await.unwrap();
+        drop(server);
+
+        let frames = client.messages().collect::<Vec<_>>().await;
+        assert_eq!(frames.len(), 1);
+        assert!(matches!(&frames[0], SMBFrame::Plain(_, raw) if raw[..] == bytes[4..]));
+    }
+
+    #[tokio::test]
+    async fn compound_frame_reads_back_as_its_messages() {
+        let (mut server, mut client) = duplex(1024);
+        let first = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess);
+        let second = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess);
+        server.write_compound(vec![first, second.clone()]).await.unwrap();
+        drop(server);
+
+        let frames = client.messages().collect::<Vec<_>>().await;
+        assert_eq!(frames.len(), 2);
+        // The first is signed over its padding too, up to where the second starts
+        assert!(matches!(&frames[0], SMBFrame::Plain(message, raw) if message.header.next_command == 72 && raw.len() == 72));
+        assert!(matches!(&frames[1], SMBFrame::Plain(message, raw) if *message == second && raw.len() == 68));
+    }
+
+    #[tokio::test]
+    async fn compound_responses_go_out_as_one_frame() {
+        let (mut server, mut client) = duplex(1024);
+        let first = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess);
+        let second = echo_response(SMBFlags::SERVER_TO_REDIR, NTStatus::StatusSuccess);
+        let sent = server.write_compound(vec![first, second]).await.unwrap();
+        drop(server);
+
+        let mut bytes = Vec::new();
+        client.read_to_end(&mut bytes).await.unwrap();
+        assert_eq!(bytes.len(), sent);
+        // One length prefix covering both, the first echo padded from 68 to 72 bytes
+        assert_eq!(u32::from_be_bytes([0, bytes[1], bytes[2], bytes[3]]), 72 + 68);
+        assert_eq!(u32::from_le_bytes(bytes[24..28].try_into().unwrap()), 72);
+        assert_eq!(&bytes[76..80], &[0xFE, b'S', b'M', b'B']);
+        assert_eq!(u32::from_le_bytes(bytes[96..100].try_into().unwrap()), 0);
+
+        let (_, compound) = SMBCompoundMessage::parse(&bytes[4..]).unwrap();
+        assert_eq!(compound.messages().len(), 2);
+    }
+}
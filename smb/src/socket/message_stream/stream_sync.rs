@@ -3,17 +3,18 @@ use std::io::{Read, Write};
 use smb_core::{SMBParseResult, SMBResult};
 use smb_core::error::SMBError;
 
-use crate::protocol::body::SMBBody;
-use crate::protocol::header::SMBSyncHeader;
-use crate::protocol::message::{Message, SMBMessage};
-use crate::socket::message_stream::{SMBMessageIterator, SMBReadStream, SMBSocketConnection, SMBWriteStream};
+use crate::protocol::message::{Message, SMBCompoundMessage, SMBFrame, SMBSyncMessage};
+use crate::socket::message_stream::{frame_buffered, SMBMessageIterator, SMBReadStream, SMBResponseFrame, SMBSocketConnection, SMBWriteStream};
 
 impl<Reader> SMBReadStream for Reader where Reader: Read + Send + Sync {
-    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&[u8], SMBMessage<SMBSyncHeader, SMBBody>> {
+    fn read_message<'a>(&'a mut self, existing: &'a mut Vec<u8>) -> SMBParseResult<&[u8], SMBFrame> {
         let mut buffer = [0_u8; 512];
 
-        if let Ok(read) = self.read(&mut buffer) {
-            existing.extend_from_slice(&buffer[..read]);
+        while !frame_buffered(existing) {
+            match self.read(&mut buffer) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => existing.extend_from_slice(&buffer[..read]),
+            }
         }
 
         Self::read_message_inner(existing)
@@ -26,10 +27,14 @@ impl<Reader> SMBReadStream for Reader where Reader: Read + Send + Sync {
 
 impl<Writer> SMBWriteStream for Writer where Writer: Write {
     fn write_message<T: Message>(&mut self, message: &T) -> SMBResult<usize> {
-        let bytes = message.as_bytes();
-        self.write_all(&bytes).map_err(SMBError::io_error)?;
+        let bytes = message.framed_bytes();
+        self.write_all(&bytes)?;
         Ok(bytes.len())
     }
+
+    fn write_compound(&mut self, messages: Vec<SMBSyncMessage>) -> SMBResult<usize> {
+        self.write_message(&SMBCompoundMessage::new(messages))
+    }
 }
 
 impl<R: SMBReadStream, W: SMBWriteStream> SMBSocketConnection<R, W> {
@@ -38,8 +43,15 @@ impl<R: SMBReadStream, W: SMBWriteStream> SMBSocketConnection<R, W> {
     }
 }
 
+impl<R: SMBReadStream> SMBMessageIterator<'_, R> {
+    pub fn next_response(&mut self) -> Option<SMBResponseFrame> {
+        let mut interim_credits = 0;
+        self.find_map(|frame| SMBResponseFrame::from_frame(frame, &mut interim_credits))
+    }
+}
+
 impl<R: SMBReadStream> Iterator for SMBMessageIterator<'_, R> {
-    type Item = SMBMessage<SMBSyncHeader, SMBBody>;
+    type Item = SMBFrame;
 
     fn next(&mut self) -> Option<Self::Item> {
         let (remaining, message) = self.reader.read_message(&mut self.buffer).ok()?;
@@ -0,0 +1,69 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::io::AsyncReadExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::RwLock;
+
+use crate::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask};
+use crate::server::{DefaultShare, SMBServer, SMBServerBuilder};
+use crate::server::share::file_system::{SMBFileSystemHandle, SMBFileSystemShare};
+use crate::server::share::ResourceHandle;
+use crate::util::auth::ntlm::NTLMAuthProvider;
+use crate::util::auth::User;
+
+pub use temp_dir::TempDir;
+
+mod temp_dir;
+
+pub type TestServerBuilder = SMBServerBuilder<&'static str, TcpListener, NTLMAuthProvider, DefaultShare<NTLMAuthProvider>, Box<dyn ResourceHandle>>;
+pub type TestServer = Arc<RwLock<SMBServer<&'static str, TcpListener, NTLMAuthProvider, DefaultShare<NTLMAuthProvider>, Box<dyn ResourceHandle>>>>;
+
+// NTLM with no users and otherwise the builder's defaults
+pub fn server_builder() -> TestServerBuilder {
+    SMBServerBuilder::default()
+        .auth_provider(NTLMAuthProvider::new(vec![], false))
+}
+
+// alice/password on a server that neither signs nor encrypts, so a plain test client gets through
+pub fn user_server_builder() -> TestServerBuilder {
+    server_builder()
+        .unencrypted_access(true)
+        .require_message_signing(false)
+        .encrypt_data(false)
+        .auth_provider(NTLMAuthProvider::new(vec![User::new("alice", "password")], false))
+}
+
+// user_server_builder() sharing `root` as "test" with full access
+pub fn share_server_builder(root: &Path) -> TestServerBuilder {
+    user_server_builder()
+        .add_fs_share("test".into(), root.to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false)
+}
+
+// A share named "test" over `root`, open to anyone with full access, for driving a share without a server
+pub fn fs_share(root: &Path) -> SMBFileSystemShare<(), SMBFileSystemHandle> {
+    SMBFileSystemShare::path("test".into(), root.to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::access_no_connect_security(true))
+}
+
+// Binds the builder to an ephemeral loopback port, the address it got is handed back with the server
+pub async fn serve(builder: TestServerBuilder) -> (TestServer, SocketAddr) {
+    let server = builder.listener_address("127.0.0.1:0").await.unwrap()
+        .build().unwrap();
+    let addr = server.read().await.local_listeners[0].lock().await.local_addr().unwrap();
+    (server, addr)
+}
+
+pub async fn test_server() -> (TestServer, SocketAddr) {
+    serve(server_builder()).await
+}
+
+// One transport frame off a raw client or server socket, length prefix included
+pub async fn read_frame(stream: &mut TcpStream) -> Vec<u8> {
+    let mut frame = vec![0; 4];
+    stream.read_exact(&mut frame).await.unwrap();
+    let len = u32::from_be_bytes([0, frame[1], frame[2], frame[3]]) as usize;
+    frame.resize(4 + len, 0);
+    stream.read_exact(&mut frame[4..]).await.unwrap();
+    frame
+}
@@ -5,6 +5,7 @@ use smb_core::{SMBParseResult, SMBResult};
 use smb_core::nt_status::NTStatus;
 pub use user::*;
 
+pub mod negotiate;
 pub mod ntlm;
 pub mod spnego;
 mod auth_context;
@@ -15,6 +16,11 @@ pub trait AuthProvider: Send + Sync {
 
     fn get_oid() -> Vec<u8>;
 
+    // Every mechanism the provider takes tokens for, matched against the client's SPNEGO mech list
+    fn mech_types() -> Vec<Vec<u8>> {
+        vec![Self::get_oid()]
+    }
+
     fn accept_security_context(&self, input_token: &Self::Message, context: &mut Self::Context) -> (NTStatus, Self::Message);
 }
 
@@ -27,9 +33,13 @@ pub trait AuthMessage {
 }
 
 pub trait AuthContext {
-    type UserName: Send + Sync;
+    type UserName: Send + Sync + PartialEq;
     fn init() -> Self;
     fn session_key(&self) -> &[u8];
     fn user_name(&self) -> SMBResult<&Self::UserName>;
+    // Providers that support Extended Protection check the client's binding against this
+    fn set_channel_bindings(&mut self, _channel_bindings: Option<Vec<u8>>) {}
+    // The mechanism picked from the client's mech list, every token after it is for that mechanism
+    fn select_mech(&mut self, _mech: &[u8]) {}
 }
 
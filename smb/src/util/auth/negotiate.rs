@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+
+use smb_core::{SMBParseResult, SMBResult};
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+
+use crate::util::auth::{AuthContext, AuthMessage, AuthProvider};
+
+// Serves two mechanisms behind one SPNEGO exchange, e.g. Kerberos with NTLM for clients that can't get a
+// ticket. Whichever the client's mech list names first wins, the preferred one when it names both. More
+// providers nest as the fallback: NegotiateAuthProvider<A, NegotiateAuthProvider<B, C>>
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NegotiateAuthProvider<P, F> {
+    preferred: P,
+    fallback: F,
+}
+
+impl<P: AuthProvider, F: AuthProvider> NegotiateAuthProvider<P, F> {
+    pub fn new(preferred: P, fallback: F) -> Self {
+        Self {
+            preferred,
+            fallback,
+        }
+    }
+}
+
+impl<P: AuthProvider + 'static, F: AuthProvider + 'static> AuthProvider for NegotiateAuthProvider<P, F>
+    where F::Context: AuthContext<UserName=<P::Context as AuthContext>::UserName> {
+    type Message = NegotiateMessage<P::Message, F::Message>;
+    type Context = NegotiateAuthContext<P, F>;
+
+    fn get_oid() -> Vec<u8> {
+        P::get_oid()
+    }
+
+    fn mech_types() -> Vec<Vec<u8>> {
+        [P::mech_types(), F::mech_types()].concat()
+    }
+
+    // Tokens arrive raw since only the context knows which mechanism they belong to
+    fn accept_security_context(&self, input_message: &Self::Message, context: &mut Self::Context) -> (NTStatus, Self::Message) {
+        let NegotiateMessage::Token(token) = input_message else {
+            return (NTStatus::InvalidParameter, NegotiateMessage::Empty);
+        };
+        match context.selected {
+            Some(NegotiateMech::Preferred) => match P::Message::parse(token) {
+                Ok((_, message)) => {
+                    let (status, reply) = self.preferred.accept_security_context(&message, &mut context.preferred);
+                    (status, NegotiateMessage::Preferred(reply))
+                },
+                Err(_) => (NTStatus::InvalidParameter, NegotiateMessage::Empty),
+            },
+            Some(NegotiateMech::Fallback) => match F::Message::parse(token) {
+                Ok((_, message)) => {
+                    let (status, reply) = self.fallback.accept_security_context(&message, &mut context.fallback);
+                    (status, NegotiateMessage::Fallback(reply))
+                },
+                Err(_) => (NTStatus::InvalidParameter, NegotiateMessage::Empty),
+            },
+            None => (NTStatus::InvalidParameter, NegotiateMessage::Empty),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NegotiateMessage<P, F> {
+    Token(Vec<u8>),
+    Preferred(P),
+    Fallback(F),
+    Empty,
+}
+
+impl<P: AuthMessage, F: AuthMessage> AuthMessage for NegotiateMessage<P, F> {
+    fn parse(data: &[u8]) -> SMBParseResult<&[u8], Self> {
+        Ok((&data[data.len()..], NegotiateMessage::Token(data.to_vec())))
+    }
+
+    fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            NegotiateMessage::Token(token) => token.clone(),
+            NegotiateMessage::Preferred(message) => message.as_bytes(),
+            NegotiateMessage::Fallback(message) => message.as_bytes(),
+            NegotiateMessage::Empty => Vec::new(),
+        }
+    }
+
+    fn empty() -> Self {
+        NegotiateMessage::Empty
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NegotiateMech {
+    Preferred,
+    Fallback,
+}
+
+// Both contexts exist from the start so anything set before the mechanism is known reaches either
+pub struct NegotiateAuthContext<P: AuthProvider, F: AuthProvider> {
+    selected: Option<NegotiateMech>,
+    preferred: P::Context,
+    fallback: F::Context,
+}
+
+impl<P: AuthProvider, F: AuthProvider> NegotiateAuthContext<P, F> {
+    pub fn selected(&self) -> Option<NegotiateMech> {
+        self.selected
+    }
+}
+
+impl<P: AuthProvider + 'static, F: AuthProvider + 'static> AuthContext for NegotiateAuthContext<P, F>
+    where F::Context: AuthContext<UserName=<P::Context as AuthContext>::UserName> {
+    type UserName = <P::Context as AuthContext>::UserName;
+
+    fn init() -> Self {
+        Self {
+            selected: None,
+            preferred: P::Context::init(),
+            fallback: F::Context::init(),
+        }
+    }
+
+    fn session_key(&self) -> &[u8] {
+        match self.selected {
+            Some(NegotiateMech::Preferred) => self.preferred.session_key(),
+            Some(NegotiateMech::Fallback) => self.fallback.session_key(),
+            None => &[],
+        }
+    }
+
+    fn user_name(&self) -> SMBResult<&Self::UserName> {
+        match self.selected {
+            Some(NegotiateMech::Preferred) => self.preferred.user_name(),
+            Some(NegotiateMech::Fallback) => self.fallback.user_name(),
+            None => Err(SMBError::server_error("No mechanism selected")),
+        }
+    }
+
+    fn set_channel_bindings(&mut self, channel_bindings: Option<Vec<u8>>) {
+        self.preferred.set_channel_bindings(channel_bindings.clone());
+        self.fallback.set_channel_bindings(channel_bindings);
+    }
+
+    fn select_mech(&mut self, mech: &[u8]) {
+        let mech = mech.to_vec();
+        self.selected = if P::mech_types().contains(&mech) {
+            Some(NegotiateMech::Preferred)
+        } else if F::mech_types().contains(&mech) {
+            Some(NegotiateMech::Fallback)
+        } else {
+            None
+        };
+        match self.selected {
+            Some(NegotiateMech::Preferred) => self.preferred.select_mech(&mech),
+            Some(NegotiateMech::Fallback) => self.fallback.select_mech(&mech),
+            None => {},
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use smb_core::error::SMBError;
+    use smb_core::nt_status::NTStatus;
+    use smb_core::{SMBParseResult, SMBResult};
+
+    use crate::util::auth::{AuthContext, AuthMessage, AuthProvider, User};
+    use crate::util::auth::negotiate::{NegotiateAuthContext, NegotiateAuthProvider, NegotiateMech, NegotiateMessage};
+    use crate::util::auth::ntlm::{NTLMAuthContext, NTLMAuthProvider, NTLMMessage, NTLMNegotiateFlags, NTLMNegotiateMessageBody};
+    use crate::util::auth::spnego::{SPNEGOToken, SPNEGOTokenInitBody};
+
+    pub(crate) const KERBEROS_OID: [u8; 9] = [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x12, 0x01, 0x02, 0x02];
+    pub(crate) const MS_KERBEROS_OID: [u8; 9] = [0x2a, 0x86, 0x48, 0x82, 0xf7, 0x12, 0x01, 0x02, 0x02];
+
+    // Stands in for a Kerberos provider, any ticket naming a user is good for that user
+    #[derive(Debug)]
+    pub(crate) struct TicketProvider;
+
+    pub(crate) struct Ticket(Vec<u8>);
+
+    #[derive(Default)]
+    pub(crate) struct TicketContext {
+        user_name: Option<String>,
+    }
+
+    impl AuthMessage for Ticket {
+        fn parse(data: &[u8]) -> SMBParseResult<&[u8], Self> {
+            Ok((&data[data.len()..], Ticket(data.to_vec())))
+        }
+
+        fn as_bytes(&self) -> Vec<u8> {
+            self.0.clone()
+        }
+
+        fn empty() -> Self {
+            Ticket(Vec::new())
+        }
+    }
+
+    impl AuthContext for TicketContext {
+        type UserName = String;
+
+        fn init() -> Self {
+            Self::default()
+        }
+
+        fn session_key(&self) -> &[u8] {
+            &[]
+        }
+
+        fn user_name(&self) -> SMBResult<&String> {
+            self.user_name.as_ref().ok_or(SMBError::server_error("No user name"))
+        }
+    }
+
+    impl AuthProvider for TicketProvider {
+        type Message = Ticket;
+        type Context = TicketContext;
+
+        fn get_oid() -> Vec<u8> {
+            MS_KERBEROS_OID.to_vec()
+        }
+
+        fn mech_types() -> Vec<Vec<u8>> {
+            vec![MS_KERBEROS_OID.to_vec(), KERBEROS_OID.to_vec()]
+        }
+
+        fn accept_security_context(&self, input_token: &Ticket, context: &mut TicketContext) -> (NTStatus, Ticket) {
+            context.user_name = Some(String::from_utf8_lossy(&input_token.0).into_owned());
+            (NTStatus::StatusSuccess, Ticket::empty())
+        }
+    }
+
+    type Provider = NegotiateAuthProvider<TicketProvider, NTLMAuthProvider>;
+
+    fn provider() -> Provider {
+        NegotiateAuthProvider::new(TicketProvider, NTLMAuthProvider::new(vec![User::new("alice", "password")], false))
+    }
+
+    fn init_token<A: AuthProvider>(mech_types: Vec<Vec<u8>>, mech_token: Vec<u8>) -> SPNEGOToken<A> {
+        let mut init = SPNEGOTokenInitBody::<NTLMAuthProvider>::new().with_mech_types(mech_types);
+        init.mech_token = Some(mech_token);
+        SPNEGOToken::parse(&SPNEGOToken::Init(init).as_bytes(true)).unwrap().1
+    }
+
+    fn ntlm_negotiate() -> Vec<u8> {
+        NTLMNegotiateMessageBody::new(NTLMNegotiateFlags::UNICODE_ENCODING | NTLMNegotiateFlags::EXTENDED_SESSION_SECURITY).as_bytes()
+    }
+
+    #[test]
+    fn kerberos_is_taken_when_the_client_offers_it() {
+        let token = init_token::<Provider>(vec![KERBEROS_OID.to_vec(), NTLMAuthProvider::get_oid()], b"alice".to_vec());
+        let mut context = NegotiateAuthContext::<TicketProvider, NTLMAuthProvider>::init();
+        let (status, reply) = token.get_message(&provider(), &mut context).unwrap();
+        assert_eq!(status, NTStatus::StatusSuccess);
+        assert!(matches!(reply, NegotiateMessage::Preferred(_)));
+        assert_eq!(token.negotiated_mech(), Some(KERBEROS_OID.to_vec()));
+        assert_eq!(context.selected(), Some(NegotiateMech::Preferred));
+        assert_eq!(context.user_name().unwrap(), "alice");
+    }
+
+    #[test]
+    fn ntlm_only_clients_fall_back_to_ntlm() {
+        let token = init_token::<Provider>(vec![NTLMAuthProvider::get_oid()], ntlm_negotiate());
+        let mut context = NegotiateAuthContext::<TicketProvider, NTLMAuthProvider>::init();
+        let (status, reply) = token.get_message(&provider(), &mut context).unwrap();
+        assert_eq!(status, NTStatus::MoreProcessingRequired);
+        assert!(matches!(reply, NegotiateMessage::Fallback(NTLMMessage::Challenge(_))));
+        assert_eq!(context.selected(), Some(NegotiateMech::Fallback));
+    }
+
+    #[test]
+    fn an_optimistic_token_for_an_unsupported_mech_is_not_used() {
+        let token = init_token::<NTLMAuthProvider>(vec![MS_KERBEROS_OID.to_vec(), NTLMAuthProvider::get_oid()], b"alice".to_vec());
+        let mut context = NTLMAuthContext::init();
+        let provider = NTLMAuthProvider::new(vec![User::new("alice", "password")], false);
+        let (status, reply) = token.get_message(&provider, &mut context).unwrap();
+        assert_eq!(status, NTStatus::MoreProcessingRequired);
+        assert!(reply.as_bytes().is_empty());
+        assert_eq!(token.negotiated_mech(), Some(NTLMAuthProvider::get_oid()));
+    }
+
+    #[test]
+    fn no_common_mech_is_refused() {
+        let token = init_token::<NTLMAuthProvider>(vec![KERBEROS_OID.to_vec()], b"alice".to_vec());
+        let mut context = NTLMAuthContext::init();
+        let provider = NTLMAuthProvider::new(vec![], false);
+        let result = token.get_message(&provider, &mut context);
+        assert!(matches!(result, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::NotSupported));
+    }
+}
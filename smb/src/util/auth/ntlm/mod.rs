@@ -1,5 +1,6 @@
 pub use ntlm_auth_provider::*;
 pub use ntlm_authenticate_message::*;
+pub use ntlm_av_pair::*;
 pub use ntlm_challenge_message::*;
 pub use ntlm_message::*;
 pub use ntlm_negotiate_message::*;
@@ -9,4 +10,5 @@ mod ntlm_message;
 mod ntlm_negotiate_message;
 mod ntlm_challenge_message;
 mod ntlm_authenticate_message;
+mod ntlm_av_pair;
 
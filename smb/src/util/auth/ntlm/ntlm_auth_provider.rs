@@ -5,22 +5,38 @@ use smb_core::nt_status::NTStatus;
 use smb_core::SMBResult;
 
 use crate::util::auth::{AuthContext, AuthProvider};
+use crate::util::auth::ntlm::ntlm_av_pair::{AvId, AvPair};
 use crate::util::auth::ntlm::ntlm_message::NTLMMessage;
 use crate::util::auth::user::User;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct NTLMAuthProvider {
     accepted_users: Vec<User>,
-    guest_supported: bool
+    guest_supported: bool,
+    computer_name: String,
+    domain_name: String,
 }
 
 impl NTLMAuthProvider {
     pub fn new(accepted_users: Vec<User>, guest_supported: bool) -> Self {
         Self {
             accepted_users,
-            guest_supported
+            guest_supported,
+            computer_name: "fakeserver".into(),
+            domain_name: "fakeserver".into(),
         }
     }
+
+    // Advertised to clients in the challenge's target info
+    pub fn with_computer_name<S: Into<String>>(mut self, computer_name: S) -> Self {
+        self.computer_name = computer_name.into();
+        self
+    }
+
+    pub fn with_domain_name<S: Into<String>>(mut self, domain_name: S) -> Self {
+        self.domain_name = domain_name.into();
+        self
+    }
 }
 
 impl AuthProvider for NTLMAuthProvider {
@@ -34,7 +50,7 @@ impl AuthProvider for NTLMAuthProvider {
     fn accept_security_context(&self, input_message: &NTLMMessage, context: &mut NTLMAuthContext) -> (NTStatus, NTLMMessage) {
         match input_message {
             NTLMMessage::Negotiate(x) => {
-                let (status, challenge) = x.get_challenge_response();
+                let (status, challenge) = x.get_challenge_response(&self.computer_name, &self.domain_name);
                 context.server_challenge = (*challenge.server_challenge()).into();
                 (status, NTLMMessage::Challenge(challenge))
             },
@@ -65,6 +81,8 @@ pub struct NTLMAuthContext {
     pub(crate) guest: Option<bool>,
     pub(crate) session_key: Vec<u8>,
     pub(crate) server_challenge: Vec<u8>,
+    pub(crate) client_target_info: Vec<AvPair>,
+    pub(crate) channel_bindings: Option<Vec<u8>>,
 }
 
 impl NTLMAuthContext {
@@ -77,6 +95,19 @@ impl NTLMAuthContext {
             guest: None,
             session_key: Vec::new(),
             server_challenge: Vec::new(),
+            client_target_info: Vec::new(),
+            channel_bindings: None,
+        }
+    }
+
+    // Extended Protection (MS-NLMP 3.2.5.1.2): a binding the client sent has to match the one the transport gives us
+    pub(crate) fn channel_bindings_match(&self) -> bool {
+        let Some(channel_bindings) = &self.channel_bindings else {
+            return true;
+        };
+        match AvPair::find(&self.client_target_info, AvId::ChannelBindings) {
+            Some(pair) if pair.value().iter().any(|byte| *byte != 0) => pair == &AvPair::channel_bindings(channel_bindings),
+            _ => true,
         }
     }
 }
@@ -101,4 +132,47 @@ impl AuthContext for NTLMAuthContext {
     fn user_name(&self) -> SMBResult<&Self::UserName> {
         self.user_name.as_ref().ok_or(SMBError::server_error("No user name"))
     }
+
+    fn set_channel_bindings(&mut self, channel_bindings: Option<Vec<u8>>) {
+        self.channel_bindings = channel_bindings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::auth::AuthContext;
+    use crate::util::auth::ntlm::{AvId, AvPair, NTLMAuthContext};
+
+    const TRANSPORT_BINDINGS: &[u8] = b"tls-server-end-point:0123456789abcdef";
+
+    fn context_with(client_target_info: Vec<AvPair>, channel_bindings: Option<&[u8]>) -> NTLMAuthContext {
+        let mut context = NTLMAuthContext::new();
+        context.client_target_info = client_target_info;
+        context.set_channel_bindings(channel_bindings.map(<[u8]>::to_vec));
+        context
+    }
+
+    #[test]
+    fn matching_channel_bindings_are_accepted() {
+        let context = context_with(vec![AvPair::channel_bindings(TRANSPORT_BINDINGS)], Some(TRANSPORT_BINDINGS));
+        assert!(context.channel_bindings_match());
+    }
+
+    #[test]
+    fn mismatching_channel_bindings_are_rejected() {
+        let context = context_with(vec![AvPair::channel_bindings(b"tls-server-end-point:other")], Some(TRANSPORT_BINDINGS));
+        assert!(!context.channel_bindings_match());
+    }
+
+    #[test]
+    fn channel_bindings_are_only_checked_when_both_sides_have_one() {
+        let unbound = context_with(vec![AvPair::new(AvId::ChannelBindings, vec![0; 16])], Some(TRANSPORT_BINDINGS));
+        assert!(unbound.channel_bindings_match());
+
+        let absent = context_with(vec![AvPair::name(AvId::NbComputerName, "CLIENT")], Some(TRANSPORT_BINDINGS));
+        assert!(absent.channel_bindings_match());
+
+        let plain_transport = context_with(vec![AvPair::channel_bindings(b"tls-server-end-point:other")], None);
+        assert!(plain_transport.channel_bindings_match());
+    }
 }
\ No newline at end of file
@@ -4,15 +4,29 @@ use nom::combinator::{map, map_res};
 use nom::IResult;
 use nom::number::complete::le_u32;
 use nom::sequence::tuple;
+use rand::RngCore;
+use rand::rngs::ThreadRng;
 use rc4::{Key, Rc4, StreamCipher};
 use rc4::consts::U16;
 use serde::{Deserialize, Serialize};
 
+use smb_core::error::SMBError;
+use smb_core::SMBResult;
+
+use crate::byte_helper::{u16_to_bytes, u32_to_bytes};
+use crate::protocol::body::filetime::FileTime;
 use crate::util::auth::ntlm::ntlm_auth_provider::NTLMAuthContext;
-use crate::util::auth::ntlm::ntlm_message::{NTLMNegotiateFlags, parse_ntlm_buffer_fields};
+use crate::util::auth::ntlm::ntlm_av_pair::{AvPair, utf16_bytes};
+use crate::util::auth::ntlm::ntlm_challenge_message::NTLMChallengeMessageBody;
+use crate::util::auth::ntlm::ntlm_message::{get_buffer, NTLMNegotiateFlags, ntlm_buffer_fields, parse_ntlm_buffer_fields};
 use crate::util::auth::user::User;
 use crate::util::crypto::ntlm_v1_extended::authenticate_v1_extended;
-use crate::util::crypto::ntlm_v2::authenticate_v2;
+use crate::util::crypto::ntlm_v2::{authenticate_v2, client_response_v2};
+
+// NTProofStr followed by the fixed NTLMv2_CLIENT_CHALLENGE fields
+const NTLM_V2_AV_PAIRS_OFFSET: usize = 44;
+// Fixed part of the authenticate message, up to and including the MIC
+const AUTHENTICATE_HEADER_LEN: u32 = 88;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct NTLMAuthenticateMessageBody {
@@ -28,6 +42,43 @@ pub struct NTLMAuthenticateMessageBody {
 }
 
 impl NTLMAuthenticateMessageBody {
+    // Client side (MS-NLMP 3.1.5.1.2): answers a challenge with an NTLMv2 response and returns the session key both ends settle on
+    pub fn for_challenge(challenge: &NTLMChallengeMessageBody, domain_name: &str, user_name: &str, password: &str) -> SMBResult<(Self, Vec<u8>)> {
+        let mut rng = ThreadRng::default();
+        let mut client_challenge = [0; 8];
+        rng.fill_bytes(&mut client_challenge);
+        let time = <[u8; 8]>::try_from(FileTime::now().as_bytes())
+            .map_err(|_| SMBError::crypto_error("Invalid timestamp length"))?;
+        let target_info = AvPair::list_as_bytes(challenge.target_info());
+        let (nt_challenge_response, session_base_key) = client_response_v2(domain_name, user_name, password, challenge.server_challenge(), &client_challenge, &time, &target_info)?;
+
+        let negotiate_flags = challenge.negotiate_flags();
+        let mut exported_session_key = [0; 16];
+        rng.fill_bytes(&mut exported_session_key);
+        let mut encrypted_session_key = vec![0; exported_session_key.len()];
+        Rc4::new(Key::<U16>::from_slice(&session_base_key))
+            .apply_keystream_b2b(&exported_session_key, &mut encrypted_session_key)
+            .map_err(|_| SMBError::crypto_error("Invalid session key length"))?;
+        let session_key = match negotiate_flags.intersects(NTLMNegotiateFlags::SIGN | NTLMNegotiateFlags::SEAL) {
+            true => exported_session_key.to_vec(),
+            false => session_base_key,
+        };
+
+        let message = Self {
+            signature: "NTLMSSP\0".into(),
+            negotiate_flags,
+            domain_name: domain_name.into(),
+            user_name: user_name.into(),
+            work_station: String::new(),
+            // Zeroed so the NT response alone is checked as NTLMv2
+            lm_challenge_response: vec![0; 24],
+            nt_challenge_response,
+            encrypted_session_key,
+            mic: vec![0; 16],
+        };
+        Ok((message, session_key))
+    }
+
     pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
         tuple((
             map_res(take(8_usize), |s: &[u8]| String::from_utf8(s.to_vec())),
@@ -99,7 +150,42 @@ impl NTLMAuthenticateMessageBody {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        Vec::new()
+        let domain_name = utf16_bytes(&self.domain_name);
+        let user_name = utf16_bytes(&self.user_name);
+        let work_station = utf16_bytes(&self.work_station);
+        let domain_name_offset = AUTHENTICATE_HEADER_LEN;
+        let user_name_offset = domain_name_offset + domain_name.len() as u32;
+        let work_station_offset = user_name_offset + user_name.len() as u32;
+        let lm_offset = work_station_offset + work_station.len() as u32;
+        let nt_offset = lm_offset + self.lm_challenge_response.len() as u32;
+        let session_key_offset = nt_offset + self.nt_challenge_response.len() as u32;
+        [
+            self.signature.as_bytes(), // 0 - 8
+            &u32_to_bytes(0x03), // 8 - 12
+            &ntlm_buffer_fields(self.lm_challenge_response.len(), lm_offset), // 12 - 20
+            &ntlm_buffer_fields(self.nt_challenge_response.len(), nt_offset), // 20 - 28
+            &ntlm_buffer_fields(domain_name.len(), domain_name_offset), // 28 - 36
+            &ntlm_buffer_fields(user_name.len(), user_name_offset), // 36 - 44
+            &ntlm_buffer_fields(work_station.len(), work_station_offset), // 44 - 52
+            &ntlm_buffer_fields(self.encrypted_session_key.len(), session_key_offset), // 52 - 60
+            &u32_to_bytes(self.negotiate_flags.bits()), // 60 - 64
+            &[6, 1], // NTLM major minor
+            &u16_to_bytes(7600), // NTLM build
+            &[0, 0, 0, 15], // NTLM current revision
+            &self.mic, // 72 - 88
+            &domain_name,
+            &user_name,
+            &work_station,
+            &self.lm_challenge_response,
+            &self.nt_challenge_response,
+            &self.encrypted_session_key,
+        ].concat()
+    }
+
+    // The AV pairs a client echoes back inside its NTLMv2 response (MS-NLMP 2.2.2.7)
+    pub fn client_target_info(&self) -> Option<Vec<AvPair>> {
+        let pairs = self.nt_challenge_response.get(NTLM_V2_AV_PAIRS_OFFSET..)?;
+        AvPair::parse_list(pairs).ok().map(|(_, pairs)| pairs)
     }
 }
 
@@ -115,6 +201,10 @@ impl NTLMAuthenticateMessageBody {
         context.work_station = Some(self.work_station.clone());
 
         context.version = Some("6.1.7200".into()); // TODO FIX
+        context.client_target_info = self.client_target_info().unwrap_or_default();
+        if !context.channel_bindings_match() {
+            return 1;
+        }
         println!("flags: {:?}, item: {:?}", self.negotiate_flags, &self);
         if self.negotiate_flags.contains(NTLMNegotiateFlags::ANONYMOUS) {
             return if guest_supported {
@@ -173,10 +263,37 @@ impl NTLMAuthenticateMessageBody {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use crate::util::auth::ntlm::{AvId, AvPair, NTLMAuthContext, NTLMAuthenticateMessageBody, NTLMChallengeMessageBody, NTLMNegotiateFlags};
+    use crate::util::auth::User;
 
-fn get_buffer(length: u16, offset: u32, buffer: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    let (remaining, slice) = take(offset as usize)(buffer)
-        .and_then(|(remaining, _)| take(length as usize)(remaining))?;
-    Ok((remaining, slice.to_vec()))
-}
+    fn challenge() -> NTLMChallengeMessageBody {
+        let flags = NTLMNegotiateFlags::UNICODE_ENCODING | NTLMNegotiateFlags::EXTENDED_SESSION_SECURITY
+            | NTLMNegotiateFlags::KEY_EXCHANGE | NTLMNegotiateFlags::SIGN | NTLMNegotiateFlags::TARGET_INFO;
+        NTLMChallengeMessageBody::new("SERVER".into(), flags, vec![AvPair::name(AvId::NbComputerName, "SERVER")])
+    }
+
+    #[test]
+    fn client_authenticate_message_is_accepted_by_the_server() {
+        let challenge = challenge();
+        let (message, session_key) = NTLMAuthenticateMessageBody::for_challenge(&challenge, "WORKGROUP", "alice", "password").unwrap();
+        let (_, parsed) = NTLMAuthenticateMessageBody::parse(&message.as_bytes()).unwrap();
+        assert_eq!(parsed, message);
+        assert_eq!(parsed.client_target_info().unwrap(), challenge.target_info());
 
+        let mut context = NTLMAuthContext::new();
+        context.server_challenge = challenge.server_challenge().to_vec();
+        assert_eq!(parsed.authenticate(&mut context, &[User::new("alice", "password")], false), 0);
+        assert_eq!(context.session_key, session_key);
+    }
+
+    #[test]
+    fn wrong_password_fails_authentication() {
+        let challenge = challenge();
+        let (message, _) = NTLMAuthenticateMessageBody::for_challenge(&challenge, "WORKGROUP", "alice", "nope").unwrap();
+        let mut context = NTLMAuthContext::new();
+        context.server_challenge = challenge.server_challenge().to_vec();
+        assert_eq!(message.authenticate(&mut context, &[User::new("alice", "password")], false), 1);
+    }
+}
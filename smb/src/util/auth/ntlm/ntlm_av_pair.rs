@@ -0,0 +1,156 @@
+use std::string::FromUtf16Error;
+
+use digest::Digest;
+use md5::Md5;
+use nom::bytes::complete::take;
+use nom::IResult;
+use nom::number::complete::le_u16;
+use num_enum::TryFromPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::byte_helper::{u16_to_bytes, u32_to_bytes};
+
+// AV_PAIR ids from MS-NLMP 2.2.2.1
+#[repr(u16)]
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone, Copy, TryFromPrimitive)]
+pub enum AvId {
+    EOL = 0x00,
+    NbComputerName = 0x01,
+    NbDomainName = 0x02,
+    DnsComputerName = 0x03,
+    DnsDomainName = 0x04,
+    DnsTreeName = 0x05,
+    Flags = 0x06,
+    Timestamp = 0x07,
+    SingleHost = 0x08,
+    TargetName = 0x09,
+    ChannelBindings = 0x0A,
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
+pub struct AvPair {
+    id: AvId,
+    value: Vec<u8>,
+}
+
+impl AvPair {
+    pub fn new(id: AvId, value: Vec<u8>) -> Self {
+        Self { id, value }
+    }
+
+    // Name pairs carry their value as UTF-16LE without a terminator
+    pub fn name(id: AvId, name: &str) -> Self {
+        Self::new(id, utf16_bytes(name))
+    }
+
+    // MsvAvChannelBindings holds the MD5 of a gss_channel_bindings_struct with only application data set
+    pub fn channel_bindings(application_data: &[u8]) -> Self {
+        let hash = Md5::new()
+            .chain_update([0; 16])
+            .chain_update(u32_to_bytes(application_data.len() as u32))
+            .chain_update(application_data)
+            .finalize();
+        Self::new(AvId::ChannelBindings, hash.to_vec())
+    }
+
+    pub fn id(&self) -> AvId {
+        self.id
+    }
+
+    pub fn value(&self) -> &[u8] {
+        &self.value
+    }
+
+    pub fn value_string(&self) -> Option<String> {
+        utf16_string(&self.value).ok()
+    }
+
+    // Reads pairs up to MsvAvEOL, dropping ids this implementation doesn't know about
+    pub fn parse_list(bytes: &[u8]) -> IResult<&[u8], Vec<AvPair>> {
+        let mut pairs = Vec::new();
+        let mut remaining = bytes;
+        loop {
+            let (rest, id) = le_u16(remaining)?;
+            let (rest, length) = le_u16(rest)?;
+            let (rest, value) = take(length as usize)(rest)?;
+            remaining = rest;
+            match AvId::try_from(id) {
+                Ok(AvId::EOL) => break,
+                Ok(id) => pairs.push(AvPair::new(id, value.to_vec())),
+                Err(_) => continue,
+            }
+        }
+        Ok((remaining, pairs))
+    }
+
+    pub fn list_as_bytes(pairs: &[AvPair]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for pair in pairs.iter().filter(|pair| pair.id != AvId::EOL) {
+            bytes.extend_from_slice(&u16_to_bytes(pair.id as u16));
+            bytes.extend_from_slice(&u16_to_bytes(pair.value.len() as u16));
+            bytes.extend_from_slice(&pair.value);
+        }
+        bytes.extend_from_slice(&u16_to_bytes(AvId::EOL as u16));
+        bytes.extend_from_slice(&u16_to_bytes(0));
+        bytes
+    }
+
+    pub fn find(pairs: &[AvPair], id: AvId) -> Option<&AvPair> {
+        pairs.iter().find(|pair| pair.id == id)
+    }
+}
+
+pub(crate) fn utf16_bytes(value: &str) -> Vec<u8> {
+    value.encode_utf16()
+        .flat_map(u16_to_bytes)
+        .collect()
+}
+
+pub(crate) fn utf16_string(bytes: &[u8]) -> Result<String, FromUtf16Error> {
+    let units: Vec<u16> = bytes.chunks_exact(2)
+        .map(|chunk| u16::from_le_bytes([chunk[0], chunk[1]]))
+        .collect();
+    String::from_utf16(&units)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::auth::ntlm::{AvId, AvPair, NTLMChallengeMessageBody, NTLMNegotiateFlags};
+
+    #[test]
+    fn av_pairs_round_trip_and_skip_unknown_ids() {
+        let pairs = vec![
+            AvPair::name(AvId::NbComputerName, "SERVER"),
+            AvPair::name(AvId::DnsDomainName, "example.com"),
+            AvPair::new(AvId::Flags, vec![0x02, 0, 0, 0]),
+        ];
+        let bytes = AvPair::list_as_bytes(&pairs);
+        let (remaining, parsed) = AvPair::parse_list(&bytes).unwrap();
+        assert!(remaining.is_empty());
+        assert_eq!(parsed, pairs);
+        assert_eq!(parsed[1].value_string().as_deref(), Some("example.com"));
+
+        let with_unknown = [&[0x20, 0x00, 0x02, 0x00, 0xAA, 0xBB][..], &bytes].concat();
+        let (_, parsed) = AvPair::parse_list(&with_unknown).unwrap();
+        assert_eq!(parsed, pairs);
+
+        assert!(AvPair::parse_list(&bytes[..(bytes.len() - 4)]).is_err());
+    }
+
+    #[test]
+    fn challenge_target_info_parses_back() {
+        let target_info = vec![
+            AvPair::name(AvId::NbComputerName, "SERVER"),
+            AvPair::name(AvId::NbDomainName, "WORKGROUP"),
+            AvPair::name(AvId::DnsComputerName, "server.example.com"),
+            AvPair::name(AvId::DnsDomainName, "example.com"),
+        ];
+        let flags = NTLMNegotiateFlags::TARGET_INFO | NTLMNegotiateFlags::UNICODE_ENCODING;
+        let challenge = NTLMChallengeMessageBody::new("SERVER".into(), flags, target_info.clone());
+
+        let (_, parsed) = NTLMChallengeMessageBody::parse(&challenge.as_bytes()).unwrap();
+        assert_eq!(parsed, challenge);
+        assert_eq!(parsed.target_info(), target_info.as_slice());
+        assert_eq!(AvPair::find(parsed.target_info(), AvId::DnsDomainName).and_then(AvPair::value_string).as_deref(), Some("example.com"));
+    }
+}
@@ -1,10 +1,18 @@
+use nom::bytes::complete::take;
+use nom::combinator::{map, map_res};
 use nom::IResult;
+use nom::number::complete::le_u32;
+use nom::sequence::tuple;
 use rand::RngCore;
 use rand::rngs::ThreadRng;
 use serde::{Deserialize, Serialize};
 
 use crate::byte_helper::{u16_to_bytes, u32_to_bytes};
-use crate::util::auth::ntlm::ntlm_message::NTLMNegotiateFlags;
+use crate::util::auth::ntlm::ntlm_av_pair::{AvPair, utf16_bytes, utf16_string};
+use crate::util::auth::ntlm::ntlm_message::{get_buffer, NTLMNegotiateFlags, parse_ntlm_buffer_fields};
+
+// Fixed part of the challenge message, including the version field
+const CHALLENGE_HEADER_LEN: u32 = 56;
 
 #[derive(Debug, Deserialize, Serialize, PartialEq, Eq, Clone)]
 pub struct NTLMChallengeMessageBody {
@@ -12,10 +20,11 @@ pub struct NTLMChallengeMessageBody {
     target_name: String,
     negotiate_flags: NTLMNegotiateFlags,
     server_challenge: [u8; 8],
+    target_info: Vec<AvPair>,
 }
 
 impl NTLMChallengeMessageBody {
-    pub fn new(target_name: String, negotiate_flags: NTLMNegotiateFlags) -> Self {
+    pub fn new(target_name: String, negotiate_flags: NTLMNegotiateFlags, target_info: Vec<AvPair>) -> Self {
         let mut server_challenge = [0; 8];
         ThreadRng::default().fill_bytes(&mut server_challenge);
         NTLMChallengeMessageBody {
@@ -23,42 +32,55 @@ impl NTLMChallengeMessageBody {
             target_name,
             negotiate_flags,
             server_challenge,
+            target_info,
         }
     }
 
     pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
-        todo!()
+        let (_, (signature, _, target_name_info, negotiate_flags, server_challenge, _, target_info_info)) = tuple((
+            map_res(take(8_usize), |s: &[u8]| String::from_utf8(s.to_vec())),
+            take(4_usize),
+            parse_ntlm_buffer_fields,
+            map(le_u32, NTLMNegotiateFlags::from_bits_truncate),
+            map_res(take(8_usize), <[u8; 8]>::try_from),
+            take(8_usize),
+            parse_ntlm_buffer_fields,
+        ))(bytes)?;
+        let (_, target_name) = map_res(
+            |bytes| get_buffer(target_name_info.0, target_name_info.1, bytes),
+            |name| utf16_string(&name),
+        )(bytes)?;
+        let (remaining, target_info) = get_buffer(target_info_info.0, target_info_info.1, bytes)?;
+        let (_, target_info) = AvPair::parse_list(&target_info)
+            .map_err(|_| nom::Err::Error(nom::error::Error::new(bytes, nom::error::ErrorKind::Verify)))?;
+        Ok((remaining, Self {
+            signature,
+            target_name,
+            negotiate_flags,
+            server_challenge,
+            target_info,
+        }))
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut name = Vec::new();
-        let fakeserver: Vec<u16> = str::encode_utf16("fakeserver").collect();
-        for i in fakeserver.iter() {
-            let bytes = u16_to_bytes(*i);
-            name.push(bytes[0]);
-            name.push(bytes[1]);
-        }
+        let name = utf16_bytes(&self.target_name);
+        let target_info = AvPair::list_as_bytes(&self.target_info);
+        let target_info_offset = CHALLENGE_HEADER_LEN + name.len() as u32;
         [
             self.signature.as_bytes(), // 0 - 8
             &u32_to_bytes(0x02), // 8 - 12
-            &u16_to_bytes(20), &u16_to_bytes(20), // 12 - 16
-            &u32_to_bytes(56), // 16 - 20
+            &u16_to_bytes(name.len() as u16), &u16_to_bytes(name.len() as u16), // 12 - 16
+            &u32_to_bytes(CHALLENGE_HEADER_LEN), // 16 - 20
             &u32_to_bytes(self.negotiate_flags.bits()), // 20 - 24
             &self.server_challenge, // 24 - 32
             &[0; 8], // 32 - 40
-            &u16_to_bytes(52), &u16_to_bytes(52), // 40-44
-            &u32_to_bytes(76), // 44 - 48
+            &u16_to_bytes(target_info.len() as u16), &u16_to_bytes(target_info.len() as u16), // 40-44
+            &u32_to_bytes(target_info_offset), // 44 - 48
             &[6, 1], // NTLM major minor
             &u16_to_bytes(7600), // NTLM build
             &[0, 0, 0, 15], // NTLM current revision
             &name,
-            &u16_to_bytes(1),
-            &u16_to_bytes(20),
-            &*name,
-            &u16_to_bytes(2),
-            &u16_to_bytes(20),
-            &name,
-            &[0; 4],
+            &target_info,
         ].concat()
     }
 }
@@ -72,7 +94,15 @@ impl NTLMChallengeMessageBody {
         &self.target_name
     }
 
+    pub fn negotiate_flags(&self) -> NTLMNegotiateFlags {
+        self.negotiate_flags
+    }
+
     pub fn server_challenge(&self) -> &[u8; 8] {
         &self.server_challenge
     }
-}
\ No newline at end of file
+
+    pub fn target_info(&self) -> &[AvPair] {
+        &self.target_info
+    }
+}
@@ -7,6 +7,7 @@ use serde::{Deserialize, Serialize};
 use smb_core::error::SMBError;
 use smb_core::SMBParseResult;
 
+use crate::byte_helper::{u16_to_bytes, u32_to_bytes};
 use crate::util::auth::AuthMessage;
 use crate::util::auth::ntlm::ntlm_authenticate_message::NTLMAuthenticateMessageBody;
 use crate::util::auth::ntlm::ntlm_challenge_message::NTLMChallengeMessageBody;
@@ -23,30 +24,18 @@ pub enum NTLMMessage {
 impl AuthMessage for NTLMMessage {
     fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> {
         let (_, msg_type) = take::<usize, &[u8], nom::error::Error<&[u8]>>(8_usize)(bytes)
-            .and_then(|(remaining, _)| le_u32(remaining))
-            .map_err(|e| {
-                SMBError::parse_error(e.to_owned())
-            })?;
+            .and_then(|(remaining, _)| le_u32(remaining))?;
         match msg_type {
             0x01 => {
-                let (remaining, body) = NTLMNegotiateMessageBody::parse(bytes)
-                    .map_err(|e| {
-                        SMBError::parse_error(e.to_owned())
-                    })?;
+                let (remaining, body) = NTLMNegotiateMessageBody::parse(bytes)?;
                 Ok((remaining, NTLMMessage::Negotiate(body)))
             },
             0x02 => {
-                let (remaining, body) = NTLMChallengeMessageBody::parse(bytes)
-                    .map_err(|e| {
-                        SMBError::parse_error(e.to_owned())
-                    })?;
+                let (remaining, body) = NTLMChallengeMessageBody::parse(bytes)?;
                 Ok((remaining, NTLMMessage::Challenge(body)))
             },
             0x03 => {
-                let (remaining, body) = NTLMAuthenticateMessageBody::parse(bytes)
-                    .map_err(|e| {
-                        SMBError::parse_error(e.to_owned())
-                    })?;
+                let (remaining, body) = NTLMAuthenticateMessageBody::parse(bytes)?;
                 Ok((remaining, NTLMMessage::Authenticate(body)))
             },
             _ => Err(SMBError::parse_error("Invalid message type"))
@@ -100,3 +89,18 @@ pub(crate) fn parse_ntlm_buffer_fields(bytes: &[u8]) -> IResult<&[u8], (u16, u32
     let (remaining, buffer_offset) = take(2_usize)(remaining).and_then(|(remaining, _)| le_u32(remaining))?;
     Ok((remaining, (length, buffer_offset)))
 }
+
+// Length, maximum length and offset of a payload buffer, the inverse of parse_ntlm_buffer_fields
+pub(crate) fn ntlm_buffer_fields(length: usize, offset: u32) -> Vec<u8> {
+    [
+        &u16_to_bytes(length as u16)[0..],
+        &u16_to_bytes(length as u16),
+        &u32_to_bytes(offset),
+    ].concat()
+}
+
+pub(crate) fn get_buffer(length: u16, offset: u32, buffer: &[u8]) -> IResult<&[u8], Vec<u8>> {
+    let (remaining, slice) = take(offset as usize)(buffer)
+        .and_then(|(remaining, _)| take(length as usize)(remaining))?;
+    Ok((remaining, slice.to_vec()))
+}
@@ -7,6 +7,8 @@ use serde::{Deserialize, Serialize};
 
 use smb_core::nt_status::NTStatus;
 
+use crate::byte_helper::u32_to_bytes;
+use crate::util::auth::ntlm::ntlm_av_pair::{AvId, AvPair};
 use crate::util::auth::ntlm::ntlm_challenge_message::NTLMChallengeMessageBody;
 use crate::util::auth::ntlm::ntlm_message::NTLMNegotiateFlags;
 
@@ -19,6 +21,16 @@ pub struct NTLMNegotiateMessageBody {
 }
 
 impl NTLMNegotiateMessageBody {
+    // Client side: no domain or workstation is supplied, so both fields stay empty
+    pub fn new(negotiate_flags: NTLMNegotiateFlags) -> Self {
+        Self {
+            signature: "NTLMSSP\0".into(),
+            negotiate_flags,
+            domain_name: String::new(),
+            workstation: String::new(),
+        }
+    }
+
     pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
         map(
             tuple((
@@ -44,12 +56,18 @@ impl NTLMNegotiateMessageBody {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        [self.signature.as_bytes()].concat()
+        [
+            self.signature.as_bytes(), // 0 - 8
+            &u32_to_bytes(0x01), // 8 - 12
+            &u32_to_bytes(self.negotiate_flags.bits()), // 12 - 16
+            &[0; 8], // 16 - 24 domain name fields
+            &[0; 8], // 24 - 32 workstation fields
+        ].concat()
     }
 }
 
 impl NTLMNegotiateMessageBody {
-    pub fn get_challenge_response(&self) -> (NTStatus, NTLMChallengeMessageBody) {
+    pub fn get_challenge_response(&self, computer_name: &str, domain_name: &str) -> (NTStatus, NTLMChallengeMessageBody) {
         fn add_if_present(
             flags: &mut NTLMNegotiateFlags,
             original: &NTLMNegotiateFlags,
@@ -121,9 +139,14 @@ impl NTLMNegotiateMessageBody {
             NTLMNegotiateFlags::KEY_EXCHANGE,
         );
 
-        let target_name = "fakeserver";
+        let target_info = vec![
+            AvPair::name(AvId::NbComputerName, computer_name),
+            AvPair::name(AvId::NbDomainName, domain_name),
+            AvPair::name(AvId::DnsComputerName, computer_name),
+            AvPair::name(AvId::DnsDomainName, domain_name),
+        ];
 
-        (NTStatus::MoreProcessingRequired, NTLMChallengeMessageBody::new(target_name.into(), negotiate_flags))
+        (NTStatus::MoreProcessingRequired, NTLMChallengeMessageBody::new(computer_name.into(), negotiate_flags, target_info))
     }
 }
 
@@ -53,7 +53,7 @@ pub fn parse_length(buffer: &[u8]) -> IResult<&[u8], usize> {
     let (remaining, len) = le_u8(buffer)?;
     if len < 0x80 { return Ok((remaining, len as usize)); }
     let field_size = (len & 0x7f) as usize;
-    fold_many_m_n(field_size, field_size, le_u8, || 0_usize, |len, item| len * 256 + item as usize)(remaining)
+    fold_many_m_n(field_size, field_size, le_u8, || 0_usize, |len, item| len.saturating_mul(256).saturating_add(item as usize))(remaining)
 }
 
 pub fn parse_field_with_len(buffer: &[u8]) -> IResult<&[u8], &[u8]> {
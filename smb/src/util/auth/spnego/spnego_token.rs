@@ -9,7 +9,7 @@ use smb_core::{SMBParseResult, SMBResult};
 use smb_core::error::SMBError;
 use smb_core::nt_status::NTStatus;
 
-use crate::util::auth::{AuthMessage, AuthProvider};
+use crate::util::auth::{AuthContext, AuthMessage, AuthProvider};
 use crate::util::auth::spnego::{SPNEGOTokenInit2Body, SPNEGOTokenInitBody, SPNEGOTokenResponseBody};
 use crate::util::auth::spnego::der_utils::{APPLICATION_TAG, DER_ENCODING_OID_TAG, get_field_size, get_length, NEG_TOKEN_INIT_TAG, NEG_TOKEN_RESP_TAG, parse_field_with_len, SPNEGO_ID};
 
@@ -24,6 +24,13 @@ impl<A: AuthProvider> SPNEGOToken<A> {
     pub fn get_message(&self, auth_provider: &A, ctx: &mut A::Context) -> SMBResult<(NTStatus, A::Message)> {
         let result = match self {
             SPNEGOToken::Init(init_msg) => {
+                let (position, mech) = self.select_mech()
+                    .ok_or(SMBError::response_error(NTStatus::NotSupported))?;
+                ctx.select_mech(&mech);
+                // The optimistic token is for the client's first choice, any other mechanism starts with a fresh one
+                if position != 0 {
+                    return Ok((NTStatus::MoreProcessingRequired, A::Message::empty()));
+                }
                 let mech_token = init_msg.mech_token.as_ref().ok_or(SMBError::parse_error("Parse failure"))?;
                 let ntlm_msg =
                     A::Message::parse(mech_token).map_err(|_e| SMBError::parse_error("Parse failure"))?.1;
@@ -40,8 +47,28 @@ impl<A: AuthProvider> SPNEGOToken<A> {
 
         Ok(result)
     }
+
+    // The mechanism an initial token settles on, later tokens continue whichever one that was
+    pub fn negotiated_mech(&self) -> Option<Vec<u8>> {
+        self.select_mech().map(|(_, mech)| mech)
+    }
+
+    // RFC 4178 4.2.1: the first mechanism in the client's list that the provider takes. A client that sent
+    // no list is taken to be using the provider's own
+    fn select_mech(&self) -> Option<(usize, Vec<u8>)> {
+        let SPNEGOToken::Init(init_msg) = self else {
+            return None;
+        };
+        let Some(offered) = init_msg.mech_types() else {
+            return Some((0, A::get_oid()));
+        };
+        let supported = A::mech_types();
+        offered.iter()
+            .position(|mech| supported.contains(mech))
+            .map(|position| (position, offered[position].clone()))
+    }
     pub fn parse(bytes: &[u8]) -> SMBParseResult<&[u8], Self> {
-        Self::parse_inner(bytes).map_err(|e| SMBError::parse_error(e.to_owned()))
+        Ok(Self::parse_inner(bytes)?)
     }
     fn parse_inner(bytes: &[u8]) -> IResult<&[u8], Self> {
         println!("bytes: {:?},", bytes);
@@ -102,4 +129,21 @@ impl<A: AuthProvider> SPNEGOToken<A> {
             bytes.to_vec()
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use smb_core::error::SMBError;
+
+    use crate::util::auth::AuthMessage;
+    use crate::util::auth::ntlm::{NTLMAuthProvider, NTLMMessage};
+    use crate::util::auth::spnego::SPNEGOToken;
+
+    #[test]
+    fn truncated_session_setup_tokens_are_parse_errors() {
+        let spnego = SPNEGOToken::<NTLMAuthProvider>::parse(&[0x60]);
+        assert!(matches!(spnego, Err(SMBError::ParseError(_))));
+
+        let ntlm = NTLMMessage::parse(b"NTLMSSP\0\x01\0");
+        assert!(matches!(ntlm, Err(SMBError::ParseError(_))));
+    }
+}
@@ -23,8 +23,9 @@ impl<T: AuthProvider> Default for SPNEGOTokenInitBody<T> {
 }
 
 impl<T: AuthProvider> SPNEGOTokenInitBody<T> {
+    // Offers every mechanism the provider takes, so a server holding several advertises them all
     pub fn new() -> Self {
-        let mech_type_list = Some(vec![T::get_oid()]);
+        let mech_type_list = Some(T::mech_types());
         Self {
             mechanism: None,
             mech_type_list,
@@ -33,6 +34,16 @@ impl<T: AuthProvider> SPNEGOTokenInitBody<T> {
         }
     }
 
+    // Mechanisms in the client's order of preference, the optimistic mech token is for the first
+    pub fn with_mech_types(mut self, mech_types: Vec<Vec<u8>>) -> Self {
+        self.mech_type_list = Some(mech_types);
+        self
+    }
+
+    pub fn mech_types(&self) -> Option<&[Vec<u8>]> {
+        self.mech_type_list.as_deref()
+    }
+
     pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
         let (remaining, _) = parse_length(bytes)?;
         let (remaining, mut tag) = le_u8(remaining)?;
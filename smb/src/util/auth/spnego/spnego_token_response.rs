@@ -49,6 +49,16 @@ impl<T: AuthProvider> SPNEGOTokenResponseBody<T> {
             mech_list_mic: None,
         }
     }
+
+    // The first reply names the mechanism the server went with, even when it has no token for the client yet
+    pub fn with_supported_mech(mut self, supported_mech: Vec<u8>) -> Self {
+        self.supported_mech = Some(supported_mech);
+        self
+    }
+
+    pub fn supported_mech(&self) -> Option<&[u8]> {
+        self.supported_mech.as_deref()
+    }
 }
 
 impl<T: AuthProvider> SPNEGOTokenResponseBody<T> {
@@ -131,6 +141,8 @@ impl<T: AuthProvider> SPNEGOTokenResponseBody<T> {
         let (remaining, _) = parse_length(buffer)?;
         let (remaining, tag) = le_u8(remaining)?;
         if tag != DER_ENCODING_ENUM_TAG { return Err(Error(nom::error::Error::new(remaining, ErrorKind::Fail))) }
+        // The enumerated value carries its own length ahead of the single state byte
+        let (remaining, _) = parse_length(remaining)?;
         map_res(le_u8, NegotiateState::try_from)(remaining)
     }
 
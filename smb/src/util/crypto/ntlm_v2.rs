@@ -38,12 +38,24 @@ pub fn authenticate_v2(domain: &str, account: &str, password: &str, server_chall
     }
 }
 
+// The client's half of NTLMv2 (MS-NLMP 3.3.2): the NT challenge response and the session base key it yields
+pub fn client_response_v2(domain: &str, account: &str, password: &str, server_challenge: &[u8], client_challenge: &[u8; 8], time: &[u8; 8], target_info: &[u8]) -> SMBResult<(Vec<u8>, Vec<u8>)> {
+    // RespType/HiRespType, then the timestamp and challenge where the client structure carries them
+    let challenge_fields = [&[1, 1, 0, 0, 0, 0, 0, 0][0..], time, client_challenge].concat();
+    let (nt_response, _, nt_proof) = compute_ntlm_v2_response(server_challenge, &challenge_fields, target_info, password, account, domain)?;
+    let response_key_nt = ntowf_v2(password, account, domain)?;
+    let session_base_key = new_hmac_from_slice(&response_key_nt)?
+        .chain_update(nt_proof).finalize().into_bytes().to_vec();
+    Ok((nt_response, session_base_key))
+}
+
 fn compute_ntlm_v2_response(server_challenge: &[u8], client_challenge: &[u8], server_name: &[u8], password: &str, account: &str, domain: &str) -> SMBResult<(Vec<u8>, Vec<u8>, Vec<u8>)> {
+    // RespType and HiRespType lead the client structure, ahead of the timestamp and challenge
+    let resp_types = &client_challenge[0..2];
     let time = &client_challenge[8..16];
     let client_challenge = &client_challenge[16..24];
     let temp = [
-        &[client_challenge[0]][0..],
-        &[client_challenge[1]],
+        resp_types,
         &[0; 6],
         &time,
         // &[0; 8],
@@ -112,4 +124,24 @@ fn ntowf_v2(password: &str, user: &str, domain: &str) -> SMBResult<Vec<u8>> {
 
 fn new_hmac_from_slice(slice: &[u8]) -> SMBResult<Hmac<Md5>> {
     <Hmac<Md5>>::new_from_slice(slice).map_err(|_| SMBError::crypto_error("Invalid length for key"))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::crypto::ntlm_v2::{authenticate_v2, client_response_v2};
+
+    #[test]
+    fn client_response_verifies_against_the_server_check() {
+        let server_challenge = [1, 2, 3, 4, 5, 6, 7, 8];
+        let target_info = [0x01, 0x00, 0x02, 0x00, b'S', 0x00, 0x00, 0x00, 0x00, 0x00];
+        let (nt_response, session_base_key) = client_response_v2("DOMAIN", "user", "password", &server_challenge, &[9; 8], &[7; 8], &target_info).unwrap();
+        assert_eq!(&nt_response[16..18], &[1, 1]);
+
+        let (verified, server_key) = authenticate_v2("DOMAIN", "user", "password", &server_challenge, &[0; 24], &nt_response).unwrap();
+        assert!(verified);
+        assert_eq!(server_key, session_base_key);
+
+        let (verified, _) = authenticate_v2("DOMAIN", "user", "wrong", &server_challenge, &[0; 24], &nt_response).unwrap();
+        assert!(!verified);
+    }
+}
@@ -1,4 +1,6 @@
 pub mod auth;
+pub mod replay;
+pub mod rpc;
 pub(crate) mod as_bytes;
 pub(crate) mod crypto;
 pub(crate) mod flags_helper;
\ No newline at end of file
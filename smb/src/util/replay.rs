@@ -0,0 +1,160 @@
+use std::io::Read;
+
+use smb_core::error::SMBError;
+use smb_core::SMBResult;
+
+use crate::protocol::body::{LegacySMBBody, SMBBody};
+use crate::protocol::header::{LegacySMBHeader, SMBSyncHeader};
+use crate::protocol::message::{Message, SMBMessage};
+
+// Parses a raw capture of one direction of a connection, such as bytes dumped straight off a socket, back
+// into messages. Every Direct TCP frame is yielded in turn, along with each request chained inside it, so a
+// frame that fails to parse shows up as an error without stopping the replay
+pub fn replay_messages<R: Read>(reader: R) -> SMBReplay<R> {
+    SMBReplay {
+        reader,
+        frame: Vec::new(),
+        next_offset: None,
+        finished: false,
+    }
+}
+
+pub struct SMBReplay<R: Read> {
+    reader: R,
+    frame: Vec<u8>,
+    // Where the next chained message starts in the current frame
+    next_offset: Option<usize>,
+    finished: bool,
+}
+
+impl<R: Read> SMBReplay<R> {
+    // False once the capture ends cleanly between frames
+    fn read_frame(&mut self) -> SMBResult<bool> {
+        let mut prefix = [0_u8; 4];
+        let mut filled = 0;
+        while filled < prefix.len() {
+            match self.reader.read(&mut prefix[filled..])? {
+                0 if filled == 0 => return Ok(false),
+                0 => return Err(SMBError::payload_too_small(prefix.len(), filled)),
+                read => filled += read,
+            }
+        }
+        // Direct TCP is a zero byte then a 24-bit big endian length, MS-SMB2 2.1
+        if prefix[0] != 0 {
+            return Err(SMBError::parse_error("Frame does not start with a Direct TCP length prefix"));
+        }
+        self.frame.resize(u32::from_be_bytes(prefix) as usize, 0);
+        self.reader.read_exact(&mut self.frame)?;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for SMBReplay<R> {
+    type Item = SMBResult<SMBMessage<SMBSyncHeader, SMBBody>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        let offset = match self.next_offset.take() {
+            Some(offset) => offset,
+            None => match self.read_frame() {
+                Ok(true) => 0,
+                Ok(false) => {
+                    self.finished = true;
+                    return None;
+                },
+                // The framing can't be trusted past a short or malformed frame
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(e));
+                },
+            },
+        };
+        let message = parse_message(&self.frame[offset..]);
+        if let Ok(message) = &message {
+            let next_offset = offset + message.header.next_command as usize;
+            if message.header.next_command != 0 && next_offset < self.frame.len() {
+                self.next_offset = Some(next_offset);
+            }
+        }
+        Some(message)
+    }
+}
+
+// SMB1 negotiates, which open many captures, are converted the same way the server converts them
+fn parse_message(bytes: &[u8]) -> SMBResult<SMBMessage<SMBSyncHeader, SMBBody>> {
+    match SMBMessage::<SMBSyncHeader, SMBBody>::parse(bytes) {
+        Ok((_, message)) => Ok(message),
+        Err(e) => {
+            let Ok((_, legacy)) = SMBMessage::<LegacySMBHeader, LegacySMBBody>::parse(bytes) else {
+                return Err(e);
+            };
+            SMBMessage::<SMBSyncHeader, SMBBody>::from_legacy(legacy)
+                .ok_or(SMBError::parse_error("Invalid legacy body"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::body::dialect::SMBDialect;
+    use crate::protocol::body::SMBBody;
+    use crate::protocol::header::command_code::SMBCommandCode;
+    use crate::util::replay::replay_messages;
+
+    // A client's NEGOTIATE offering 2.0.2 through 3.0.2, then its first SESSION_SETUP carrying an SPNEGO
+    // wrapped NTLMSSP NEGOTIATE_MESSAGE, as read off the socket
+    const NEGOTIATE_AND_SESSION_SETUP: [u8; 278] = [
+        0x00, 0x00, 0x00, 0x6c, 0xfe, 0x53, 0x4d, 0x42, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xff, 0xfe, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x24, 0x00, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x7f, 0x00, 0x00, 0x00,
+        0x5a, 0x8c, 0x1e, 0x2f, 0x93, 0xb0, 0x4d, 0x7c, 0x8e, 0x21, 0xf4, 0xa6, 0xb9, 0xd0, 0xc3, 0x17,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x02, 0x10, 0x02, 0x00, 0x03, 0x02, 0x03,
+        0x00, 0x00, 0x00, 0xa2, 0xfe, 0x53, 0x4d, 0x42, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x01, 0x00, 0x1f, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0xff, 0xfe, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x19, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x58, 0x00, 0x4a, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x60, 0x48, 0x06, 0x06,
+        0x2b, 0x06, 0x01, 0x05, 0x05, 0x02, 0xa0, 0x3e, 0x30, 0x3c, 0xa0, 0x0e, 0x30, 0x0c, 0x06, 0x0a,
+        0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x02, 0x02, 0x0a, 0xa2, 0x2a, 0x04, 0x28, 0x4e, 0x54,
+        0x4c, 0x4d, 0x53, 0x53, 0x50, 0x00, 0x01, 0x00, 0x00, 0x00, 0x97, 0x82, 0x08, 0xe2, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0a, 0x00,
+        0x61, 0x4a, 0x00, 0x00, 0x00, 0x0f,
+    ];
+
+    #[test]
+    fn captured_negotiate_and_session_setup_replay() {
+        let messages = replay_messages(&NEGOTIATE_AND_SESSION_SETUP[..]).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2);
+
+        let negotiate = messages[0].as_ref().unwrap();
+        assert_eq!(negotiate.header.command, SMBCommandCode::Negotiate);
+        assert_eq!(negotiate.header.credits, 31);
+        let SMBBody::NegotiateRequest(body) = &negotiate.body else {
+            panic!("expected a negotiate request, got {:?}", negotiate.body);
+        };
+        assert_eq!(body.dialects, vec![SMBDialect::V2_0_2, SMBDialect::V2_1_0, SMBDialect::V3_0_0, SMBDialect::V3_0_2]);
+
+        let session_setup = messages[1].as_ref().unwrap();
+        assert_eq!(session_setup.header.command, SMBCommandCode::SessionSetup);
+        assert_eq!(session_setup.header.message_id, 1);
+        let SMBBody::SessionSetupRequest(body) = &session_setup.body else {
+            panic!("expected a session setup request, got {:?}", session_setup.body);
+        };
+        assert_eq!(body.buffer().len(), 74);
+        assert_eq!(&body.buffer()[34..42], b"NTLMSSP\0");
+    }
+
+    #[test]
+    fn truncated_capture_ends_with_an_error() {
+        let truncated = &NEGOTIATE_AND_SESSION_SETUP[..200];
+        let messages = replay_messages(truncated).collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].is_ok());
+        assert!(messages[1].is_err());
+    }
+}
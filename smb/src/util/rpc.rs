@@ -0,0 +1,391 @@
+use nom::bytes::complete::take;
+use nom::combinator::{map, map_res};
+use nom::IResult;
+use nom::multi::count;
+use nom::number::complete::{le_u16, le_u32, le_u8};
+use nom::sequence::tuple;
+use uuid::{uuid, Uuid};
+
+use crate::byte_helper::{u16_to_bytes, u32_to_bytes};
+
+pub const RPC_VERSION: u8 = 5;
+pub const RPC_HEADER_SIZE: usize = 16;
+// The largest fragment Windows servers advertise over SMB pipes
+pub const RPC_MAX_FRAGMENT: u16 = 4280;
+
+// PFC_FIRST_FRAG | PFC_LAST_FRAG, responses always fit in a single fragment
+const SINGLE_FRAGMENT: u8 = 0x03;
+// Little-endian integers, ASCII characters, IEEE floats
+const DATA_REPRESENTATION: [u8; 4] = [0x10, 0, 0, 0];
+
+pub const NDR_TRANSFER_SYNTAX: RPCSyntaxId = RPCSyntaxId {
+    uuid: uuid!("8a885d04-1ceb-11c9-9fe8-08002b104860"),
+    version: 2,
+};
+
+// Fault status for a call to an operation the interface doesn't implement
+pub const NCA_OP_RANGE_ERROR: u32 = 0x1C010002;
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RPCPacketType {
+    Request = 0,
+    Response = 2,
+    Fault = 3,
+    Bind = 11,
+    BindAck = 12,
+    BindNak = 13,
+}
+
+impl TryFrom<u8> for RPCPacketType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Request),
+            2 => Ok(Self::Response),
+            3 => Ok(Self::Fault),
+            11 => Ok(Self::Bind),
+            12 => Ok(Self::BindAck),
+            13 => Ok(Self::BindNak),
+            other => Err(other),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RPCSyntaxId {
+    pub uuid: Uuid,
+    pub version: u32,
+}
+
+impl RPCSyntaxId {
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                map_res(take(16_usize), Uuid::from_slice_le),
+                le_u32,
+            )),
+            |(uuid, version)| Self { uuid, version },
+        )(bytes)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [
+            &self.uuid.to_bytes_le()[..],
+            &u32_to_bytes(self.version),
+        ].concat()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RPCHeader {
+    pub packet_type: u8,
+    pub flags: u8,
+    pub frag_length: u16,
+    pub auth_length: u16,
+    pub call_id: u32,
+}
+
+impl RPCHeader {
+    pub fn new(packet_type: RPCPacketType, call_id: u32) -> Self {
+        Self {
+            packet_type: packet_type as u8,
+            flags: SINGLE_FRAGMENT,
+            frag_length: 0,
+            auth_length: 0,
+            call_id,
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((
+                le_u8,
+                le_u8,
+                le_u8,
+                le_u8,
+                take(4_usize),
+                le_u16,
+                le_u16,
+                le_u32,
+            )),
+            |(_, _, packet_type, flags, _, frag_length, auth_length, call_id)| Self {
+                packet_type,
+                flags,
+                frag_length,
+                auth_length,
+                call_id,
+            },
+        )(bytes)
+    }
+
+    pub fn packet_type(&self) -> Result<RPCPacketType, u8> {
+        RPCPacketType::try_from(self.packet_type)
+    }
+
+    // Wraps a PDU body with this header, filling in the fragment length
+    pub fn with_body(&self, body: &[u8]) -> Vec<u8> {
+        [
+            &[RPC_VERSION, 0, self.packet_type, self.flags][..],
+            &DATA_REPRESENTATION,
+            &u16_to_bytes((RPC_HEADER_SIZE + body.len()) as u16),
+            &u16_to_bytes(self.auth_length),
+            &u32_to_bytes(self.call_id),
+            body,
+        ].concat()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RPCContextElement {
+    pub context_id: u16,
+    pub abstract_syntax: RPCSyntaxId,
+    pub transfer_syntaxes: Vec<RPCSyntaxId>,
+}
+
+impl RPCContextElement {
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        let (remaining, (context_id, transfer_count, _, abstract_syntax)) = tuple((
+            le_u16,
+            le_u8,
+            take(1_usize),
+            RPCSyntaxId::parse,
+        ))(bytes)?;
+        let (remaining, transfer_syntaxes) = count(RPCSyntaxId::parse, transfer_count as usize)(remaining)?;
+        Ok((remaining, Self { context_id, abstract_syntax, transfer_syntaxes }))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = [
+            &u16_to_bytes(self.context_id)[..],
+            &[self.transfer_syntaxes.len() as u8, 0],
+            &self.abstract_syntax.as_bytes(),
+        ].concat();
+        for syntax in self.transfer_syntaxes.iter() {
+            bytes.extend_from_slice(&syntax.as_bytes());
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RPCBind {
+    pub max_xmit_frag: u16,
+    pub max_recv_frag: u16,
+    pub assoc_group_id: u32,
+    pub contexts: Vec<RPCContextElement>,
+}
+
+impl RPCBind {
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        let (remaining, (max_xmit_frag, max_recv_frag, assoc_group_id, context_count, _)) = tuple((
+            le_u16,
+            le_u16,
+            le_u32,
+            le_u8,
+            take(3_usize),
+        ))(bytes)?;
+        let (remaining, contexts) = count(RPCContextElement::parse, context_count as usize)(remaining)?;
+        Ok((remaining, Self { max_xmit_frag, max_recv_frag, assoc_group_id, contexts }))
+    }
+
+    pub fn as_bytes(&self, call_id: u32) -> Vec<u8> {
+        let mut body = [
+            &u16_to_bytes(self.max_xmit_frag)[..],
+            &u16_to_bytes(self.max_recv_frag),
+            &u32_to_bytes(self.assoc_group_id),
+            &[self.contexts.len() as u8, 0, 0, 0],
+        ].concat();
+        for context in self.contexts.iter() {
+            body.extend_from_slice(&context.as_bytes());
+        }
+        RPCHeader::new(RPCPacketType::Bind, call_id).with_body(&body)
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RPCBindResultCode {
+    Acceptance = 0,
+    UserRejection = 1,
+    ProviderRejection = 2,
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RPCBindRejectReason {
+    NotSpecified = 0,
+    AbstractSyntaxNotSupported = 1,
+    TransferSyntaxesNotSupported = 2,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RPCBindResult {
+    pub result: u16,
+    pub reason: u16,
+    pub transfer_syntax: RPCSyntaxId,
+}
+
+impl RPCBindResult {
+    pub fn accepted(transfer_syntax: RPCSyntaxId) -> Self {
+        Self {
+            result: RPCBindResultCode::Acceptance as u16,
+            reason: RPCBindRejectReason::NotSpecified as u16,
+            transfer_syntax,
+        }
+    }
+
+    // Rejected results carry a zeroed syntax
+    pub fn rejected(reason: RPCBindRejectReason) -> Self {
+        Self {
+            result: RPCBindResultCode::ProviderRejection as u16,
+            reason: reason as u16,
+            transfer_syntax: RPCSyntaxId { uuid: Uuid::nil(), version: 0 },
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        map(
+            tuple((le_u16, le_u16, RPCSyntaxId::parse)),
+            |(result, reason, transfer_syntax)| Self { result, reason, transfer_syntax },
+        )(bytes)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        [
+            &u16_to_bytes(self.result)[..],
+            &u16_to_bytes(self.reason),
+            &self.transfer_syntax.as_bytes(),
+        ].concat()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RPCBindAck {
+    pub max_xmit_frag: u16,
+    pub max_recv_frag: u16,
+    pub assoc_group_id: u32,
+    pub secondary_address: String,
+    pub results: Vec<RPCBindResult>,
+}
+
+impl RPCBindAck {
+    pub fn parse(bytes: &[u8]) -> IResult<&[u8], Self> {
+        let (remaining, (max_xmit_frag, max_recv_frag, assoc_group_id, address_length)) = tuple((
+            le_u16,
+            le_u16,
+            le_u32,
+            le_u16,
+        ))(bytes)?;
+        let (remaining, address) = map_res(take(address_length), |slice: &[u8]| {
+            String::from_utf8(slice.split(|byte| *byte == 0).next().unwrap_or_default().to_vec())
+        })(remaining)?;
+        let (remaining, _) = take(Self::address_padding(address_length as usize))(remaining)?;
+        let (remaining, (result_count, _)) = tuple((le_u8, take(3_usize)))(remaining)?;
+        let (remaining, results) = count(RPCBindResult::parse, result_count as usize)(remaining)?;
+        Ok((remaining, Self {
+            max_xmit_frag,
+            max_recv_frag,
+            assoc_group_id,
+            secondary_address: address,
+            results,
+        }))
+    }
+
+    pub fn as_bytes(&self, call_id: u32) -> Vec<u8> {
+        // The secondary address goes out NUL terminated, an empty one is just its length
+        let address = match self.secondary_address.is_empty() {
+            true => Vec::new(),
+            false => [self.secondary_address.as_bytes(), &[0]].concat(),
+        };
+        let mut body = [
+            &u16_to_bytes(self.max_xmit_frag)[..],
+            &u16_to_bytes(self.max_recv_frag),
+            &u32_to_bytes(self.assoc_group_id),
+            &u16_to_bytes(address.len() as u16),
+            &address,
+            &vec![0; Self::address_padding(address.len())],
+            &[self.results.len() as u8, 0, 0, 0],
+        ].concat();
+        for result in self.results.iter() {
+            body.extend_from_slice(&result.as_bytes());
+        }
+        RPCHeader::new(RPCPacketType::BindAck, call_id).with_body(&body)
+    }
+
+    // The result list starts 4-byte aligned from the start of the PDU
+    fn address_padding(address_length: usize) -> usize {
+        let address_end = RPC_HEADER_SIZE + 10 + address_length;
+        (4 - address_end % 4) % 4
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RPCRequest<'a> {
+    pub alloc_hint: u32,
+    pub context_id: u16,
+    pub opnum: u16,
+    pub stub: &'a [u8],
+}
+
+impl<'a> RPCRequest<'a> {
+    pub fn parse(bytes: &'a [u8]) -> IResult<&'a [u8], Self> {
+        let (stub, (alloc_hint, context_id, opnum)) = tuple((le_u32, le_u16, le_u16))(bytes)?;
+        Ok((&[], Self { alloc_hint, context_id, opnum, stub }))
+    }
+}
+
+pub fn rpc_fault(call_id: u32, context_id: u16, status: u32) -> Vec<u8> {
+    let body = [
+        &u32_to_bytes(0)[..], // alloc hint
+        &u16_to_bytes(context_id),
+        &[0, 0], // cancel count, reserved
+        &u32_to_bytes(status),
+        &u32_to_bytes(0),
+    ].concat();
+    RPCHeader::new(RPCPacketType::Fault, call_id).with_body(&body)
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::uuid;
+
+    use crate::util::rpc::{NDR_TRANSFER_SYNTAX, RPC_HEADER_SIZE, RPCBind, RPCBindAck, RPCBindResult, RPCContextElement, RPCHeader, RPCPacketType, RPCSyntaxId};
+
+    #[test]
+    fn bind_round_trips_through_its_wire_form() {
+        let bind = RPCBind {
+            max_xmit_frag: 4280,
+            max_recv_frag: 4280,
+            assoc_group_id: 0,
+            contexts: vec![RPCContextElement {
+                context_id: 0,
+                abstract_syntax: RPCSyntaxId { uuid: uuid!("4b324fc8-1670-01d3-1278-5a47bf6ee188"), version: 3 },
+                transfer_syntaxes: vec![NDR_TRANSFER_SYNTAX],
+            }],
+        };
+        let bytes = bind.as_bytes(7);
+        let (body, header) = RPCHeader::parse(&bytes).unwrap();
+        assert_eq!(header.packet_type(), Ok(RPCPacketType::Bind));
+        assert_eq!(header.frag_length as usize, bytes.len());
+        assert_eq!(header.call_id, 7);
+        assert_eq!(RPCBind::parse(body).unwrap().1, bind);
+    }
+
+    #[test]
+    fn bind_ack_result_list_is_aligned_after_the_address() {
+        for address in ["", "\\PIPE\\srvsvc", "\\PIPE\\lsarpc"] {
+            let ack = RPCBindAck {
+                max_xmit_frag: 4280,
+                max_recv_frag: 4280,
+                assoc_group_id: 0x1234,
+                secondary_address: address.into(),
+                results: vec![RPCBindResult::accepted(NDR_TRANSFER_SYNTAX)],
+            };
+            let bytes = ack.as_bytes(1);
+            assert_eq!((bytes.len() - RPC_HEADER_SIZE - 24) % 4, 0);
+            assert_eq!(RPCBindAck::parse(&bytes[RPC_HEADER_SIZE..]).unwrap().1, ack);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+
+use smb_core::error::SMBError;
+use smb_core::nt_status::NTStatus;
+use smb_reader::protocol::body::tree_connect::access_mask::{SMBAccessMask, SMBDirectoryAccessMask};
+use smb_reader::server::{DefaultShare, SMBServer, SMBServerBuilder, StartSMBServer};
+use smb_reader::util::auth::ntlm::NTLMAuthProvider;
+use smb_reader::util::auth::User;
+
+#[allow(dead_code)]
+#[path = "../examples/list_share.rs"]
+mod list_share;
+#[path = "../src/test_util/temp_dir.rs"]
+mod temp_dir;
+
+type TestServer = Arc<RwLock<SMBServer<String>>>;
+
+async fn start_server(root: &Path) -> (TestServer, String) {
+    // Reserve a free port up front since the server doesn't hand back its bound address
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let server = SMBServerBuilder::<_, TcpListener, NTLMAuthProvider, DefaultShare<NTLMAuthProvider>, _>::default()
+        .unencrypted_access(true)
+        .require_message_signing(false)
+        .encrypt_data(false)
+        .add_fs_share("test".into(), root.to_string_lossy().into_owned(), |_| true, |_| SMBAccessMask::Directory(SMBDirectoryAccessMask::GENERIC_ALL), false)
+        .auth_provider(NTLMAuthProvider::new(vec![User::new("alice", "password")], false))
+        .listener_address(addr.clone()).await.unwrap()
+        .build().unwrap();
+    server.clone().spawn();
+    (server, addr)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn example_lists_the_share_root() {
+    let root = temp_dir::TempDir::new("list_share");
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::write(root.join("a.txt"), b"a").unwrap();
+    fs::write(root.join("b.txt"), b"b").unwrap();
+    let (server, addr) = start_server(&root).await;
+
+    let result = list_share::list_share_root(&addr, "test", "", "alice", "password").await;
+    let rejected = list_share::list_share_root(&addr, "test", "", "alice", "wrong").await;
+    server.read().await.shutdown();
+
+    let mut names = result.unwrap();
+    names.retain(|name| name != "." && name != "..");
+    names.sort();
+    assert_eq!(names, vec!["a.txt", "b.txt", "docs"]);
+    assert!(matches!(rejected, Err(SMBError::ResponseError(e)) if e.status() == NTStatus::LogonFailure));
+}
@@ -1,2 +1,28 @@
 extern crate smb_derive;
 extern crate smb_reader;
+
+use smb_core::{SMBByteSize, SMBFromBytes, SMBToBytes};
+use smb_derive::{SMBByteSize, SMBFromBytes, SMBToBytes};
+
+#[derive(Debug, PartialEq, Eq, SMBByteSize, SMBFromBytes, SMBToBytes)]
+struct AlignedVector {
+    #[smb_direct(start(fixed = 0))]
+    kind: u16,
+    #[smb_vector(order = 1, align = 4, count(inner(start = 2, num_type = "u16")), offset(inner(start = 4, num_type = "u16")))]
+    entries: Vec<[u8; 3]>,
+}
+
+#[test]
+fn vector_offsets_and_padding_follow_alignment() {
+    let value = AlignedVector { kind: 7, entries: vec![[1, 2, 3], [4, 5, 6], [7, 8, 9]] };
+    let bytes = value.smb_to_bytes();
+    assert_eq!(bytes.len(), value.smb_byte_size());
+    assert_eq!(bytes, vec![
+        7, 0, 3, 0, 8, 0, 0, 0,
+        1, 2, 3, 0,
+        4, 5, 6, 0,
+        7, 8, 9,
+    ]);
+    let (_, parsed) = AlignedVector::smb_from_bytes(&bytes).unwrap();
+    assert_eq!(parsed, value);
+}